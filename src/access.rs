@@ -0,0 +1,99 @@
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::groups::list_groups;
+use crate::permissions::{resolve_user, ObjectType, PermissionType};
+use crate::repositories::{audit, AuditResult, Paging};
+use crate::workspace::{list_workspace_permissions, WorkspaceRole};
+
+/// The level a user has on a repository and the grants it comes from
+#[derive(Debug, Clone, Serialize)]
+pub struct Access {
+    pub repository: String,
+    /// Highest level among the grants
+    pub permission: PermissionType,
+    /// `direct` for a grant to the user, `group:<slug>` for a grant to a group they belong to,
+    /// and `workspace:owner` when they own the workspace
+    pub via: Vec<String>,
+}
+
+/// What a user can reach across the workspace
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessReport {
+    pub user: String,
+    pub groups: Vec<String>,
+    pub access: Vec<Access>,
+    /// Repositories whose permissions could not be listed, with the error
+    #[serde(skip)]
+    pub failed: Vec<(String, Arc<BbdanError>)>,
+}
+
+/// Repositories the user, given by UUID or nickname, can reach directly, through the groups they belong to
+/// or as an owner of the workspace
+pub async fn access(bitbucket: Bitbucket, user: &str) -> Result<AccessReport, BbdanError> {
+    let (uuid, nickname) = resolve_user(&bitbucket, user).await?;
+    let groups: Vec<String> = list_groups(&bitbucket)
+        .await?
+        .into_iter()
+        .filter(|g| g.has_member(&uuid, &nickname))
+        .map(|g| g.slug)
+        .collect();
+    let owner = list_workspace_permissions(&bitbucket, Some(WorkspaceRole::Owner))
+        .await?
+        .iter()
+        .any(|p| p.user.uuid == uuid);
+    let (results, _) = audit(bitbucket, &Paging::default(), |_| {}).await?;
+
+    let mut report = access_of(&results, &uuid, &groups, owner);
+    report.user = nickname;
+    Ok(report)
+}
+
+/// Access of the user with the UUID, member of the groups and possibly owner of the workspace,
+/// from the audited permissions
+pub fn access_of(
+    results: &[AuditResult],
+    uuid: &str,
+    groups: &[String],
+    owner: bool,
+) -> AccessReport {
+    let mut report = AccessReport {
+        user: uuid.to_string(),
+        groups: groups.to_vec(),
+        access: Vec::new(),
+        failed: Vec::new(),
+    };
+    for (repo, result) in results {
+        let permissions = match result {
+            Ok(permissions) => permissions,
+            Err(e) => {
+                report.failed.push((repo.to_string(), e.clone()));
+                continue;
+            }
+        };
+        let mut grants: Vec<(PermissionType, String)> = permissions
+            .iter()
+            .filter_map(|p| match p.object_type {
+                ObjectType::User if p.id == uuid => Some((p.permission, "direct".to_string())),
+                ObjectType::Group if groups.contains(&p.id) => {
+                    Some((p.permission, format!("group:{}", p.id)))
+                }
+                _ => None,
+            })
+            .collect();
+        if owner {
+            grants.push((PermissionType::Admin, "workspace:owner".to_string()));
+        }
+        let Some(permission) = grants.iter().map(|(level, _)| *level).max() else {
+            continue;
+        };
+        report.access.push(Access {
+            repository: repo.to_string(),
+            permission,
+            via: grants.into_iter().map(|(_, via)| via).collect(),
+        });
+    }
+    report
+}
@@ -0,0 +1,222 @@
+use clap::ArgEnum;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::{
+    list, permission_type_to_str, resolve_user, ObjectType, Options, Permission, PermissionType,
+};
+use crate::reconcile::{apply_changes, Change, Summary};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum Action {
+    Add,
+    Update,
+    Remove,
+}
+
+/// A row of the changes file: `repo,principal_type,principal,permission,action`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    /// Line in the file, for error messages
+    pub line: usize,
+    pub repo: String,
+    pub object_type: ObjectType,
+    /// UUID or nickname of a user, slug of a group
+    pub principal: String,
+    /// Empty for remove
+    pub permission: Option<PermissionType>,
+    pub action: Action,
+}
+
+/// Parse the rows of a changes file. A header row starting with `repo` and `#` comments are skipped.
+/// Every invalid row is reported in the error, so the file can be fixed in one go.
+pub fn parse_rows(content: &str) -> Result<Vec<Row>, BbdanError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record
+            .position()
+            .map(|p| p.line() as usize)
+            .unwrap_or_default();
+        // comments are skipped here rather than by the reader, which would not count their lines
+        if record.get(0) == Some("repo") || record.get(0).is_some_and(|f| f.starts_with('#')) {
+            continue;
+        }
+        match parse_row(&record) {
+            Ok((repo, object_type, principal, permission, action)) => rows.push(Row {
+                line,
+                repo,
+                object_type,
+                principal,
+                permission,
+                action,
+            }),
+            Err(e) => errors.push(format!("line {}: {}", line, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(BbdanError::Invalid(errors.join("\n")));
+    }
+    Ok(rows)
+}
+
+type Fields = (String, ObjectType, String, Option<PermissionType>, Action);
+
+fn parse_row(record: &csv::StringRecord) -> Result<Fields, String> {
+    let [repo, object_type, principal, permission, action] = record.iter().collect::<Vec<_>>()[..]
+    else {
+        return Err(format!("expected 5 fields, got {}", record.len()));
+    };
+    if repo.is_empty() || principal.is_empty() {
+        return Err("repo and principal are required".to_string());
+    }
+    let object_type = ObjectType::from_str(object_type, true).map_err(|_| {
+        format!(
+            "unknown principal type {:?}, expected user or group",
+            object_type
+        )
+    })?;
+    let action = Action::from_str(action, true).map_err(|_| {
+        format!(
+            "unknown action {:?}, expected add, update or remove",
+            action
+        )
+    })?;
+    let permission = match (permission, action) {
+        ("", Action::Remove) => None,
+        ("", _) => return Err("permission is required to add or update".to_string()),
+        (permission, _) => Some(
+            PermissionType::from_str(permission, true)
+                .map_err(|_| format!("unknown permission {:?}", permission))?,
+        ),
+    };
+    Ok((
+        repo.to_string(),
+        object_type,
+        principal.to_string(),
+        permission,
+        action,
+    ))
+}
+
+/// Apply the changes of a CSV file, confirming each, with a summary per repository.
+/// The file and the users in it are validated before anything is changed;
+/// the rows of a repository are checked against its permissions before they are applied.
+pub async fn apply_csv(
+    bitbucket: Bitbucket,
+    path: &Path,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    let rows = parse_rows(&fs::read_to_string(path)?)?;
+
+    // nickname -> (uuid, nickname)
+    let mut users: HashMap<String, (String, String)> = HashMap::new();
+    let mut errors = Vec::new();
+    for row in rows.iter().filter(|r| r.object_type == ObjectType::User) {
+        if users.contains_key(&row.principal) {
+            continue;
+        }
+        match resolve_user(&bitbucket, &row.principal).await {
+            Ok(user) => {
+                users.insert(row.principal.to_string(), user);
+            }
+            Err(e) => errors.push(format!("line {}: {}", row.line, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(BbdanError::Invalid(errors.join("\n")));
+    }
+
+    let mut by_repo: BTreeMap<&str, Vec<&Row>> = BTreeMap::new();
+    for row in &rows {
+        by_repo.entry(&row.repo).or_default().push(row);
+    }
+
+    let mut results = Vec::new();
+    for (repo, rows) in by_repo {
+        tracing::info!("Repository: {}", repo);
+        let dest = Bitbucket {
+            slug: repo.to_string(),
+            ..bitbucket.clone()
+        };
+        let result = async {
+            let before = list(dest.clone()).await?;
+            let changes = changes(&before, &rows, &users)?;
+            apply_changes(dest, changes, options).await
+        }
+        .await
+        .map_err(Arc::new);
+        results.push((repo.to_string(), result));
+    }
+    Ok(results)
+}
+
+/// Turn the rows of a repository into changes against its permissions.
+/// Adding an existing grant at the same level and removing a missing one change nothing;
+/// adding at another level and updating a missing grant are errors.
+fn changes(
+    before: &[Permission],
+    rows: &[&Row],
+    users: &HashMap<String, (String, String)>,
+) -> Result<Vec<Change>, BbdanError> {
+    let mut changes = Vec::new();
+    let mut errors = Vec::new();
+    for row in rows {
+        let (id, alias) = match row.object_type {
+            ObjectType::User => users[&row.principal].clone(),
+            ObjectType::Group => (row.principal.to_string(), row.principal.to_string()),
+        };
+        let current = before
+            .iter()
+            .find(|p| p.object_type == row.object_type && p.id == id);
+        let desired = |permission| Permission {
+            object_type: row.object_type,
+            alias: alias.to_string(),
+            id: id.to_string(),
+            permission,
+            via: Vec::new(),
+        };
+        match (row.action, current, row.permission) {
+            (Action::Add, None, Some(level)) => changes.push(Change::Add(desired(level))),
+            (Action::Add | Action::Update, Some(p), Some(level)) if p.permission == level => {
+                changes.push(Change::Unchanged(p.clone()))
+            }
+            (Action::Add, Some(p), _) => errors.push(format!(
+                "line {}: {} already has {} on {}, use update",
+                row.line,
+                row.principal,
+                permission_type_to_str(p.permission),
+                row.repo
+            )),
+            (Action::Update, Some(p), Some(level)) => changes.push(Change::Update {
+                before: p.clone(),
+                after: desired(level),
+            }),
+            (Action::Update, None, _) => errors.push(format!(
+                "line {}: {} has no permission on {}, use add",
+                row.line, row.principal, row.repo
+            )),
+            (Action::Remove, Some(p), _) => changes.push(Change::Remove(p.clone())),
+            (Action::Remove, None, _) => {
+                tracing::info!("Not granted: {} on {}", row.principal, row.repo)
+            }
+            // parse_rows requires the permission to add or update
+            (Action::Add | Action::Update, _, None) => unreachable!(),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(BbdanError::Invalid(errors.join("\n")));
+    }
+    Ok(changes)
+}
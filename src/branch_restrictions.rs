@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::Options;
+use crate::reconcile::Summary;
+use crate::resources::{self, Resource};
+
+/// Columns of the branch restrictions in list
+pub const HEADER: &[&str] = &["id", "kind", "branches", "value", "users", "groups"];
+
+/// A rule of who can push, merge or delete on the matching branches, an item of branch-restrictions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BranchRestriction {
+    /// Absent from the restrictions to create
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    /// e.g. `push`, `force` or `require_approvals_to_merge`
+    pub kind: String,
+    /// `glob` or `branching_model`
+    #[serde(default)]
+    pub branch_match_kind: String,
+    /// Glob of the branch names, with `glob`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// e.g. `production`, with `branching_model`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch_type: Option<String>,
+    /// Number of approvals or builds, for the kinds that take one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<u64>,
+    /// Users exempt from a push or merge restriction
+    #[serde(default)]
+    pub users: Vec<RestrictionUser>,
+    /// Groups exempt from a push or merge restriction
+    #[serde(default)]
+    pub groups: Vec<RestrictionGroup>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestrictionUser {
+    pub uuid: String,
+    #[serde(default)]
+    pub nickname: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestrictionGroup {
+    pub slug: String,
+    #[serde(default)]
+    pub name: String,
+}
+
+impl BranchRestriction {
+    /// The branches the rule applies to, a glob or `type:<branch type>`
+    pub fn branches(&self) -> String {
+        match (&self.branch_type, &self.pattern) {
+            (Some(branch_type), _) if self.branch_match_kind == "branching_model" => {
+                format!("type:{}", branch_type)
+            }
+            (_, Some(pattern)) => pattern.to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn user_names(&self) -> BTreeSet<&str> {
+        self.users
+            .iter()
+            .map(|u| match u.nickname.as_str() {
+                "" => u.uuid.as_str(),
+                nickname => nickname,
+            })
+            .collect()
+    }
+
+    fn group_slugs(&self) -> BTreeSet<&str> {
+        self.groups.iter().map(|g| g.slug.as_str()).collect()
+    }
+
+    /// The rule as a row of [`HEADER`]
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.id.map(|id| id.to_string()).unwrap_or_default(),
+            self.kind.to_string(),
+            self.branches(),
+            self.value.map(|v| v.to_string()).unwrap_or_default(),
+            join(&self.user_names()),
+            join(&self.group_slugs()),
+        ]
+    }
+}
+
+impl Resource for BranchRestriction {
+    const PATH: &'static str = "branch-restrictions";
+
+    fn key(&self) -> Option<String> {
+        self.id.map(|id| id.to_string())
+    }
+
+    /// Whether both rules restrict the same branches in the same way, whatever their ids
+    fn same(&self, other: &BranchRestriction) -> bool {
+        let uuids = |r: &BranchRestriction| -> BTreeSet<String> {
+            r.users.iter().map(|u| u.uuid.to_string()).collect()
+        };
+        self.kind == other.kind
+            && self.branch_match_kind == other.branch_match_kind
+            && self.branches() == other.branches()
+            && self.value == other.value
+            && uuids(self) == uuids(other)
+            && self.group_slugs() == other.group_slugs()
+    }
+
+    /// The rule in words, e.g. `kind=push, branches=main, users=alice`
+    fn describe(&self) -> String {
+        let mut words = format!("kind={}, branches={}", self.kind, self.branches());
+        if let Some(value) = self.value {
+            words += &format!(", value={}", value);
+        }
+        if !self.users.is_empty() {
+            words += &format!(", users={}", join(&self.user_names()));
+        }
+        if !self.groups.is_empty() {
+            words += &format!(", groups={}", join(&self.group_slugs()));
+        }
+        words
+    }
+
+    fn created(&self) -> Self {
+        BranchRestriction {
+            id: None,
+            ..self.clone()
+        }
+    }
+}
+
+fn join(names: &BTreeSet<&str>) -> String {
+    names.iter().copied().collect::<Vec<_>>().join(" ")
+}
+
+/// Branch restrictions to remove without the interactive picker
+#[derive(Debug, Clone, Default)]
+pub struct RestrictionFilter {
+    pub ids: Vec<u64>,
+    /// e.g. `push`
+    pub kinds: Vec<String>,
+}
+
+impl RestrictionFilter {
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty() && self.kinds.is_empty()
+    }
+
+    pub fn matches(&self, r: &BranchRestriction) -> bool {
+        r.id.is_some_and(|id| self.ids.contains(&id)) || self.kinds.contains(&r.kind)
+    }
+}
+
+pub async fn list_restrictions(
+    bitbucket: &Bitbucket,
+) -> Result<Vec<BranchRestriction>, BbdanError> {
+    resources::list_all(bitbucket, BranchRestriction::PATH).await
+}
+
+/// Copy the branch restrictions of src to each destination, with the result per destination.
+/// The rules a destination already has are left as they are, and with `prune` those src lacks are removed.
+pub async fn copy_restrictions(
+    src: Bitbucket,
+    dests: &[Bitbucket],
+    prune: bool,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    resources::copy::<BranchRestriction>(src, dests, prune, options).await
+}
+
+/// Remove the branch restrictions the filter matches, or those picked interactively when it is empty
+pub async fn remove_restrictions(
+    bitbucket: Bitbucket,
+    filter: &RestrictionFilter,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    if filter.is_empty() {
+        if options.yes {
+            return Err(BbdanError::Invalid(
+                "--id or --kind is required in non-interactive mode".to_string(),
+            ));
+        }
+        return resources::remove_items::<BranchRestriction>(&bitbucket, None, options).await;
+    }
+    resources::remove_items(&bitbucket, Some(&|r| filter.matches(r)), options).await
+}
@@ -0,0 +1,185 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::{confirm_each, Options};
+use crate::reconcile::Summary;
+use crate::resources;
+
+/// Columns of the branching model in show
+pub const HEADER: &[&str] = &["kind", "enabled", "value"];
+
+const PATH: &str = "branching-model/settings";
+
+/// The branching model of the repository as configured, branching-model/settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BranchingModel {
+    pub development: ModelBranch,
+    pub production: ModelBranch,
+    #[serde(default)]
+    pub branch_types: Vec<BranchType>,
+}
+
+/// The development or production branch of the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelBranch {
+    /// Ignored when the main branch is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub use_mainbranch: bool,
+    /// Only the production branch can be disabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+}
+
+/// Prefix of the branches of a kind, e.g. `feature/` for `feature`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BranchType {
+    pub kind: String,
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+}
+
+/// A branch or branch type of the model, a row of [`HEADER`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelRow {
+    pub kind: String,
+    pub enabled: bool,
+    /// The branch name or prefix
+    pub value: String,
+}
+
+impl ModelRow {
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.kind.to_string(),
+            self.enabled.to_string(),
+            self.value.to_string(),
+        ]
+    }
+
+    fn describe(&self) -> String {
+        match self.enabled {
+            true => self.value.to_string(),
+            false => "disabled".to_string(),
+        }
+    }
+}
+
+impl ModelBranch {
+    fn row(&self, kind: &str) -> ModelRow {
+        ModelRow {
+            kind: kind.to_string(),
+            enabled: self.enabled.unwrap_or(true),
+            value: match self.use_mainbranch {
+                true => "(main branch)".to_string(),
+                false => self.name.clone().unwrap_or_default(),
+            },
+        }
+    }
+}
+
+impl BranchingModel {
+    /// The development and production branches, then the branch types
+    pub fn rows(&self) -> Vec<ModelRow> {
+        let mut rows = vec![
+            self.development.row("development"),
+            self.production.row("production"),
+        ];
+        for t in &self.branch_types {
+            rows.push(ModelRow {
+                kind: t.kind.to_string(),
+                enabled: t.enabled,
+                value: t.prefix.clone().unwrap_or_default(),
+            });
+        }
+        rows
+    }
+
+    /// The part of the settings to update for the row of this kind
+    fn part(&self, kind: &str) -> Result<(&'static str, Value), BbdanError> {
+        Ok(match kind {
+            "development" => ("development", serde_json::to_value(&self.development)?),
+            "production" => ("production", serde_json::to_value(&self.production)?),
+            _ => {
+                let t = self.branch_types.iter().find(|t| t.kind == kind);
+                ("branch_types", serde_json::to_value(t)?)
+            }
+        })
+    }
+}
+
+pub async fn branching_model(bitbucket: &Bitbucket) -> Result<BranchingModel, BbdanError> {
+    resources::get(bitbucket, PATH).await
+}
+
+/// Copy the branching model of src to each destination, with the result per destination
+pub async fn copy_branching_model(
+    src: Bitbucket,
+    dests: &[Bitbucket],
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    let model = branching_model(&src).await?;
+    let mut results = Vec::new();
+    for dest in dests {
+        tracing::info!("Repository: {}", dest.slug);
+        let result = apply_branching_model(dest, &model, options)
+            .await
+            .map_err(Arc::new);
+        results.push((dest.slug.to_string(), result));
+    }
+    Ok(results)
+}
+
+async fn apply_branching_model(
+    dest: &Bitbucket,
+    model: &BranchingModel,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let current = branching_model(dest).await?.rows();
+    let mut summary = Summary::default();
+
+    let mut changes = Vec::new();
+    for row in model.rows() {
+        match current.iter().find(|c| c.kind == row.kind) {
+            Some(c) if *c == row => summary.unchanged += 1,
+            c => changes.push((row, c)),
+        }
+    }
+    let messages: Vec<String> = changes
+        .iter()
+        .map(|(row, c)| {
+            let from = c.map(ModelRow::describe).unwrap_or_default();
+            format!("Set {}: {} -> {}.", row.kind, from, row.describe())
+        })
+        .collect();
+
+    // One update of the settings with every selected part, branch types not in it are left as they are
+    let mut body = Map::new();
+    let mut branch_types = Vec::new();
+    for ((row, _), selected) in changes.into_iter().zip(confirm_each(&messages, options)?) {
+        if !selected {
+            summary.skipped += 1;
+            continue;
+        }
+        summary.updated += 1;
+        match model.part(&row.kind)? {
+            ("branch_types", t) => branch_types.push(t),
+            (key, part) => {
+                body.insert(key.to_string(), part);
+            }
+        }
+    }
+    if !branch_types.is_empty() {
+        body.insert("branch_types".to_string(), Value::from(branch_types));
+    }
+    if !body.is_empty() {
+        resources::put(dest, PATH, &body, options).await?;
+    }
+    Ok(summary)
+}
@@ -0,0 +1,305 @@
+use chrono::{DateTime, Local, Utc};
+use reqwest::header::AUTHORIZATION;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+use crate::error::BbdanError;
+use crate::history::Recorder;
+use crate::metrics;
+use crate::oauth::OAuthSession;
+use crate::output::{Output, OutputMessage};
+use crate::telemetry::SPAN_TARGET;
+
+// Bitbucket APIを実行する
+
+pub const BASE_URL: &str = "https://api.bitbucket.org/2.0";
+
+#[derive(Debug, Clone)]
+pub struct Bitbucket {
+    pub http_client: reqwest::Client,
+    pub base_url: String,
+    pub auth: Auth,
+    pub workspace: String,
+    pub slug: String,
+    /// Number of items requested per page
+    pub pagelen: u32,
+    /// Number of requests sent at the same time by bulk operations
+    pub parallel: usize,
+    /// File logging every request, set by --log-http
+    pub http_log: Option<Arc<HttpLog>>,
+    /// History the listings and changes are recorded in, when enabled in the config
+    pub history: Option<Recorder>,
+}
+
+impl Bitbucket {
+    /// Client sending requests with the shared HTTP client and the credentials
+    pub fn client(&self) -> BitbucketClient {
+        BitbucketClient {
+            http_log: self.http_log.clone(),
+            ..BitbucketClient::new(
+                self.http_client.clone(),
+                self.base_url.to_string(),
+                self.auth.clone(),
+            )
+        }
+    }
+
+    /// URL of a resource of the repository, e.g. `permissions-config/users`, or of the repository itself for ``
+    pub fn repository_url(&self, path: &str) -> String {
+        if path.is_empty() {
+            return format!(
+                r#"{}/repositories/{}/{}"#,
+                self.base_url, self.workspace, self.slug
+            );
+        }
+        format!(
+            r#"{}/repositories/{}/{}/{}"#,
+            self.base_url, self.workspace, self.slug, path
+        )
+    }
+
+    /// URL of a resource of the workspace, e.g. `members`
+    pub fn workspace_url(&self, path: &str) -> String {
+        format!(
+            r#"{}/workspaces/{}/{}"#,
+            self.base_url, self.workspace, path
+        )
+    }
+
+    /// URL of a resource of the 1.0 API, which still serves the groups of a workspace, e.g. `groups/{workspace}`
+    pub fn v1_url(&self, path: &str) -> String {
+        let base = self.base_url.strip_suffix("/2.0").unwrap_or(&self.base_url);
+        format!("{}/1.0/{}", base, path)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Auth {
+    Basic {
+        username: String,
+        password: String,
+    },
+    Bearer {
+        token: String,
+    },
+    /// OAuth 2.0 access token, refreshed while the process runs
+    OAuth(Arc<OAuthSession>),
+}
+
+impl Auth {
+    pub async fn apply(&self, req: RequestBuilder) -> RequestBuilder {
+        match self {
+            Auth::Basic { username, password } => req.basic_auth(username, Some(password)),
+            Auth::Bearer { token } => req.bearer_auth(token),
+            Auth::OAuth(session) => req.bearer_auth(session.access_token().await),
+        }
+    }
+}
+
+/// A page of a paginated API response
+#[derive(Debug, Deserialize)]
+pub struct Page<T> {
+    pub values: Vec<T>,
+    /// URL of the next page, absent on the last page
+    pub next: Option<String>,
+    /// Total number of items, not returned by every endpoint
+    #[allow(dead_code)]
+    pub size: Option<u32>,
+}
+
+/// Build the HTTP client shared by every request
+pub fn http_client(
+    timeout: Duration,
+    connect_timeout: Duration,
+) -> Result<reqwest::Client, BbdanError> {
+    Ok(reqwest::Client::builder()
+        .timeout(timeout)
+        .connect_timeout(connect_timeout)
+        .build()?)
+}
+
+/// Appends a line per request to the --log-http file
+#[derive(Debug)]
+pub struct HttpLog {
+    file: Mutex<File>,
+    output: Output,
+}
+
+impl HttpLog {
+    pub fn open(path: &Path, output: Output) -> Result<Self, BbdanError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+            output,
+        })
+    }
+
+    fn write(&self, message: &OutputMessage) {
+        let line = message.to_formatted(self.output);
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writeln!(file, "{}", line) {
+            tracing::warn!("failed to write the HTTP log: {}", e);
+        }
+    }
+}
+
+pub struct BitbucketClient {
+    pub http_client: reqwest::Client,
+    pub base_url: String,
+    pub auth: Auth,
+    pub http_log: Option<Arc<HttpLog>>,
+}
+impl BitbucketClient {
+    pub fn new(http_client: reqwest::Client, base_url: String, auth: Auth) -> Self {
+        Self {
+            http_client,
+            base_url,
+            auth,
+            http_log: None,
+        }
+    }
+
+    /// Absolute URL of a path relative to the base URL
+    pub fn url(&self, path: &str) -> String {
+        format!(r#"{}/{}"#, self.base_url, path)
+    }
+
+    pub async fn http_get(&self, url: String) -> Result<Response, reqwest::Error> {
+        self.http_get_url(self.url(&url)).await
+    }
+
+    /// GET an absolute URL, e.g. the `next` link of a paginated response
+    pub async fn http_get_url(&self, full_url: String) -> Result<Response, reqwest::Error> {
+        self.send(self.http_client.get(full_url)).await
+    }
+
+    pub async fn http_put_url<T: Serialize + ?Sized>(
+        &self,
+        full_url: String,
+        body: &T,
+    ) -> Result<Response, reqwest::Error> {
+        self.send(self.http_client.put(full_url).json(body)).await
+    }
+
+    pub async fn http_post_url<T: Serialize + ?Sized>(
+        &self,
+        full_url: String,
+        body: &T,
+    ) -> Result<Response, reqwest::Error> {
+        self.send(self.http_client.post(full_url).json(body)).await
+    }
+
+    pub async fn http_delete_url(&self, full_url: String) -> Result<Response, reqwest::Error> {
+        self.send(self.http_client.delete(full_url)).await
+    }
+
+    /// Send the request with the credentials, waiting and retrying as long as Retry-After says when it is rate limited
+    /// and retrying once with a refreshed OAuth token when the token is rejected
+    async fn send(&self, req: RequestBuilder) -> Result<Response, reqwest::Error> {
+        let mut retries = 0;
+        let mut renewed = false;
+        loop {
+            let attempt = match req.try_clone() {
+                Some(attempt) => self.auth.apply(attempt).await,
+                None => return self.auth.apply(req).await.send().await,
+            };
+            let request = attempt.build()?;
+            let authorization = request.headers().get(AUTHORIZATION).cloned();
+            let method = request.method().to_string();
+            let span = tracing::info_span!(
+                target: SPAN_TARGET,
+                "request",
+                otel.name = %format!("HTTP {}", method),
+                otel.kind = "client",
+                otel.status_code = tracing::field::Empty,
+                http.method = %method,
+                http.url = %request.url(),
+                http.status_code = tracing::field::Empty,
+            );
+            let started = Instant::now();
+            let resp = match self
+                .http_client
+                .execute(request)
+                .instrument(span.clone())
+                .await
+            {
+                Ok(resp) => resp,
+                Err(e) => {
+                    span.record("otel.status_code", "ERROR");
+                    return Err(e);
+                }
+            };
+            span.record("http.status_code", resp.status().as_u16());
+            if resp.status().is_client_error() || resp.status().is_server_error() {
+                span.record("otel.status_code", "ERROR");
+            }
+            tracing::debug!("{} {}", resp.status(), resp.url());
+            metrics::record_request(&method, resp.status().as_u16());
+            if let Some(log) = &self.http_log {
+                log.write(&OutputMessage::new(
+                    Local::now(),
+                    resp.url().to_string(),
+                    resp.status(),
+                    started.elapsed(),
+                ));
+            }
+            if let (StatusCode::UNAUTHORIZED, Auth::OAuth(session), false) =
+                (resp.status(), &self.auth, renewed)
+            {
+                let rejected = authorization
+                    .as_ref()
+                    .and_then(|h| h.to_str().ok())
+                    .and_then(|h| h.strip_prefix("Bearer "))
+                    .unwrap_or_default();
+                match session.renew(rejected).await {
+                    Ok(()) => {
+                        renewed = true;
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::warn!("failed to refresh the OAuth token: {}", e);
+                        return Ok(resp);
+                    }
+                }
+            }
+            if resp.status() != StatusCode::TOO_MANY_REQUESTS {
+                return Ok(resp);
+            }
+            metrics::record_rate_limit();
+            if retries == RATE_LIMIT_RETRIES {
+                return Ok(resp);
+            }
+            let wait = match retry_after(&resp) {
+                Some(wait) => wait,
+                None => return Ok(resp),
+            };
+
+            retries += 1;
+            tracing::warn!("rate limited, waiting {}s", wait.as_secs());
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Number of times a rate limited request is retried before giving up
+pub const RATE_LIMIT_RETRIES: u32 = 5;
+
+/// Parse Retry-After given either in seconds or as an HTTP date
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let value = resp.headers().get("retry-after")?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
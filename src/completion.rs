@@ -0,0 +1,123 @@
+use clap::{Arg, Command};
+use clap_complete::{generate, Shell};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Positional arguments taking a repository slug
+const REPO_ARGS: [&str; 5] = ["repo", "src-repo", "dest-repo", "repo-a", "repo-b"];
+
+/// Hidden subcommand the completion scripts call to list the repository slugs
+pub const COMPLETE_REPOS: &str = "complete-repos";
+
+/// How long the listed repository slugs are reused before asking the API again
+pub const CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Completion script for the shell. For bash, zsh and fish the repository arguments
+/// are completed with the slugs of the workspace by calling `bbdan complete-repos`.
+pub fn script(cmd: &mut Command, shell: Shell) -> String {
+    let name = cmd.get_name().to_string();
+    let mut buf = Vec::new();
+    generate(shell, cmd, &name, &mut buf);
+    let script = String::from_utf8_lossy(&buf).into_owned();
+
+    let repos = format!("{} {} 2>/dev/null", name, COMPLETE_REPOS);
+    match shell {
+        Shell::Bash => {
+            // positionals are listed as placeholders like <REPO> among the candidates
+            let substitution = format!(" $({})", repos);
+            repo_args(cmd)
+                .map(|(_, arg)| {
+                    arg.get_value_names()
+                        .and_then(|names| names.first().copied())
+                        .unwrap_or_else(|| arg.get_id())
+                })
+                .fold(script, |script, value_name| {
+                    script
+                        .replace(&format!(" <{}>", value_name), &substitution)
+                        .replace(&format!(" [{}]", value_name), &substitution)
+                })
+                // copy and diff take two repositories
+                .replace(&substitution.repeat(2), &substitution)
+        }
+        Shell::Zsh => {
+            let function = format!(
+                "_{}_repos() {{\n    local -a repos\n    repos=(${{(f)\"$({})\"}})\n    compadd -a repos\n}}\n",
+                name, repos
+            );
+            let script = script
+                .lines()
+                .map(|line| {
+                    // positionals without completion look like ':repo:' or '::repo -- help:'
+                    let arg = line.trim_start_matches(['\'', ':']);
+                    let is_repo = REPO_ARGS.iter().any(|name| {
+                        arg.starts_with(&format!("{}:", name))
+                            || arg.starts_with(&format!("{} -- ", name))
+                    });
+                    match line.strip_suffix(":' \\") {
+                        Some(head) if is_repo => format!("{}:_{}_repos' \\", head, name),
+                        _ => line.to_string(),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            // the functions have to be defined before the completion runs at the end of the script
+            match script.split_once('\n') {
+                Some((compdef, rest)) => format!("{}\n\n{}\n{}\n", compdef, function, rest),
+                None => script,
+            }
+        }
+        Shell::Fish => {
+            let mut subcommands: Vec<&str> = repo_args(cmd).map(|(sc, _)| sc).collect();
+            subcommands.dedup();
+            format!(
+                "{}complete -c {} -n \"__fish_seen_subcommand_from {}\" -f -a \"({})\"\n",
+                script,
+                name,
+                subcommands.join(" "),
+                repos
+            )
+        }
+        _ => script,
+    }
+}
+
+/// Repository arguments with the names of their subcommands
+fn repo_args<'a, 'help>(
+    cmd: &'a Command<'help>,
+) -> impl Iterator<Item = (&'a str, &'a Arg<'help>)> {
+    cmd.get_subcommands().flat_map(|sc| {
+        sc.get_positionals()
+            .filter(|arg| REPO_ARGS.contains(&arg.get_id()))
+            .map(move |arg| (sc.get_name(), arg))
+    })
+}
+
+fn cache_path(workspace: &str) -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join("bbdan").join("repos").join(workspace))
+}
+
+/// Repository slugs of the workspace listed within CACHE_TTL
+pub fn cached_repositories(workspace: &str) -> Option<Vec<String>> {
+    let path = cache_path(workspace)?;
+    let age = fs::metadata(&path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())?;
+    if age > CACHE_TTL {
+        return None;
+    }
+    let content = fs::read_to_string(path).ok()?;
+    Some(content.lines().map(String::from).collect())
+}
+
+/// Remember the repository slugs of the workspace for the next completions
+pub fn cache_repositories(workspace: &str, slugs: &[String]) -> std::io::Result<()> {
+    let Some(path) = cache_path(workspace) else {
+        return Ok(());
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, slugs.join("\n") + "\n")
+}
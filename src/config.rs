@@ -0,0 +1,238 @@
+use dialoguer::{theme::ColorfulTheme, Input, Password};
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::{fs, process};
+
+use crate::client::{Auth, BitbucketClient};
+use crate::email::SmtpConfig;
+use crate::error::BbdanError;
+use crate::history::HistoryConfig;
+use crate::journal::JournalConfig;
+use crate::notify::NotifyConfig;
+use crate::permissions::Protected;
+use crate::scopes::granted_scopes;
+
+fn netrc_path() -> Option<PathBuf> {
+    match std::env::var_os("NETRC") {
+        Some(p) => Some(PathBuf::from(p)),
+        None => dirs::home_dir().map(|d| d.join(".netrc")),
+    }
+}
+
+/// Look up login and password for the host in ~/.netrc, falling back to the `default` entry
+pub fn netrc_credentials(host: &str) -> Option<(String, String)> {
+    let content = fs::read_to_string(netrc_path()?).ok()?;
+    parse_netrc(&content, host)
+}
+
+#[derive(Debug, Default)]
+struct NetrcEntry {
+    // None for the `default` entry
+    machine: Option<String>,
+    login: String,
+    password: String,
+}
+
+/// Pick login and password for the host out of netrc content, falling back to the `default` entry
+pub fn parse_netrc(content: &str, host: &str) -> Option<(String, String)> {
+    let mut entries: Vec<NetrcEntry> = Vec::new();
+    let mut tokens = content.split_whitespace();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => entries.push(NetrcEntry {
+                machine: tokens.next().map(String::from),
+                ..Default::default()
+            }),
+            "default" => entries.push(NetrcEntry::default()),
+            "login" | "password" => {
+                let value = tokens.next().unwrap_or_default().to_string();
+                if let Some(entry) = entries.last_mut() {
+                    if token == "login" {
+                        entry.login = value;
+                    } else {
+                        entry.password = value;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    entries
+        .iter()
+        .find(|e| e.machine.as_deref() == Some(host))
+        .or_else(|| entries.iter().find(|e| e.machine.is_none()))
+        .map(|e| (e.login.to_string(), e.password.to_string()))
+}
+
+// Config file
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// Username used when --username is omitted
+    pub username: Option<String>,
+    /// API base URL used when --base-url is omitted
+    pub base_url: Option<String>,
+    /// Workspace used when --workspace is omitted
+    pub workspace: Option<String>,
+    /// Command printing the app password to stdout, e.g. `pass show bitbucket/app-password`
+    pub credential_helper: Option<String>,
+    /// Number of changes to a repository above which it is left unchanged, when --max-changes is omitted
+    pub max_changes: Option<usize>,
+    /// Globs of users and groups copy, sync and diff leave alone, in addition to --ignore
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Channels watch, audit and check post to with --notify
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    /// Mail server audit and diff send reports through with --email-to
+    #[serde(default)]
+    pub smtp: SmtpConfig,
+    /// Database recording the permissions seen and changed, read by history
+    #[serde(default)]
+    pub history: HistoryConfig,
+    /// Journal every change is appended to
+    #[serde(default)]
+    pub journal: JournalConfig,
+    /// Users and groups that copy, sync, import and remove never remove
+    #[serde(default)]
+    pub protected: Protected,
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("bbdan").join("config.toml"))
+}
+
+/// Load the config file given by --config, or the default one if it exists
+pub fn load_config(path: Option<&Path>) -> Result<Config, BbdanError> {
+    let content = match path {
+        Some(p) => fs::read_to_string(p)?,
+        None => match config_path().and_then(|p| fs::read_to_string(p).ok()) {
+            Some(content) => content,
+            None => return Ok(Config::default()),
+        },
+    };
+    Ok(toml::from_str(&content)?)
+}
+
+/// Set a top-level key in the config file, keeping the other keys as they are
+pub fn save_config_value(
+    path: Option<&Path>,
+    key: &str,
+    value: &str,
+) -> Result<PathBuf, BbdanError> {
+    let path = path
+        .map(Path::to_path_buf)
+        .or_else(config_path)
+        .ok_or_else(|| BbdanError::Config("config directory not found".to_string()))?;
+    let mut table: toml::Table = match fs::read_to_string(&path) {
+        Ok(content) => toml::from_str(&content)?,
+        Err(_) => toml::Table::new(),
+    };
+    table.insert(key.to_string(), toml::Value::String(value.to_string()));
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, toml::to_string(&table)?)?;
+    Ok(path)
+}
+
+/// Run the credential helper and return the first line of its output as the secret
+pub fn run_credential_helper(command: &str) -> Result<String, BbdanError> {
+    let output = if cfg!(windows) {
+        process::Command::new("cmd")
+            .args(["/C", command])
+            .output()?
+    } else {
+        process::Command::new("sh").args(["-c", command]).output()?
+    };
+    if !output.status.success() {
+        return Err(BbdanError::Config(format!(
+            "credential helper `{}` failed: {}",
+            command, output.status
+        )));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let secret = stdout.lines().next().unwrap_or_default().trim();
+    if secret.is_empty() {
+        return Err(BbdanError::Config(format!(
+            "credential helper `{}` printed nothing",
+            command
+        )));
+    }
+    Ok(secret.to_string())
+}
+
+// OS keyring
+
+pub const KEYRING_SERVICE: &str = "bbdan";
+
+pub fn keyring_entry(username: &str) -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, username)
+}
+
+/// Who login stored the credentials of, and where
+#[derive(Debug, Clone)]
+pub struct LoginResult {
+    pub username: String,
+    pub display_name: String,
+    /// Scopes granted to the app password, none when the API does not tell
+    pub scopes: Option<Vec<String>>,
+    /// Config file the username was written to
+    pub config_path: PathBuf,
+}
+
+/// Prompt for username and app password, validate them against the API
+/// and store the password in the OS keyring and the username in the config file
+pub async fn login(
+    http_client: reqwest::Client,
+    username: Option<String>,
+    base_url: &str,
+    config: Option<&Path>,
+) -> Result<LoginResult, BbdanError> {
+    let username = match username {
+        Some(u) => u,
+        None => Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Username")
+            .interact_text()?,
+    };
+    let password = Password::with_theme(&ColorfulTheme::default())
+        .with_prompt("App password")
+        .interact()?;
+
+    let client = BitbucketClient::new(
+        http_client,
+        base_url.to_string(),
+        Auth::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        },
+    );
+    let resp = client.http_get("user".to_string()).await?;
+    if !resp.status().is_success() {
+        return Err(BbdanError::Auth(format!(
+            "invalid username or app password: {}",
+            resp.status()
+        )));
+    }
+
+    let scopes = granted_scopes(&resp);
+    let user: Value = resp.json().await?;
+
+    keyring_entry(&username)?.set_password(&password)?;
+    let config_path = save_config_value(config, "username", &username)?;
+
+    Ok(LoginResult {
+        display_name: user["display_name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        username,
+        scopes,
+        config_path,
+    })
+}
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::{confirm, Options};
+use crate::reconcile::{ChangeFilter, Summary};
+use crate::resources;
+use crate::setup::{clone_setup, Skip};
+
+/// What a repository is created with, taken from the template
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewRepository {
+    #[serde(default = "git")]
+    pub scm: String,
+    pub project: ProjectKey,
+    #[serde(default)]
+    pub is_private: bool,
+    #[serde(default)]
+    pub fork_policy: String,
+}
+
+fn git() -> String {
+    "git".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectKey {
+    pub key: String,
+}
+
+/// Create the repository with the project, privacy and fork policy of the template,
+/// in the given project instead when there is one, then copy the permissions,
+/// default reviewers and webhooks of the template to it.
+/// Returns the result of the creation and of each copied setting.
+pub async fn create_like(
+    template: Bitbucket,
+    new: Bitbucket,
+    project: Option<String>,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    let mut repository: NewRepository = resources::get(&template, "").await?;
+    if let Some(key) = project {
+        repository.project.key = key;
+    }
+    let message = format!(
+        "Create {} in the project {}, {}, fork policy {}.",
+        new.slug,
+        repository.project.key,
+        if repository.is_private {
+            "private"
+        } else {
+            "public"
+        },
+        repository.fork_policy
+    );
+    if !confirm(message, options)? {
+        return Err(BbdanError::Aborted);
+    }
+    resources::post(&new, "", &repository, options).await?;
+    let created = Summary {
+        added: 1,
+        ..Summary::default()
+    };
+    let mut results = vec![("repository".to_string(), Ok(created))];
+
+    if options.dry_run {
+        tracing::info!(
+            "The permissions, default reviewers and webhooks of {} are copied once {} exists",
+            template.slug,
+            new.slug
+        );
+        return Ok(results);
+    }
+
+    let skip = Skip {
+        skip_branch_restrictions: true,
+        skip_deploy_keys: true,
+        skip_pipelines_vars: true,
+        ..Skip::default()
+    };
+    results.extend(clone_setup(&template, &new, &skip, &ChangeFilter::default(), options).await?);
+    Ok(results)
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::{confirm, resolve_user, Options};
+use crate::reconcile::Summary;
+use crate::resources::{self, Resource};
+
+/// Columns of the default reviewers in list
+pub const HEADER: &[&str] = &["uuid", "nickname", "display_name"];
+
+/// A user added as a reviewer to every new pull request, an item of default-reviewers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reviewer {
+    pub uuid: String,
+    #[serde(default)]
+    pub nickname: String,
+    #[serde(default)]
+    pub display_name: String,
+}
+
+impl Reviewer {
+    /// The reviewer as a row of [`HEADER`]
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.uuid.to_string(),
+            self.nickname.to_string(),
+            self.display_name.to_string(),
+        ]
+    }
+
+    fn is(&self, user: &str) -> bool {
+        self.uuid == user || (!self.nickname.is_empty() && self.nickname == user)
+    }
+}
+
+impl Resource for Reviewer {
+    const PATH: &'static str = "default-reviewers";
+    const PUT: bool = true;
+
+    fn key(&self) -> Option<String> {
+        Some(self.uuid.to_string())
+    }
+
+    fn same(&self, other: &Reviewer) -> bool {
+        self.uuid == other.uuid
+    }
+
+    fn describe(&self) -> String {
+        format!("id={}, name={}", self.uuid, self.nickname)
+    }
+}
+
+pub async fn list_reviewers(bitbucket: &Bitbucket) -> Result<Vec<Reviewer>, BbdanError> {
+    resources::list_all(bitbucket, Reviewer::PATH).await
+}
+
+/// Add a user, given by UUID or nickname, to the default reviewers of the repository
+pub async fn add_reviewer(
+    bitbucket: Bitbucket,
+    user: &str,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let (uuid, nickname) = resolve_user(&bitbucket, user).await?;
+    let reviewer = Reviewer {
+        uuid,
+        nickname,
+        display_name: String::new(),
+    };
+    let mut summary = Summary::default();
+    if list_reviewers(&bitbucket)
+        .await?
+        .iter()
+        .any(|r| r.same(&reviewer))
+    {
+        tracing::info!("{} is already a default reviewer", reviewer.describe());
+        summary.unchanged += 1;
+    } else if confirm(format!("Add: {}.", reviewer.describe()), options)? {
+        resources::create(&bitbucket, &reviewer, options).await?;
+        summary.added += 1;
+    } else {
+        summary.skipped += 1;
+    }
+    Ok(summary)
+}
+
+/// Remove the users, given by UUID or nickname, from the default reviewers of the repository,
+/// or those picked interactively when none are given
+pub async fn remove_reviewers(
+    bitbucket: Bitbucket,
+    users: &[String],
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    if users.is_empty() {
+        if options.yes {
+            return Err(BbdanError::Invalid(
+                "--user is required in non-interactive mode".to_string(),
+            ));
+        }
+        return resources::remove_items::<Reviewer>(&bitbucket, None, options).await;
+    }
+    let matches = |r: &Reviewer| users.iter().any(|u| r.is(u));
+    resources::remove_items(&bitbucket, Some(&matches), options).await
+}
+
+/// Copy the default reviewers of src to each destination, with the result per destination
+pub async fn copy_reviewers(
+    src: Bitbucket,
+    dests: &[Bitbucket],
+    prune: bool,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    resources::copy::<Reviewer>(src, dests, prune, options).await
+}
@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::{confirm, Options};
+use crate::reconcile::Summary;
+use crate::resources::{self, Resource};
+
+/// Columns of the deploy keys in list
+pub const HEADER: &[&str] = &["id", "label", "key", "comment"];
+
+/// An SSH key with read-only access to the repository, an item of deploy-keys
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeployKey {
+    /// Absent from the keys to add
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    /// Public key, e.g. `ssh-ed25519 AAAA...`
+    pub key: String,
+    #[serde(default)]
+    pub label: String,
+    /// Comment of the public key, split from it by the API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl DeployKey {
+    /// Algorithm and key, without the comment
+    fn material(&self) -> String {
+        self.key
+            .split_whitespace()
+            .take(2)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The algorithm and the end of the key, enough to tell keys apart, e.g. `ssh-ed25519 ...q3Zk`
+    pub fn short(&self) -> String {
+        let mut parts = self.key.split_whitespace();
+        let algorithm = parts.next().unwrap_or_default();
+        let key = parts.next().unwrap_or_default();
+        let end = key.len().saturating_sub(8);
+        format!("{} ...{}", algorithm, key.get(end..).unwrap_or_default())
+    }
+
+    /// The key as a row of [`HEADER`]
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.id.map(|id| id.to_string()).unwrap_or_default(),
+            self.label.to_string(),
+            self.short(),
+            self.comment.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl Resource for DeployKey {
+    const PATH: &'static str = "deploy-keys";
+
+    fn key(&self) -> Option<String> {
+        self.id.map(|id| id.to_string())
+    }
+
+    /// Whether both are the same public key, whatever their labels and comments
+    fn same(&self, other: &DeployKey) -> bool {
+        self.material() == other.material()
+    }
+
+    fn describe(&self) -> String {
+        format!("label={}, key={}", self.label, self.short())
+    }
+
+    fn created(&self) -> Self {
+        DeployKey {
+            id: None,
+            key: match &self.comment {
+                Some(comment) => format!("{} {}", self.material(), comment),
+                None => self.key.to_string(),
+            },
+            label: self.label.to_string(),
+            comment: None,
+        }
+    }
+}
+
+/// Deploy keys to remove without the interactive picker
+#[derive(Debug, Clone, Default)]
+pub struct DeployKeyFilter {
+    pub ids: Vec<u64>,
+    pub labels: Vec<String>,
+}
+
+impl DeployKeyFilter {
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty() && self.labels.is_empty()
+    }
+
+    pub fn matches(&self, k: &DeployKey) -> bool {
+        k.id.is_some_and(|id| self.ids.contains(&id)) || self.labels.contains(&k.label)
+    }
+}
+
+pub async fn list_deploy_keys(bitbucket: &Bitbucket) -> Result<Vec<DeployKey>, BbdanError> {
+    resources::list_all(bitbucket, DeployKey::PATH).await
+}
+
+/// Add a public key, e.g. the content of `id_ed25519.pub`, as a deploy key of the repository
+pub async fn add_deploy_key(
+    bitbucket: Bitbucket,
+    key: &str,
+    label: &str,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let deploy_key = DeployKey {
+        id: None,
+        key: key.trim().to_string(),
+        label: label.to_string(),
+        comment: None,
+    };
+    if deploy_key.material().split_whitespace().count() != 2 {
+        return Err(BbdanError::Invalid(format!(
+            "{} is not an SSH public key",
+            key.trim()
+        )));
+    }
+    let mut summary = Summary::default();
+    if let Some(existing) = list_deploy_keys(&bitbucket)
+        .await?
+        .into_iter()
+        .find(|k| k.same(&deploy_key))
+    {
+        tracing::info!("The key is already deployed as {}", existing.describe());
+        summary.unchanged += 1;
+    } else if confirm(format!("Add: {}.", deploy_key.describe()), options)? {
+        resources::create(&bitbucket, &deploy_key, options).await?;
+        summary.added += 1;
+    } else {
+        summary.skipped += 1;
+    }
+    Ok(summary)
+}
+
+/// Remove the deploy keys the filter matches, or those picked interactively when it is empty
+pub async fn remove_deploy_keys(
+    bitbucket: Bitbucket,
+    filter: &DeployKeyFilter,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    if filter.is_empty() {
+        if options.yes {
+            return Err(BbdanError::Invalid(
+                "--id or --label is required in non-interactive mode".to_string(),
+            ));
+        }
+        return resources::remove_items::<DeployKey>(&bitbucket, None, options).await;
+    }
+    resources::remove_items(&bitbucket, Some(&|k| filter.matches(k)), options).await
+}
+
+/// Copy the deploy keys of src to each destination, with the result per destination
+pub async fn copy_deploy_keys(
+    src: Bitbucket,
+    dests: &[Bitbucket],
+    prune: bool,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    resources::copy::<DeployKey>(src, dests, prune, options).await
+}
@@ -0,0 +1,262 @@
+use clap::ArgEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::{list, ObjectType, Options, Permission, PermissionType};
+use crate::reconcile::{reconcile, Change, ChangeFilter, Summary};
+use crate::terraform;
+
+pub const DOCUMENT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, ArgEnum)]
+pub enum DocumentFormat {
+    Json,
+    Yaml,
+    /// Resources and import blocks for the Bitbucket Terraform provider, export only
+    Terraform,
+}
+
+impl DocumentFormat {
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".yaml") || path.ends_with(".yml") {
+            DocumentFormat::Yaml
+        } else if path.ends_with(".tf") {
+            DocumentFormat::Terraform
+        } else {
+            DocumentFormat::Json
+        }
+    }
+}
+
+/// Versioned document of the permissions of a repository, sorted for stable output
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PermissionsDocument {
+    pub version: u32,
+    pub workspace: String,
+    pub repository: String,
+    #[serde(flatten)]
+    pub grants: Grants,
+}
+
+/// User and group grants of a repository
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Grants {
+    #[serde(default)]
+    pub users: Vec<UserGrant>,
+    #[serde(default)]
+    pub groups: Vec<GroupGrant>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserGrant {
+    pub uuid: String,
+    #[serde(default)]
+    pub nickname: String,
+    pub permission: PermissionType,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupGrant {
+    pub slug: String,
+    #[serde(default)]
+    pub name: String,
+    pub permission: PermissionType,
+}
+
+impl Grants {
+    pub fn new(permissions: &[Permission]) -> Self {
+        let mut users: Vec<UserGrant> = Vec::new();
+        let mut groups: Vec<GroupGrant> = Vec::new();
+        for p in permissions {
+            match p.object_type {
+                ObjectType::User => users.push(UserGrant {
+                    uuid: p.id.to_string(),
+                    nickname: p.alias.to_string(),
+                    permission: p.permission,
+                }),
+                ObjectType::Group => groups.push(GroupGrant {
+                    slug: p.id.to_string(),
+                    name: p.alias.to_string(),
+                    permission: p.permission,
+                }),
+            }
+        }
+        users.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+        groups.sort_by(|a, b| a.slug.cmp(&b.slug));
+        Self { users, groups }
+    }
+
+    pub fn permissions(&self) -> Vec<Permission> {
+        let users = self.users.iter().map(|u| Permission {
+            object_type: ObjectType::User,
+            alias: u.nickname.to_string(),
+            id: u.uuid.to_string(),
+            permission: u.permission,
+            via: Vec::new(),
+        });
+        let groups = self.groups.iter().map(|g| Permission {
+            object_type: ObjectType::Group,
+            alias: g.name.to_string(),
+            id: g.slug.to_string(),
+            permission: g.permission,
+            via: Vec::new(),
+        });
+        groups.chain(users).collect()
+    }
+}
+
+impl PermissionsDocument {
+    pub fn new(bitbucket: &Bitbucket, permissions: &[Permission]) -> Self {
+        Self {
+            version: DOCUMENT_VERSION,
+            workspace: bitbucket.workspace.to_string(),
+            repository: bitbucket.slug.to_string(),
+            grants: Grants::new(permissions),
+        }
+    }
+
+    pub fn parse(content: &str, format: DocumentFormat) -> Result<Self, BbdanError> {
+        let document: Self = match format {
+            DocumentFormat::Json => serde_json::from_str(content)?,
+            DocumentFormat::Yaml => serde_yaml::from_str(content)?,
+            DocumentFormat::Terraform => return Err(terraform_is_export_only()),
+        };
+        if document.version > DOCUMENT_VERSION {
+            return Err(BbdanError::Invalid(format!(
+                "document version {} is newer than the supported version {}",
+                document.version, DOCUMENT_VERSION
+            )));
+        }
+        Ok(document)
+    }
+
+    pub fn render(&self, format: DocumentFormat) -> Result<String, BbdanError> {
+        Ok(match format {
+            DocumentFormat::Json => serde_json::to_string_pretty(self)? + "\n",
+            DocumentFormat::Yaml => serde_yaml::to_string(self)?,
+            DocumentFormat::Terraform => terraform::hcl(
+                &self.workspace,
+                &self.repository,
+                &self.grants.permissions(),
+            ),
+        })
+    }
+}
+
+fn terraform_is_export_only() -> BbdanError {
+    BbdanError::Invalid("the terraform format can only be exported".to_string())
+}
+
+/// Changes turning the permissions of `source` into those of `destination`, as grants in the document format
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiffDocument {
+    pub version: u32,
+    pub workspace: String,
+    pub source: String,
+    pub destination: String,
+    #[serde(default)]
+    pub add: Grants,
+    /// Grants changing level, with the level of `destination`
+    #[serde(default)]
+    pub update: Grants,
+    #[serde(default)]
+    pub remove: Grants,
+}
+
+impl DiffDocument {
+    pub fn new(workspace: &str, source: &str, destination: &str, changes: &[Change]) -> Self {
+        let (mut add, mut update, mut remove) = (Vec::new(), Vec::new(), Vec::new());
+        for change in changes {
+            match change {
+                Change::Add(p) => add.push(p.clone()),
+                Change::Update { after, .. } => update.push(after.clone()),
+                Change::Remove(p) => remove.push(p.clone()),
+                Change::Unchanged(_) => {}
+            }
+        }
+        Self {
+            version: DOCUMENT_VERSION,
+            workspace: workspace.to_string(),
+            source: source.to_string(),
+            destination: destination.to_string(),
+            add: Grants::new(&add),
+            update: Grants::new(&update),
+            remove: Grants::new(&remove),
+        }
+    }
+}
+
+pub async fn import(
+    bitbucket: Bitbucket,
+    file: &str,
+    format: DocumentFormat,
+    filter: &ChangeFilter,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let content = fs::read_to_string(file)?;
+    let document = PermissionsDocument::parse(&content, format)?;
+    reconcile(bitbucket, &document.grants.permissions(), filter, options).await
+}
+
+/// Desired permissions of many repositories
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncDocument {
+    pub version: u32,
+    pub repositories: BTreeMap<String, Grants>,
+}
+
+impl SyncDocument {
+    pub fn parse(content: &str, format: DocumentFormat) -> Result<Self, BbdanError> {
+        let document: Self = match format {
+            DocumentFormat::Json => serde_json::from_str(content)?,
+            DocumentFormat::Yaml => serde_yaml::from_str(content)?,
+            DocumentFormat::Terraform => return Err(terraform_is_export_only()),
+        };
+        if document.version > DOCUMENT_VERSION {
+            return Err(BbdanError::Invalid(format!(
+                "document version {} is newer than the supported version {}",
+                document.version, DOCUMENT_VERSION
+            )));
+        }
+        Ok(document)
+    }
+}
+
+/// Reconcile every repository in the file and return the result per repository
+pub async fn sync(
+    bitbucket: Bitbucket,
+    file: &str,
+    format: DocumentFormat,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    let document = SyncDocument::parse(&fs::read_to_string(file)?, format)?;
+
+    let mut results = Vec::new();
+    for (repo, grants) in document.repositories {
+        tracing::info!("Repository: {}", repo);
+        let dest = Bitbucket {
+            slug: repo.to_string(),
+            ..bitbucket.clone()
+        };
+        let result = reconcile(
+            dest,
+            &grants.permissions(),
+            &ChangeFilter::default(),
+            options,
+        )
+        .await
+        .map_err(Arc::new);
+        results.push((repo, result));
+    }
+    Ok(results)
+}
+
+/// The permissions of the repository as a document to import later
+pub async fn export(bitbucket: Bitbucket, format: DocumentFormat) -> Result<String, BbdanError> {
+    let permissions = list(bitbucket.clone()).await?;
+    PermissionsDocument::new(&bitbucket, &permissions).render(format)
+}
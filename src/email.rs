@@ -0,0 +1,120 @@
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment as MailAttachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Deserialize;
+
+use crate::error::BbdanError;
+
+/// Environment variable overriding `password` of the `[smtp]` table
+pub const SMTP_PASSWORD_ENV: &str = "BBDAN_SMTP_PASSWORD";
+
+/// Mail server reports are sent through with --email-to, the `[smtp]` table of the config file
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct SmtpConfig {
+    pub host: Option<String>,
+    /// 587 for starttls, 465 for tls and 25 for none by default
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender, e.g. `bbdan <bbdan@example.com>`
+    pub from: Option<String>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    #[default]
+    Starttls,
+    Tls,
+    /// Plain text, for a relay on localhost
+    None,
+}
+
+/// A report attached to the email
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: Vec<String>,
+    pub subject: String,
+    pub body: String,
+    pub attachments: Vec<Attachment>,
+}
+
+impl SmtpConfig {
+    /// Whether reports can be sent: the host and the sender are set
+    pub fn is_complete(&self) -> bool {
+        self.host.is_some() && self.from.is_some()
+    }
+}
+
+/// Send the email through the mail server of the config
+pub async fn send(config: &SmtpConfig, email: &Email) -> Result<(), BbdanError> {
+    let (Some(host), Some(from)) = (&config.host, &config.from) else {
+        return Err(BbdanError::Config(
+            "host and from are required in the [smtp] table of the config file".to_string(),
+        ));
+    };
+    let mailbox = |address: &str| {
+        address
+            .parse::<Mailbox>()
+            .map_err(|e| BbdanError::Invalid(format!("invalid address {:?}: {}", address, e)))
+    };
+
+    let mut builder = Message::builder()
+        .from(mailbox(from)?)
+        .subject(&email.subject);
+    for to in &email.to {
+        builder = builder.to(mailbox(to)?);
+    }
+    let mut body = MultiPart::mixed().singlepart(SinglePart::plain(email.body.to_string()));
+    for attachment in &email.attachments {
+        // the reports are all UTF-8
+        let content_type =
+            ContentType::parse(&format!("{}; charset=utf-8", attachment.content_type))
+                .map_err(|e| BbdanError::Invalid(e.to_string()))?;
+        body = body.singlepart(
+            MailAttachment::new(attachment.filename.to_string())
+                .body(attachment.content.to_string(), content_type),
+        );
+    }
+    let message = builder.multipart(body).map_err(mail_error)?;
+
+    let transport = match config.tls {
+        SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host),
+        SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host),
+        SmtpTls::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+            host,
+        )),
+    }
+    .map_err(mail_error)?;
+    let transport = match config.port {
+        Some(port) => transport.port(port),
+        None => transport,
+    };
+    let password = std::env::var(SMTP_PASSWORD_ENV)
+        .ok()
+        .or_else(|| config.password.clone());
+    let transport = match (&config.username, password) {
+        (Some(username), Some(password)) => {
+            transport.credentials(Credentials::new(username.to_string(), password))
+        }
+        _ => transport,
+    };
+
+    transport.build().send(message).await.map_err(mail_error)?;
+    tracing::info!("Mailed {} to {}", email.subject, email.to.join(", "));
+    Ok(())
+}
+
+fn mail_error(e: impl std::fmt::Display) -> BbdanError {
+    BbdanError::Mail(e.to_string())
+}
@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::Options;
+use crate::pipelines::{set_variable_at, Variable};
+use crate::reconcile::Summary;
+use crate::resources::{self, Resource};
+
+/// Columns of the deployment environments in list
+pub const HEADER: &[&str] = &["uuid", "name", "type"];
+
+/// A deployment environment of the repository, e.g. staging, an item of environments
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Environment {
+    /// Absent from the environments to create
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    pub name: String,
+    pub environment_type: EnvironmentType,
+}
+
+/// `Test`, `Staging` or `Production`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnvironmentType {
+    pub name: String,
+}
+
+impl Environment {
+    /// The environment as a row of [`HEADER`]
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.uuid.clone().unwrap_or_default(),
+            self.name.to_string(),
+            self.environment_type.name.to_string(),
+        ]
+    }
+
+    /// Path of the deployment variables of the environment
+    fn variables_path(&self) -> String {
+        format!(
+            "deployments_config/environments/{}/variables",
+            self.uuid.clone().unwrap_or_default()
+        )
+    }
+}
+
+impl Resource for Environment {
+    const PATH: &'static str = "environments";
+
+    fn key(&self) -> Option<String> {
+        self.uuid.clone()
+    }
+
+    /// Whether both are the same environment, matched by name across repositories
+    fn same(&self, other: &Environment) -> bool {
+        self.name == other.name
+    }
+
+    fn describe(&self) -> String {
+        format!("name={}, type={}", self.name, self.environment_type.name)
+    }
+
+    fn created(&self) -> Self {
+        Environment {
+            uuid: None,
+            ..self.clone()
+        }
+    }
+}
+
+pub async fn list_environments(bitbucket: &Bitbucket) -> Result<Vec<Environment>, BbdanError> {
+    resources::list_all(bitbucket, Environment::PATH).await
+}
+
+/// The environment of the repository with this name
+async fn find_environment(bitbucket: &Bitbucket, name: &str) -> Result<Environment, BbdanError> {
+    list_environments(bitbucket)
+        .await?
+        .into_iter()
+        .find(|e| e.name == name)
+        .ok_or_else(|| {
+            BbdanError::Invalid(format!("{} has no environment {}", bitbucket.slug, name))
+        })
+}
+
+/// The deployment variables of the environment with this name
+pub async fn list_deployment_variables(
+    bitbucket: &Bitbucket,
+    environment: &str,
+) -> Result<Vec<Variable>, BbdanError> {
+    let environment = find_environment(bitbucket, environment).await?;
+    resources::list_all(bitbucket, &environment.variables_path()).await
+}
+
+/// Set a deployment variable of the environment with this name, creating it or replacing its value
+pub async fn set_deployment_variable(
+    bitbucket: Bitbucket,
+    environment: &str,
+    key: &str,
+    value: &str,
+    secured: bool,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let environment = find_environment(&bitbucket, environment).await?;
+    let path = environment.variables_path();
+    set_variable_at(&bitbucket, &path, key, value, secured, options).await
+}
+
+/// Copy the environments of src and their deployment variables to each destination,
+/// with the result per destination. Environments are matched by name, and the variables
+/// a destination already has keep their values.
+pub async fn copy_environments(
+    src: Bitbucket,
+    dests: &[Bitbucket],
+    prune: bool,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    let environments = list_environments(&src).await?;
+    let mut variables = Vec::new();
+    for environment in &environments {
+        let path = environment.variables_path();
+        variables.push(resources::list_all::<Variable>(&src, &path).await?);
+    }
+
+    let mut results = Vec::new();
+    for dest in dests {
+        tracing::info!("Repository: {}", dest.slug);
+        let result = copy_to(dest, &environments, &variables, prune, options)
+            .await
+            .map_err(Arc::new);
+        results.push((dest.slug.to_string(), result));
+    }
+    Ok(results)
+}
+
+async fn copy_to(
+    dest: &Bitbucket,
+    environments: &[Environment],
+    variables: &[Vec<Variable>],
+    prune: bool,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let mut summary =
+        resources::copy_items(dest, Environment::PATH, environments, prune, options).await?;
+
+    let current = list_environments(dest).await?;
+    for (environment, variables) in environments.iter().zip(variables) {
+        match current.iter().find(|e| e.same(environment)) {
+            Some(target) => {
+                tracing::info!("Environment: {}", target.name);
+                let path = target.variables_path();
+                summary += resources::copy_items(dest, &path, variables, prune, options).await?;
+            }
+            // Not created in a dry run or when skipped
+            None if !variables.is_empty() => {
+                tracing::info!(
+                    "{} variables of {} are not copied without the environment",
+                    variables.len(),
+                    environment.name
+                );
+            }
+            None => {}
+        }
+    }
+    Ok(summary)
+}
@@ -0,0 +1,129 @@
+use reqwest::{Response, StatusCode};
+use serde::de::DeserializeOwned;
+
+// Exit codes of the bbdan binary
+/// Nothing failed and nothing differs
+pub const EXIT_SUCCESS: i32 = 0;
+/// Invalid arguments, config or input files, or any error not covered below
+pub const EXIT_USAGE: i32 = 1;
+/// Credentials are missing, rejected or lack scopes or access
+pub const EXIT_AUTH: i32 = 2;
+/// The API failed or answered unexpectedly
+pub const EXIT_API: i32 = 3;
+/// diff or watch --once found differences, or a dry run has changes pending
+pub const EXIT_DRIFT: i32 = 4;
+/// Some repositories of a bulk command failed
+pub const EXIT_PARTIAL: i32 = 5;
+/// check found repositories breaking the policy
+pub const EXIT_VIOLATION: i32 = 6;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BbdanError {
+    /// Credentials are missing or were rejected
+    #[error("authentication failed: {0}")]
+    Auth(String),
+    /// The API answered with an error status
+    #[error("request to {url} failed: {status}")]
+    Http { url: String, status: StatusCode },
+    /// The request could not be sent or its body could not be read
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    /// The response body does not have the expected shape
+    #[error("unexpected response from {url}: {message}")]
+    UnexpectedResponse { url: String, message: String },
+    /// The mail server could not be reached or refused the report
+    #[error("failed to send the email: {0}")]
+    Mail(String),
+    /// The history database could not be read or written
+    #[error("history database: {0}")]
+    History(String),
+    /// The user cancelled a prompt
+    #[error("aborted by user")]
+    Aborted,
+    /// The config file, credential helper or keyring cannot be used
+    #[error("{0}")]
+    Config(String),
+    /// The arguments or the input file cannot be applied
+    #[error("{0}")]
+    Invalid(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    /// tera keeps the line and the cause of a template error in its sources
+    #[error("{}", error_chain(.0))]
+    Template(#[from] tera::Error),
+    #[error(transparent)]
+    Yaml(#[from] serde_yaml::Error),
+    #[error(transparent)]
+    TomlDe(#[from] toml::de::Error),
+    #[error(transparent)]
+    TomlSer(#[from] toml::ser::Error),
+    #[error(transparent)]
+    Keyring(#[from] keyring::Error),
+}
+
+impl BbdanError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BbdanError::Auth(_) => EXIT_AUTH,
+            // the credentials lack the scope or the access
+            BbdanError::Http { status, .. } if *status == StatusCode::FORBIDDEN => EXIT_AUTH,
+            BbdanError::Http { .. }
+            | BbdanError::Request(_)
+            | BbdanError::UnexpectedResponse { .. }
+            | BbdanError::Mail(_) => EXIT_API,
+            _ => EXIT_USAGE,
+        }
+    }
+}
+
+/// Exit code of a bulk command whose repositories failed with these errors, none when none failed.
+/// Credentials that are rejected or lack access fail the repositories alike, so EXIT_AUTH outranks EXIT_PARTIAL.
+pub fn failures_exit_code<'a>(errors: impl IntoIterator<Item = &'a BbdanError>) -> Option<i32> {
+    let mut code = None;
+    for e in errors {
+        if e.exit_code() == EXIT_AUTH {
+            return Some(EXIT_AUTH);
+        }
+        code = Some(EXIT_PARTIAL);
+    }
+    code
+}
+
+/// The error followed by all of its sources
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        message += &format!(": {}", e);
+        source = e.source();
+    }
+    message
+}
+
+/// Pass a successful response through, or turn its status into an error.
+/// 401 means the credentials were rejected.
+pub fn error_for_status(resp: Response) -> Result<Response, BbdanError> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let url = resp.url().to_string();
+    Err(match status {
+        StatusCode::UNAUTHORIZED => BbdanError::Auth(format!("{} rejected the credentials", url)),
+        _ => BbdanError::Http { url, status },
+    })
+}
+
+/// Deserialize the response body, reporting the URL and the missing or mistyped field on failure
+pub async fn parse_json<T: DeserializeOwned>(resp: Response) -> Result<T, BbdanError> {
+    let url = resp.url().to_string();
+    let body = resp.text().await?;
+    serde_json::from_str(&body).map_err(|e| BbdanError::UnexpectedResponse {
+        url,
+        message: e.to_string(),
+    })
+}
@@ -0,0 +1,270 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+
+use crate::client::Bitbucket;
+use crate::error::{error_for_status, parse_json, BbdanError};
+use crate::journal;
+use crate::permissions::{confirm, resolve_user, ObjectType, Options, Permission, PermissionType};
+use crate::reconcile::Summary;
+use crate::resources;
+use crate::workspace::{list_workspace_permissions, WorkspaceRole, WorkspaceUser};
+
+/// Columns of the groups in list
+pub const HEADER: &[&str] = &["slug", "name", "members"];
+
+/// Columns of the members of a group in show
+pub const MEMBER_HEADER: &[&str] = &["uuid", "nickname", "display_name"];
+
+/// A group of the workspace with its members
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    pub slug: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub members: Vec<GroupMember>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupMember {
+    #[serde(default)]
+    pub uuid: String,
+    #[serde(default)]
+    pub nickname: String,
+    #[serde(default)]
+    pub display_name: String,
+}
+
+impl GroupMember {
+    /// The member as a row of [`MEMBER_HEADER`]
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.uuid.to_string(),
+            self.nickname.to_string(),
+            self.display_name.to_string(),
+        ]
+    }
+}
+
+impl Group {
+    /// The group as a row of [`HEADER`]
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.slug.to_string(),
+            self.name.to_string(),
+            self.members.len().to_string(),
+        ]
+    }
+
+    /// Whether the user, given by UUID and nickname, is a member
+    pub fn has_member(&self, uuid: &str, nickname: &str) -> bool {
+        self.members
+            .iter()
+            .any(|m| m.uuid == uuid || (!nickname.is_empty() && m.nickname == nickname))
+    }
+}
+
+/// Groups of the workspace. The 2.0 API has no groups, so they come from the 1.0 API.
+pub async fn list_groups(bitbucket: &Bitbucket) -> Result<Vec<Group>, BbdanError> {
+    let url = bitbucket.v1_url(&format!("groups/{}", bitbucket.workspace));
+    let resp = error_for_status(bitbucket.client().http_get_url(url).await?)?;
+    parse_json(resp).await
+}
+
+/// The group of the workspace with this slug or name
+pub async fn find_group(bitbucket: &Bitbucket, group: &str) -> Result<Group, BbdanError> {
+    list_groups(bitbucket)
+        .await?
+        .into_iter()
+        .find(|g| g.slug == group || g.name == group)
+        .ok_or_else(|| {
+            BbdanError::Invalid(format!(
+                "group {} is not in workspace {}",
+                group, bitbucket.workspace
+            ))
+        })
+}
+
+/// URL of the membership of the user, given by UUID, in the group
+fn member_url(bitbucket: &Bitbucket, group: &Group, uuid: &str) -> String {
+    bitbucket.v1_url(&format!(
+        "groups/{}/{}/members/{}",
+        bitbucket.workspace, group.slug, uuid
+    ))
+}
+
+/// Add the users, given by UUID or nickname, to the group of the workspace.
+/// Nothing is changed when more users than --max-changes would be added.
+pub async fn add_members(
+    bitbucket: &Bitbucket,
+    group: &str,
+    users: &[String],
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let group = find_group(bitbucket, group).await?;
+    let mut summary = Summary::default();
+    let mut missing = Vec::new();
+    for user in users {
+        let (uuid, nickname) = resolve_user(bitbucket, user).await?;
+        if group.has_member(&uuid, &nickname) {
+            tracing::info!("{} is already a member of {}", nickname, group.slug);
+            summary.unchanged += 1;
+            continue;
+        }
+        missing.push((uuid, nickname));
+    }
+    options.check_max_changes(&group.slug, missing.len())?;
+
+    for (uuid, nickname) in missing {
+        if !confirm(
+            format!("Add {} to the group {}.", nickname, group.slug),
+            options,
+        )? {
+            summary.skipped += 1;
+            continue;
+        }
+        let url = member_url(bitbucket, &group, &uuid);
+        summary.added += 1;
+        if options.dry_run {
+            tracing::info!("PUT {} (dry-run)", url);
+            continue;
+        }
+        tracing::info!("PUT {}", url);
+        journal::ready()?;
+        // The 1.0 API takes an empty object
+        error_for_status(
+            bitbucket
+                .client()
+                .http_put_url(url.clone(), &json!({}))
+                .await?,
+        )?;
+        resources::journal(bitbucket, "PUT", &url, None);
+    }
+    Ok(summary)
+}
+
+/// Remove the users, given by UUID or nickname, from the group of the workspace.
+/// Nothing is changed when more users than --max-changes would be removed.
+pub async fn remove_members(
+    bitbucket: &Bitbucket,
+    group: &str,
+    users: &[String],
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let group = find_group(bitbucket, group).await?;
+    let mut summary = Summary::default();
+    let mut members = Vec::new();
+    for user in users {
+        let member = group
+            .members
+            .iter()
+            .find(|m| m.uuid == *user || (!m.nickname.is_empty() && m.nickname == *user));
+        let Some(member) = member else {
+            tracing::info!("{} is not a member of {}", user, group.slug);
+            summary.unchanged += 1;
+            continue;
+        };
+        members.push(member);
+    }
+    options.check_max_changes(&group.slug, members.len())?;
+
+    for member in members {
+        if !confirm(
+            format!("Remove {} from the group {}.", member.nickname, group.slug),
+            options,
+        )? {
+            summary.skipped += 1;
+            continue;
+        }
+        let url = member_url(bitbucket, &group, &member.uuid);
+        summary.removed += 1;
+        if options.dry_run {
+            tracing::info!("DELETE {} (dry-run)", url);
+            continue;
+        }
+        tracing::info!("DELETE {}", url);
+        journal::ready()?;
+        error_for_status(bitbucket.client().http_delete_url(url.clone()).await?)?;
+        resources::journal(bitbucket, "DELETE", &url, None);
+    }
+    Ok(summary)
+}
+
+/// Members of every group of the workspace and its owners, listed once and reused for every repository
+#[derive(Debug, Clone, Default)]
+pub struct GroupMembers {
+    groups: HashMap<String, Vec<GroupMember>>,
+    /// Owners of the workspace, who administer every repository whatever it grants
+    owners: Vec<WorkspaceUser>,
+}
+
+impl GroupMembers {
+    pub async fn load(bitbucket: &Bitbucket) -> Result<Self, BbdanError> {
+        let groups = list_groups(bitbucket).await?;
+        let owners = list_workspace_permissions(bitbucket, Some(WorkspaceRole::Owner)).await?;
+        Ok(GroupMembers {
+            groups: groups.into_iter().map(|g| (g.slug, g.members)).collect(),
+            owners: owners.into_iter().map(|p| p.user).collect(),
+        })
+    }
+
+    /// The effective grant of each user: group grants are replaced by a grant to each member,
+    /// and a user granted several levels, directly or through groups, gets the highest.
+    /// Owners of the workspace are added as admins of every repository.
+    /// Each grant is kept in `via`, so a direct grant can be told from an inherited one.
+    /// Groups that cannot be resolved, e.g. of another workspace, are kept as they are.
+    pub fn expand(&self, permissions: Vec<Permission>) -> Vec<Permission> {
+        let mut expanded: Vec<Permission> = Vec::new();
+        let mut grant = |p: Permission| match expanded
+            .iter_mut()
+            .find(|e| e.object_type == p.object_type && e.id == p.id)
+        {
+            Some(e) => {
+                e.permission = e.permission.max(p.permission);
+                e.via.extend(p.via);
+            }
+            None => expanded.push(p),
+        };
+        for p in permissions {
+            let members = match p.object_type {
+                ObjectType::Group => self.groups.get(&p.id),
+                ObjectType::User => None,
+            };
+            match members {
+                Some(members) => {
+                    for m in members {
+                        grant(Permission {
+                            object_type: ObjectType::User,
+                            alias: match m.nickname.is_empty() {
+                                true => m.display_name.to_string(),
+                                false => m.nickname.to_string(),
+                            },
+                            id: m.uuid.to_string(),
+                            permission: p.permission,
+                            via: vec![format!("group:{}", p.id)],
+                        });
+                    }
+                }
+                None if p.object_type == ObjectType::Group => {
+                    tracing::warn!("The members of the group {} are unknown", p.id);
+                    grant(p);
+                }
+                None => grant(Permission {
+                    via: vec!["direct".to_string()],
+                    ..p
+                }),
+            }
+        }
+        for owner in &self.owners {
+            grant(Permission {
+                object_type: ObjectType::User,
+                alias: owner.name().to_string(),
+                id: owner.uuid.to_string(),
+                permission: PermissionType::Admin,
+                via: vec!["workspace:owner".to_string()],
+            });
+        }
+        expanded
+    }
+}
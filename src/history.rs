@@ -0,0 +1,335 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::client::Bitbucket;
+use crate::document::Grants;
+use crate::error::BbdanError;
+use crate::permissions::{object_type_to_str, permission_type_to_str, ObjectType, Permission};
+use crate::reconcile::{diff_permissions, Change};
+use crate::watch::describe;
+
+/// Where the permissions seen and changed are recorded, the `[history]` table of the config file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Record the permissions listed and changed by every run, off by default
+    pub enabled: bool,
+    /// Database file [default: bbdan/history.sqlite3 in the user data directory]
+    pub path: Option<PathBuf>,
+}
+
+impl HistoryConfig {
+    /// The database file, unless there is no user data directory to keep it in
+    pub fn path(&self) -> Option<PathBuf> {
+        self.path
+            .clone()
+            .or_else(|| dirs::data_dir().map(|d| d.join("bbdan").join("history.sqlite3")))
+    }
+}
+
+/// How a state of a repository was recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// Listed from the API
+    Observed,
+    /// Left by a change bbdan applied
+    Applied,
+}
+
+impl Source {
+    fn as_str(self) -> &'static str {
+        match self {
+            Source::Observed => "observed",
+            Source::Applied => "applied",
+        }
+    }
+}
+
+/// A bbdan invocation that recorded states
+#[derive(Debug, Clone, Serialize)]
+pub struct Run {
+    pub id: i64,
+    pub started_at: DateTime<Utc>,
+    /// `user@host` of the process, or the host when the user is unknown
+    pub user: String,
+    /// The subcommand, e.g. `sync`
+    pub command: String,
+}
+
+/// A recorded state of a repository that differs from the one before
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub recorded_at: DateTime<Utc>,
+    pub source: String,
+    pub run: Run,
+    /// The changes from the previous state in words, or the grants of the first state
+    pub changes: Vec<String>,
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    started_at TEXT NOT NULL,
+    user TEXT NOT NULL,
+    command TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS states (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    workspace TEXT NOT NULL,
+    repository TEXT NOT NULL,
+    recorded_at TEXT NOT NULL,
+    source TEXT NOT NULL,
+    grants TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS states_repository ON states (workspace, repository, id);
+";
+
+/// The history database
+pub struct History {
+    conn: Connection,
+    command: String,
+    /// Inserted with the first state, so runs that see no permissions leave nothing behind
+    run_id: Option<i64>,
+}
+
+impl History {
+    pub fn open(path: &Path, command: &str) -> Result<Self, BbdanError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path).map_err(history_error)?;
+        // several runs may record at once, e.g. watch and a sync
+        conn.busy_timeout(Duration::from_secs(5))
+            .map_err(history_error)?;
+        conn.execute_batch(SCHEMA).map_err(history_error)?;
+        Ok(Self {
+            conn,
+            command: command.to_string(),
+            run_id: None,
+        })
+    }
+
+    fn run_id(&mut self) -> Result<i64, BbdanError> {
+        if let Some(id) = self.run_id {
+            return Ok(id);
+        }
+        let user = actor();
+        self.conn
+            .execute(
+                "INSERT INTO runs (started_at, user, command) VALUES (?1, ?2, ?3)",
+                params![Utc::now(), user, self.command],
+            )
+            .map_err(history_error)?;
+        let id = self.conn.last_insert_rowid();
+        self.run_id = Some(id);
+        Ok(id)
+    }
+
+    /// The last recorded permissions of the repository
+    fn latest(&self, workspace: &str, repo: &str) -> Result<Option<Grants>, BbdanError> {
+        let grants: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT grants FROM states WHERE workspace = ?1 AND repository = ?2
+                 ORDER BY id DESC LIMIT 1",
+                params![workspace, repo],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(history_error)?;
+        Ok(grants.map(|g| serde_json::from_str(&g)).transpose()?)
+    }
+
+    /// Record the permissions of the repository. An observed state is only recorded
+    /// when it differs from the last one, so the history holds changes rather than every listing.
+    pub fn record(
+        &mut self,
+        workspace: &str,
+        repo: &str,
+        source: Source,
+        permissions: &[Permission],
+    ) -> Result<(), BbdanError> {
+        let grants = serde_json::to_string(&Grants::new(permissions))?;
+        if source == Source::Observed {
+            let latest = self.latest(workspace, repo)?;
+            if latest.map(|g| serde_json::to_string(&g)).transpose()? == Some(grants.clone()) {
+                return Ok(());
+            }
+        }
+        let run_id = self.run_id()?;
+        self.conn
+            .execute(
+                "INSERT INTO states (run_id, workspace, repository, recorded_at, source, grants)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![run_id, workspace, repo, Utc::now(), source.as_str(), grants],
+            )
+            .map_err(history_error)?;
+        Ok(())
+    }
+
+    /// Record the state left by the change on top of the last recorded state.
+    /// Nothing is recorded without one.
+    pub fn record_change(
+        &mut self,
+        workspace: &str,
+        repo: &str,
+        change: &Change,
+    ) -> Result<(), BbdanError> {
+        let Some(latest) = self.latest(workspace, repo)? else {
+            return Ok(());
+        };
+        let mut permissions = latest.permissions();
+        let (Change::Add(p)
+        | Change::Update { after: p, .. }
+        | Change::Remove(p)
+        | Change::Unchanged(p)) = change;
+        permissions.retain(|q| q.object_type != p.object_type || q.id != p.id);
+        if matches!(change, Change::Add(_) | Change::Update { .. }) {
+            permissions.push(p.clone());
+        }
+        self.record(workspace, repo, Source::Applied, &permissions)
+    }
+
+    /// The recorded states of the repository that changed something, oldest first
+    pub fn entries(&self, workspace: &str, repo: &str) -> Result<Vec<Entry>, BbdanError> {
+        let mut statement = self
+            .conn
+            .prepare(
+                "SELECT s.recorded_at, s.source, s.grants, r.id, r.started_at, r.user, r.command
+                 FROM states s JOIN runs r ON r.id = s.run_id
+                 WHERE s.workspace = ?1 AND s.repository = ?2 ORDER BY s.id",
+            )
+            .map_err(history_error)?;
+        let rows = statement
+            .query_map(params![workspace, repo], |row| {
+                Ok((
+                    row.get::<_, DateTime<Utc>>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    Run {
+                        id: row.get(3)?,
+                        started_at: row.get(4)?,
+                        user: row.get(5)?,
+                        command: row.get(6)?,
+                    },
+                ))
+            })
+            .map_err(history_error)?;
+
+        let mut entries = Vec::new();
+        let mut previous: Option<Vec<Permission>> = None;
+        for row in rows {
+            let (recorded_at, source, grants, run) = row.map_err(history_error)?;
+            let permissions = serde_json::from_str::<Grants>(&grants)?.permissions();
+            let changes: Vec<String> = match &previous {
+                None => permissions.iter().map(grant).collect(),
+                Some(before) => diff_permissions(before, &permissions)
+                    .iter()
+                    .filter(|c| !matches!(c, Change::Unchanged(_)))
+                    .map(describe)
+                    .collect(),
+            };
+            if previous.is_none() || !changes.is_empty() {
+                entries.push(Entry {
+                    recorded_at,
+                    source,
+                    run,
+                    changes,
+                });
+            }
+            previous = Some(permissions);
+        }
+        Ok(entries)
+    }
+}
+
+/// A grant in words, e.g. `has user alice admin`
+fn grant(p: &Permission) -> String {
+    let name = match p.object_type {
+        ObjectType::User if !p.alias.is_empty() => &p.alias,
+        _ => &p.id,
+    };
+    format!(
+        "has {} {} {}",
+        object_type_to_str(p.object_type),
+        name,
+        permission_type_to_str(p.permission)
+    )
+}
+
+/// `user@host` of the process, or the host when the user is unknown
+pub(crate) fn actor() -> String {
+    match std::env::var("USER").or_else(|_| std::env::var("USERNAME")) {
+        Ok(user) => format!("{}@{}", user, hostname()),
+        Err(_) => hostname(),
+    }
+}
+
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn history_error(e: rusqlite::Error) -> BbdanError {
+    BbdanError::History(e.to_string())
+}
+
+/// The history of this run, shared by every repository it lists and changes.
+/// Each record runs on a blocking thread, so SQLite never stalls the requests in flight.
+#[derive(Clone)]
+pub struct Recorder(Arc<Mutex<History>>);
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder").finish_non_exhaustive()
+    }
+}
+
+impl Recorder {
+    pub fn new(history: History) -> Self {
+        Self(Arc::new(Mutex::new(history)))
+    }
+
+    /// Run `f` on the history. Failures are logged, they never fail the command.
+    async fn with_history(
+        &self,
+        f: impl FnOnce(&mut History) -> Result<(), BbdanError> + Send + 'static,
+    ) {
+        let history = self.0.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut history = history.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut history)
+        })
+        .await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!("failed to record the history: {}", e),
+            Err(e) => tracing::warn!("failed to record the history: {}", e),
+        }
+    }
+
+    /// Record the permissions just listed from the repository
+    pub async fn observed(&self, workspace: &str, repo: &str, permissions: &[Permission]) {
+        let (workspace, repo) = (workspace.to_string(), repo.to_string());
+        let permissions = permissions.to_vec();
+        self.with_history(move |h| h.record(&workspace, &repo, Source::Observed, &permissions))
+            .await;
+    }
+
+    /// Record a change just applied to the repository
+    pub async fn applied(&self, bitbucket: &Bitbucket, change: &Change) {
+        let (workspace, repo) = (bitbucket.workspace.to_string(), bitbucket.slug.to_string());
+        let change = change.clone();
+        self.with_history(move |h| h.record_change(&workspace, &repo, &change))
+            .await;
+    }
+}
@@ -0,0 +1,173 @@
+use hmac::{Hmac, Mac};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::client::Bitbucket;
+use crate::document::SyncDocument;
+use crate::error::BbdanError;
+use crate::metrics;
+use crate::watch::{self, Drift};
+
+/// Header of the event, e.g. `repo:push`
+const EVENT_HEADER: &str = "X-Event-Key";
+/// Header of the HMAC-SHA256 of the body, `sha256=<hex>`, sent when the webhook has a secret
+const SIGNATURE_HEADER: &str = "X-Hub-Signature";
+
+#[derive(Deserialize)]
+struct Payload {
+    repository: Option<PayloadRepository>,
+}
+
+#[derive(Deserialize)]
+struct PayloadRepository {
+    /// `workspace/slug`
+    full_name: String,
+}
+
+struct State {
+    bitbucket: Bitbucket,
+    baseline: SyncDocument,
+    secret: Option<String>,
+    /// The drift last seen per repository, so only new drift is logged and counted like in watch
+    drift: Mutex<Drift>,
+}
+
+/// Receive the webhooks of the workspace on `addr` until interrupted. The repository of each event
+/// is listed again and compared with the baseline in the background, logging drift when it first appears.
+pub async fn serve(
+    bitbucket: Bitbucket,
+    baseline: SyncDocument,
+    addr: SocketAddr,
+    secret: Option<String>,
+) -> Result<(), BbdanError> {
+    let state = Arc::new(State {
+        bitbucket,
+        baseline,
+        secret,
+        drift: Mutex::new(Drift::new()),
+    });
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(state, req).await) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)
+        .map_err(|e| BbdanError::Invalid(format!("failed to listen on {}: {}", addr, e)))?
+        .serve(make_service);
+    tracing::info!("Listening for webhooks on {}", server.local_addr());
+    server
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+        .map_err(|e| BbdanError::Invalid(format!("webhook server failed: {}", e)))
+}
+
+async fn handle(state: Arc<State>, req: Request<Body>) -> Response<Body> {
+    if req.method() == Method::GET && req.uri().path() == metrics::METRICS_PATH {
+        return metrics::response();
+    }
+    if req.method() != Method::POST {
+        return respond(StatusCode::METHOD_NOT_ALLOWED, "POST webhooks here");
+    }
+    let event = header(&req, EVENT_HEADER).unwrap_or_default();
+    let signature = header(&req, SIGNATURE_HEADER);
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return respond(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    if let Some(secret) = &state.secret {
+        if !verify_signature(secret, &body, signature.as_deref()) {
+            tracing::warn!("Rejected a webhook with an invalid signature");
+            return respond(StatusCode::UNAUTHORIZED, "invalid signature");
+        }
+    }
+    if event == "diagnostics:ping" {
+        return respond(StatusCode::OK, "pong");
+    }
+
+    let payload: Payload = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(e) => return respond(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    let Some(slug) = payload.repository.and_then(|r| {
+        r.full_name
+            .split_once('/')
+            .map(|(_, slug)| slug.to_string())
+    }) else {
+        tracing::debug!("Ignored {} without a repository", event);
+        return respond(StatusCode::ACCEPTED, "no repository");
+    };
+    if !state.baseline.repositories.contains_key(&slug) {
+        tracing::debug!(
+            "Ignored {} of {}, which is not in the baseline",
+            event,
+            slug
+        );
+        return respond(StatusCode::ACCEPTED, "not watched");
+    }
+
+    tracing::info!("Received {} of {}", event, slug);
+    tokio::spawn(async move {
+        let drift = watch::detect_repositories(
+            state.bitbucket.clone(),
+            &state.baseline,
+            vec![slug.to_string()],
+        )
+        .await;
+        let new = {
+            let mut seen = state.drift.lock().unwrap_or_else(|e| e.into_inner());
+            let new = watch::new_drift(&seen, &drift);
+            match drift.get(&slug) {
+                Some(changes) => seen.insert(slug.to_string(), changes.clone()),
+                None => seen.remove(&slug),
+            };
+            new
+        };
+        if new.is_empty() && !drift.is_empty() {
+            tracing::info!("No new drift, {} still differs", slug);
+        } else {
+            watch::log(&new);
+            metrics::record_drift(&new);
+        }
+    });
+    respond(StatusCode::ACCEPTED, "re-auditing")
+}
+
+/// Whether the signature header is the HMAC-SHA256 of the body with the secret
+pub fn verify_signature(secret: &str, body: &[u8], signature: Option<&str>) -> bool {
+    let Some(hex) = signature.and_then(|s| s.strip_prefix("sha256=")) else {
+        return false;
+    };
+    let Ok(expected) = hex::decode(hex) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+fn header(req: &Request<Body>, name: &str) -> Option<String> {
+    req.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+fn respond(status: StatusCode, message: &str) -> Response<Body> {
+    let mut resp = Response::new(Body::from(format!("{}\n", message)));
+    *resp.status_mut() = status;
+    resp
+}
@@ -0,0 +1,531 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::history::actor;
+use crate::permissions::{list, ObjectType, Options, Permission, PermissionType};
+use crate::reconcile::{apply_changes, Change, Summary};
+
+/// Where every change is journaled, the `[journal]` table of the config file
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct JournalConfig {
+    /// Journal file [default: bbdan/journal.jsonl in the user data directory]
+    pub path: Option<PathBuf>,
+}
+
+impl JournalConfig {
+    /// The journal file, unless there is no user data directory to keep it in
+    pub fn path(&self) -> Option<PathBuf> {
+        self.path
+            .clone()
+            .or_else(|| dirs::data_dir().map(|d| d.join("bbdan").join("journal.jsonl")))
+    }
+}
+
+/// A change made to the workspace, a line of the journal.
+/// Each entry holds the hash of the one before, so editing or removing a line breaks the chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub timestamp: DateTime<Utc>,
+    /// The bbdan run that made the change, e.g. `20240102T030405Z-1234`
+    pub run: String,
+    /// `user@host` of the run
+    pub actor: String,
+    /// The subcommand of the run, e.g. `copy`
+    pub command: String,
+    /// The run an `undo` reverted, absent for the other commands
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverts: Option<String>,
+    pub workspace: String,
+    /// Empty for changes to the workspace itself, e.g. to its groups
+    pub repository: String,
+    #[serde(flatten)]
+    pub change: Journaled,
+    /// Hash of the previous entry, empty for the first
+    pub previous: String,
+    /// SHA-256 of the entry with an empty hash, in hex
+    pub hash: String,
+}
+
+/// What an entry of the journal changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Journaled {
+    /// A grant put or deleted
+    Grant {
+        #[serde(rename = "type")]
+        object_type: ObjectType,
+        id: String,
+        alias: String,
+        /// Level before the change, none when it was granted
+        before: Option<PermissionType>,
+        /// Level after the change, none when it was revoked
+        after: Option<PermissionType>,
+    },
+    /// Any other request changing the workspace, e.g. creating a webhook or adding a member to a group
+    Request {
+        method: String,
+        url: String,
+        /// The body sent, with secrets masked
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        body: Option<Value>,
+    },
+}
+
+impl JournalEntry {
+    fn digest(&self) -> Result<String, BbdanError> {
+        let unhashed = JournalEntry {
+            hash: String::new(),
+            ..self.clone()
+        };
+        Ok(hex::encode(Sha256::digest(serde_json::to_vec(&unhashed)?)))
+    }
+}
+
+/// Appends the changes of this run to the journal
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    run: String,
+    actor: String,
+    command: String,
+    /// The run this one reverts, set by undo
+    reverts: Option<String>,
+    /// Whether the journal was found intact, checked before the first change of the run
+    checked: OnceLock<Result<(), String>>,
+}
+
+impl Journal {
+    pub fn new(path: &Path, command: &str) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            run: format!(
+                "{}-{}",
+                Utc::now().format("%Y%m%dT%H%M%SZ"),
+                std::process::id()
+            ),
+            actor: actor(),
+            command: command.to_string(),
+            reverts: None,
+            checked: OnceLock::new(),
+        }
+    }
+
+    /// Check that every entry can be read and the chain is intact, so the changes can be appended to it.
+    /// Done once per run, later calls return the same result.
+    pub fn check(&self) -> Result<(), BbdanError> {
+        self.checked
+            .get_or_init(|| {
+                self.check_file().map_err(|e| {
+                    format!(
+                        "the journal {} is broken, no change is made: {}",
+                        self.path.display(),
+                        e
+                    )
+                })
+            })
+            .clone()
+            .map_err(BbdanError::Invalid)
+    }
+
+    fn check_file(&self) -> Result<(), BbdanError> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        file.lock_shared()?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        verify(&parse(&content)?)
+    }
+
+    /// Append the change made to the repository
+    pub fn append(&self, bitbucket: &Bitbucket, change: &Change) -> Result<(), BbdanError> {
+        let (p, before, after) = match change {
+            Change::Add(p) => (p, None, Some(p.permission)),
+            Change::Update { before, after } => {
+                (after, Some(before.permission), Some(after.permission))
+            }
+            Change::Remove(p) => (p, Some(p.permission), None),
+            Change::Unchanged(_) => return Ok(()),
+        };
+        self.push(
+            bitbucket,
+            Journaled::Grant {
+                object_type: p.object_type,
+                id: p.id.to_string(),
+                alias: p.alias.to_string(),
+                before,
+                after,
+            },
+        )
+    }
+
+    /// Append a request made to change something other than a grant
+    pub fn append_request(
+        &self,
+        bitbucket: &Bitbucket,
+        method: &str,
+        url: &str,
+        body: Option<Value>,
+    ) -> Result<(), BbdanError> {
+        self.push(
+            bitbucket,
+            Journaled::Request {
+                method: method.to_string(),
+                url: url.to_string(),
+                body: body.map(mask),
+            },
+        )
+    }
+
+    fn push(&self, bitbucket: &Bitbucket, change: Journaled) -> Result<(), BbdanError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // other runs wait until this entry is appended, so no two entries follow the same one
+        file.lock()?;
+
+        let mut entry = JournalEntry {
+            timestamp: Utc::now(),
+            run: self.run.to_string(),
+            actor: self.actor.to_string(),
+            command: self.command.to_string(),
+            reverts: self.reverts.clone(),
+            workspace: bitbucket.workspace.to_string(),
+            repository: bitbucket.slug.to_string(),
+            change,
+            previous: last_hash(&mut file)?,
+            hash: String::new(),
+        };
+        entry.hash = entry.digest()?;
+
+        file.write_all(format!("{}\n", serde_json::to_string(&entry)?).as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Hash of the last entry of the journal, read back from its end. Empty for an empty journal.
+fn last_hash(file: &mut File) -> Result<String, BbdanError> {
+    let len = file.metadata()?.len();
+    let mut size = 4096;
+    loop {
+        let start = len.saturating_sub(size);
+        file.seek(SeekFrom::Start(start))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        let tail = String::from_utf8_lossy(&tail);
+        let tail = tail.trim_end();
+        let line = match tail.rfind('\n') {
+            Some(i) => &tail[i + 1..],
+            None if start == 0 => tail,
+            // the last line starts before the tail read
+            None => {
+                size *= 2;
+                continue;
+            }
+        };
+        if line.is_empty() {
+            return Ok(String::new());
+        }
+        let entry: JournalEntry = serde_json::from_str(line)
+            .map_err(|e| BbdanError::Invalid(format!("last journal line: {}", e)))?;
+        return Ok(entry.hash);
+    }
+}
+
+/// The body without the secrets it sets: the secret of webhooks and the values of secured variables
+fn mask(mut body: Value) -> Value {
+    if let Value::Object(object) = &mut body {
+        let secured = object.get("secured") == Some(&Value::Bool(true));
+        for (key, value) in object.iter_mut() {
+            if key == "secret" || secured && key == "value" {
+                *value = Value::String("********".to_string());
+            }
+        }
+    }
+    body
+}
+
+/// Entries of the journal, oldest first. A missing journal has none.
+pub fn read(path: &Path) -> Result<Vec<JournalEntry>, BbdanError> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    parse(&content)
+}
+
+fn parse(content: &str) -> Result<Vec<JournalEntry>, BbdanError> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| BbdanError::Invalid(format!("journal line {}: {}", i + 1, e)))
+        })
+        .collect()
+}
+
+/// Check that no entry was changed, removed or inserted since it was appended
+pub fn verify(entries: &[JournalEntry]) -> Result<(), BbdanError> {
+    let mut previous = "";
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.previous != previous {
+            return Err(BbdanError::Invalid(format!(
+                "journal entry {} does not follow the entry before it",
+                i + 1
+            )));
+        }
+        if entry.digest()? != entry.hash {
+            return Err(BbdanError::Invalid(format!(
+                "journal entry {} was modified",
+                i + 1
+            )));
+        }
+        previous = &entry.hash;
+    }
+    Ok(())
+}
+
+/// The journal the changes of this run are appended to, once enabled
+static JOURNAL: OnceLock<Mutex<Journal>> = OnceLock::new();
+
+/// Journal the changes of this run
+pub fn enable(journal: Journal) {
+    let _ = JOURNAL.set(Mutex::new(journal));
+}
+
+/// Check the journal can be appended to, when it is enabled. Called before sending a change,
+/// so a broken journal stops the run before anything is changed.
+pub fn ready() -> Result<(), BbdanError> {
+    let Some(journal) = JOURNAL.get() else {
+        return Ok(());
+    };
+    let journal = journal.lock().unwrap_or_else(|e| e.into_inner());
+    journal.check()
+}
+
+/// Append a change just applied to the repository, when the journal is enabled
+pub fn record(bitbucket: &Bitbucket, change: &Change) -> Result<(), BbdanError> {
+    let Some(journal) = JOURNAL.get() else {
+        return Ok(());
+    };
+    // one append at a time, so each entry follows the one before
+    let journal = journal.lock().unwrap_or_else(|e| e.into_inner());
+    journal.append(bitbucket, change).map_err(unjournaled)
+}
+
+/// Append a request just made, when the journal is enabled
+pub fn record_request(
+    bitbucket: &Bitbucket,
+    method: &str,
+    url: &str,
+    body: Option<Value>,
+) -> Result<(), BbdanError> {
+    let Some(journal) = JOURNAL.get() else {
+        return Ok(());
+    };
+    let journal = journal.lock().unwrap_or_else(|e| e.into_inner());
+    journal
+        .append_request(bitbucket, method, url, body)
+        .map_err(unjournaled)
+}
+
+fn unjournaled(e: BbdanError) -> BbdanError {
+    BbdanError::Invalid(format!(
+        "the change was applied but could not be journaled: {}",
+        e
+    ))
+}
+
+/// Journal the changes of this run as reverting `run`, so `undo --last` skips both
+pub fn reverting(run: &str) {
+    let Some(journal) = JOURNAL.get() else {
+        return;
+    };
+    let mut journal = journal.lock().unwrap_or_else(|e| e.into_inner());
+    journal.reverts = Some(run.to_string());
+}
+
+/// The run that made the latest change to the workspace, leaving out the undo runs and the runs they reverted,
+/// so each `undo --last` goes one run further back
+pub fn last_run<'a>(entries: &'a [JournalEntry], workspace: &str) -> Option<&'a str> {
+    let reverted: HashSet<&str> = entries
+        .iter()
+        .filter_map(|e| e.reverts.as_deref())
+        .collect();
+    entries
+        .iter()
+        .rev()
+        .filter(|e| e.workspace == workspace)
+        .find(|e| e.command != "undo" && !reverted.contains(e.run.as_str()))
+        .map(|e| e.run.as_str())
+}
+
+/// A grant changed by the run, with its level before the first change and after the last
+struct Changed<'a> {
+    repository: &'a str,
+    object_type: ObjectType,
+    id: &'a str,
+    alias: &'a str,
+    before: Option<PermissionType>,
+    after: Option<PermissionType>,
+}
+
+impl Changed<'_> {
+    /// The grant at some level, or none
+    fn grant(&self, level: Option<PermissionType>) -> Option<Permission> {
+        level.map(|permission| Permission {
+            object_type: self.object_type,
+            alias: self.alias.to_string(),
+            id: self.id.to_string(),
+            permission,
+            via: Vec::new(),
+        })
+    }
+}
+
+/// The changes reverting what the run did to the workspace, per repository.
+/// A grant changed several times by the run goes back to its level before the first change.
+/// Only grants are reverted, the other requests of the run are left as they are.
+pub fn reverse(
+    entries: &[JournalEntry],
+    workspace: &str,
+    run: &str,
+) -> BTreeMap<String, Vec<Change>> {
+    // in the order first changed
+    let mut grants: Vec<Changed> = Vec::new();
+    for entry in entries
+        .iter()
+        .filter(|e| e.workspace == workspace && e.run == run)
+    {
+        let Journaled::Grant {
+            object_type,
+            id,
+            alias,
+            before,
+            after,
+        } = &entry.change
+        else {
+            continue;
+        };
+        match grants.iter_mut().find(|g| {
+            g.repository == entry.repository && g.object_type == *object_type && g.id == id
+        }) {
+            Some(g) => g.after = *after,
+            None => grants.push(Changed {
+                repository: &entry.repository,
+                object_type: *object_type,
+                id,
+                alias,
+                before: *before,
+                after: *after,
+            }),
+        }
+    }
+
+    let mut changes: BTreeMap<String, Vec<Change>> = BTreeMap::new();
+    for g in grants.into_iter().rev() {
+        let change = match (g.grant(g.after), g.grant(g.before)) {
+            (Some(after), Some(before)) if after.permission == before.permission => continue,
+            (Some(after), Some(before)) => Change::Update {
+                before: after,
+                after: before,
+            },
+            (Some(after), None) => Change::Remove(after),
+            (None, Some(before)) => Change::Add(before),
+            (None, None) => continue,
+        };
+        changes
+            .entry(g.repository.to_string())
+            .or_default()
+            .push(change);
+    }
+    changes
+}
+
+/// Revert the changes of a run, repository by repository, and return the result per repository.
+/// Grants changed again since the run are left as they are.
+pub async fn undo(
+    bitbucket: Bitbucket,
+    entries: &[JournalEntry],
+    run: &str,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    let changes = reverse(entries, &bitbucket.workspace, run);
+    if changes.is_empty() {
+        return Err(BbdanError::Invalid(format!(
+            "run {} changed nothing in workspace {} to undo",
+            run, bitbucket.workspace
+        )));
+    }
+    reverting(run);
+
+    let mut results = Vec::new();
+    for (repo, changes) in changes {
+        tracing::info!("Repository: {}", repo);
+        let dest = Bitbucket {
+            slug: repo.to_string(),
+            ..bitbucket.clone()
+        };
+        let result = undo_repository(dest, changes, options)
+            .await
+            .map_err(Arc::new);
+        results.push((repo, result));
+    }
+    Ok(results)
+}
+
+async fn undo_repository(
+    dest: Bitbucket,
+    changes: Vec<Change>,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let current = list(dest.clone()).await?;
+    let mut skipped = 0;
+    let mut unchanged_since = Vec::new();
+    for change in changes {
+        // the grant as the run left it, none when the run revoked it
+        let (left, p) = match &change {
+            Change::Add(p) => (None, p),
+            Change::Update { before: p, .. } | Change::Remove(p) | Change::Unchanged(p) => {
+                (Some(p.permission), p)
+            }
+        };
+        let now = current
+            .iter()
+            .find(|q| q.object_type == p.object_type && q.id == p.id)
+            .map(|q| q.permission);
+        if now == left {
+            unchanged_since.push(change);
+        } else {
+            tracing::warn!(
+                "Skip: id={}, name={} was changed again since the run",
+                p.id,
+                p.alias
+            );
+            skipped += 1;
+        }
+    }
+    let mut summary = apply_changes(dest, unchanged_since, options).await?;
+    summary.skipped += skipped;
+    Ok(summary)
+}
@@ -0,0 +1,55 @@
+//! Manage Bitbucket Cloud repository permissions.
+//!
+//! The `bbdan` binary is a thin CLI over this crate; the same functions can be called from other tools.
+
+pub mod access;
+pub mod batch;
+pub mod branch_restrictions;
+pub mod branching_model;
+pub mod client;
+pub mod completion;
+pub mod config;
+pub mod create;
+pub mod default_reviewers;
+pub mod deploy_keys;
+pub mod document;
+pub mod email;
+pub mod environments;
+pub mod error;
+pub mod groups;
+pub mod history;
+pub mod hooks;
+pub mod journal;
+pub mod lifecycle;
+pub mod mapping;
+pub mod matrix;
+pub mod metrics;
+pub mod notify;
+pub mod oauth;
+pub mod offboard;
+pub mod output;
+pub mod permissions;
+pub mod pipelines;
+pub mod plan;
+pub mod policy;
+pub mod progress;
+pub mod query;
+pub mod reconcile;
+pub mod report;
+pub mod repositories;
+pub mod resources;
+pub mod scopes;
+pub mod settings;
+pub mod setup;
+pub mod snapshot;
+pub mod telemetry;
+pub mod terraform;
+pub mod tui;
+pub mod watch;
+pub mod webhooks;
+pub mod workspace;
+
+pub use client::{Auth, Bitbucket, BitbucketClient, BASE_URL};
+pub use error::BbdanError;
+pub use permissions::{add, list, remove, update, ObjectType, Options, Permission, PermissionType};
+pub use reconcile::{copy, diff, Change, Summary};
@@ -0,0 +1,54 @@
+use dialoguer::{theme::ColorfulTheme, Input};
+use serde_json::Value;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::{list, Options, Permission, PermissionType};
+use crate::reconcile::{reconcile, ChangeFilter, Summary};
+use crate::resources;
+
+// Deleting and archiving repositories, with safeguards against doing it by mistake
+
+/// Delete the repository for good, after its slug is typed back.
+/// `--yes` skips typing it for automation.
+pub async fn delete_repository(bitbucket: &Bitbucket, options: Options) -> Result<(), BbdanError> {
+    // Fail on a mistyped slug before prompting
+    let _: Value = resources::get(bitbucket, "").await?;
+
+    if !options.yes && !options.dry_run {
+        let typed: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "This deletes {} with its code, pull requests and settings. Type its slug to continue",
+                bitbucket.slug
+            ))
+            .allow_empty(true)
+            .interact_text()?;
+        if typed.trim() != bitbucket.slug {
+            return Err(BbdanError::Aborted);
+        }
+    }
+    resources::delete(bitbucket, "", options).await
+}
+
+/// Soft-archive the repository: downgrade every write and admin grant to read,
+/// so it can still be read and restored later
+pub async fn archive_repository(
+    bitbucket: &Bitbucket,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let read_only: Vec<Permission> = list(bitbucket.clone())
+        .await?
+        .into_iter()
+        .map(|p| Permission {
+            permission: PermissionType::Read,
+            ..p
+        })
+        .collect();
+    reconcile(
+        bitbucket.clone(),
+        &read_only,
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+}
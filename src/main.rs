@@ -1,32 +1,237 @@
-use ansi_term::Colour;
-use chrono::{DateTime, Local};
-use clap::{ArgEnum, Parser, Subcommand};
-use dialoguer::{theme::ColorfulTheme, Confirm, MultiSelect};
-use reqwest::{Response, StatusCode};
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::{HashMap, HashSet};
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::path::PathBuf;
-use std::thread::sleep;
-use std::time::{Duration, Instant};
-use std::{default, fs, process};
+use bbdan::access::access;
+use bbdan::batch::apply_csv;
+use bbdan::branch_restrictions::{
+    self, copy_restrictions, list_restrictions, remove_restrictions, BranchRestriction,
+    RestrictionFilter,
+};
+use bbdan::branching_model::{self, branching_model, copy_branching_model, ModelRow};
+use bbdan::client::{http_client, Auth, Bitbucket, HttpLog, BASE_URL};
+use bbdan::completion::{self, cache_repositories, cached_repositories};
+use bbdan::config::{
+    keyring_entry, load_config, login, netrc_credentials, run_credential_helper, Config,
+};
+use bbdan::create::create_like;
+use bbdan::default_reviewers::{
+    self, add_reviewer, copy_reviewers, list_reviewers, remove_reviewers, Reviewer,
+};
+use bbdan::deploy_keys::{
+    self, add_deploy_key, copy_deploy_keys, list_deploy_keys, remove_deploy_keys, DeployKey,
+    DeployKeyFilter,
+};
+use bbdan::document::{export, import, sync, DocumentFormat, SyncDocument};
+use bbdan::email::{self, Attachment, Email, SmtpConfig};
+use bbdan::environments::{
+    self, copy_environments, list_deployment_variables, list_environments, set_deployment_variable,
+    Environment,
+};
+use bbdan::error::{
+    failures_exit_code, EXIT_AUTH, EXIT_DRIFT, EXIT_PARTIAL, EXIT_USAGE, EXIT_VIOLATION,
+};
+use bbdan::groups::{
+    self, add_members, find_group, list_groups, remove_members, Group, GroupMember, GroupMembers,
+};
+use bbdan::history::{History, Recorder};
+use bbdan::hooks;
+use bbdan::journal::{self, Journal};
+use bbdan::lifecycle::{archive_repository, delete_repository};
+use bbdan::mapping::Mapping;
+use bbdan::matrix::{Matrix, MatrixFormat};
+use bbdan::metrics;
+use bbdan::notify::{Notification, Notifier};
+use bbdan::oauth::{OAuthClient, OAuthFlow, OAuthSession, OAUTH_AUTHORIZE_URL, OAUTH_TOKEN_URL};
+use bbdan::offboard::{offboard, Outcome};
+use bbdan::output::{self, Field, Output, Template};
+use bbdan::permissions::{
+    add, list, remove, sort_permissions, update, ListFilter, ObjectType, Options, PermissionType,
+    RemoveFilter, SortKey,
+};
+use bbdan::pipelines::{
+    self, copy_pipelines_config, copy_variables, list_variables, pipelines_config,
+    set_pipelines_enabled, set_variable, PipelinesConfig, Variable,
+};
+use bbdan::plan::{apply_plan, Plan};
+use bbdan::policy::Policy;
+use bbdan::progress;
+use bbdan::query::Query;
+use bbdan::reconcile::{
+    copy, copy_to_many, diff, merge, Change, ChangeFilter, Conflict, Ignore, Summary,
+};
+use bbdan::report;
+use bbdan::repositories::{
+    self, audit, audit_repositories, list_repositories, pick_repository, read_slugs, AuditResult,
+    Paging, Repository, Selector,
+};
+use bbdan::scopes::{verify, Credentials, VerifyReport};
+use bbdan::settings::{self, copy_settings, repository_settings, Setting, SettingValue};
+use bbdan::setup::{clone_setup, Skip};
+use bbdan::snapshot::{list_snapshots, restore, snapshot_dir, Snapshot};
+use bbdan::telemetry;
+use bbdan::tui;
+use bbdan::watch;
+use bbdan::webhooks::{
+    self, add_webhook, copy_webhooks, list_webhooks, remove_webhooks, Webhook, WebhookFilter,
+};
+use bbdan::workspace::{self, list_workspace_permissions, WorkspacePermission, WorkspaceRole};
+use bbdan::BbdanError;
+use chrono::SecondsFormat;
+use clap::{
+    ArgGroup, Args as ClapArgs, CommandFactory, ErrorKind, FromArgMatches, Parser, Subcommand,
+};
+use clap_complete::Shell;
+use dialoguer::{theme::ColorfulTheme, Input, Password};
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::Level;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
 
 #[derive(Parser, Debug)]
 #[clap(name = "bbdan", version, about, long_about = None, arg_required_else_help = true)]
 struct Args {
     /// Username
     #[clap(short, long, value_name = "USERNAME")]
-    username: String,
+    username: Option<String>,
 
-    /// App password
-    #[clap(short, long, value_name = "APP PASSWORD")]
-    password: String,
+    /// App password. Prompted for when omitted and not stored anywhere
+    #[clap(
+        short,
+        long,
+        value_name = "APP PASSWORD",
+        env = "BITBUCKET_APP_PASSWORD",
+        hide_env_values = true
+    )]
+    password: Option<String>,
+
+    /// Repository or workspace access token
+    #[clap(
+        short,
+        long,
+        value_name = "ACCESS TOKEN",
+        env = "BITBUCKET_TOKEN",
+        hide_env_values = true
+    )]
+    token: Option<String>,
+
+    /// OAuth consumer key. Uses OAuth 2.0 instead of the app password when set
+    #[clap(long, value_name = "CLIENT ID", env = "BITBUCKET_CLIENT_ID")]
+    client_id: Option<String>,
+
+    /// OAuth consumer secret
+    #[clap(
+        long,
+        value_name = "CLIENT SECRET",
+        env = "BITBUCKET_CLIENT_SECRET",
+        hide_env_values = true
+    )]
+    client_secret: Option<String>,
+
+    /// OAuth grant type
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "FLOW",
+        default_value = "client-credentials"
+    )]
+    oauth_flow: OAuthFlow,
+
+    /// OAuth authorization URL of the authorization code flow
+    #[clap(
+        long,
+        value_name = "URL",
+        env = "BITBUCKET_OAUTH_AUTHORIZE_URL",
+        default_value = OAUTH_AUTHORIZE_URL
+    )]
+    oauth_authorize_url: String,
+
+    /// OAuth token URL
+    #[clap(
+        long,
+        value_name = "URL",
+        env = "BITBUCKET_OAUTH_TOKEN_URL",
+        default_value = OAUTH_TOKEN_URL
+    )]
+    oauth_token_url: String,
+
+    /// Config file [default: ~/.config/bbdan/config.toml]
+    #[clap(long, value_name = "FILE", env = "BBDAN_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Bitbucket API base URL [default: https://api.bitbucket.org/2.0]
+    #[clap(long, value_name = "URL", env = "BITBUCKET_BASE_URL")]
+    base_url: Option<String>,
 
     /// Workspace
-    #[clap(short, long, value_name = "WORKSPACE")]
-    workspace: String,
+    #[clap(short, long, value_name = "WORKSPACE", env = "BITBUCKET_WORKSPACE")]
+    workspace: Option<String>,
+
+    /// Apply changes without confirmation prompts
+    #[clap(short, long, global = true, visible_alias = "non-interactive")]
+    yes: bool,
+
+    /// Print the changes a command would make to the workspace without applying them
+    #[clap(long, global = true)]
+    dry_run: bool,
+
+    /// Change nothing in a repository when copy, sync, restore or apply would change more grants, or a copy or remove of its settings more items, than this [default: max_changes of the config file]
+    #[clap(long, global = true, value_name = "N")]
+    max_changes: Option<usize>,
+
+    /// Leave users and groups whose UUID, nickname, slug or name matches this glob out of copy, sync and diff, e.g. 'bot-*'. Can be repeated
+    #[clap(
+        long,
+        global = true,
+        value_name = "PATTERN",
+        multiple_occurrences = true
+    )]
+    ignore: Vec<String>,
+
+    /// Number of items requested per page from the API
+    #[clap(
+        long,
+        global = true,
+        value_name = "N",
+        default_value = "100",
+        value_parser = clap::value_parser!(u32).range(1..=100)
+    )]
+    pagelen: u32,
+
+    /// Number of requests sent at the same time by copy and audit
+    #[clap(
+        long,
+        global = true,
+        value_name = "N",
+        default_value = "8",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    parallel: u32,
+
+    /// Seconds to wait for a response before giving up on a request
+    #[clap(long, global = true, value_name = "SECS", default_value = "30")]
+    timeout: u64,
+
+    /// Seconds to wait for a connection to the API
+    #[clap(long, global = true, value_name = "SECS", default_value = "10")]
+    connect_timeout: u64,
+
+    /// Log more, -v for each response and -vv for everything
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Log only warnings and errors
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Never color the output, prompts and logs. Also set by the NO_COLOR environment variable
+    #[clap(long, global = true)]
+    no_color: bool,
+
+    /// Append a line per API request (time, URL, status, response time) to this file in the output type
+    #[clap(long, global = true, value_name = "FILE")]
+    log_http: Option<PathBuf>,
 
     /// Output type
     #[clap(
@@ -34,499 +239,3037 @@ struct Args {
         long,
         arg_enum,
         value_name = "OUTPUT TYPE",
-        default_value = "text"
+        default_value = "table"
     )]
     output: Output,
 
+    /// Render list, diff and audit with this tera template instead of --output
+    #[clap(long, global = true, value_name = "FILE")]
+    format_template: Option<PathBuf>,
+
+    /// Write the output of list, diff, audit and repos to this file, replacing it atomically. `-` for stdout
+    #[clap(long, global = true, value_name = "FILE", default_value = "-")]
+    output_file: PathBuf,
+
     #[clap(subcommand)]
     command: Commands,
 }
 
-#[derive(Debug, Clone, ArgEnum, Copy)]
-enum Output {
-    Csv,
-    Json,
-    Text,
-}
-
+// parsed once per run, boxing the arguments would gain nothing
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Subcommand)]
-enum Commands {
-    /// List permission of repo
-    List { repo: String },
-    /// Copy permission setting from src_repo to dest_repo
-    Copy { src_repo: String, dest_repo: String },
-    /// Remove permission
-    Remove { repo: String },
+enum PlanCommand {
+    /// Plan a copy of the permissions of src_repo to the destinations
+    Copy {
+        src_repo: String,
+        /// Workspace of src_repo, when it is not the workspace of the destinations
+        #[clap(long, value_name = "WORKSPACE")]
+        src_workspace: Option<String>,
+        /// JSON or YAML file mapping the group slugs and users of the source workspace to the destination's
+        #[clap(long, value_name = "FILE")]
+        map: Option<PathBuf>,
+        /// One or more destinations, `-` reads them from stdin
+        #[clap(required_unless_present_any = &["repos-from", "project", "pattern"])]
+        dest_repos: Vec<String>,
+        /// Copy to the repositories in this file, one slug per line. `-` for stdin
+        #[clap(
+            long,
+            visible_alias = "dest-file",
+            value_name = "FILE",
+            conflicts_with = "dest-repos"
+        )]
+        repos_from: Option<PathBuf>,
+        #[clap(flatten)]
+        selector: Selector,
+        #[clap(flatten)]
+        filter: ChangeFilter,
+    },
+    /// Plan a sync of the repositories to a desired-state file
+    Sync {
+        /// Desired-state file
+        #[clap(short, long, value_name = "FILE")]
+        file: String,
+        /// File format [default: guessed from the file extension, otherwise json]
+        #[clap(long, arg_enum, value_name = "FORMAT")]
+        format: Option<DocumentFormat>,
+    },
 }
 
-struct OutputMessage {
-    datetime: DateTime<Local>,
-    url: String,
-    status_code: StatusCode,
-    elapsed: Duration,
+/// Source and destinations of copying a setting other than the permissions, e.g. branch restrictions
+#[derive(Debug, ClapArgs)]
+struct CopyTargets {
+    src_repo: String,
+    /// One or more destinations, `-` reads them from stdin
+    #[clap(required_unless_present_any = &["repos-from", "project", "pattern"])]
+    dest_repos: Vec<String>,
+    /// Copy to the repositories in this file, one slug per line. `-` for stdin
+    #[clap(long, value_name = "FILE", conflicts_with = "dest-repos")]
+    repos_from: Option<PathBuf>,
+    #[clap(flatten)]
+    selector: Selector,
 }
 
-impl OutputMessage {
-    fn new(
-        datetime: DateTime<Local>,
-        url: String,
-        status_code: StatusCode,
-        elapsed: Duration,
-    ) -> Self {
-        Self {
-            datetime,
-            url,
-            status_code,
-            elapsed,
-        }
-    }
-
-    fn to_formatted(&self, output: Output) -> String {
-        let dt = self.datetime.format("%Y-%m-%d %H:%M:%S").to_string();
-        let url = self.url.as_str().to_string();
-        let st = self.status_code.to_string();
-        let response_time = format!(
-            "{}.{:03}",
-            self.elapsed.as_secs(),
-            self.elapsed.subsec_nanos() / 1_000_000
-        );
-
-        match output {
-            Output::Csv => {
-                format!(r#""{}","{}","{}","{}""#, dt, url, st, response_time)
-            }
-            Output::Json => {
-                format!(
-                    r#"{{"datetime": "{}","url: "{}","statusCode": "{}","responseTime": "{}"}}"#,
-                    dt, url, st, response_time
-                )
-            }
-            Output::Text => {
-                format!("{} {} {} {}", dt, url, st, response_time)
-            }
-        }
-    }
+/// Copying the items of a setting, e.g. the deploy keys
+#[derive(Debug, ClapArgs)]
+struct ResourceCopy {
+    #[clap(flatten)]
+    targets: CopyTargets,
+    /// Also remove what the destinations have and src_repo does not
+    #[clap(long)]
+    prune: bool,
 }
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
-    let username: String = args.username;
-    let password: String = args.password;
-    let workspace: String = args.workspace;
-    let output: Output = args.output;
-
-    match args.command {
-        Commands::List { repo } => {
-            let bitbucket = Bitbucket {
-                username: username.to_string(),
-                password: password.to_string(),
-                workspace: workspace.to_string(),
-                slug: repo.to_string(),
-            };
-
-            let result = list(bitbucket).await;
-            println!("Repository: {}", repo.to_string());
-            for p in &result.ok().unwrap() {
-                println!(
-                    "{:?}, {:?}, {:?}, {:?}",
-                    p.object_type, p.id, p.alias, p.permission,
-                );
-            }
-        }
-        Commands::Copy {
-            src_repo,
-            dest_repo,
-        } => {
-            let src = Bitbucket {
-                username: username.to_string(),
-                password: password.to_string(),
-                workspace: workspace.to_string(),
-                slug: src_repo,
-            };
-            let dest = Bitbucket {
-                username: username.to_string(),
-                password: password.to_string(),
-                workspace: workspace.to_string(),
-                slug: dest_repo,
-            };
-            let result = copy(src, dest).await;
-            result.ok();
-        }
-        Commands::Remove { repo } => {
-            let bitbucket = Bitbucket {
-                username: username.to_string(),
-                password: password.to_string(),
-                workspace: workspace.to_string(),
-                slug: repo.to_string(),
-            };
-
-            let result = remove(bitbucket).await;
-            result.ok();
-        }
-    }
+#[derive(Debug, Subcommand)]
+enum BranchRestrictionsCommand {
+    /// List the branch restrictions of a repository
+    List {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+    },
+    /// Copy the branch restrictions of a repository to others, leaving the ones they already have
+    Copy(ResourceCopy),
+    /// Remove branch restrictions of a repository
+    Remove {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+        /// Remove the restriction with this id instead of picking interactively
+        #[clap(long = "id", value_name = "ID")]
+        ids: Vec<u64>,
+        /// Remove the restrictions of this kind, e.g. `push`, instead of picking interactively
+        #[clap(long = "kind", value_name = "KIND")]
+        kinds: Vec<String>,
+    },
 }
 
-// Bitbucket APIを実行する
-
-const BASE_URL: &str = "https://api.bitbucket.org/2.0";
-
-#[derive(Debug, Clone)]
-struct Bitbucket {
-    username: String,
-    password: String,
-    workspace: String,
-    slug: String,
+#[derive(Debug, Subcommand)]
+enum DefaultReviewersCommand {
+    /// List the default reviewers of a repository
+    List {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+    },
+    /// Add a user to the default reviewers of a repository
+    Add {
+        repo: String,
+        /// User to add (UUID or nickname)
+        #[clap(long, value_name = "USER")]
+        user: String,
+    },
+    /// Remove default reviewers of a repository
+    Remove {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+        /// Remove this user (UUID or nickname) instead of picking interactively
+        #[clap(long, value_name = "USER")]
+        user: Vec<String>,
+    },
+    /// Copy the default reviewers of a repository to others, leaving the ones they already have
+    Copy(ResourceCopy),
 }
 
-// #[derive(Debug, Serialize, Deserialize)]
-// struct GroupPermissions {
-//     values: Vec<GroupPermission>
-// }
-// #[derive(Debug, Serialize, Deserialize)]
-// struct GroupPermission {
-//     permission: String,
-//
-// }
+#[derive(Debug, Subcommand)]
+enum DeployKeysCommand {
+    /// List the deploy keys of a repository
+    List {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+    },
+    /// Give an SSH key read-only access to a repository
+    #[clap(group(ArgGroup::new("public-key").required(true).args(&["key", "key-file"])))]
+    Add {
+        repo: String,
+        /// Public key, e.g. `ssh-ed25519 AAAA... ci@example.com`
+        #[clap(long, value_name = "KEY")]
+        key: Option<String>,
+        /// Read the public key from this file, e.g. `id_ed25519.pub`
+        #[clap(long, value_name = "FILE")]
+        key_file: Option<PathBuf>,
+        #[clap(long, value_name = "LABEL", default_value = "")]
+        label: String,
+    },
+    /// Remove deploy keys of a repository
+    Remove {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+        /// Remove the key with this id instead of picking interactively
+        #[clap(long = "id", value_name = "ID")]
+        ids: Vec<u64>,
+        /// Remove the keys with this label instead of picking interactively
+        #[clap(long = "label", value_name = "LABEL")]
+        labels: Vec<String>,
+    },
+    /// Copy the deploy keys of a repository to others, leaving the ones they already have
+    Copy(ResourceCopy),
+}
 
-#[derive(Debug, Clone)]
-struct Permission {
-    object_type: ObjectType,
-    alias: String,
-    id: String,
-    permission: PermissionType,
+#[derive(Debug, Subcommand)]
+enum HooksCommand {
+    /// List the webhooks of a repository
+    List {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+    },
+    /// Add a webhook to a repository
+    Add {
+        repo: String,
+        /// URL called on the events
+        #[clap(long, value_name = "URL")]
+        url: String,
+        /// Event calling the URL, e.g. `repo:push` or `pullrequest:created`
+        #[clap(long = "event", value_name = "EVENT", required = true)]
+        events: Vec<String>,
+        #[clap(long, value_name = "DESCRIPTION", default_value = "")]
+        description: String,
+        /// Secret signing the requests, so the receiver can verify them
+        #[clap(long, value_name = "SECRET")]
+        secret: Option<String>,
+        /// Add the webhook disabled
+        #[clap(long)]
+        inactive: bool,
+    },
+    /// Remove webhooks of a repository
+    Remove {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+        /// Remove the webhook with this UUID instead of picking interactively
+        #[clap(long = "uuid", value_name = "UUID")]
+        uuids: Vec<String>,
+        /// Remove the webhooks calling this URL instead of picking interactively
+        #[clap(long = "url", value_name = "URL")]
+        urls: Vec<String>,
+    },
+    /// Copy the webhooks of a repository to others, leaving the ones they already have
+    Copy(ResourceCopy),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum ObjectType {
-    User,
-    Group,
+#[derive(Debug, Subcommand)]
+enum PipelinesVarsCommand {
+    /// List the pipelines variables of a repository. Values of secured variables are not shown.
+    List {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+    },
+    /// Set a pipelines variable of a repository, creating it or replacing its value
+    Set {
+        repo: String,
+        key: String,
+        value: String,
+        /// Keep the value secret, it cannot be read back
+        #[clap(long)]
+        secured: bool,
+    },
+    /// Copy the pipelines variables of a repository to others, leaving the ones they already have.
+    /// Secured variables are created with an empty value.
+    Copy(ResourceCopy),
 }
 
-fn object_type_from_str(s: &str) -> ObjectType {
-    match s {
-        "user" => return ObjectType::User,
-        "group" => return ObjectType::Group,
-        _ => return ObjectType::User,
-    }
+#[derive(Debug, Subcommand)]
+enum EnvironmentsCommand {
+    /// List the deployment environments of a repository
+    List {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+    },
+    /// Copy the deployment environments of a repository and their variables to others,
+    /// leaving the ones they already have. Secured variables are created with an empty value.
+    Copy(ResourceCopy),
+    /// List and set the deployment variables of an environment
+    Vars {
+        #[clap(subcommand)]
+        command: EnvironmentVarsCommand,
+    },
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum PermissionType {
-    Read,
-    Write,
-    Admin,
+#[derive(Debug, Subcommand)]
+enum EnvironmentVarsCommand {
+    /// List the deployment variables of an environment. Values of secured variables are not shown.
+    List { repo: String, environment: String },
+    /// Set a deployment variable of an environment, creating it or replacing its value
+    Set {
+        repo: String,
+        environment: String,
+        key: String,
+        value: String,
+        /// Keep the value secret, it cannot be read back
+        #[clap(long)]
+        secured: bool,
+    },
 }
-fn permission_type_from_str(s: &str) -> PermissionType {
-    match s {
-        "read" => return PermissionType::Read,
-        "write" => return PermissionType::Write,
-        "admin" => return PermissionType::Admin,
-        _ => return PermissionType::Read,
-    }
+
+#[derive(Debug, Subcommand)]
+enum PipelinesCommand {
+    /// Show whether Pipelines is enabled on a repository
+    Show {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+    },
+    /// Enable Pipelines on a repository
+    Enable { repo: String },
+    /// Disable Pipelines on a repository
+    Disable { repo: String },
+    /// Enable or disable Pipelines on repositories as it is on another
+    Copy(CopyTargets),
 }
-fn permission_type_to_str(p: PermissionType) -> String {
-    match p {
-        PermissionType::Read => return String::from("read"),
-        PermissionType::Write => return String::from("write"),
-        PermissionType::Admin => return String::from("admin"),
-        _ => return String::from("read"),
-    }
+
+#[derive(Debug, Subcommand)]
+enum SettingsCommand {
+    /// Show the fork policy, wiki, issues, main branch, language and privacy of a repository
+    Show {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+    },
+    /// Make the settings of repositories the same as those of another
+    Copy {
+        #[clap(flatten)]
+        targets: CopyTargets,
+        /// Copy only this setting instead of all of them
+        #[clap(long, arg_enum, value_name = "SETTING")]
+        only: Vec<Setting>,
+    },
 }
 
-struct BitbucketClient {
-    http_client: reqwest::Client,
-    base_url: String,
-    username: String,
-    password: String,
+#[derive(Debug, Subcommand)]
+enum BranchingModelCommand {
+    /// Show the development and production branches and the branch prefixes of a repository
+    Show {
+        /// Picked from the repositories of the workspace when omitted
+        repo: Option<String>,
+    },
+    /// Make the branching model of repositories the same as that of another
+    Copy(CopyTargets),
 }
-impl BitbucketClient {
-    fn new(
-        http_client: reqwest::Client,
-        base_url: String,
-        username: String,
-        password: String,
-    ) -> Self {
-        Self {
-            http_client,
-            base_url,
-            username,
-            password,
-        }
-    }
 
-    async fn http_get(&self, url: String) -> Result<Response, reqwest::Error> {
-        let full_url = format!(r#"{}/{}"#, self.base_url, url);
-        let resp = self
-            .http_client
-            .get(full_url)
-            .basic_auth(&self.username, Some(&self.password))
-            .send()
-            .await;
-        return resp;
-    }
+#[derive(Debug, Subcommand)]
+enum RepoCommand {
+    /// Delete a repository for good, after typing its slug back. `--yes` skips typing it.
+    Delete { repo: String },
+    /// Soft-archive a repository by downgrading every write and admin grant to read
+    Archive { repo: String },
 }
 
-async fn list(bitbucket: Bitbucket) -> Result<Vec<Permission>, Box<dyn std::error::Error>> {
-    let mut permissions: Vec<Permission> = Vec::new();
+#[derive(Debug, Subcommand)]
+enum GroupsCommand {
+    /// List the groups of the workspace with their number of members
+    List,
+    /// Show the members of a group of the workspace
+    Show {
+        /// Slug or name of the group
+        group: String,
+    },
+    /// Add users to a group of the workspace
+    AddMember {
+        /// Slug or name of the group
+        group: String,
+        /// UUID or nickname of the users
+        #[clap(required = true)]
+        users: Vec<String>,
+    },
+    /// Remove users from a group of the workspace
+    RemoveMember {
+        /// Slug or name of the group
+        group: String,
+        /// UUID or nickname of the users
+        #[clap(required = true)]
+        users: Vec<String>,
+    },
+}
 
-    let client = BitbucketClient::new(
-        reqwest::Client::new(),
-        BASE_URL.to_string(),
-        bitbucket.username,
-        bitbucket.password,
-    );
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// List permission of repo
+    List {
+        /// Picked from the repositories of the workspace when omitted, `-` reads many from stdin
+        repo: Option<String>,
+        /// List the repositories in this file, one slug per line. `-` for stdin
+        #[clap(long, value_name = "FILE", conflicts_with = "repo")]
+        repos_from: Option<PathBuf>,
+        #[clap(flatten)]
+        selector: Selector,
+        /// Show only users or groups
+        #[clap(long = "type", arg_enum, value_name = "TYPE")]
+        object_type: Option<ObjectType>,
+        /// Show only this permission level
+        #[clap(long, arg_enum, value_name = "PERMISSION")]
+        permission: Option<PermissionType>,
+        /// Sort by this key instead of the API response order
+        #[clap(long, arg_enum, value_name = "KEY")]
+        sort: Option<SortKey>,
+        /// Sort in descending order
+        #[clap(long, requires = "sort")]
+        desc: bool,
+        /// Output only these columns, in this order
+        #[clap(long, arg_enum, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<Field>,
+        /// Show only the permissions matching this filter, e.g. "permission == 'admin' && type == 'user'"
+        #[clap(long, value_name = "EXPR", value_parser = Query::parse)]
+        query: Option<Query>,
+        /// Replace each group grant by a grant to each of its members, with the highest level per user
+        #[clap(long)]
+        expand_members: bool,
+    },
+    /// Copy permission setting from src_repo to dest_repo
+    Copy {
+        src_repo: String,
+        /// Workspace of src_repo, when it is not the workspace of the destinations
+        #[clap(long, value_name = "WORKSPACE")]
+        src_workspace: Option<String>,
+        /// JSON or YAML file mapping the group slugs and users of the source workspace to the destination's
+        #[clap(long, value_name = "FILE")]
+        map: Option<PathBuf>,
+        /// One or more destinations, `-` reads them from stdin
+        #[clap(required_unless_present_any = &["repos-from", "project", "pattern"])]
+        dest_repos: Vec<String>,
+        /// Copy to the repositories in this file, one slug per line. `-` for stdin
+        #[clap(
+            long,
+            visible_alias = "dest-file",
+            value_name = "FILE",
+            conflicts_with = "dest-repos"
+        )]
+        repos_from: Option<PathBuf>,
+        #[clap(flatten)]
+        selector: Selector,
+        #[clap(flatten)]
+        filter: ChangeFilter,
+    },
+    /// Copy the union of the grants of several repositories to another, e.g. when consolidating them
+    Merge {
+        /// Source repository. Can be repeated
+        #[clap(
+            long = "from",
+            value_name = "REPO",
+            required = true,
+            multiple_occurrences = true
+        )]
+        from: Vec<String>,
+        /// Destination repository
+        #[clap(long, value_name = "REPO")]
+        to: String,
+        /// Level taken when the sources grant a user or group different ones
+        #[clap(long, arg_enum, value_name = "CONFLICT", default_value = "highest")]
+        on_conflict: Conflict,
+        #[clap(flatten)]
+        filter: ChangeFilter,
+    },
+    /// Show the differences of permissions from repo_a to repo_b
+    Diff {
+        repo_a: String,
+        repo_b: String,
+        /// Mail the output as an attachment to these addresses, through the `[smtp]` server of the config file
+        #[clap(long, value_name = "ADDRESS", value_delimiter = ',')]
+        email_to: Vec<String>,
+    },
+    /// Export permissions of repo to a JSON or YAML file
+    Export {
+        repo: String,
+        /// Output file, `-` for stdout
+        #[clap(short = 'o', long = "output", value_name = "FILE", default_value = "-")]
+        file: String,
+        /// File format [default: guessed from the file extension, otherwise json]
+        #[clap(long, arg_enum, value_name = "FORMAT")]
+        format: Option<DocumentFormat>,
+    },
+    /// Apply permissions from a file exported by export to repo
+    Import {
+        repo: String,
+        /// Input file
+        #[clap(short, long, value_name = "FILE")]
+        file: String,
+        /// File format [default: guessed from the file extension, otherwise json]
+        #[clap(long, arg_enum, value_name = "FORMAT")]
+        format: Option<DocumentFormat>,
+        #[clap(flatten)]
+        filter: ChangeFilter,
+    },
+    /// Save the permissions of a repository, or of every repository, to restore them later
+    #[clap(group(ArgGroup::new("scope").required(true).args(&["repo", "all", "list"])))]
+    Snapshot {
+        repo: Option<String>,
+        /// Snapshot every repository in the workspace
+        #[clap(long)]
+        all: bool,
+        /// List the saved snapshots instead
+        #[clap(long)]
+        list: bool,
+        /// Directory of the snapshots [default: bbdan/snapshots/<workspace> in the user data directory]
+        #[clap(long, value_name = "DIR")]
+        dir: Option<PathBuf>,
+    },
+    /// Reconcile a repository back to its permissions in a snapshot
+    Restore {
+        repo: String,
+        /// Snapshot name, as listed by `snapshot --list`, or snapshot file
+        #[clap(long, value_name = "SNAPSHOT")]
+        from: String,
+        /// Directory of the snapshots [default: bbdan/snapshots/<workspace> in the user data directory]
+        #[clap(long, value_name = "DIR")]
+        dir: Option<PathBuf>,
+    },
+    /// Show the grants and settings changed, as appended to the journal
+    Journal {
+        /// Only the changes of this run
+        #[clap(long, value_name = "RUN")]
+        run: Option<String>,
+        /// Check that no entry was modified or removed, instead of showing them
+        #[clap(long)]
+        verify: bool,
+    },
+    /// Revert the grants a run added, changed and removed, as recorded in the journal
+    #[clap(group(ArgGroup::new("which").required(true).args(&["last", "run"])))]
+    Undo {
+        /// Undo the latest run that changed the workspace
+        #[clap(long)]
+        last: bool,
+        /// Undo this run, as shown by `journal`
+        #[clap(long, value_name = "RUN")]
+        run: Option<String>,
+    },
+    /// Show how the permissions of a repository changed, as recorded in the history database
+    History { repo: String },
+    /// Reconcile many repositories to a desired-state file
+    Sync {
+        /// Desired-state file
+        #[clap(short, long, value_name = "FILE")]
+        file: String,
+        /// File format [default: guessed from the file extension, otherwise json]
+        #[clap(long, arg_enum, value_name = "FORMAT")]
+        format: Option<DocumentFormat>,
+    },
+    /// List permissions of every repository in the workspace
+    Audit {
+        #[clap(flatten)]
+        paging: Paging,
+        /// Output only these columns, in this order
+        #[clap(long, arg_enum, value_name = "FIELDS", value_delimiter = ',')]
+        fields: Vec<Field>,
+        /// Show only the permissions matching this filter, e.g. "permission == 'admin' && type == 'user'"
+        #[clap(long, value_name = "EXPR", value_parser = Query::parse)]
+        query: Option<Query>,
+        /// Replace each group grant by a grant to each of its members, with the highest level per user
+        #[clap(long)]
+        expand_members: bool,
+        /// Also write an HTML report with a summary and sortable tables to this file
+        #[clap(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+        /// Mail the output, and the report of --report, as attachments to these addresses, through the `[smtp]` server of the config file
+        #[clap(long, value_name = "ADDRESS", value_delimiter = ',')]
+        email_to: Vec<String>,
+        /// Post a summary to the channels of the `[notify]` table of the config file
+        #[clap(long)]
+        notify: bool,
+    },
+    /// Apply the changes listed in a file, with a summary per repository
+    #[clap(group(ArgGroup::new("changes").required(true).args(&["csv", "plan"])))]
+    Apply {
+        /// CSV file with rows of `repo,principal_type,principal,permission,action`, action being add, update or remove
+        #[clap(long, value_name = "FILE")]
+        csv: Option<PathBuf>,
+        /// Plan file written by `plan`, applied only when no planned repository changed since
+        #[clap(long, value_name = "FILE")]
+        plan: Option<PathBuf>,
+    },
+    /// Write the changes a command would make to a plan file, to review and then apply with `apply --plan`
+    Plan {
+        /// Plan file
+        #[clap(long, value_name = "FILE", default_value = "plan.json")]
+        out: PathBuf,
+        #[clap(subcommand)]
+        command: PlanCommand,
+    },
+    /// Check every repository against the rules of a policy file and report the violations
+    Check {
+        /// Policy file with `forbid` and `require` rules
+        #[clap(short, long, value_name = "FILE")]
+        policy: PathBuf,
+        /// Post a summary to the channels of the `[notify]` table of the config file
+        #[clap(long)]
+        notify: bool,
+    },
+    /// Compare the permissions with a baseline periodically and log the changes made since
+    Watch {
+        /// Baseline or desired-state file, e.g. written by `-o json audit`
+        #[clap(short, long, value_name = "FILE")]
+        baseline: String,
+        /// File format [default: guessed from the file extension, otherwise json]
+        #[clap(long, arg_enum, value_name = "FORMAT")]
+        format: Option<DocumentFormat>,
+        /// Time between checks, e.g. 30s, 15m or 1h
+        #[clap(long, value_name = "DURATION", default_value = "15m", value_parser = humantime::parse_duration)]
+        interval: Duration,
+        /// Check once and exit, with 4 when there is drift
+        #[clap(long)]
+        once: bool,
+        /// Post new drift to the channels of the `[notify]` table of the config file
+        #[clap(long)]
+        notify: bool,
+        /// Serve Prometheus metrics on /metrics of this address, e.g. 127.0.0.1:9090
+        #[clap(long, value_name = "ADDR", conflicts_with = "once")]
+        metrics: Option<SocketAddr>,
+    },
+    /// Receive workspace webhooks and compare the repository of each event with a baseline.
+    /// Prometheus metrics are served on /metrics of the same address.
+    ServeHooks {
+        /// Baseline or desired-state file, e.g. written by `-o json audit`
+        #[clap(short, long, value_name = "FILE")]
+        baseline: String,
+        /// File format [default: guessed from the file extension, otherwise json]
+        #[clap(long, arg_enum, value_name = "FORMAT")]
+        format: Option<DocumentFormat>,
+        /// Address to listen on
+        #[clap(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+        /// Secret of the webhook; requests without its signature are rejected
+        #[clap(
+            long,
+            value_name = "SECRET",
+            env = "BBDAN_WEBHOOK_SECRET",
+            hide_env_values = true
+        )]
+        secret: Option<String>,
+    },
+    /// Grid of the permission levels of every principal on every repository, for access reviews
+    Matrix {
+        /// Output file, `-` for stdout
+        #[clap(short = 'o', long = "output", value_name = "FILE", default_value = "-")]
+        file: String,
+        /// File format [default: guessed from the file extension, otherwise csv]
+        #[clap(long, arg_enum, value_name = "FORMAT")]
+        format: Option<MatrixFormat>,
+    },
+    /// List repositories in the workspace
+    Repos {
+        #[clap(flatten)]
+        paging: Paging,
+    },
+    /// Remove permission
+    Remove {
+        /// Picked from the repositories of the workspace when omitted, `-` reads many from stdin
+        repo: Vec<String>,
+        /// Remove from the repositories in this file, one slug per line. `-` for stdin
+        #[clap(long, value_name = "FILE", conflicts_with = "repo")]
+        repos_from: Option<PathBuf>,
+        #[clap(flatten)]
+        selector: Selector,
+        /// Remove the permission of this user (UUID or nickname) instead of picking interactively
+        #[clap(long, value_name = "USER")]
+        user: Vec<String>,
+        /// Remove the permission of this group (slug) instead of picking interactively
+        #[clap(long, value_name = "GROUP")]
+        group: Vec<String>,
+        /// Remove the users and groups listed in this file, one per line: `user:<USER>`, `group:<GROUP>`,
+        /// or a bare UUID or group slug
+        #[clap(long, value_name = "FILE")]
+        from_file: Option<PathBuf>,
+        /// Remove the permissions of every user, leaving the groups
+        #[clap(long)]
+        all_users: bool,
+        /// Only remove grants at this level, of anyone unless --user, --group or --all-users is given
+        #[clap(long, arg_enum, value_name = "PERMISSION")]
+        permission: Vec<PermissionType>,
+    },
+    /// Grant a permission to a user or group
+    #[clap(group(ArgGroup::new("principal").required(true).args(&["user", "group"])))]
+    Add {
+        #[clap(required_unless_present_any = &["project", "pattern"])]
+        repo: Option<String>,
+        #[clap(flatten)]
+        selector: Selector,
+        /// User to grant (UUID or nickname)
+        #[clap(long, value_name = "USER")]
+        user: Option<String>,
+        /// Group to grant (slug)
+        #[clap(long, value_name = "GROUP")]
+        group: Option<String>,
+        /// Permission level
+        #[clap(long, arg_enum, value_name = "PERMISSION")]
+        permission: PermissionType,
+    },
+    /// Change the permission level of an existing grant
+    #[clap(group(ArgGroup::new("principal").required(true).args(&["user", "group"])))]
+    Update {
+        #[clap(required_unless_present_any = &["project", "pattern"])]
+        repo: Option<String>,
+        #[clap(flatten)]
+        selector: Selector,
+        /// User to change (UUID or nickname)
+        #[clap(long, value_name = "USER")]
+        user: Option<String>,
+        /// Group to change (slug)
+        #[clap(long, value_name = "GROUP")]
+        group: Option<String>,
+        /// New permission level
+        #[clap(long, arg_enum, value_name = "PERMISSION")]
+        permission: PermissionType,
+    },
+    /// Show the repositories a user can reach, directly or through their groups, and at what level
+    Access {
+        /// User to look up (UUID or nickname)
+        #[clap(long, value_name = "USER")]
+        user: String,
+    },
+    /// Remove the grants of a user or group from every repository in the workspace
+    #[clap(group(ArgGroup::new("principal").required(true).args(&["user", "group"])))]
+    Offboard {
+        /// User to remove (UUID or nickname)
+        #[clap(long, value_name = "USER")]
+        user: Option<String>,
+        /// Group to remove (slug)
+        #[clap(long, value_name = "GROUP")]
+        group: Option<String>,
+    },
+    /// Manage who can push, merge or delete on which branches of repositories
+    BranchRestrictions {
+        #[clap(subcommand)]
+        command: BranchRestrictionsCommand,
+    },
+    /// Manage the users added as reviewers to every new pull request of repositories
+    DefaultReviewers {
+        #[clap(subcommand)]
+        command: DefaultReviewersCommand,
+    },
+    /// Manage the SSH keys with read-only access to repositories
+    DeployKeys {
+        #[clap(subcommand)]
+        command: DeployKeysCommand,
+    },
+    /// Manage the webhooks of repositories
+    Hooks {
+        #[clap(subcommand)]
+        command: HooksCommand,
+    },
+    /// Manage the pipelines variables of repositories
+    PipelinesVars {
+        #[clap(subcommand)]
+        command: PipelinesVarsCommand,
+    },
+    /// Enable or disable Pipelines on repositories
+    Pipelines {
+        #[clap(subcommand)]
+        command: PipelinesCommand,
+    },
+    /// Show and copy the settings of repositories, e.g. their fork policy
+    Settings {
+        #[clap(subcommand)]
+        command: SettingsCommand,
+    },
+    /// Show and copy the branching model of repositories
+    BranchingModel {
+        #[clap(subcommand)]
+        command: BranchingModelCommand,
+    },
+    /// List and copy the deployment environments of repositories and their variables
+    Environments {
+        #[clap(subcommand)]
+        command: EnvironmentsCommand,
+    },
+    /// Create a repository like another, with its permissions, default reviewers and webhooks
+    Create {
+        new_repo: String,
+        /// Repository to take the project, privacy, fork policy and settings from
+        #[clap(long, value_name = "TEMPLATE_REPO")]
+        like: String,
+        /// Create the repository in this project instead of that of the template
+        #[clap(long, value_name = "KEY")]
+        project: Option<String>,
+    },
+    /// Delete or archive repositories
+    Repo {
+        #[clap(subcommand)]
+        command: RepoCommand,
+    },
+    /// Copy the permissions, branch restrictions, default reviewers, webhooks, deploy keys
+    /// and pipelines variables of a repository to another
+    CloneSetup {
+        src_repo: String,
+        dest_repo: String,
+        #[clap(flatten)]
+        skip: Skip,
+        #[clap(flatten)]
+        filter: ChangeFilter,
+    },
+    /// List the members of the workspace and their level, above the grants of each repository
+    WorkspacePermissions {
+        /// Show only the members at this level
+        #[clap(long, arg_enum, value_name = "ROLE")]
+        role: Option<WorkspaceRole>,
+    },
+    /// List the groups of the workspace and their members
+    Groups {
+        #[clap(subcommand)]
+        command: GroupsCommand,
+    },
+    /// Validate username and app password and store them
+    Login,
+    /// Check credentials and the scopes required by each command
+    Verify {
+        /// Also check admin access to the permissions of this repository
+        repo: Option<String>,
+    },
+    /// Browse repositories and their permissions, and queue changes to review and apply
+    Tui,
+    /// Print the completion script for a shell
+    Completions {
+        #[clap(arg_enum)]
+        shell: Shell,
+    },
+    /// List repository slugs for the completion scripts
+    #[clap(name = completion::COMPLETE_REPOS, hide = true)]
+    CompleteRepos,
+}
 
-    let resp = client
-        .http_get(format!(
-            r#"repositories/{}/{}/permissions-config/groups"#,
-            bitbucket.workspace, bitbucket.slug,
-        ))
-        .await
-        .unwrap();
+#[tokio::main]
+async fn main() {
+    run().await;
+    telemetry::shutdown();
+}
 
-    if !resp.status().is_success() {
-        println!("failed to get permission");
-        return Ok(permissions);
+async fn run() {
+    let matches = Args::command()
+        .try_get_matches()
+        .unwrap_or_else(|e| usage_error(e));
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| usage_error(e));
+    if args.no_color || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        // prompts, progress bars and tables all style through console
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
     }
+    init_logging(args.verbose, args.quiet);
+    telemetry::start(matches.subcommand_name().unwrap_or_default());
+    let config = match load_config(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("failed to load config: {}", e);
+            exit(e.exit_code());
+        }
+    };
 
-    let permission_groups: Value = resp.json().await?;
-
-    for v in permission_groups["values"].as_array().unwrap() {
-        let p = Permission {
-            permission: permission_type_from_str(v["permission"].as_str().unwrap()),
-            object_type: object_type_from_str(v["group"]["type"].as_str().unwrap()),
-            alias: String::from(v["group"]["name"].as_str().unwrap()),
-            id: String::from(v["group"]["slug"].as_str().unwrap()),
-        };
-        permissions.push(p);
-    }
+    let base_url = args
+        .base_url
+        .clone()
+        .or_else(|| config.base_url.clone())
+        .unwrap_or_else(|| BASE_URL.to_string())
+        .trim_end_matches('/')
+        .to_string();
 
-    let resp_users = client
-        .http_get(format!(
-            r#"repositories/{}/{}/permissions-config/users"#,
-            bitbucket.workspace, bitbucket.slug,
-        ))
-        .await
-        .unwrap();
+    let http_client = match http_client(
+        Duration::from_secs(args.timeout),
+        Duration::from_secs(args.connect_timeout),
+    ) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("failed to create HTTP client: {}", e);
+            exit(e.exit_code());
+        }
+    };
 
-    if !resp_users.status().is_success() {
-        println!("failed to get permission");
-        return Ok(vec![]);
+    if let Commands::Completions { shell } = args.command {
+        print!("{}", completion::script(&mut Args::command(), shell));
+        return;
     }
 
-    let permission_users: Value = resp_users.json().await?;
-
-    for v in permission_users["values"].as_array().unwrap() {
-        let p = Permission {
-            permission: permission_type_from_str(v["permission"].as_str().unwrap()),
-            object_type: object_type_from_str(v["user"]["type"].as_str().unwrap()),
-            alias: String::from(v["user"]["nickname"].as_str().unwrap()),
-            id: String::from(v["user"]["uuid"].as_str().unwrap()),
+    let workspace = args.workspace.clone().or_else(|| config.workspace.clone());
+    let terminal = output::is_terminal(&args.output_file);
+    if let Commands::CompleteRepos = args.command {
+        let Some(workspace) = workspace else {
+            return;
         };
-        permissions.push(p);
-    }
-
-    Ok(permissions)
-}
-
-async fn copy(
-    src: Bitbucket,
-    dest: Bitbucket,
-) -> Result<Vec<Permission>, Box<dyn std::error::Error>> {
-    let permissions_src = list(src).await.ok().unwrap();
-    let permissions_before = list(dest.clone()).await.ok().unwrap();
-
-    let mut dest_ids: HashMap<String, &Permission> = HashMap::new();
-    for p in &permissions_before {
-        dest_ids.insert(p.id.to_string(), &p);
+        let slugs = match cached_repositories(&workspace) {
+            Some(slugs) => slugs,
+            None => {
+                let slugs =
+                    match complete_repos(&args, &config, &http_client, &base_url, &workspace).await
+                    {
+                        Ok(slugs) => slugs,
+                        Err(e) => {
+                            eprintln!("failed to list repositories: {}", e);
+                            exit(e.exit_code());
+                        }
+                    };
+                if let Err(e) = cache_repositories(&workspace, &slugs) {
+                    tracing::debug!("failed to cache repositories: {}", e);
+                }
+                slugs
+            }
+        };
+        for slug in slugs {
+            println!("{}", slug);
+        }
+        return;
     }
 
-    let mut src_ids: HashSet<String> = HashSet::new();
-    let client = reqwest::Client::new();
-    for p in permissions_src {
-        src_ids.insert(p.id.to_string());
-
-        if dest_ids.contains_key(&p.id) {
-            let dests = dest_ids.get(&p.id).unwrap();
-            if p.permission == dests.permission {
-                println!("Not change: id={}, name={}", p.id, p.alias);
-                continue;
-            } else {
-                let message = format!(
-                    "Permission update: id={}, name={}, before={}, after={}. Continue?",
-                    p.id,
-                    p.alias,
-                    permission_type_to_str(p.permission),
-                    permission_type_to_str(dests.permission),
+    if let Commands::Login = args.command {
+        let username = args.username.or(config.username);
+        match login(http_client, username, &base_url, args.config.as_deref()).await {
+            Ok(result) => {
+                println!("Logged in as {} ({})", result.display_name, result.username);
+                println!(
+                    "Scopes: {}",
+                    result
+                        .scopes
+                        .map(|scopes| scopes.join(", "))
+                        .unwrap_or_else(|| "unknown".to_string())
+                );
+                println!(
+                    "Stored app password in the keyring and username in {}",
+                    result.config_path.display()
                 );
-                if Confirm::with_theme(&ColorfulTheme::default())
-                    .with_prompt(message)
-                    .default(true)
-                    .wait_for_newline(true)
-                    .interact()
-                    .unwrap()
-                {
-                    println!("Continue");
-                } else {
-                    println!("Skip");
-                    continue;
-                }
             }
-        } else {
-            let message = format!("Add: id={}, name={}. Continue?", p.id, p.alias);
-            if Confirm::with_theme(&ColorfulTheme::default())
-                .with_prompt(message)
-                .default(true)
-                .wait_for_newline(true)
-                .interact()
-                .unwrap()
-            {
-                println!("Continue");
-            } else {
-                println!("Skip");
-                continue;
+            Err(e) => {
+                eprintln!("failed to login: {}", e);
+                exit(e.exit_code());
             }
         }
+        return;
+    }
 
-        let url = if p.object_type == ObjectType::User {
-            format!(
-                r#"{}/repositories/{}/{}/permissions-config/users/{}"#,
-                BASE_URL, dest.workspace, dest.slug, p.id,
-            )
-        } else {
-            format!(
-                r#"{}/repositories/{}/{}/permissions-config/groups/{}"#,
-                BASE_URL, dest.workspace, dest.slug, p.id,
-            )
-        };
-
-        let mut map = HashMap::new();
-        map.insert("permission", permission_type_to_str(p.permission));
+    let auth = match resolve_auth(&args, &config, &http_client, &base_url, true).await {
+        Ok(auth) => auth,
+        Err(e) => {
+            eprintln!("failed to authenticate: {}", e);
+            exit(e.exit_code());
+        }
+    };
+    let workspace: String = match workspace {
+        Some(w) => w,
+        None => usage_error(Args::command().error(
+            ErrorKind::MissingRequiredArgument,
+            "--workspace is required",
+        )),
+    };
 
-        println!("PUT {}", url);
+    let http_log = match &args.log_http {
+        Some(path) => match HttpLog::open(path, args.output) {
+            Ok(log) => Some(Arc::new(log)),
+            Err(e) => {
+                eprintln!("failed to open {}: {}", path.display(), e);
+                exit(e.exit_code());
+            }
+        },
+        None => None,
+    };
 
-        let resp = client
-            .put(url)
-            .basic_auth(&dest.username, Some(&dest.password))
-            .json(&map)
-            .send()
-            .await?;
+    let template = match &args.format_template {
+        Some(path) => match Template::load(path) {
+            Ok(template) => Some(template),
+            Err(e) => {
+                eprintln!("failed to load {}: {}", path.display(), e);
+                exit(e.exit_code());
+            }
+        },
+        None => None,
+    };
 
-        if !resp.status().is_success() {
-            println!("failed to request");
-            return Ok(vec![]);
+    let ignore = match Ignore::new(&[config.ignore.clone(), args.ignore.clone()].concat()) {
+        Ok(ignore) => ignore,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(e.exit_code());
+        }
+    };
+    let options = Options {
+        yes: args.yes,
+        dry_run: args.dry_run,
+        max_changes: args.max_changes.or(config.max_changes),
+        // both are leaked once for the whole run, so the options stay Copy
+        protected: Some(Box::leak(Box::new(config.protected.clone()))),
+        ignore: Some(Box::leak(Box::new(ignore))),
+    };
+    let command = matches.subcommand_name().unwrap_or_default();
+    let history = match config.history.path() {
+        Some(path) if config.history.enabled => match History::open(&path, command) {
+            Ok(history) => Some(Recorder::new(history)),
+            Err(e) => {
+                tracing::warn!("the history is not recorded: {}", e);
+                None
+            }
+        },
+        None if config.history.enabled => {
+            tracing::debug!("no user data directory, the history is not recorded");
+            None
         }
+        _ => None,
+    };
+    let target = |slug: &str| Bitbucket {
+        http_client: http_client.clone(),
+        base_url: base_url.to_string(),
+        auth: auth.clone(),
+        workspace: workspace.to_string(),
+        slug: slug.to_string(),
+        pagelen: args.pagelen,
+        parallel: args.parallel as usize,
+        http_log: http_log.clone(),
+        history: history.clone(),
+    };
 
-        let result: Value = resp.json().await?;
-        println!("result: {}", result);
+    match config.journal.path() {
+        Some(path) => journal::enable(Journal::new(&path, command)),
+        None => tracing::warn!("no user data directory, the changes are not journaled"),
     }
 
-    for p in permissions_before {
-        if src_ids.contains(&p.id) {
-            continue;
-        }
+    match args.command {
+        Commands::List {
+            repo,
+            repos_from,
+            selector,
+            object_type,
+            permission,
+            sort,
+            desc,
+            fields,
+            query,
+            expand_members,
+        } => {
+            let filter = ListFilter {
+                object_type,
+                permission,
+            };
+            let members = group_members(expand_members, &target("")).await;
+            let select = |repo: &str, permissions: &mut Vec<_>| {
+                if let Some(members) = &members {
+                    *permissions = members.expand(std::mem::take(permissions));
+                }
+                if let Some(key) = sort {
+                    sort_permissions(permissions, key, desc);
+                }
+                permissions.retain(|p| {
+                    filter.matches(p) && query.as_ref().is_none_or(|q| q.matches(repo, p))
+                });
+            };
 
-        let message = format!("Remove: id={}, name={}. Continue?", p.id, p.alias);
-        if Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(message)
-            .default(true)
-            .wait_for_newline(true)
-            .interact()
-            .unwrap()
-        {
-            println!("Continue");
-        } else {
-            println!("Skip");
-            continue;
-        }
-        let url = if p.object_type == ObjectType::User {
-            format!(
-                r#"{}/repositories/{}/{}/permissions-config/users/{}"#,
-                BASE_URL, dest.workspace, dest.slug, p.id,
-            )
-        } else {
-            format!(
-                r#"{}/repositories/{}/{}/permissions-config/groups/{}"#,
-                BASE_URL, dest.workspace, dest.slug, p.id,
+            // many repositories are listed like audit does
+            let many = many_repos(
+                repo.as_deref(),
+                repos_from.as_deref(),
+                &selector,
+                &target(""),
             )
-        };
-
-        println!("DELETE {}", url);
-
-        let resp = client
-            .delete(url)
-            .basic_auth(&dest.username, Some(&dest.password))
-            .send()
-            .await?;
+            .await;
+            if let Some(slugs) = many {
+                let mut results = audit_repositories(target(""), slugs, |_| {}).await;
+                record_observed(history.as_ref(), &workspace, &results).await;
+                for (repo, result) in &mut results {
+                    match result {
+                        Ok(permissions) => select(repo, permissions),
+                        Err(e) => {
+                            eprintln!("{}: failed to list: {}", repo, e);
+                        }
+                    }
+                }
+                let rendered = match &template {
+                    Some(template) => template.audit(&workspace, &results),
+                    None => output::audit(&workspace, &results, &fields, args.output, terminal),
+                };
+                write_output(rendered, &args.output_file);
+                exit_if_unlisted(&results);
+                return;
+            }
 
-        if !resp.status().is_success() {
-            println!("failed to request");
-            return Ok(vec![]);
+            let repo = repo_or_pick(repo, &target(""), options).await;
+            let bitbucket = target(&repo);
+            let mut permissions = match list(bitbucket).await {
+                Ok(permissions) => permissions,
+                Err(e) => {
+                    eprintln!("failed to list: {}", e);
+                    exit(e.exit_code());
+                }
+            };
+            if let Some(history) = &history {
+                history.observed(&workspace, &repo, &permissions).await;
+            }
+            select(&repo, &mut permissions);
+            let rendered = match &template {
+                Some(template) => template.permissions(&workspace, &repo, &permissions),
+                None => output::permissions(
+                    &workspace,
+                    &repo,
+                    &permissions,
+                    &fields,
+                    args.output,
+                    terminal,
+                ),
+            };
+            write_output(rendered, &args.output_file);
         }
+        Commands::Copy {
+            src_repo,
+            src_workspace,
+            map,
+            dest_repos,
+            repos_from,
+            selector,
+            filter,
+        } => {
+            let src = source(target(&src_repo), src_workspace);
+            let mapping = mapping(map);
+            let first = dest_repos.first().map(String::as_str);
+            let dests = match many_repos(first, repos_from.as_deref(), &selector, &target("")).await
+            {
+                Some(dests) => dests,
+                None if dest_repos.len() == 1 => {
+                    match copy(src, target(&dest_repos[0]), &mapping, &filter, options).await {
+                        Ok(summary) => exit_if_pending(&summary, options),
+                        Err(e) => {
+                            eprintln!("failed to copy: {}", e);
+                            exit(e.exit_code());
+                        }
+                    }
+                    return;
+                }
+                None => dest_repos,
+            };
 
-        let result: Value = resp.json().await?;
-        println!("result: {}", result);
-    }
-
-    let permissions_after = list(dest).await.ok().unwrap();
-    Ok(permissions_after)
-}
+            let dests: Vec<Bitbucket> = dests.iter().map(|d| target(d)).collect();
+            match copy_to_many(src, &dests, &mapping, &filter, options).await {
+                Ok(results) => print_summaries(&results, options),
+                Err(e) => {
+                    eprintln!("failed to copy: {}", e);
+                    exit(e.exit_code());
+                }
+            }
+        }
+        Commands::Merge {
+            from,
+            to,
+            on_conflict,
+            filter,
+        } => {
+            let srcs: Vec<Bitbucket> = from.iter().map(|repo| target(repo)).collect();
 
-async fn remove(bitbucket: Bitbucket) -> Result<(), Box<dyn std::error::Error>> {
-    let permissions = list(bitbucket.clone()).await.ok().unwrap();
+            match merge(&srcs, target(&to), on_conflict, &filter, options).await {
+                Ok(summary) => exit_if_pending(&summary, options),
+                Err(e) => {
+                    eprintln!("failed to merge: {}", e);
+                    exit(e.exit_code());
+                }
+            }
+        }
+        Commands::Diff {
+            repo_a,
+            repo_b,
+            email_to,
+        } => {
+            let a = target(&repo_a);
+            let b = target(&repo_b);
+            let smtp = smtp(&email_to, &config);
 
-    let multiselected: Vec<String> = permissions
-        .iter()
-        .map(|x| {
-            format!(
-                "{:?} - {:?} - {:?} - {:?}",
-                x.object_type, x.id, x.alias, x.permission
-            )
-        })
-        .collect();
+            let changes = match diff(a, b, options).await {
+                Ok(changes) => changes,
+                Err(e) => {
+                    eprintln!("failed to diff: {}", e);
+                    exit(e.exit_code());
+                }
+            };
+            let render = |terminal| match &template {
+                Some(template) => template.diff(&workspace, &repo_a, &repo_b, &changes),
+                None => output::diff(
+                    &workspace,
+                    &repo_a,
+                    &repo_b,
+                    &changes,
+                    args.output,
+                    terminal,
+                ),
+            };
+            write_output(render(terminal), &args.output_file);
+            // the attachment is never read in the terminal
+            let mailed = smtp.as_ref().and_then(|_| render(false).ok());
+            if let (Some(smtp), Some(content)) = (&smtp, mailed) {
+                let subject = format!("Permission differences from {} to {}", repo_a, repo_b);
+                let attachment = output_attachment(
+                    &format!("diff-{}-{}", repo_a, repo_b),
+                    content,
+                    args.output,
+                    template.is_some(),
+                );
+                email_report(smtp, &email_to, subject, vec![attachment]).await;
+            }
+            if changes.iter().any(|c| !matches!(c, Change::Unchanged(_))) {
+                exit(EXIT_DRIFT);
+            }
+        }
+        Commands::Export { repo, file, format } => {
+            let bitbucket = target(&repo);
+            let format = format.unwrap_or_else(|| DocumentFormat::from_path(&file));
 
-    let selections = MultiSelect::with_theme(&ColorfulTheme::default())
-        .with_prompt("Pick permission you want to remove")
-        .items(&multiselected[..])
-        .interact()
-        .unwrap();
+            let result = export(bitbucket, format)
+                .await
+                .and_then(|content| output::write(Path::new(&file), &content));
+            if let Err(e) = result {
+                eprintln!("failed to export: {}", e);
+                exit(e.exit_code());
+            }
+            if file != "-" {
+                tracing::info!("Exported the permissions to {}", file);
+            }
+        }
+        Commands::Import {
+            repo,
+            file,
+            format,
+            filter,
+        } => {
+            let bitbucket = target(&repo);
+            let format = format.unwrap_or_else(|| DocumentFormat::from_path(&file));
 
-    if selections.is_empty() {
-        println!("You did not select anything :(");
-    } else {
-        let client = reqwest::Client::new();
+            match import(bitbucket, &file, format, &filter, options).await {
+                Ok(summary) => exit_if_pending(&summary, options),
+                Err(e) => {
+                    eprintln!("failed to import: {}", e);
+                    exit(e.exit_code());
+                }
+            }
+        }
+        Commands::Sync { file, format } => {
+            let bitbucket = target("");
+            let format = format.unwrap_or_else(|| DocumentFormat::from_path(&file));
 
-        for selection in selections {
-            let p = permissions[selection].clone();
+            match sync(bitbucket, &file, format, options).await {
+                Ok(results) => print_summaries(&results, options),
+                Err(e) => {
+                    eprintln!("failed to sync: {}", e);
+                    exit(e.exit_code());
+                }
+            }
+        }
+        Commands::Snapshot {
+            repo,
+            all,
+            list: list_only,
+            dir,
+        } => {
+            let dir = snapshots(dir, &workspace);
+            if list_only {
+                let snapshots = match list_snapshots(&dir) {
+                    Ok(snapshots) => snapshots,
+                    Err(e) => {
+                        eprintln!("failed to list snapshots: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let out: String = snapshots
+                    .iter()
+                    .map(|s| {
+                        format!(
+                            "{}\t{}\t{} repositories\n",
+                            s.name(),
+                            s.taken_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                            s.repositories.len()
+                        )
+                    })
+                    .collect();
+                write_output(Ok(out), &args.output_file);
+                return;
+            }
 
-            let url = if p.object_type == ObjectType::User {
-                format!(
-                    r#"{}/repositories/{}/{}/permissions-config/users/{}"#,
-                    BASE_URL, bitbucket.workspace, bitbucket.slug, p.id,
-                )
-            } else {
-                format!(
-                    r#"{}/repositories/{}/{}/permissions-config/groups/{}"#,
-                    BASE_URL, bitbucket.workspace, bitbucket.slug, p.id,
-                )
+            let results: Vec<AuditResult> = match repo {
+                Some(repo) if !all => match list(target(&repo)).await {
+                    Ok(permissions) => vec![(repo, Ok(permissions))],
+                    Err(e) => {
+                        eprintln!("failed to list: {}", e);
+                        exit(e.exit_code());
+                    }
+                },
+                _ => match audit(target(""), &Paging::default(), |_| {}).await {
+                    Ok((results, _)) => results,
+                    Err(e) => {
+                        eprintln!("failed to audit: {}", e);
+                        exit(e.exit_code());
+                    }
+                },
             };
-
-            let resp = client
-                .delete(url)
-                .basic_auth(&bitbucket.username, Some(&bitbucket.password))
-                .send()
-                .await?;
-
-            if !resp.status().is_success() {
-                println!("failed to request");
-                return Ok(());
+            record_observed(history.as_ref(), &workspace, &results).await;
+            for (repo, result) in &results {
+                if let Err(e) = result {
+                    eprintln!("{}: failed to list, not in the snapshot: {}", repo, e);
+                }
+            }
+            let snapshot = Snapshot::new(&workspace, &results);
+            match snapshot.save(&dir) {
+                Ok(path) => tracing::info!(
+                    "Saved {} repositories to {}",
+                    snapshot.repositories.len(),
+                    path.display()
+                ),
+                Err(e) => {
+                    eprintln!("failed to save the snapshot: {}", e);
+                    exit(e.exit_code());
+                }
             }
+            write_output(Ok(format!("{}\n", snapshot.name())), &args.output_file);
+            exit_if_unlisted(&results);
+        }
+        Commands::Restore { repo, from, dir } => {
+            let bitbucket = target(&repo);
+            let snapshot = match Snapshot::load(&snapshots(dir, &workspace), &from) {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    eprintln!("failed to load the snapshot: {}", e);
+                    exit(e.exit_code());
+                }
+            };
 
-            let result: Value = resp.json().await?;
-            println!("result: {}", result);
+            match restore(bitbucket, &snapshot, options).await {
+                Ok(summary) => exit_if_pending(&summary, options),
+                Err(e) => {
+                    eprintln!("failed to restore: {}", e);
+                    exit(e.exit_code());
+                }
+            }
         }
-    };
+        Commands::Journal { run, verify } => {
+            let path = journal_path(&config);
+            let entries = match journal::read(&path) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("failed to read the journal: {}", e);
+                    exit(e.exit_code());
+                }
+            };
+            if verify {
+                if let Err(e) = journal::verify(&entries) {
+                    eprintln!("{}", e);
+                    exit(e.exit_code());
+                }
+                tracing::info!("The journal of {} entries is intact", entries.len());
+                return;
+            }
+            let entries: Vec<_> = entries
+                .into_iter()
+                .filter(|e| run.as_ref().is_none_or(|run| e.run == *run))
+                .collect();
+            write_output(
+                output::journal(&entries, args.output, terminal),
+                &args.output_file,
+            );
+        }
+        Commands::Undo { last: _, run } => {
+            let path = journal_path(&config);
+            let entries = match journal::read(&path) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("failed to read the journal: {}", e);
+                    exit(e.exit_code());
+                }
+            };
+            let run = match run
+                .as_deref()
+                .or_else(|| journal::last_run(&entries, &workspace))
+            {
+                Some(run) => run.to_string(),
+                None => {
+                    eprintln!("the journal has no changes to {} to undo", workspace);
+                    exit(EXIT_USAGE);
+                }
+            };
+            tracing::info!("Undoing run {}", run);
 
-    Ok(())
+            match journal::undo(target(""), &entries, &run, options).await {
+                Ok(results) => print_summaries(&results, options),
+                Err(e) => {
+                    eprintln!("failed to undo: {}", e);
+                    exit(e.exit_code());
+                }
+            }
+        }
+        Commands::History { repo } => {
+            let Some(path) = config.history.path() else {
+                eprintln!("no user data directory to keep the history in, set path in the [history] table of the config file");
+                exit(EXIT_USAGE);
+            };
+            let entries = match History::open(&path, "history")
+                .and_then(|history| history.entries(&workspace, &repo))
+            {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("failed to read the history: {}", e);
+                    exit(e.exit_code());
+                }
+            };
+            write_output(
+                output::history(&workspace, &repo, &entries, args.output, terminal),
+                &args.output_file,
+            );
+        }
+        Commands::Repos { paging } => {
+            let bitbucket = target("");
+
+            let (repositories, cursor) = match list_repositories(&bitbucket, &paging).await {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("failed to list repositories: {}", e);
+                    exit(e.exit_code());
+                }
+            };
+            let rendered = match args.output {
+                // a slug per line, to pipe into the commands reading slugs from stdin
+                Output::Text => Ok(repositories
+                    .iter()
+                    .map(|r| format!("{}\n", r.slug))
+                    .collect()),
+                _ => output::resources(
+                    &format!("Workspace: {}", workspace),
+                    repositories::HEADER,
+                    &repositories,
+                    Repository::row,
+                    args.output,
+                    terminal,
+                ),
+            };
+            write_output(rendered, &args.output_file);
+            if let Some(cursor) = cursor {
+                eprintln!(
+                    "More repositories available, resume with --cursor '{}'",
+                    cursor
+                );
+            }
+        }
+        Commands::Audit {
+            paging,
+            fields,
+            query,
+            report,
+            notify,
+            email_to,
+            expand_members,
+        } => {
+            let bitbucket = target("");
+            let notifier = notifier(notify, &config, &http_client);
+            let smtp = smtp(&email_to, &config);
+            let members = group_members(expand_members, &bitbucket).await;
+
+            // NDJSON to stdout is streamed as each repository is done instead of after the whole audit
+            let streaming = template.is_none()
+                && matches!(args.output, Output::Ndjson)
+                && args.output_file == Path::new("-");
+            let select = |result: &AuditResult| {
+                let result = match &members {
+                    Some(members) => (
+                        result.0.to_string(),
+                        result.1.clone().map(|p| members.expand(p)),
+                    ),
+                    None => result.clone(),
+                };
+                match &query {
+                    Some(query) => query.filter_audit(&result),
+                    None => result,
+                }
+            };
+            let print_result = |result: &_| {
+                if !streaming {
+                    return;
+                }
+                progress::suspend(|| {
+                    write_output(
+                        output::audit_ndjson(&select(result), &fields),
+                        Path::new("-"),
+                    )
+                });
+            };
+            let (results, cursor) = match audit(bitbucket, &paging, print_result).await {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("failed to audit: {}", e);
+                    exit(e.exit_code());
+                }
+            };
+            record_observed(history.as_ref(), &workspace, &results).await;
+            let results: Vec<AuditResult> = results.iter().map(select).collect();
+            for (repo, result) in &results {
+                if let Err(e) = result {
+                    eprintln!("{}: failed to list: {}", repo, e);
+                }
+            }
+            if !streaming {
+                let rendered = match &template {
+                    Some(template) => template.audit(&workspace, &results),
+                    None => output::audit(&workspace, &results, &fields, args.output, terminal),
+                };
+                write_output(rendered, &args.output_file);
+            }
+            if let Some(path) = &report {
+                write_output(Ok(report::html(&workspace, &results)), path);
+                tracing::info!("Wrote the report to {}", path.display());
+            }
+            if let Some(cursor) = cursor {
+                eprintln!(
+                    "More repositories available, resume with --cursor '{}'",
+                    cursor
+                );
+            }
+            if let Some(notifier) = &notifier {
+                send(notifier, Notification::audit(&workspace, &results)).await;
+            }
+            if let Some(smtp) = &smtp {
+                let rendered = match &template {
+                    Some(template) => template.audit(&workspace, &results),
+                    None => output::audit(&workspace, &results, &fields, args.output, false),
+                };
+                let mut attachments = match rendered {
+                    Ok(content) => vec![output_attachment(
+                        &format!("audit-{}", workspace),
+                        content,
+                        args.output,
+                        template.is_some(),
+                    )],
+                    Err(e) => {
+                        eprintln!("failed to render the email: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                if report.is_some() {
+                    attachments.push(Attachment {
+                        filename: format!("audit-{}.html", workspace),
+                        content_type: "text/html".to_string(),
+                        content: report::html(&workspace, &results),
+                    });
+                }
+                let subject = format!("Permissions of {}", workspace);
+                email_report(smtp, &email_to, subject, attachments).await;
+            }
+            exit_if_unlisted(&results);
+        }
+        Commands::Remove {
+            repo,
+            repos_from,
+            selector,
+            user,
+            group,
+            from_file,
+            all_users,
+            permission,
+        } => {
+            let mut filter = RemoveFilter {
+                users: user,
+                groups: group,
+                all_users,
+                permissions: permission,
+            };
+            if let Some(path) = from_file {
+                match RemoveFilter::load(&path) {
+                    Ok(listed) => {
+                        filter.users.extend(listed.users);
+                        filter.groups.extend(listed.groups);
+                    }
+                    Err(e) => {
+                        eprintln!("failed to read {}: {}", path.display(), e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+
+            let first = repo.first().map(String::as_str);
+            let many = match many_repos(first, repos_from.as_deref(), &selector, &target("")).await
+            {
+                Some(slugs) => Some(slugs),
+                None if repo.len() > 1 => Some(repo.clone()),
+                None => None,
+            };
+            if let Some(slugs) = many {
+                let mut errors = Vec::new();
+                for repo in slugs {
+                    if let Err(e) = remove(target(&repo), filter.clone(), options).await {
+                        eprintln!("{}: failed to remove: {}", repo, e);
+                        errors.push(e);
+                    }
+                }
+                if let Some(code) = failures_exit_code(&errors) {
+                    exit(code);
+                }
+                return;
+            }
+
+            let repo = repo_or_pick(repo.into_iter().next(), &target(""), options).await;
+            let bitbucket = target(&repo);
+            if let Err(e) = remove(bitbucket, filter, options).await {
+                eprintln!("failed to remove: {}", e);
+                exit(e.exit_code());
+            }
+        }
+        Commands::Add {
+            repo,
+            selector,
+            user,
+            group,
+            permission,
+        } => {
+            let principal = match (user, group) {
+                (Some(user), _) => (ObjectType::User, user),
+                (None, Some(group)) => (ObjectType::Group, group),
+                (None, None) => unreachable!(),
+            };
+
+            if let Some(slugs) = many_repos(repo.as_deref(), None, &selector, &target("")).await {
+                let mut errors = Vec::new();
+                for repo in slugs {
+                    if let Err(e) = add(target(&repo), principal.clone(), permission, options).await
+                    {
+                        eprintln!("{}: failed to add: {}", repo, e);
+                        errors.push(e);
+                    }
+                }
+                if let Some(code) = failures_exit_code(&errors) {
+                    exit(code);
+                }
+                return;
+            }
+
+            let bitbucket = target(&repo.unwrap_or_default());
+            if let Err(e) = add(bitbucket, principal, permission, options).await {
+                eprintln!("failed to add: {}", e);
+                exit(e.exit_code());
+            }
+        }
+        Commands::Update {
+            repo,
+            selector,
+            user,
+            group,
+            permission,
+        } => {
+            let principal = match (user, group) {
+                (Some(user), _) => (ObjectType::User, user),
+                (None, Some(group)) => (ObjectType::Group, group),
+                (None, None) => unreachable!(),
+            };
+
+            if let Some(slugs) = many_repos(repo.as_deref(), None, &selector, &target("")).await {
+                let mut errors = Vec::new();
+                for repo in slugs {
+                    if let Err(e) =
+                        update(target(&repo), principal.clone(), permission, options).await
+                    {
+                        eprintln!("{}: failed to update: {}", repo, e);
+                        errors.push(e);
+                    }
+                }
+                if let Some(code) = failures_exit_code(&errors) {
+                    exit(code);
+                }
+                return;
+            }
+
+            let bitbucket = target(&repo.unwrap_or_default());
+            if let Err(e) = update(bitbucket, principal, permission, options).await {
+                eprintln!("failed to update: {}", e);
+                exit(e.exit_code());
+            }
+        }
+        Commands::Apply { csv, plan } => {
+            let bitbucket = target("");
+
+            let (file, result) = match (csv, plan) {
+                (Some(csv), _) => {
+                    let result = apply_csv(bitbucket, &csv, options).await;
+                    (csv, result)
+                }
+                (None, Some(path)) => {
+                    let result = match Plan::load(&path) {
+                        Ok(plan) => apply_plan(bitbucket, &plan, options).await,
+                        Err(e) => Err(e),
+                    };
+                    (path, result)
+                }
+                (None, None) => unreachable!("clap requires --csv or --plan"),
+            };
+            match result {
+                Ok(results) => print_summaries(&results, options),
+                Err(e) => {
+                    eprintln!("failed to apply {}: {}", file.display(), e);
+                    exit(e.exit_code());
+                }
+            }
+        }
+        Commands::Plan { out, command } => {
+            let planned = match &command {
+                PlanCommand::Copy {
+                    src_repo,
+                    dest_repos,
+                    ..
+                } => format!("copy {} {}", src_repo, dest_repos.join(" ")),
+                PlanCommand::Sync { file, .. } => format!("sync -f {}", file),
+            };
+            let mut plan = Plan::new(&workspace, planned.trim_end());
+            let result = match command {
+                PlanCommand::Copy {
+                    src_repo,
+                    src_workspace,
+                    map,
+                    dest_repos,
+                    repos_from,
+                    selector,
+                    filter,
+                } => {
+                    let src = source(target(&src_repo), src_workspace);
+                    let mapping = mapping(map);
+                    let first = dest_repos.first().map(String::as_str);
+                    let dests = many_repos(first, repos_from.as_deref(), &selector, &target(""))
+                        .await
+                        .unwrap_or(dest_repos);
+                    async {
+                        let permissions = mapping.translate(&target(""), list(src).await?).await?;
+                        for dest in &dests {
+                            plan.add(target(dest), &permissions, &filter, options)
+                                .await?;
+                        }
+                        Ok(())
+                    }
+                    .await
+                }
+                PlanCommand::Sync { file, format } => {
+                    let format = format.unwrap_or_else(|| DocumentFormat::from_path(&file));
+                    match std::fs::read_to_string(&file)
+                        .map_err(BbdanError::from)
+                        .and_then(|content| SyncDocument::parse(&content, format))
+                    {
+                        Ok(document) => {
+                            let mut result = Ok(());
+                            for (repo, grants) in &document.repositories {
+                                result = plan
+                                    .add(
+                                        target(repo),
+                                        &grants.permissions(),
+                                        &ChangeFilter::default(),
+                                        options,
+                                    )
+                                    .await;
+                                if result.is_err() {
+                                    break;
+                                }
+                            }
+                            result
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+            };
+            if let Err(e) = result.and_then(|_| plan.save(&out)) {
+                eprintln!("failed to plan: {}", e);
+                exit(e.exit_code());
+            }
+            match plan.describe() {
+                Ok(lines) => {
+                    for line in lines {
+                        println!("  {}", line);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("failed to plan: {}", e);
+                    exit(e.exit_code());
+                }
+            }
+            tracing::info!(
+                "Planned {} changes to {} repositories in {}, apply them with `bbdan apply --plan {}`",
+                plan.len(),
+                plan.repositories.len(),
+                out.display(),
+                out.display()
+            );
+        }
+        Commands::Check { policy, notify } => {
+            let bitbucket = target("");
+            let notifier = notifier(notify, &config, &http_client);
+            let policy = match Policy::load(&policy) {
+                Ok(policy) => policy,
+                Err(e) => {
+                    eprintln!("failed to load {}: {}", policy.display(), e);
+                    exit(e.exit_code());
+                }
+            };
+
+            let (results, _) = match audit(bitbucket, &Paging::default(), |_| {}).await {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("failed to audit: {}", e);
+                    exit(e.exit_code());
+                }
+            };
+            for (repo, result) in &results {
+                if let Err(e) = result {
+                    eprintln!("{}: failed to list: {}", repo, e);
+                }
+            }
+            let violations = policy.evaluate(&results);
+            write_output(
+                output::check(&workspace, &results, &violations, args.output, terminal),
+                &args.output_file,
+            );
+            if let Some(notifier) = &notifier {
+                send(notifier, Notification::check(&workspace, &violations)).await;
+            }
+            if !violations.is_empty() {
+                exit(EXIT_VIOLATION);
+            }
+            exit_if_unlisted(&results);
+        }
+        Commands::Watch {
+            baseline,
+            format,
+            interval,
+            once,
+            notify,
+            metrics,
+        } => {
+            let bitbucket = target("");
+            let notifier = notifier(notify, &config, &http_client);
+            let document = load_baseline(&baseline, format);
+
+            if once {
+                let drift = watch::detect(bitbucket.clone(), &document).await;
+                watch::log(&drift);
+                if let (Some(notifier), false) = (&notifier, drift.is_empty()) {
+                    send(notifier, Notification::drift(&bitbucket.workspace, &drift)).await;
+                }
+                if drift.values().any(Result::is_err) {
+                    exit(EXIT_PARTIAL);
+                }
+                if !drift.is_empty() {
+                    exit(EXIT_DRIFT);
+                }
+                return;
+            }
+            if let Some(addr) = metrics {
+                tokio::spawn(async move {
+                    if let Err(e) = metrics::serve(addr).await {
+                        eprintln!("failed to serve metrics: {}", e);
+                        exit(e.exit_code());
+                    }
+                });
+            }
+            tracing::info!(
+                "Watching {} repositories every {}",
+                document.repositories.len(),
+                humantime::format_duration(interval)
+            );
+            watch::watch(bitbucket, &document, interval, notifier.as_ref()).await;
+        }
+        Commands::ServeHooks {
+            baseline,
+            format,
+            listen,
+            secret,
+        } => {
+            let bitbucket = target("");
+            let document = load_baseline(&baseline, format);
+
+            if let Err(e) = hooks::serve(bitbucket, document, listen, secret).await {
+                eprintln!("failed to serve webhooks: {}", e);
+                exit(e.exit_code());
+            }
+        }
+        Commands::Matrix { file, format } => {
+            let bitbucket = target("");
+            let format = format.unwrap_or_else(|| MatrixFormat::from_path(&file));
+
+            let (results, _) = match audit(bitbucket, &Paging::default(), |_| {}).await {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("failed to audit: {}", e);
+                    exit(e.exit_code());
+                }
+            };
+            for (repo, result) in &results {
+                if let Err(e) = result {
+                    eprintln!("{}: failed to list: {}", repo, e);
+                }
+            }
+            let matrix = Matrix::new(&results);
+            let rendered = match format {
+                MatrixFormat::Csv => matrix.csv(),
+                MatrixFormat::Html => Ok(report::matrix(&workspace, &matrix)),
+            };
+            write_output(rendered, Path::new(&file));
+            exit_if_unlisted(&results);
+        }
+        Commands::Access { user } => {
+            let bitbucket = target("");
+
+            let report = match access(bitbucket, &user).await {
+                Ok(report) => report,
+                Err(e) => {
+                    eprintln!("failed to look up access: {}", e);
+                    exit(e.exit_code());
+                }
+            };
+            for (repo, e) in &report.failed {
+                eprintln!("{}: failed to list: {}", repo, e);
+            }
+            write_output(
+                output::access(&workspace, &report, args.output, terminal),
+                &args.output_file,
+            );
+            if let Some(code) = failures_exit_code(report.failed.iter().map(|(_, e)| e.as_ref())) {
+                exit(code);
+            }
+        }
+        Commands::BranchRestrictions { command } => match command {
+            BranchRestrictionsCommand::List { repo } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                let restrictions = match list_restrictions(&target(&repo)).await {
+                    Ok(restrictions) => restrictions,
+                    Err(e) => {
+                        eprintln!("failed to list branch restrictions: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let rendered = output::resources(
+                    &format!("Repository: {}", repo),
+                    branch_restrictions::HEADER,
+                    &restrictions,
+                    BranchRestriction::row,
+                    args.output,
+                    terminal,
+                );
+                write_output(rendered, &args.output_file);
+            }
+            BranchRestrictionsCommand::Copy(copy) => {
+                let dests = destinations(&copy.targets, &target).await;
+                match copy_restrictions(target(&copy.targets.src_repo), &dests, copy.prune, options)
+                    .await
+                {
+                    Ok(results) => print_summaries(&results, options),
+                    Err(e) => {
+                        eprintln!("failed to copy branch restrictions: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+            BranchRestrictionsCommand::Remove { repo, ids, kinds } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                let filter = RestrictionFilter { ids, kinds };
+                match remove_restrictions(target(&repo), &filter, options).await {
+                    Ok(summary) => exit_if_pending(&summary, options),
+                    Err(e) => {
+                        eprintln!("failed to remove branch restrictions: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+        },
+        Commands::DefaultReviewers { command } => match command {
+            DefaultReviewersCommand::List { repo } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                let reviewers = match list_reviewers(&target(&repo)).await {
+                    Ok(reviewers) => reviewers,
+                    Err(e) => {
+                        eprintln!("failed to list default reviewers: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let rendered = output::resources(
+                    &format!("Repository: {}", repo),
+                    default_reviewers::HEADER,
+                    &reviewers,
+                    Reviewer::row,
+                    args.output,
+                    terminal,
+                );
+                write_output(rendered, &args.output_file);
+            }
+            DefaultReviewersCommand::Add { repo, user } => {
+                match add_reviewer(target(&repo), &user, options).await {
+                    Ok(summary) => exit_if_pending(&summary, options),
+                    Err(e) => {
+                        eprintln!("failed to add the default reviewer: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+            DefaultReviewersCommand::Remove { repo, user } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                match remove_reviewers(target(&repo), &user, options).await {
+                    Ok(summary) => exit_if_pending(&summary, options),
+                    Err(e) => {
+                        eprintln!("failed to remove default reviewers: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+            DefaultReviewersCommand::Copy(copy) => {
+                let dests = destinations(&copy.targets, &target).await;
+                match copy_reviewers(target(&copy.targets.src_repo), &dests, copy.prune, options)
+                    .await
+                {
+                    Ok(results) => print_summaries(&results, options),
+                    Err(e) => {
+                        eprintln!("failed to copy default reviewers: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+        },
+        Commands::DeployKeys { command } => match command {
+            DeployKeysCommand::List { repo } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                let keys = match list_deploy_keys(&target(&repo)).await {
+                    Ok(keys) => keys,
+                    Err(e) => {
+                        eprintln!("failed to list deploy keys: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let rendered = output::resources(
+                    &format!("Repository: {}", repo),
+                    deploy_keys::HEADER,
+                    &keys,
+                    DeployKey::row,
+                    args.output,
+                    terminal,
+                );
+                write_output(rendered, &args.output_file);
+            }
+            DeployKeysCommand::Add {
+                repo,
+                key,
+                key_file,
+                label,
+            } => {
+                let key = match (key, key_file) {
+                    (Some(key), _) => key,
+                    (None, Some(path)) => match std::fs::read_to_string(&path) {
+                        Ok(key) => key,
+                        Err(e) => {
+                            eprintln!("failed to read {}: {}", path.display(), e);
+                            exit(BbdanError::from(e).exit_code());
+                        }
+                    },
+                    (None, None) => unreachable!(),
+                };
+                match add_deploy_key(target(&repo), &key, &label, options).await {
+                    Ok(summary) => exit_if_pending(&summary, options),
+                    Err(e) => {
+                        eprintln!("failed to add the deploy key: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+            DeployKeysCommand::Remove { repo, ids, labels } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                let filter = DeployKeyFilter { ids, labels };
+                match remove_deploy_keys(target(&repo), &filter, options).await {
+                    Ok(summary) => exit_if_pending(&summary, options),
+                    Err(e) => {
+                        eprintln!("failed to remove deploy keys: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+            DeployKeysCommand::Copy(copy) => {
+                let dests = destinations(&copy.targets, &target).await;
+                match copy_deploy_keys(target(&copy.targets.src_repo), &dests, copy.prune, options)
+                    .await
+                {
+                    Ok(results) => print_summaries(&results, options),
+                    Err(e) => {
+                        eprintln!("failed to copy deploy keys: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+        },
+        Commands::Hooks { command } => match command {
+            HooksCommand::List { repo } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                let webhooks = match list_webhooks(&target(&repo)).await {
+                    Ok(webhooks) => webhooks,
+                    Err(e) => {
+                        eprintln!("failed to list webhooks: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let rendered = output::resources(
+                    &format!("Repository: {}", repo),
+                    webhooks::HEADER,
+                    &webhooks,
+                    Webhook::row,
+                    args.output,
+                    terminal,
+                );
+                write_output(rendered, &args.output_file);
+            }
+            HooksCommand::Add {
+                repo,
+                url,
+                events,
+                description,
+                secret,
+                inactive,
+            } => {
+                let webhook = Webhook {
+                    uuid: None,
+                    url,
+                    description,
+                    active: !inactive,
+                    events,
+                    skip_cert_verification: false,
+                    secret_set: secret.is_some(),
+                    secret,
+                };
+                match add_webhook(target(&repo), webhook, options).await {
+                    Ok(summary) => exit_if_pending(&summary, options),
+                    Err(e) => {
+                        eprintln!("failed to add the webhook: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+            HooksCommand::Remove { repo, uuids, urls } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                let filter = WebhookFilter { uuids, urls };
+                match remove_webhooks(target(&repo), &filter, options).await {
+                    Ok(summary) => exit_if_pending(&summary, options),
+                    Err(e) => {
+                        eprintln!("failed to remove webhooks: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+            HooksCommand::Copy(copy) => {
+                let dests = destinations(&copy.targets, &target).await;
+                match copy_webhooks(target(&copy.targets.src_repo), &dests, copy.prune, options)
+                    .await
+                {
+                    Ok(results) => print_summaries(&results, options),
+                    Err(e) => {
+                        eprintln!("failed to copy webhooks: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+        },
+        Commands::PipelinesVars { command } => match command {
+            PipelinesVarsCommand::List { repo } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                let variables = match list_variables(&target(&repo)).await {
+                    Ok(variables) => variables,
+                    Err(e) => {
+                        eprintln!("failed to list pipelines variables: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let rendered = output::resources(
+                    &format!("Repository: {}", repo),
+                    pipelines::VARIABLE_HEADER,
+                    &variables,
+                    Variable::row,
+                    args.output,
+                    terminal,
+                );
+                write_output(rendered, &args.output_file);
+            }
+            PipelinesVarsCommand::Set {
+                repo,
+                key,
+                value,
+                secured,
+            } => match set_variable(target(&repo), &key, &value, secured, options).await {
+                Ok(summary) => exit_if_pending(&summary, options),
+                Err(e) => {
+                    eprintln!("failed to set the pipelines variable: {}", e);
+                    exit(e.exit_code());
+                }
+            },
+            PipelinesVarsCommand::Copy(copy) => {
+                let dests = destinations(&copy.targets, &target).await;
+                match copy_variables(target(&copy.targets.src_repo), &dests, copy.prune, options)
+                    .await
+                {
+                    Ok(results) => print_summaries(&results, options),
+                    Err(e) => {
+                        eprintln!("failed to copy pipelines variables: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+        },
+        Commands::Pipelines { command } => match command {
+            PipelinesCommand::Show { repo } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                let config = match pipelines_config(&target(&repo)).await {
+                    Ok(config) => config,
+                    Err(e) => {
+                        eprintln!("failed to read the pipelines configuration: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let rendered = output::resources(
+                    &format!("Repository: {}", repo),
+                    pipelines::CONFIG_HEADER,
+                    &[config],
+                    PipelinesConfig::row,
+                    args.output,
+                    terminal,
+                );
+                write_output(rendered, &args.output_file);
+            }
+            PipelinesCommand::Enable { ref repo } | PipelinesCommand::Disable { ref repo } => {
+                let enabled = matches!(command, PipelinesCommand::Enable { .. });
+                match set_pipelines_enabled(&target(repo), enabled, options).await {
+                    Ok(summary) => exit_if_pending(&summary, options),
+                    Err(e) => {
+                        eprintln!("failed to configure pipelines: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+            PipelinesCommand::Copy(copy) => {
+                let dests = destinations(&copy, &target).await;
+                match copy_pipelines_config(target(&copy.src_repo), &dests, options).await {
+                    Ok(results) => print_summaries(&results, options),
+                    Err(e) => {
+                        eprintln!("failed to copy the pipelines configuration: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+        },
+        Commands::Settings { command } => match command {
+            SettingsCommand::Show { repo } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                let settings = match repository_settings(&target(&repo)).await {
+                    Ok(settings) => settings,
+                    Err(e) => {
+                        eprintln!("failed to read the settings: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let rendered = output::resources(
+                    &format!("Repository: {}", repo),
+                    settings::HEADER,
+                    &settings.rows(),
+                    SettingValue::row,
+                    args.output,
+                    terminal,
+                );
+                write_output(rendered, &args.output_file);
+            }
+            SettingsCommand::Copy { targets, only } => {
+                let dests = destinations(&targets, &target).await;
+                match copy_settings(target(&targets.src_repo), &dests, &only, options).await {
+                    Ok(results) => print_summaries(&results, options),
+                    Err(e) => {
+                        eprintln!("failed to copy the settings: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+        },
+        Commands::BranchingModel { command } => match command {
+            BranchingModelCommand::Show { repo } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                let model = match branching_model(&target(&repo)).await {
+                    Ok(model) => model,
+                    Err(e) => {
+                        eprintln!("failed to read the branching model: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let rendered = output::resources(
+                    &format!("Repository: {}", repo),
+                    branching_model::HEADER,
+                    &model.rows(),
+                    ModelRow::row,
+                    args.output,
+                    terminal,
+                );
+                write_output(rendered, &args.output_file);
+            }
+            BranchingModelCommand::Copy(copy) => {
+                let dests = destinations(&copy, &target).await;
+                match copy_branching_model(target(&copy.src_repo), &dests, options).await {
+                    Ok(results) => print_summaries(&results, options),
+                    Err(e) => {
+                        eprintln!("failed to copy the branching model: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+        },
+        Commands::Environments { command } => match command {
+            EnvironmentsCommand::List { repo } => {
+                let repo = repo_or_pick(repo, &target(""), options).await;
+                let environments = match list_environments(&target(&repo)).await {
+                    Ok(environments) => environments,
+                    Err(e) => {
+                        eprintln!("failed to list environments: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let rendered = output::resources(
+                    &format!("Repository: {}", repo),
+                    environments::HEADER,
+                    &environments,
+                    Environment::row,
+                    args.output,
+                    terminal,
+                );
+                write_output(rendered, &args.output_file);
+            }
+            EnvironmentsCommand::Copy(copy) => {
+                let dests = destinations(&copy.targets, &target).await;
+                match copy_environments(target(&copy.targets.src_repo), &dests, copy.prune, options)
+                    .await
+                {
+                    Ok(results) => print_summaries(&results, options),
+                    Err(e) => {
+                        eprintln!("failed to copy environments: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+            EnvironmentsCommand::Vars {
+                command: EnvironmentVarsCommand::List { repo, environment },
+            } => {
+                let variables = match list_deployment_variables(&target(&repo), &environment).await
+                {
+                    Ok(variables) => variables,
+                    Err(e) => {
+                        eprintln!("failed to list deployment variables: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let rendered = output::resources(
+                    &format!("Repository: {}, environment: {}", repo, environment),
+                    pipelines::VARIABLE_HEADER,
+                    &variables,
+                    Variable::row,
+                    args.output,
+                    terminal,
+                );
+                write_output(rendered, &args.output_file);
+            }
+            EnvironmentsCommand::Vars {
+                command:
+                    EnvironmentVarsCommand::Set {
+                        repo,
+                        environment,
+                        key,
+                        value,
+                        secured,
+                    },
+            } => {
+                let bitbucket = target(&repo);
+                match set_deployment_variable(
+                    bitbucket,
+                    &environment,
+                    &key,
+                    &value,
+                    secured,
+                    options,
+                )
+                .await
+                {
+                    Ok(summary) => exit_if_pending(&summary, options),
+                    Err(e) => {
+                        eprintln!("failed to set the deployment variable: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+        },
+        Commands::Create {
+            new_repo,
+            like,
+            project,
+        } => match create_like(target(&like), target(&new_repo), project, options).await {
+            Ok(results) => print_summaries(&results, options),
+            Err(e) => {
+                eprintln!("failed to create {}: {}", new_repo, e);
+                exit(e.exit_code());
+            }
+        },
+        Commands::Repo { command } => match command {
+            RepoCommand::Delete { repo } => {
+                if let Err(e) = delete_repository(&target(&repo), options).await {
+                    eprintln!("failed to delete {}: {}", repo, e);
+                    exit(e.exit_code());
+                }
+                if options.dry_run {
+                    exit(EXIT_DRIFT);
+                }
+            }
+            RepoCommand::Archive { repo } => {
+                match archive_repository(&target(&repo), options).await {
+                    Ok(summary) => exit_if_pending(&summary, options),
+                    Err(e) => {
+                        eprintln!("failed to archive {}: {}", repo, e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+        },
+        Commands::CloneSetup {
+            src_repo,
+            dest_repo,
+            skip,
+            filter,
+        } => {
+            let (src, dest) = (target(&src_repo), target(&dest_repo));
+            match clone_setup(&src, &dest, &skip, &filter, options).await {
+                Ok(results) => print_summaries(&results, options),
+                Err(e) => {
+                    eprintln!("failed to clone the setup: {}", e);
+                    exit(e.exit_code());
+                }
+            }
+        }
+        Commands::WorkspacePermissions { role } => {
+            let permissions = match list_workspace_permissions(&target(""), role).await {
+                Ok(permissions) => permissions,
+                Err(e) => {
+                    eprintln!("failed to list workspace permissions: {}", e);
+                    exit(e.exit_code());
+                }
+            };
+            let rendered = output::resources(
+                &format!("Workspace: {}", workspace),
+                workspace::HEADER,
+                &permissions,
+                WorkspacePermission::row,
+                args.output,
+                terminal,
+            );
+            write_output(rendered, &args.output_file);
+        }
+        Commands::Groups { command } => match command {
+            GroupsCommand::List => {
+                let groups = match list_groups(&target("")).await {
+                    Ok(groups) => groups,
+                    Err(e) => {
+                        eprintln!("failed to list groups: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let rendered = output::resources(
+                    &format!("Workspace: {}", workspace),
+                    groups::HEADER,
+                    &groups,
+                    Group::row,
+                    args.output,
+                    terminal,
+                );
+                write_output(rendered, &args.output_file);
+            }
+            GroupsCommand::Show { group } => {
+                let group = match find_group(&target(""), &group).await {
+                    Ok(group) => group,
+                    Err(e) => {
+                        eprintln!("failed to show the group: {}", e);
+                        exit(e.exit_code());
+                    }
+                };
+                let rendered = output::resources(
+                    &format!("Group: {} ({})", group.slug, group.name),
+                    groups::MEMBER_HEADER,
+                    &group.members,
+                    GroupMember::row,
+                    args.output,
+                    terminal,
+                );
+                write_output(rendered, &args.output_file);
+            }
+            GroupsCommand::AddMember { group, users } => {
+                match add_members(&target(""), &group, &users, options).await {
+                    Ok(summary) => exit_if_pending(&summary, options),
+                    Err(e) => {
+                        eprintln!("failed to add members: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+            GroupsCommand::RemoveMember { group, users } => {
+                match remove_members(&target(""), &group, &users, options).await {
+                    Ok(summary) => exit_if_pending(&summary, options),
+                    Err(e) => {
+                        eprintln!("failed to remove members: {}", e);
+                        exit(e.exit_code());
+                    }
+                }
+            }
+        },
+        Commands::Offboard { user, group } => {
+            let bitbucket = target("");
+            let principal = match (user, group) {
+                (Some(user), _) => (ObjectType::User, user),
+                (None, Some(group)) => (ObjectType::Group, group),
+                (None, None) => unreachable!(),
+            };
+
+            let outcomes = match offboard(bitbucket, principal, options).await {
+                Ok(outcomes) => outcomes,
+                Err(e) => {
+                    eprintln!("failed to offboard: {}", e);
+                    exit(e.exit_code());
+                }
+            };
+            println!("Summary:");
+            if outcomes.is_empty() {
+                println!("  no grants found");
+            }
+            for (repo, outcome) in &outcomes {
+                match outcome {
+                    Outcome::Removed(_) if options.dry_run => {
+                        println!("  {}: {} (dry-run)", repo, outcome)
+                    }
+                    _ => println!("  {}: {}", repo, outcome),
+                }
+            }
+            if let Some(code) = failures_exit_code(outcomes.iter().filter_map(|(_, o)| o.error())) {
+                exit(code);
+            }
+            if options.dry_run
+                && outcomes
+                    .iter()
+                    .any(|(_, o)| matches!(o, Outcome::Removed(_)))
+            {
+                exit(EXIT_DRIFT);
+            }
+        }
+        Commands::Verify { repo } => {
+            let bitbucket = target(repo.as_deref().unwrap_or_default());
+
+            match verify(bitbucket.clone(), repo.is_some()).await {
+                Ok(report) => {
+                    print_verify_report(&report, &bitbucket);
+                    if !report.ok() {
+                        exit(EXIT_AUTH);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("failed to verify: {}", e);
+                    exit(e.exit_code());
+                }
+            }
+        }
+        Commands::Tui => {
+            let bitbucket = target("");
+
+            if let Err(e) = tui::run(bitbucket, options).await {
+                eprintln!("failed to run the TUI: {}", e);
+                exit(e.exit_code());
+            }
+        }
+        Commands::Login | Commands::Completions { .. } | Commands::CompleteRepos => unreachable!(),
+    }
+}
+
+/// Print what verify found out, with what to do about anything missing
+fn print_verify_report(report: &VerifyReport, bitbucket: &Bitbucket) {
+    match report.credentials {
+        Credentials::Invalid => {
+            println!("Credentials are invalid. Check the username and app password or token.");
+            return;
+        }
+        Credentials::NoWorkspaceAccess => {
+            println!(
+                "Credentials are valid but workspace {} is not accessible.",
+                bitbucket.workspace
+            );
+            return;
+        }
+        Credentials::Valid => println!("Credentials are valid."),
+    }
+
+    match &report.granted {
+        Some(granted) => {
+            println!("Scopes: {}", granted.join(", "));
+            for (command, missing) in &report.commands {
+                if missing.is_empty() {
+                    println!("  {}: OK", command);
+                } else {
+                    println!("  {}: missing {}", command, missing.join(", "));
+                }
+            }
+            if report
+                .commands
+                .iter()
+                .any(|(_, missing)| !missing.is_empty())
+            {
+                println!("Grant the missing scopes to the app password in Personal settings > App passwords, or create a new token with them.");
+            }
+        }
+        None => println!("Could not determine the granted scopes."),
+    }
+
+    match report.repository {
+        Some(status) if status.is_success() => {
+            println!("Repository {}: admin access OK", bitbucket.slug)
+        }
+        Some(status) => println!(
+            "Repository {}: cannot read permissions ({}). Admin access to the repository is required.",
+            bitbucket.slug, status
+        ),
+        None => {}
+    }
+}
+
+/// Repository slugs of the workspace for the completion scripts, never prompting for a password
+async fn complete_repos(
+    args: &Args,
+    config: &Config,
+    http_client: &reqwest::Client,
+    base_url: &str,
+    workspace: &str,
+) -> Result<Vec<String>, BbdanError> {
+    let bitbucket = Bitbucket {
+        http_client: http_client.clone(),
+        base_url: base_url.to_string(),
+        auth: resolve_auth(args, config, http_client, base_url, false).await?,
+        workspace: workspace.to_string(),
+        slug: String::new(),
+        pagelen: args.pagelen,
+        parallel: args.parallel as usize,
+        http_log: None,
+        history: None,
+    };
+    let (repositories, _) = list_repositories(&bitbucket, &Paging::default()).await?;
+    Ok(repositories.into_iter().map(|r| r.slug).collect())
+}
+
+/// Repositories selected by --project and --match, read from --repos-from, or from stdin when the repo argument is `-`.
+/// None for a single repository.
+async fn many_repos(
+    repo: Option<&str>,
+    repos_from: Option<&Path>,
+    selector: &Selector,
+    bitbucket: &Bitbucket,
+) -> Option<Vec<String>> {
+    if !selector.is_empty() {
+        if repo.is_some() || repos_from.is_some() {
+            usage_error(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                "--project and --match cannot be used with a repository argument or --repos-from",
+            ));
+        }
+        return match selector.select(bitbucket).await {
+            Ok(slugs) => Some(slugs),
+            Err(e) => {
+                eprintln!("failed to select repositories: {}", e);
+                exit(e.exit_code());
+            }
+        };
+    }
+    let path = match (repo, repos_from) {
+        (_, Some(path)) => path,
+        (Some("-"), None) => Path::new("-"),
+        _ => return None,
+    };
+    Some(read_repos(path))
+}
+
+/// The repositories to copy a setting to
+async fn destinations(copy: &CopyTargets, target: impl Fn(&str) -> Bitbucket) -> Vec<Bitbucket> {
+    let first = copy.dest_repos.first().map(String::as_str);
+    let dests = many_repos(
+        first,
+        copy.repos_from.as_deref(),
+        &copy.selector,
+        &target(""),
+    )
+    .await
+    .unwrap_or_else(|| copy.dest_repos.clone());
+    dests.iter().map(|d| target(d)).collect()
+}
+
+/// Repository slugs in the file or stdin, or exit if it cannot be read
+fn read_repos(path: &Path) -> Vec<String> {
+    match read_slugs(path) {
+        Ok(slugs) => slugs,
+        Err(e) => {
+            eprintln!("failed to read repositories from {}: {}", path.display(), e);
+            exit(e.exit_code());
+        }
+    }
+}
+
+/// The repository given on the command line, or the one picked from the workspace
+async fn repo_or_pick(repo: Option<String>, bitbucket: &Bitbucket, options: Options) -> String {
+    match repo {
+        Some(repo) => repo,
+        None => match pick_repository(bitbucket, options).await {
+            Ok(repo) => repo,
+            Err(e) => {
+                eprintln!("failed to pick a repository: {}", e);
+                exit(e.exit_code());
+            }
+        },
+    }
+}
+
+/// Exit when some repositories could not be listed, with EXIT_AUTH when the credentials were refused
+fn exit_if_unlisted(results: &[AuditResult]) {
+    let errors = results
+        .iter()
+        .filter_map(|(_, result)| result.as_ref().err());
+    if let Some(code) = failures_exit_code(errors.map(|e| e.as_ref())) {
+        exit(code);
+    }
+}
+
+/// Record the permissions listed from the repositories in the history, when it is enabled
+async fn record_observed(history: Option<&Recorder>, workspace: &str, results: &[AuditResult]) {
+    let Some(history) = history else {
+        return;
+    };
+    for (repo, result) in results {
+        if let Ok(permissions) = result {
+            history.observed(workspace, repo, permissions).await;
+        }
+    }
+}
+
+/// Print the summary of each repository, exiting with EXIT_PARTIAL when some failed,
+/// or EXIT_AUTH when the credentials were refused
+/// and with EXIT_DRIFT when a dry run has changes pending
+fn print_summaries(results: &[(String, Result<Summary, Arc<BbdanError>>)], options: Options) {
+    println!("Summary:");
+    for (repo, result) in results {
+        match result {
+            Ok(summary) => println!("  {}: {}", repo, summary),
+            Err(e) => println!("  {}: failed: {}", repo, e),
+        }
+    }
+    let errors = results
+        .iter()
+        .filter_map(|(_, result)| result.as_ref().err());
+    if let Some(code) = failures_exit_code(errors.map(|e| e.as_ref())) {
+        exit(code);
+    }
+    if options.dry_run
+        && results
+            .iter()
+            .any(|(_, r)| r.as_ref().is_ok_and(Summary::has_changes))
+    {
+        exit(EXIT_DRIFT);
+    }
+}
+
+/// The members of the groups of the workspace when group grants are expanded, listed once for the run
+async fn group_members(expand: bool, bitbucket: &Bitbucket) -> Option<GroupMembers> {
+    if !expand {
+        return None;
+    }
+    match GroupMembers::load(bitbucket).await {
+        Ok(members) => Some(members),
+        Err(e) => {
+            eprintln!("failed to list the members of the groups: {}", e);
+            exit(e.exit_code());
+        }
+    }
+}
+
+/// Exit with EXIT_DRIFT when a dry run has changes pending
+fn exit_if_pending(summary: &Summary, options: Options) {
+    if options.dry_run && summary.has_changes() {
+        exit(EXIT_DRIFT);
+    }
+}
+
+/// Print a usage error and exit with EXIT_USAGE. --help and --version exit successfully.
+fn usage_error(e: clap::Error) -> ! {
+    if !e.use_stderr() {
+        e.exit();
+    }
+    let _ = e.print();
+    exit(EXIT_USAGE);
+}
+
+/// Notifier of --notify, exiting when no channel is configured
+fn notifier(notify: bool, config: &Config, http_client: &reqwest::Client) -> Option<Notifier> {
+    if !notify {
+        return None;
+    }
+    if config.notify.is_empty() {
+        eprintln!(
+            "--notify needs a slack or teams webhook URL in the [notify] table of the config file"
+        );
+        exit(EXIT_USAGE);
+    }
+    Some(Notifier {
+        http_client: http_client.clone(),
+        config: config.notify.clone(),
+    })
+}
+
+/// Post the notification, exiting when a channel failed
+async fn send(notifier: &Notifier, notification: Notification) {
+    if let Err(e) = notifier.send(&notification).await {
+        eprintln!("failed to notify: {}", e);
+        exit(e.exit_code());
+    }
+}
+
+/// Mail server of --email-to, exiting when the config has none
+fn smtp(email_to: &[String], config: &Config) -> Option<SmtpConfig> {
+    if email_to.is_empty() {
+        return None;
+    }
+    if !config.smtp.is_complete() {
+        eprintln!("--email-to needs host and from in the [smtp] table of the config file");
+        exit(EXIT_USAGE);
+    }
+    Some(config.smtp.clone())
+}
+
+/// The rendered output as an attachment named after `name` and --output
+fn output_attachment(name: &str, content: String, output: Output, template: bool) -> Attachment {
+    // a template can render anything
+    let (extension, content_type) = if template {
+        ("txt", "text/plain")
+    } else {
+        output.file_type()
+    };
+    Attachment {
+        filename: format!("{}.{}", name, extension),
+        content_type: content_type.to_string(),
+        content,
+    }
+}
+
+/// Mail the report, exiting when it cannot be sent
+async fn email_report(
+    smtp: &SmtpConfig,
+    to: &[String],
+    subject: String,
+    attachments: Vec<Attachment>,
+) {
+    let email = Email {
+        to: to.to_vec(),
+        body: format!("{}, generated by bbdan.\n", subject),
+        subject,
+        attachments,
+    };
+    if let Err(e) = email::send(smtp, &email).await {
+        eprintln!("{}", e);
+        exit(e.exit_code());
+    }
+}
+
+/// The journal file, exiting when there is no default
+fn journal_path(config: &Config) -> PathBuf {
+    match config.journal.path() {
+        Some(path) => path,
+        None => {
+            eprintln!("no user data directory to keep the journal in, set path in the [journal] table of the config file");
+            exit(EXIT_USAGE);
+        }
+    }
+}
+
+/// The source repository in its own workspace, when given
+fn source(bitbucket: Bitbucket, workspace: Option<String>) -> Bitbucket {
+    match workspace {
+        Some(workspace) => Bitbucket {
+            workspace,
+            ..bitbucket
+        },
+        None => bitbucket,
+    }
+}
+
+/// Mapping of the file, or none, exiting when it cannot be loaded
+fn mapping(path: Option<PathBuf>) -> Mapping {
+    let Some(path) = path else {
+        return Mapping::default();
+    };
+    match Mapping::load(&path) {
+        Ok(mapping) => mapping,
+        Err(e) => {
+            eprintln!("failed to load {}: {}", path.display(), e);
+            exit(e.exit_code());
+        }
+    }
+}
+
+/// Directory of the snapshots, exiting when there is no default
+fn snapshots(dir: Option<PathBuf>, workspace: &str) -> PathBuf {
+    match dir.or_else(|| snapshot_dir(workspace)) {
+        Some(dir) => dir,
+        None => {
+            eprintln!("no user data directory to keep snapshots in, use --dir");
+            exit(EXIT_USAGE);
+        }
+    }
+}
+
+/// Baseline of watch and serve-hooks, exiting when it cannot be read
+fn load_baseline(path: &str, format: Option<DocumentFormat>) -> SyncDocument {
+    let format = format.unwrap_or_else(|| DocumentFormat::from_path(path));
+    match std::fs::read_to_string(path)
+        .map_err(BbdanError::from)
+        .and_then(|content| SyncDocument::parse(&content, format))
+    {
+        Ok(document) => document,
+        Err(e) => {
+            eprintln!("failed to load {}: {}", path, e);
+            exit(e.exit_code());
+        }
+    }
+}
+
+/// Write the rendered output of a read command, or exit if it cannot be rendered or written
+fn write_output(rendered: Result<String, BbdanError>, file: &Path) {
+    match rendered.and_then(|out| output::write(file, &out)) {
+        Ok(()) => {}
+        // the reader, e.g. `head`, has seen enough
+        Err(BbdanError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => exit(0),
+        Err(e) => {
+            eprintln!("failed to write the output: {}", e);
+            exit(e.exit_code());
+        }
+    }
+}
+
+/// Log operational messages to stderr so stdout only carries the command output
+fn init_logging(verbose: u8, quiet: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => Level::WARN,
+        (false, 0) => Level::INFO,
+        (false, 1) => Level::DEBUG,
+        (false, _) => Level::TRACE,
+    };
+    // messages of the HTTP libraries only with -vv
+    let filter = Targets::new()
+        .with_target("bbdan", level)
+        .with_target(telemetry::SPAN_TARGET, LevelFilter::OFF)
+        .with_default(if verbose >= 2 {
+            Level::TRACE
+        } else {
+            Level::WARN
+        });
+    // the spans, and the messages of bbdan within them, when exporting traces
+    let otel = match telemetry::layer() {
+        Ok(layer) => layer.map(|layer| {
+            layer.with_filter(
+                Targets::new()
+                    .with_target("bbdan", level)
+                    .with_target(telemetry::SPAN_TARGET, Level::TRACE),
+            )
+        }),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(e.exit_code());
+        }
+    };
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(|| progress::Stderr)
+                .with_ansi(console::colors_enabled_stderr())
+                .without_time()
+                .with_target(false)
+                .with_filter(filter),
+        )
+        .with(otel)
+        .init();
+}
+
+/// Exit with the code once the traces are sent
+fn exit(code: i32) -> ! {
+    telemetry::shutdown();
+    std::process::exit(code)
+}
+
+/// Decide how to authenticate from the command line arguments.
+/// An access token takes precedence, then OAuth when a client id is given,
+/// otherwise username and app password are required.
+/// The app password and the OAuth authorization code are prompted for only when `prompt` is set.
+async fn resolve_auth(
+    args: &Args,
+    config: &Config,
+    http_client: &reqwest::Client,
+    base_url: &str,
+    prompt: bool,
+) -> Result<Auth, BbdanError> {
+    if let Some(token) = &args.token {
+        return Ok(Auth::Bearer {
+            token: token.to_string(),
+        });
+    }
+
+    if let Some(client_id) = &args.client_id {
+        let client_secret = match &args.client_secret {
+            Some(s) => s.to_string(),
+            None => usage_error(Args::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "--client-secret is required with --client-id",
+            )),
+        };
+        let oauth = OAuthClient::new(
+            http_client.clone(),
+            client_id.to_string(),
+            client_secret,
+            args.oauth_authorize_url.to_string(),
+            args.oauth_token_url.to_string(),
+        );
+        let session = OAuthSession::start(oauth, args.oauth_flow, |url| {
+            if !prompt {
+                return Err(BbdanError::Auth(
+                    "no cached OAuth token, authorize bbdan first".to_string(),
+                ));
+            }
+            eprintln!(
+                "Open the following URL in your browser and authorize bbdan:\n{}",
+                url
+            );
+            Ok(Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Authorization code")
+                .interact_text()?)
+        })
+        .await?;
+        return Ok(Auth::OAuth(Arc::new(session)));
+    }
+
+    let username = args.username.as_ref().or(config.username.as_ref());
+    match (username, &args.password) {
+        (Some(username), Some(password)) => Ok(Auth::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        }),
+        (Some(username), None) => {
+            if let Some(helper) = &config.credential_helper {
+                return Ok(Auth::Basic {
+                    username: username.to_string(),
+                    password: run_credential_helper(helper)?,
+                });
+            }
+            match keyring_entry(username).and_then(|e| e.get_password()) {
+                Ok(password) => Ok(Auth::Basic {
+                    username: username.to_string(),
+                    password,
+                }),
+                Err(_) if !prompt => Err(BbdanError::Auth(format!(
+                    "no app password stored for {}",
+                    username
+                ))),
+                Err(_) => Ok(Auth::Basic {
+                    username: username.to_string(),
+                    password: Password::with_theme(&ColorfulTheme::default())
+                        .with_prompt(format!("App password for {}", username))
+                        .interact()?,
+                }),
+            }
+        }
+        (None, Some(_)) => usage_error(Args::command().error(
+            ErrorKind::MissingRequiredArgument,
+            "--username is required with --password",
+        )),
+        (None, None) => {
+            let host = reqwest::Url::parse(base_url)
+                .map_err(|e| BbdanError::Invalid(format!("invalid base URL {}: {}", base_url, e)))?
+                .host_str()
+                .unwrap_or_default()
+                .to_string();
+            match netrc_credentials(&host) {
+                Some((username, password)) => Ok(Auth::Basic { username, password }),
+                None => usage_error(Args::command()
+                    .error(
+                        ErrorKind::MissingRequiredArgument,
+                        format!(
+                            "--username and --password are required unless --token or --client-id is given or {} is in ~/.netrc",
+                            host
+                        ),
+                    )),
+            }
+        }
+    }
 }
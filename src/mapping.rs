@@ -0,0 +1,63 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::client::Bitbucket;
+use crate::document::DocumentFormat;
+use crate::error::BbdanError;
+use crate::permissions::{resolve_user, ObjectType, Permission};
+
+/// Groups and users of one workspace and who they are in another, to copy grants across workspaces
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Mapping {
+    /// Slug or name in the source workspace to slug in the destination
+    pub groups: BTreeMap<String, String>,
+    /// UUID or nickname in the source workspace to UUID or nickname in the destination
+    pub users: BTreeMap<String, String>,
+}
+
+impl Mapping {
+    /// Load a JSON or YAML mapping file, by its extension
+    pub fn load(path: &Path) -> Result<Self, BbdanError> {
+        let content = fs::read_to_string(path)?;
+        match DocumentFormat::from_path(&path.to_string_lossy()) {
+            DocumentFormat::Yaml => Ok(serde_yaml::from_str(&content)?),
+            _ => Ok(serde_json::from_str(&content)?),
+        }
+    }
+
+    /// The grants as they are given in the workspace of dest. Users mapped by nickname are resolved there,
+    /// and the groups and users that are not mapped are kept as they are.
+    pub async fn translate(
+        &self,
+        dest: &Bitbucket,
+        permissions: Vec<Permission>,
+    ) -> Result<Vec<Permission>, BbdanError> {
+        let mut translated = Vec::new();
+        for p in permissions {
+            let names = match p.object_type {
+                ObjectType::User => &self.users,
+                ObjectType::Group => &self.groups,
+            };
+            let Some(name) = names.get(&p.id).or_else(|| names.get(&p.alias)) else {
+                translated.push(p);
+                continue;
+            };
+            let (id, alias) = match p.object_type {
+                ObjectType::User => resolve_user(dest, name).await?,
+                ObjectType::Group => (name.to_string(), name.to_string()),
+            };
+            tracing::debug!(
+                "Map: id={}, name={} to id={}, name={}",
+                p.id,
+                p.alias,
+                id,
+                alias
+            );
+            translated.push(Permission { id, alias, ..p });
+        }
+        Ok(translated)
+    }
+}
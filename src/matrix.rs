@@ -0,0 +1,109 @@
+use clap::ArgEnum;
+use std::collections::BTreeSet;
+
+use crate::error::BbdanError;
+use crate::output::csv;
+use crate::permissions::{object_type_to_str, permission_type_to_str, ObjectType, PermissionType};
+use crate::repositories::AuditResult;
+
+#[derive(Debug, Clone, Copy, ArgEnum)]
+pub enum MatrixFormat {
+    Csv,
+    /// A self-contained page with a sortable table
+    Html,
+}
+
+impl MatrixFormat {
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".html") || path.ends_with(".htm") {
+            MatrixFormat::Html
+        } else {
+            MatrixFormat::Csv
+        }
+    }
+}
+
+/// A user or group holding a grant somewhere in the workspace
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Principal {
+    pub object_type: ObjectType,
+    pub id: String,
+    pub alias: String,
+}
+
+impl Principal {
+    /// Column header, e.g. `group:devs` or `user:alice`. Groups go by slug and users by nickname.
+    pub fn label(&self) -> String {
+        let name = match self.object_type {
+            ObjectType::User if !self.alias.is_empty() => &self.alias,
+            _ => &self.id,
+        };
+        format!("{}:{}", object_type_to_str(self.object_type), name)
+    }
+}
+
+/// Repositories × principals grid of permission levels. Repositories that failed are left out.
+#[derive(Debug, Clone)]
+pub struct Matrix {
+    /// Groups first, then users
+    pub principals: Vec<Principal>,
+    /// The level of each principal on the repository, in the order of `principals`
+    pub rows: Vec<(String, Vec<Option<PermissionType>>)>,
+}
+
+impl Matrix {
+    pub fn new(results: &[AuditResult]) -> Self {
+        let principals: BTreeSet<Principal> = results
+            .iter()
+            .flat_map(|(_, result)| result.iter().flatten())
+            .map(|p| Principal {
+                object_type: p.object_type,
+                id: p.id.to_string(),
+                alias: p.alias.to_string(),
+            })
+            .collect();
+        // a principal is the same whatever alias a repository reports
+        let mut principals: Vec<Principal> = principals.into_iter().collect();
+        principals.dedup_by(|a, b| a.object_type == b.object_type && a.id == b.id);
+        principals.sort_by_key(|p| (p.object_type != ObjectType::Group, p.label()));
+
+        let rows = results
+            .iter()
+            .filter_map(|(repo, result)| {
+                let permissions = result.as_ref().ok()?;
+                let levels = principals
+                    .iter()
+                    .map(|principal| {
+                        permissions
+                            .iter()
+                            .find(|p| {
+                                p.object_type == principal.object_type && p.id == principal.id
+                            })
+                            .map(|p| p.permission)
+                    })
+                    .collect();
+                Some((repo.to_string(), levels))
+            })
+            .collect();
+        Matrix { principals, rows }
+    }
+
+    /// A row per repository and a column per principal, empty where there is no grant
+    pub fn csv(&self) -> Result<String, BbdanError> {
+        let labels: Vec<String> = self.principals.iter().map(Principal::label).collect();
+        let mut header = vec!["repository"];
+        header.extend(labels.iter().map(String::as_str));
+        csv(
+            &header,
+            self.rows.iter().map(|(repo, levels)| {
+                let mut row = vec![repo.to_string()];
+                row.extend(
+                    levels
+                        .iter()
+                        .map(|l| l.map(permission_type_to_str).unwrap_or_default()),
+                );
+                row
+            }),
+        )
+    }
+}
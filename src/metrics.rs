@@ -0,0 +1,161 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{Encoder, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+
+use crate::error::BbdanError;
+use crate::permissions::{permission_type_to_str, Permission};
+use crate::reconcile::Change;
+use crate::watch::Drift;
+
+/// Path the metrics are served on
+pub const METRICS_PATH: &str = "/metrics";
+
+struct Metrics {
+    registry: Registry,
+    api_requests: IntCounterVec,
+    rate_limited: IntCounter,
+    drift_changes: IntCounterVec,
+    permissions: IntGaugeVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let registry =
+        Registry::new_custom(Some("bbdan".to_string()), None).expect("the metric prefix is valid");
+    let api_requests = IntCounterVec::new(
+        Opts::new("api_requests_total", "Requests sent to the Bitbucket API"),
+        &["method", "status"],
+    )
+    .expect("the metric is valid");
+    let rate_limited = IntCounter::new(
+        "rate_limited_total",
+        "Requests the Bitbucket API answered with 429 Too Many Requests",
+    )
+    .expect("the metric is valid");
+    let drift_changes = IntCounterVec::new(
+        Opts::new(
+            "drift_changes_total",
+            "Grants found changed since the baseline, by the change",
+        ),
+        &["change"],
+    )
+    .expect("the metric is valid");
+    let permissions = IntGaugeVec::new(
+        Opts::new(
+            "permissions",
+            "Grants of each repository at each level, as of its last check",
+        ),
+        &["repository", "permission"],
+    )
+    .expect("the metric is valid");
+    for collector in [
+        Box::new(api_requests.clone()) as Box<dyn prometheus::core::Collector>,
+        Box::new(rate_limited.clone()),
+        Box::new(drift_changes.clone()),
+        Box::new(permissions.clone()),
+    ] {
+        registry
+            .register(collector)
+            .expect("the metric is registered once");
+    }
+    Metrics {
+        registry,
+        api_requests,
+        rate_limited,
+        drift_changes,
+        permissions,
+    }
+});
+
+/// Count a response of the API
+pub fn record_request(method: &str, status: u16) {
+    METRICS
+        .api_requests
+        .with_label_values(&[method, &status.to_string()])
+        .inc();
+}
+
+/// Count a rate limited response of the API
+pub fn record_rate_limit() {
+    METRICS.rate_limited.inc();
+}
+
+/// Count the changes of the drift
+pub fn record_drift(drift: &Drift) {
+    for change in drift.values().flatten().flatten() {
+        let label = match change {
+            Change::Add(_) => "add",
+            Change::Remove(_) => "remove",
+            Change::Update { .. } => "update",
+            Change::Unchanged(_) => continue,
+        };
+        METRICS.drift_changes.with_label_values(&[label]).inc();
+    }
+}
+
+/// Set the number of grants per level of a repository that was just listed
+pub fn record_permissions(repository: &str, permissions: &[Permission]) {
+    let mut levels: BTreeMap<String, i64> = BTreeMap::new();
+    for p in permissions {
+        *levels
+            .entry(permission_type_to_str(p.permission))
+            .or_default() += 1;
+    }
+    for level in ["read", "write", "admin"] {
+        METRICS
+            .permissions
+            .with_label_values(&[repository, level])
+            .set(levels.get(level).copied().unwrap_or_default());
+    }
+}
+
+/// The metrics in the Prometheus text format
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&METRICS.registry.gather(), &mut buffer)
+        .expect("the text format is written to memory");
+    String::from_utf8(buffer).expect("the text format is UTF-8")
+}
+
+/// The response to a scrape of METRICS_PATH
+pub fn response() -> Response<Body> {
+    let mut resp = Response::new(Body::from(render()));
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    resp
+}
+
+/// Serve the metrics on `addr` until interrupted
+pub async fn serve(addr: SocketAddr) -> Result<(), BbdanError> {
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+            if req.method() == Method::GET && req.uri().path() == METRICS_PATH {
+                return Ok::<_, Infallible>(response());
+            }
+            let mut resp = Response::new(Body::from(format!("GET {}\n", METRICS_PATH)));
+            *resp.status_mut() = StatusCode::NOT_FOUND;
+            Ok(resp)
+        }))
+    });
+
+    let server = Server::try_bind(&addr)
+        .map_err(|e| BbdanError::Invalid(format!("failed to listen on {}: {}", addr, e)))?
+        .serve(make_service);
+    tracing::info!(
+        "Serving metrics on http://{}{}",
+        server.local_addr(),
+        METRICS_PATH
+    );
+    server
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+        .map_err(|e| BbdanError::Invalid(format!("metrics server failed: {}", e)))
+}
@@ -0,0 +1,159 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+use crate::error::BbdanError;
+use crate::permissions::{permission_type_to_str, PermissionType};
+use crate::policy::Violation;
+use crate::repositories::AuditResult;
+use crate::watch::{describe, Drift};
+
+/// Lines of a message at most, the rest is counted
+const MAX_LINES: usize = 20;
+
+/// Incoming webhook URLs of the channels to notify, the `[notify]` table of the config file
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct NotifyConfig {
+    /// Slack incoming webhook
+    pub slack: Option<String>,
+    /// Microsoft Teams incoming webhook
+    pub teams: Option<String>,
+}
+
+impl NotifyConfig {
+    pub fn is_empty(&self) -> bool {
+        self.slack.is_none() && self.teams.is_none()
+    }
+}
+
+/// A summary posted to the channels
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub title: String,
+    pub lines: Vec<String>,
+}
+
+impl Notification {
+    /// Grants changed since the baseline, as watch finds them
+    pub fn drift(workspace: &str, drift: &Drift) -> Self {
+        let lines = drift
+            .iter()
+            .flat_map(|(repo, changes)| match changes {
+                Ok(changes) => changes
+                    .iter()
+                    .map(|c| format!("{}: {}", repo, describe(c)))
+                    .collect(),
+                Err(e) => vec![format!("{}: failed to list: {}", repo, e)],
+            })
+            .collect();
+        Self {
+            title: format!(
+                "Permissions of {} drifted from the baseline in {} repositories",
+                workspace,
+                drift.len()
+            ),
+            lines,
+        }
+    }
+
+    /// Number of grants per level and the repositories that failed
+    pub fn audit(workspace: &str, results: &[AuditResult]) -> Self {
+        let mut levels: BTreeMap<PermissionType, usize> = BTreeMap::new();
+        for p in results
+            .iter()
+            .flat_map(|(_, result)| result.iter().flatten())
+        {
+            *levels.entry(p.permission).or_default() += 1;
+        }
+        let mut lines: Vec<String> = levels
+            .iter()
+            .rev()
+            .map(|(level, count)| format!("{}: {} grants", permission_type_to_str(*level), count))
+            .collect();
+        lines.extend(results.iter().filter_map(|(repo, result)| {
+            let e = result.as_ref().err()?;
+            Some(format!("{}: failed to list: {}", repo, e))
+        }));
+        Self {
+            title: format!("Audit of {}: {} repositories", workspace, results.len()),
+            lines,
+        }
+    }
+
+    /// Policy violations, as check finds them
+    pub fn check(workspace: &str, violations: &[Violation]) -> Self {
+        let title = match violations.len() {
+            0 => format!("Policy check of {}: no violations", workspace),
+            n => format!("Policy check of {}: {} violations", workspace, n),
+        };
+        Self {
+            title,
+            lines: violations
+                .iter()
+                .map(|v| format!("{}: {}: {}", v.repository, v.rule, v.message))
+                .collect(),
+        }
+    }
+
+    /// The lines to post, cut at MAX_LINES
+    fn body(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.lines.iter().take(MAX_LINES).cloned().collect();
+        if self.lines.len() > MAX_LINES {
+            lines.push(format!("… and {} more", self.lines.len() - MAX_LINES));
+        }
+        lines
+    }
+}
+
+/// Posts notifications to the channels of the config
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    pub http_client: reqwest::Client,
+    pub config: NotifyConfig,
+}
+
+impl Notifier {
+    /// Post to every configured channel, trying all of them before reporting the first failure.
+    /// Errors name the channel rather than the URL, which holds its secret.
+    pub async fn send(&self, notification: &Notification) -> Result<(), BbdanError> {
+        let lines = notification.body();
+        let mut requests = Vec::new();
+        if let Some(url) = &self.config.slack {
+            let text = std::iter::once(format!("*{}*", notification.title))
+                .chain(lines.iter().map(|l| format!("• {}", l)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            requests.push(("Slack", url, json!({ "text": text })));
+        }
+        if let Some(url) = &self.config.teams {
+            requests.push((
+                "Teams",
+                url,
+                json!({
+                    "@type": "MessageCard",
+                    "@context": "https://schema.org/extensions",
+                    "summary": notification.title,
+                    "title": notification.title,
+                    "text": lines.iter().map(|l| format!("- {}", l)).collect::<Vec<_>>().join("\n"),
+                }),
+            ));
+        }
+
+        let mut result = Ok(());
+        for (channel, url, body) in requests {
+            let sent = match self.http_client.post(url).json(&body).send().await {
+                Ok(resp) if resp.status().is_success() => {
+                    tracing::debug!("Notified {}", channel);
+                    Ok(())
+                }
+                Ok(resp) => Err(BbdanError::Http {
+                    url: format!("the {} webhook", channel),
+                    status: resp.status(),
+                }),
+                Err(e) => Err(e.without_url().into()),
+            };
+            result = result.and(sent);
+        }
+        result
+    }
+}
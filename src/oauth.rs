@@ -0,0 +1,269 @@
+use chrono::{DateTime, Local};
+use clap::ArgEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+use crate::error::BbdanError;
+
+pub const OAUTH_AUTHORIZE_URL: &str = "https://bitbucket.org/site/oauth2/authorize";
+pub const OAUTH_TOKEN_URL: &str = "https://bitbucket.org/site/oauth2/access_token";
+
+#[derive(Debug, Clone, ArgEnum, Copy)]
+pub enum OAuthFlow {
+    ClientCredentials,
+    AuthorizationCode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuthToken {
+    pub client_id: String,
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_at: DateTime<Local>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: i64,
+}
+
+pub struct OAuthClient {
+    pub http_client: reqwest::Client,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Authorization endpoint of the authorization code flow, OAUTH_AUTHORIZE_URL by default
+    pub authorize_url: String,
+    /// Token endpoint, OAUTH_TOKEN_URL by default
+    pub token_url: String,
+}
+impl OAuthClient {
+    pub fn new(
+        http_client: reqwest::Client,
+        client_id: String,
+        client_secret: String,
+        authorize_url: String,
+        token_url: String,
+    ) -> Self {
+        Self {
+            http_client,
+            client_id,
+            client_secret,
+            authorize_url,
+            token_url,
+        }
+    }
+
+    pub async fn request_token(&self, params: &[(&str, &str)]) -> Result<OAuthToken, BbdanError> {
+        let resp = self
+            .http_client
+            .post(&self.token_url)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(params)
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            return Err(BbdanError::Auth(format!(
+                "failed to obtain OAuth token: {}",
+                resp.status()
+            )));
+        }
+
+        let token: TokenResponse = resp.json().await?;
+        Ok(OAuthToken {
+            client_id: self.client_id.to_string(),
+            access_token: token.access_token,
+            refresh_token: token.refresh_token,
+            expires_at: Local::now() + chrono::Duration::seconds(token.expires_in),
+        })
+    }
+
+    pub async fn client_credentials(&self) -> Result<OAuthToken, BbdanError> {
+        self.request_token(&[("grant_type", "client_credentials")])
+            .await
+    }
+
+    /// URL the user opens to authorize bbdan and get the code of the authorization code flow
+    pub fn authorize_url(&self) -> String {
+        format!(
+            "{}?client_id={}&response_type=code",
+            self.authorize_url, self.client_id
+        )
+    }
+
+    /// Exchange the code the user got from the authorize URL for a token
+    pub async fn authorization_code(&self, code: &str) -> Result<OAuthToken, BbdanError> {
+        self.request_token(&[("grant_type", "authorization_code"), ("code", code)])
+            .await
+    }
+
+    pub async fn refresh(&self, refresh_token: &str) -> Result<OAuthToken, BbdanError> {
+        self.request_token(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ])
+        .await
+    }
+}
+
+fn oauth_token_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("bbdan").join("oauth_token.json"))
+}
+
+fn load_oauth_token(client_id: &str) -> Option<OAuthToken> {
+    let content = fs::read_to_string(oauth_token_path()?).ok()?;
+    let token: OAuthToken = serde_json::from_str(&content).ok()?;
+    if token.client_id != client_id {
+        return None;
+    }
+    Some(token)
+}
+
+fn save_oauth_token(token: &OAuthToken) -> Result<(), BbdanError> {
+    let path = oauth_token_path()
+        .ok_or_else(|| BbdanError::Config("config directory not found".to_string()))?;
+    write_oauth_token(&path, token)
+}
+
+fn write_oauth_token(path: &Path, token: &OAuthToken) -> Result<(), BbdanError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // the tokens are credentials, readable by the owner only
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // a file written by an earlier version keeps its mode on open
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(serde_json::to_string(token)?.as_bytes())?;
+    Ok(())
+}
+
+/// Whether the token is still valid for at least a minute
+fn is_fresh(token: &OAuthToken) -> bool {
+    token.expires_at > Local::now() + chrono::Duration::seconds(60)
+}
+
+/// Return a valid access token, reusing the cached one and refreshing it when it has expired.
+/// The authorization code flow calls `authorize` with the authorize URL for the code the user gets there.
+pub async fn oauth_token(
+    oauth: &OAuthClient,
+    flow: OAuthFlow,
+    authorize: impl FnOnce(&str) -> Result<String, BbdanError>,
+) -> Result<OAuthToken, BbdanError> {
+    if let Some(token) = load_oauth_token(&oauth.client_id) {
+        if is_fresh(&token) {
+            return Ok(token);
+        }
+        if let Some(refresh_token) = &token.refresh_token {
+            if let Ok(token) = oauth.refresh(refresh_token).await {
+                save_oauth_token(&token)?;
+                return Ok(token);
+            }
+        }
+    }
+
+    let token = match flow {
+        OAuthFlow::ClientCredentials => oauth.client_credentials().await?,
+        OAuthFlow::AuthorizationCode => {
+            let code = authorize(&oauth.authorize_url())?;
+            oauth.authorization_code(&code).await?
+        }
+    };
+    save_oauth_token(&token)?;
+    Ok(token)
+}
+
+/// Keeps the access token valid for as long as the process runs,
+/// refreshing it when it is about to expire or the API rejects it
+pub struct OAuthSession {
+    oauth: OAuthClient,
+    token: Mutex<OAuthToken>,
+    /// File the refreshed tokens are written to, none to keep them in memory only
+    cache: Option<PathBuf>,
+}
+
+impl fmt::Debug for OAuthSession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OAuthSession")
+            .field("client_id", &self.oauth.client_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl OAuthSession {
+    /// Session starting with the token, keeping the refreshed ones in memory only
+    pub fn new(oauth: OAuthClient, token: OAuthToken) -> Self {
+        Self {
+            oauth,
+            token: Mutex::new(token),
+            cache: None,
+        }
+    }
+
+    /// Session starting with the cached or newly obtained token, caching the refreshed ones like [`oauth_token`]
+    pub async fn start(
+        oauth: OAuthClient,
+        flow: OAuthFlow,
+        authorize: impl FnOnce(&str) -> Result<String, BbdanError>,
+    ) -> Result<Self, BbdanError> {
+        let token = oauth_token(&oauth, flow, authorize).await?;
+        Ok(Self {
+            cache: oauth_token_path(),
+            ..Self::new(oauth, token)
+        })
+    }
+
+    /// The access token to send, refreshed first when it is about to expire.
+    /// A failed refresh is logged and the old token sent, for the API to reject.
+    pub async fn access_token(&self) -> String {
+        let mut token = self.token.lock().await;
+        if !is_fresh(&token) {
+            if let Err(e) = self.refresh(&mut token).await {
+                tracing::warn!("failed to refresh the OAuth token: {}", e);
+            }
+        }
+        token.access_token.to_string()
+    }
+
+    /// Refresh the token after the API rejected `rejected`,
+    /// unless a request sent at the same time has already replaced it
+    pub async fn renew(&self, rejected: &str) -> Result<(), BbdanError> {
+        let mut token = self.token.lock().await;
+        if token.access_token != rejected {
+            return Ok(());
+        }
+        self.refresh(&mut token).await
+    }
+
+    async fn refresh(&self, token: &mut OAuthToken) -> Result<(), BbdanError> {
+        let mut refreshed = match &token.refresh_token {
+            Some(refresh_token) => self.oauth.refresh(refresh_token).await?,
+            None => self.oauth.client_credentials().await?,
+        };
+        if refreshed.refresh_token.is_none() {
+            refreshed.refresh_token = token.refresh_token.take();
+        }
+        if let Some(path) = &self.cache {
+            write_oauth_token(path, &refreshed)?;
+        }
+        tracing::debug!("Refreshed the OAuth token");
+        *token = refreshed;
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::{confirm, permission_type_to_str, ObjectType, Options, Permission};
+use crate::reconcile::{apply, Change};
+use crate::repositories::{audit, Paging};
+
+/// What happened to the grant of the principal on a repository
+#[derive(Debug)]
+pub enum Outcome {
+    /// Removed, or would be in a dry run
+    Removed(Permission),
+    /// Not confirmed, or protected
+    Skipped(Permission),
+    /// The permissions of the repository could not be listed
+    Unlisted(Arc<BbdanError>),
+    Failed(BbdanError),
+}
+
+impl Outcome {
+    /// Why the grant could not be removed, when it could not
+    pub fn error(&self) -> Option<&BbdanError> {
+        match self {
+            Outcome::Unlisted(e) => Some(e),
+            Outcome::Failed(e) => Some(e),
+            Outcome::Removed(_) | Outcome::Skipped(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Removed(p) => write!(f, "removed {}", permission_type_to_str(p.permission)),
+            Outcome::Skipped(p) => write!(f, "skipped {}", permission_type_to_str(p.permission)),
+            Outcome::Unlisted(e) => write!(f, "failed to list: {}", e),
+            Outcome::Failed(e) => write!(f, "failed: {}", e),
+        }
+    }
+}
+
+/// Remove the direct grants of a user or group, given by id or name, from every repository in the workspace.
+/// Protected users and groups are skipped on every repository.
+/// Returns the outcome per repository where the principal had a grant or the permissions could not be listed.
+pub async fn offboard(
+    bitbucket: Bitbucket,
+    principal: (ObjectType, String),
+    options: Options,
+) -> Result<Vec<(String, Outcome)>, BbdanError> {
+    let (object_type, name) = principal;
+    let (results, _) = audit(bitbucket.clone(), &Paging::default(), |_| {}).await?;
+
+    let mut outcomes = Vec::new();
+    for (repo, result) in results {
+        let permissions = match result {
+            Ok(permissions) => permissions,
+            Err(e) => {
+                outcomes.push((repo, Outcome::Unlisted(e)));
+                continue;
+            }
+        };
+        let Some(p) = permissions
+            .into_iter()
+            .find(|p| p.object_type == object_type && (p.id == name || p.alias == name))
+        else {
+            continue;
+        };
+        if options.is_protected(&p) {
+            outcomes.push((repo, Outcome::Skipped(p)));
+            continue;
+        }
+
+        let message = format!(
+            "Remove: repository={}, id={}, name={}, permission={}.",
+            repo,
+            p.id,
+            p.alias,
+            permission_type_to_str(p.permission)
+        );
+        if !confirm(message, options)? {
+            outcomes.push((repo, Outcome::Skipped(p)));
+            continue;
+        }
+        let target = Bitbucket {
+            slug: repo.to_string(),
+            ..bitbucket.clone()
+        };
+        let outcome = match apply(&target, Change::Remove(p.clone()), options).await {
+            Ok(()) => Outcome::Removed(p),
+            Err(e) => Outcome::Failed(e),
+        };
+        outcomes.push((repo, outcome));
+    }
+    Ok(outcomes)
+}
@@ -0,0 +1,1166 @@
+use chrono::{DateTime, Local, SecondsFormat};
+use clap::ArgEnum;
+use comfy_table::{presets, Cell, Color, ContentArrangement, Table};
+use reqwest::StatusCode;
+use serde::ser::SerializeMap;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::time::Duration;
+use tera::Tera;
+
+use crate::access::AccessReport;
+use crate::document::{DiffDocument, Grants, PermissionsDocument, SyncDocument, DOCUMENT_VERSION};
+use crate::error::BbdanError;
+use crate::history::Entry;
+use crate::journal::{JournalEntry, Journaled};
+use crate::permissions::{
+    object_type_to_str, permission_type_to_str, ObjectType, Permission, PermissionType,
+};
+use crate::policy::Violation;
+use crate::reconcile::Change;
+use crate::repositories::AuditResult;
+
+#[derive(Debug, Clone, ArgEnum, Copy)]
+pub enum Output {
+    Csv,
+    Json,
+    /// JUnit XML for CI, a test case per repository
+    Junit,
+    /// A report with a heading and a table per repository, for pasting into wikis
+    Markdown,
+    /// A JSON object per line, written as soon as it is fetched
+    Ndjson,
+    /// Aligned columns for reading in a terminal
+    Table,
+    Text,
+    Yaml,
+}
+
+/// A permission with its repository, a line of the NDJSON output
+#[derive(Serialize)]
+struct RepositoryPermission<'a> {
+    repository: &'a str,
+    #[serde(flatten)]
+    permission: &'a Permission,
+}
+
+/// A change, a line of the NDJSON output of diff and an item of `changes` in templates
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangeRecord<'a> {
+    change: &'a str,
+    object_type: ObjectType,
+    id: &'a str,
+    alias: &'a str,
+    before: Option<PermissionType>,
+    after: Option<PermissionType>,
+}
+
+impl Output {
+    /// File extension and media type of the output, for attachments
+    pub fn file_type(self) -> (&'static str, &'static str) {
+        match self {
+            Output::Csv => ("csv", "text/csv"),
+            Output::Json => ("json", "application/json"),
+            Output::Junit => ("xml", "application/xml"),
+            Output::Markdown => ("md", "text/markdown"),
+            Output::Ndjson => ("ndjson", "application/x-ndjson"),
+            Output::Table | Output::Text => ("txt", "text/plain"),
+            Output::Yaml => ("yaml", "application/yaml"),
+        }
+    }
+}
+
+/// A column selected by --fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum Field {
+    Repository,
+    Type,
+    Id,
+    Alias,
+    Permission,
+    /// Where the grant comes from, with --expand-members
+    Via,
+}
+
+impl Field {
+    /// Column name of CSV and table
+    fn header(self) -> &'static str {
+        match self {
+            Field::Repository => "repository",
+            Field::Type => "type",
+            Field::Id => "id",
+            Field::Alias => "alias",
+            Field::Permission => "permission",
+            Field::Via => "via",
+        }
+    }
+
+    /// Key of JSON and YAML, following the serialization of Permission
+    fn key(self) -> &'static str {
+        match self {
+            Field::Type => "objectType",
+            _ => self.header(),
+        }
+    }
+
+    pub fn value(self, repo: &str, p: &Permission) -> String {
+        match self {
+            Field::Repository => repo.to_string(),
+            Field::Type => object_type_to_str(p.object_type),
+            Field::Id => p.id.to_string(),
+            Field::Alias => p.alias.to_string(),
+            Field::Permission => permission_type_to_str(p.permission),
+            Field::Via => p.via.join(" "),
+        }
+    }
+}
+
+/// The fields of a permission selected by --fields, serialized in their order
+struct Selected<'a> {
+    fields: &'a [Field],
+    repo: &'a str,
+    permission: &'a Permission,
+}
+
+impl Serialize for Selected<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.fields.len()))?;
+        for field in self.fields {
+            map.serialize_entry(field.key(), &field.value(self.repo, self.permission))?;
+        }
+        map.end()
+    }
+}
+
+/// Render only the selected fields of the permissions, with `title` above text and tables
+fn selected<'a>(
+    title: String,
+    fields: &[Field],
+    permissions: impl Iterator<Item = (&'a str, &'a Permission)> + Clone,
+    output: Output,
+    terminal: bool,
+) -> Result<String, BbdanError> {
+    let headers: Vec<&str> = fields.iter().map(|f| f.header()).collect();
+    let rows = || {
+        permissions
+            .clone()
+            .map(|(repo, p)| fields.iter().map(|f| f.value(repo, p)).collect())
+    };
+    let records = || {
+        permissions.clone().map(|(repo, permission)| Selected {
+            fields,
+            repo,
+            permission,
+        })
+    };
+    match output {
+        Output::Json => Ok(serde_json::to_string_pretty(&records().collect::<Vec<_>>())? + "\n"),
+        Output::Ndjson => ndjson(records()),
+        Output::Yaml => Ok(serde_yaml::to_string(&records().collect::<Vec<_>>())?),
+        Output::Csv => csv(&headers, rows()),
+        Output::Table => Ok(format!("{}\n{}", title, table(&headers, rows(), terminal))),
+        Output::Markdown => Ok(format!(
+            "# {}\n\n{}",
+            title,
+            markdown_table(&headers, rows())
+        )),
+        Output::Junit => Ok(junit(
+            &title,
+            &[JunitCase {
+                name: title.to_string(),
+                system_out: csv(&headers, rows())?,
+                failure: None,
+                error: None,
+            }],
+        )),
+        Output::Text => {
+            let mut out = title + "\n";
+            for row in rows() {
+                let values: Vec<String> = row.iter().map(|v| format!("{:?}", v)).collect();
+                out += &values.join(", ");
+                out += "\n";
+            }
+            Ok(out)
+        }
+    }
+}
+
+const PERMISSION_HEADER: &[&str] = &["type", "id", "alias", "permission"];
+const EXPANDED_HEADER: &[&str] = &["type", "id", "alias", "permission", "via"];
+const CHANGE_HEADER: &[&str] = &["change", "type", "id", "alias", "before", "after"];
+const ACCESS_HEADER: &[&str] = &["repository", "permission", "via"];
+const VIOLATION_HEADER: &[&str] = &["repository", "rule", "message"];
+const JOURNAL_HEADER: &[&str] = &[
+    "timestamp",
+    "run",
+    "actor",
+    "command",
+    "repository",
+    "type",
+    "id",
+    "alias",
+    "before",
+    "after",
+];
+const HISTORY_HEADER: &[&str] = &["recorded_at", "source", "run", "user", "command", "change"];
+
+/// Render the permissions of a repository as list prints them, or only `fields` when any are given.
+/// YAML is the document written by export, so it can be imported back.
+pub fn permissions(
+    workspace: &str,
+    repo: &str,
+    permissions: &[Permission],
+    fields: &[Field],
+    output: Output,
+    terminal: bool,
+) -> Result<String, BbdanError> {
+    if !fields.is_empty() {
+        let title = format!("Repository: {}", repo);
+        return selected(
+            title,
+            fields,
+            permissions.iter().map(|p| (repo, p)),
+            output,
+            terminal,
+        );
+    }
+    let expanded = is_expanded(permissions);
+    let header = permission_header(expanded);
+    let rows = || permissions.iter().map(|p| permission_row(p, expanded));
+    match output {
+        Output::Json => Ok(serde_json::to_string_pretty(permissions)? + "\n"),
+        Output::Yaml => Ok(serde_yaml::to_string(&PermissionsDocument {
+            version: DOCUMENT_VERSION,
+            workspace: workspace.to_string(),
+            repository: repo.to_string(),
+            grants: Grants::new(permissions),
+        })?),
+        Output::Ndjson => ndjson(permissions.iter().map(|p| RepositoryPermission {
+            repository: repo,
+            permission: p,
+        })),
+        Output::Csv => csv(header, rows()),
+        Output::Markdown => Ok(format!("# {}\n\n{}", repo, markdown_table(header, rows()))),
+        Output::Junit => Ok(junit(
+            &format!("list {}", workspace),
+            &[JunitCase {
+                name: repo.to_string(),
+                system_out: csv(header, rows())?,
+                failure: None,
+                error: None,
+            }],
+        )),
+        Output::Table => Ok(format!(
+            "Repository: {}\n{}",
+            repo,
+            table(header, rows(), terminal)
+        )),
+        Output::Text => {
+            let mut out = format!("Repository: {}\n", repo);
+            for p in permissions {
+                out += &format!(
+                    "{:?}, {:?}, {:?}, {:?}",
+                    p.object_type, p.id, p.alias, p.permission,
+                );
+                if expanded {
+                    out += &format!(", {:?}", p.via);
+                }
+                out += "\n";
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Render the changes turning `repo_a` into `repo_b` as diff prints them
+pub fn diff(
+    workspace: &str,
+    repo_a: &str,
+    repo_b: &str,
+    changes: &[Change],
+    output: Output,
+    terminal: bool,
+) -> Result<String, BbdanError> {
+    let document = || DiffDocument::new(workspace, repo_a, repo_b, changes);
+    let rows = || changes.iter().filter_map(change_row);
+    match output {
+        Output::Json => Ok(serde_json::to_string_pretty(&document())? + "\n"),
+        Output::Yaml => Ok(serde_yaml::to_string(&document())?),
+        Output::Ndjson => ndjson(changes.iter().filter_map(change_record)),
+        Output::Csv => csv(CHANGE_HEADER, rows()),
+        // differences fail the test case, so CI reports drift
+        Output::Junit => {
+            let changed = rows().count();
+            Ok(junit(
+                &format!("diff {}", workspace),
+                &[JunitCase {
+                    name: format!("{} -> {}", repo_a, repo_b),
+                    system_out: csv(CHANGE_HEADER, rows())?,
+                    failure: (changed > 0).then(|| format!("{} permissions differ", changed)),
+                    error: None,
+                }],
+            ))
+        }
+        Output::Markdown => {
+            let mut out = format!("# {} -> {}\n", repo_a, repo_b);
+            for (title, kind) in [
+                ("Added", "add"),
+                ("Updated", "update"),
+                ("Removed", "remove"),
+            ] {
+                // the section tells the change
+                let rows: Vec<Vec<String>> = rows()
+                    .filter(|row| row[0] == kind)
+                    .map(|row| row[1..].to_vec())
+                    .collect();
+                if !rows.is_empty() {
+                    let table = markdown_table(&CHANGE_HEADER[1..], rows);
+                    out += &format!("\n## {}\n\n{}", title, table);
+                }
+            }
+            if rows().next().is_none() {
+                out += "\nNo differences.\n";
+            }
+            Ok(out)
+        }
+        Output::Table => Ok(format!(
+            "Repository: {} -> {}\n{}",
+            repo_a,
+            repo_b,
+            table(CHANGE_HEADER, rows(), terminal)
+        )),
+        Output::Text => {
+            let mut out = format!("Repository: {} -> {}\n", repo_a, repo_b);
+            for change in changes {
+                match change {
+                    Change::Add(p) => {
+                        out += &format!(
+                            "+ {:?}, {:?}, {:?}, {:?}\n",
+                            p.object_type, p.id, p.alias, p.permission
+                        )
+                    }
+                    Change::Remove(p) => {
+                        out += &format!(
+                            "- {:?}, {:?}, {:?}, {:?}\n",
+                            p.object_type, p.id, p.alias, p.permission
+                        )
+                    }
+                    Change::Update { before, after } => {
+                        out += &format!(
+                            "~ {:?}, {:?}, {:?}, {:?} -> {:?}\n",
+                            after.object_type,
+                            after.id,
+                            after.alias,
+                            before.permission,
+                            after.permission
+                        )
+                    }
+                    Change::Unchanged(_) => {}
+                }
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Render the policy violations of the audited repositories, as check prints them
+pub fn check(
+    workspace: &str,
+    results: &[AuditResult],
+    violations: &[Violation],
+    output: Output,
+    terminal: bool,
+) -> Result<String, BbdanError> {
+    let rows = || {
+        violations.iter().map(|v| {
+            vec![
+                v.repository.to_string(),
+                v.rule.to_string(),
+                v.message.to_string(),
+            ]
+        })
+    };
+    match output {
+        Output::Json => Ok(serde_json::to_string_pretty(&serde_json::json!({
+            "workspace": workspace,
+            "violations": violations,
+        }))? + "\n"),
+        Output::Yaml => Ok(serde_yaml::to_string(&serde_json::json!({
+            "workspace": workspace,
+            "violations": violations,
+        }))?),
+        Output::Ndjson => ndjson(violations),
+        Output::Csv => csv(VIOLATION_HEADER, rows()),
+        // a test case per repository, failing with its violations
+        Output::Junit => {
+            let cases: Vec<JunitCase> = results
+                .iter()
+                .map(|(repo, result)| {
+                    let messages: Vec<String> = violations
+                        .iter()
+                        .filter(|v| &v.repository == repo)
+                        .map(|v| format!("{}: {}", v.rule, v.message))
+                        .collect();
+                    JunitCase {
+                        name: repo.to_string(),
+                        system_out: String::new(),
+                        failure: (!messages.is_empty()).then(|| messages.join("\n")),
+                        error: result
+                            .as_ref()
+                            .err()
+                            .map(|e| format!("failed to list: {}", e)),
+                    }
+                })
+                .collect();
+            Ok(junit(&format!("check {}", workspace), &cases))
+        }
+        Output::Markdown if violations.is_empty() => {
+            Ok(format!("# {}\n\nNo violations.\n", workspace))
+        }
+        Output::Markdown => Ok(format!(
+            "# {}\n\n{}",
+            workspace,
+            markdown_table(VIOLATION_HEADER, rows())
+        )),
+        Output::Table => Ok(format!(
+            "Workspace: {}\n{}",
+            workspace,
+            table(VIOLATION_HEADER, rows(), terminal)
+        )),
+        Output::Text => {
+            let mut out = format!("Workspace: {}\n", workspace);
+            for v in violations {
+                out += &format!("{}: {}: {}\n", v.repository, v.rule, v.message);
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Render the repositories a user can reach, as access prints them
+pub fn access(
+    workspace: &str,
+    report: &AccessReport,
+    output: Output,
+    terminal: bool,
+) -> Result<String, BbdanError> {
+    let rows = || {
+        report.access.iter().map(|a| {
+            vec![
+                a.repository.to_string(),
+                permission_type_to_str(a.permission),
+                a.via.join(" "),
+            ]
+        })
+    };
+    let title = match report.groups.as_slice() {
+        [] => format!("User: {}", report.user),
+        groups => format!("User: {} (member of {})", report.user, groups.join(", ")),
+    };
+    match output {
+        Output::Json => Ok(serde_json::to_string_pretty(report)? + "\n"),
+        Output::Yaml => Ok(serde_yaml::to_string(report)?),
+        Output::Ndjson => ndjson(&report.access),
+        Output::Csv => csv(ACCESS_HEADER, rows()),
+        Output::Junit => {
+            let mut cases = Vec::new();
+            for row in rows() {
+                cases.push(JunitCase {
+                    name: row[0].to_string(),
+                    system_out: csv(&ACCESS_HEADER[1..], [row[1..].to_vec()])?,
+                    failure: None,
+                    error: None,
+                });
+            }
+            cases.extend(report.failed.iter().map(|(repo, e)| JunitCase {
+                name: repo.to_string(),
+                system_out: String::new(),
+                failure: None,
+                error: Some(format!("failed to list: {}", e)),
+            }));
+            Ok(junit(
+                &format!("access {} {}", workspace, report.user),
+                &cases,
+            ))
+        }
+        Output::Markdown => Ok(format!(
+            "# {}\n\n{}",
+            report.user,
+            markdown_table(ACCESS_HEADER, rows())
+        )),
+        Output::Table => Ok(format!(
+            "{}\n{}",
+            title,
+            table(ACCESS_HEADER, rows(), terminal)
+        )),
+        Output::Text => {
+            let mut out = title + "\n";
+            for row in rows() {
+                out += &format!("{}\n", row.join(", "));
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Render the journaled changes, a row per change
+pub fn journal(
+    entries: &[JournalEntry],
+    output: Output,
+    terminal: bool,
+) -> Result<String, BbdanError> {
+    let level = |p: &Option<PermissionType>| p.map(permission_type_to_str).unwrap_or_default();
+    let rows = || {
+        entries.iter().map(|e| {
+            let mut row = vec![
+                e.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true),
+                e.run.to_string(),
+                e.actor.to_string(),
+                e.command.to_string(),
+                format!("{}/{}", e.workspace, e.repository),
+            ];
+            row.extend(match &e.change {
+                Journaled::Grant {
+                    object_type,
+                    id,
+                    alias,
+                    before,
+                    after,
+                } => [
+                    object_type_to_str(*object_type),
+                    id.to_string(),
+                    alias.to_string(),
+                    level(before),
+                    level(after),
+                ],
+                // the method and URL in place of the id and alias, and the body sent as the level after
+                Journaled::Request { method, url, body } => [
+                    "request".to_string(),
+                    method.to_string(),
+                    url.to_string(),
+                    String::new(),
+                    body.as_ref().map(Value::to_string).unwrap_or_default(),
+                ],
+            });
+            row
+        })
+    };
+    match output {
+        Output::Json => Ok(serde_json::to_string_pretty(entries)? + "\n"),
+        Output::Yaml => Ok(serde_yaml::to_string(entries)?),
+        Output::Ndjson => ndjson(entries),
+        Output::Csv => csv(JOURNAL_HEADER, rows()),
+        Output::Junit => {
+            let mut cases = Vec::new();
+            for row in rows() {
+                cases.push(JunitCase {
+                    name: format!("{} {}", row[0], row[4]),
+                    system_out: csv(&JOURNAL_HEADER[5..], [row[5..].to_vec()])?,
+                    failure: None,
+                    error: None,
+                });
+            }
+            Ok(junit("journal", &cases))
+        }
+        Output::Markdown => Ok(markdown_table(JOURNAL_HEADER, rows())),
+        Output::Table => Ok(table(JOURNAL_HEADER, rows(), terminal)),
+        Output::Text => Ok(rows().map(|row| row.join(", ") + "\n").collect()),
+    }
+}
+
+/// Render the recorded changes of a repository, a row per change
+pub fn history(
+    workspace: &str,
+    repo: &str,
+    entries: &[Entry],
+    output: Output,
+    terminal: bool,
+) -> Result<String, BbdanError> {
+    let rows = || {
+        entries.iter().flat_map(|e| {
+            e.changes.iter().map(|change| {
+                vec![
+                    e.recorded_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    e.source.to_string(),
+                    e.run.id.to_string(),
+                    e.run.user.to_string(),
+                    e.run.command.to_string(),
+                    change.to_string(),
+                ]
+            })
+        })
+    };
+    match output {
+        Output::Json => Ok(serde_json::to_string_pretty(entries)? + "\n"),
+        Output::Yaml => Ok(serde_yaml::to_string(entries)?),
+        Output::Ndjson => ndjson(entries),
+        Output::Csv => csv(HISTORY_HEADER, rows()),
+        Output::Junit => {
+            let cases: Vec<JunitCase> = entries
+                .iter()
+                .map(|e| JunitCase {
+                    name: format!(
+                        "{} {}",
+                        e.recorded_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                        e.source
+                    ),
+                    system_out: e.changes.join("\n"),
+                    failure: None,
+                    error: None,
+                })
+                .collect();
+            Ok(junit(&format!("history {} {}", workspace, repo), &cases))
+        }
+        Output::Markdown => Ok(format!(
+            "# {}\n\n{}",
+            repo,
+            markdown_table(HISTORY_HEADER, rows())
+        )),
+        Output::Table => Ok(format!(
+            "Repository: {}\n{}",
+            repo,
+            table(HISTORY_HEADER, rows(), terminal)
+        )),
+        Output::Text => {
+            let mut out = format!("Repository: {}\n", repo);
+            for row in rows() {
+                out += &format!("{}\n", row.join(", "));
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Render the items of a setting of a repository other than its permissions, e.g. branch restrictions,
+/// a row per item. JSON and YAML are the items as the API returns them.
+pub fn resources<T: Serialize>(
+    title: &str,
+    header: &[&str],
+    items: &[T],
+    row: impl Fn(&T) -> Vec<String>,
+    output: Output,
+    terminal: bool,
+) -> Result<String, BbdanError> {
+    let rows = || items.iter().map(&row);
+    match output {
+        Output::Json => Ok(serde_json::to_string_pretty(items)? + "\n"),
+        Output::Yaml => Ok(serde_yaml::to_string(items)?),
+        Output::Ndjson => ndjson(items),
+        Output::Csv => csv(header, rows()),
+        Output::Junit => Ok(junit(
+            title,
+            &[JunitCase {
+                name: title.to_string(),
+                system_out: csv(header, rows())?,
+                failure: None,
+                error: None,
+            }],
+        )),
+        Output::Markdown => Ok(format!("# {}\n\n{}", title, markdown_table(header, rows()))),
+        Output::Table => Ok(format!("{}\n{}", title, table(header, rows(), terminal))),
+        Output::Text => {
+            let mut out = format!("{}\n", title);
+            for row in rows() {
+                out += &format!("{}\n", row.join(", "));
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Render the permissions of every audited repository, or only `fields` when any are given.
+/// Repositories that failed are left out.
+/// JSON and YAML are the document read by sync, so the audit can be applied back.
+pub fn audit(
+    workspace: &str,
+    results: &[AuditResult],
+    fields: &[Field],
+    output: Output,
+    terminal: bool,
+) -> Result<String, BbdanError> {
+    let document = || SyncDocument {
+        version: DOCUMENT_VERSION,
+        repositories: results
+            .iter()
+            .filter_map(|(repo, result)| {
+                let permissions = result.as_ref().ok()?;
+                Some((repo.to_string(), Grants::new(permissions)))
+            })
+            .collect(),
+    };
+    let permissions = || {
+        results
+            .iter()
+            .flat_map(|(repo, result)| result.iter().flatten().map(move |p| (repo.as_str(), p)))
+    };
+    if !fields.is_empty() {
+        let title = format!("Workspace: {}", workspace);
+        return selected(title, fields, permissions(), output, terminal);
+    }
+    let expanded = results
+        .iter()
+        .any(|(_, result)| result.as_ref().is_ok_and(|p| is_expanded(p)));
+    let mut header = vec!["repository"];
+    header.extend(permission_header(expanded));
+    let header = header.as_slice();
+    let rows = || {
+        permissions().map(|(repo, p)| {
+            let mut row = vec![repo.to_string()];
+            row.extend(permission_row(p, expanded));
+            row
+        })
+    };
+    match output {
+        Output::Json => Ok(serde_json::to_string_pretty(&document())? + "\n"),
+        Output::Yaml => Ok(serde_yaml::to_string(&document())?),
+        Output::Ndjson => Ok(results
+            .iter()
+            .map(|result| audit_ndjson(result, fields))
+            .collect::<Result<Vec<_>, _>>()?
+            .concat()),
+        Output::Csv => csv(header, rows()),
+        Output::Junit => {
+            let mut cases = Vec::new();
+            for (repo, result) in results {
+                cases.push(match result {
+                    Ok(permissions) => JunitCase {
+                        name: repo.to_string(),
+                        system_out: csv(
+                            permission_header(expanded),
+                            permissions.iter().map(|p| permission_row(p, expanded)),
+                        )?,
+                        failure: None,
+                        error: None,
+                    },
+                    Err(e) => JunitCase {
+                        name: repo.to_string(),
+                        system_out: String::new(),
+                        failure: None,
+                        error: Some(format!("failed to list: {}", e)),
+                    },
+                });
+            }
+            Ok(junit(&format!("audit {}", workspace), &cases))
+        }
+        Output::Markdown => {
+            let mut out = format!("# {}\n", workspace);
+            for (repo, result) in results {
+                out += &format!("\n## {}\n\n", repo);
+                match result {
+                    Ok(permissions) => {
+                        let rows = permissions.iter().map(|p| permission_row(p, expanded));
+                        out += &markdown_table(permission_header(expanded), rows);
+                    }
+                    Err(e) => {
+                        out += &format!("Failed to list: {}\n", markdown_escape(&e.to_string()))
+                    }
+                }
+            }
+            Ok(out)
+        }
+        Output::Table => Ok(format!(
+            "Workspace: {}\n{}",
+            workspace,
+            table(header, rows(), terminal)
+        )),
+        Output::Text => {
+            let mut out = format!("Workspace: {}\n", workspace);
+            for (repo, p) in permissions() {
+                out += &format!(
+                    "{}, {:?}, {:?}, {:?}, {:?}",
+                    repo, p.object_type, p.id, p.alias, p.permission,
+                );
+                if expanded {
+                    out += &format!(", {:?}", p.via);
+                }
+                out += "\n";
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// A user template given by --format-template, rendered instead of --output
+pub struct Template {
+    tera: Tera,
+}
+
+const TEMPLATE_NAME: &str = "format-template";
+
+impl Template {
+    pub fn load(path: &Path) -> Result<Self, BbdanError> {
+        let mut tera = Tera::default();
+        tera.add_raw_template(TEMPLATE_NAME, &fs::read_to_string(path)?)?;
+        Ok(Self { tera })
+    }
+
+    /// `workspace`, `repository` and `permissions` for list
+    pub fn permissions(
+        &self,
+        workspace: &str,
+        repo: &str,
+        permissions: &[Permission],
+    ) -> Result<String, BbdanError> {
+        self.render(serde_json::json!({
+            "workspace": workspace,
+            "repository": repo,
+            "permissions": permissions,
+        }))
+    }
+
+    /// `workspace`, `source`, `destination` and `changes` for diff
+    pub fn diff(
+        &self,
+        workspace: &str,
+        repo_a: &str,
+        repo_b: &str,
+        changes: &[Change],
+    ) -> Result<String, BbdanError> {
+        let changes: Vec<ChangeRecord> = changes.iter().filter_map(change_record).collect();
+        self.render(serde_json::json!({
+            "workspace": workspace,
+            "source": repo_a,
+            "destination": repo_b,
+            "changes": changes,
+        }))
+    }
+
+    /// `workspace` and `repositories`, each with `repository` and either `permissions` or `error`, for audit
+    pub fn audit(&self, workspace: &str, results: &[AuditResult]) -> Result<String, BbdanError> {
+        let repositories: Vec<serde_json::Value> = results
+            .iter()
+            .map(|(repo, result)| match result {
+                Ok(permissions) => serde_json::json!({
+                    "repository": repo,
+                    "permissions": permissions,
+                }),
+                Err(e) => serde_json::json!({
+                    "repository": repo,
+                    "error": e.to_string(),
+                }),
+            })
+            .collect();
+        self.render(serde_json::json!({
+            "workspace": workspace,
+            "repositories": repositories,
+        }))
+    }
+
+    fn render(&self, context: serde_json::Value) -> Result<String, BbdanError> {
+        let context = tera::Context::from_value(context)?;
+        Ok(self.tera.render(TEMPLATE_NAME, &context)?)
+    }
+}
+
+/// Whether the output written to `path` is read in a terminal
+pub fn is_terminal(path: &Path) -> bool {
+    path == Path::new("-") && io::stdout().is_terminal()
+}
+
+/// Print the output, or write it to the file through a temporary file so readers never see a partial file
+pub fn write(path: &Path, content: &str) -> Result<(), BbdanError> {
+    if path == Path::new("-") {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(content.as_bytes())?;
+        stdout.flush()?;
+        return Ok(());
+    }
+    let name = path
+        .file_name()
+        .ok_or_else(|| BbdanError::Invalid(format!("{} is not a file", path.display())))?;
+    let tmp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    fs::write(&tmp, content)?;
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// NDJSON lines of the permissions of an audited repository, printed as soon as it is done
+pub fn audit_ndjson((repo, result): &AuditResult, fields: &[Field]) -> Result<String, BbdanError> {
+    let permissions = result.iter().flatten();
+    if !fields.is_empty() {
+        return ndjson(permissions.map(|permission| Selected {
+            fields,
+            repo,
+            permission,
+        }));
+    }
+    ndjson(permissions.map(|p| RepositoryPermission {
+        repository: repo,
+        permission: p,
+    }))
+}
+
+/// Whether the group grants were expanded, so the grants tell where they come from
+fn is_expanded(permissions: &[Permission]) -> bool {
+    permissions.iter().any(|p| !p.via.is_empty())
+}
+
+fn permission_header(expanded: bool) -> &'static [&'static str] {
+    match expanded {
+        true => EXPANDED_HEADER,
+        false => PERMISSION_HEADER,
+    }
+}
+
+fn permission_row(p: &Permission, expanded: bool) -> Vec<String> {
+    let mut row = vec![
+        object_type_to_str(p.object_type),
+        p.id.to_string(),
+        p.alias.to_string(),
+        permission_type_to_str(p.permission),
+    ];
+    if expanded {
+        row.push(p.via.join(" "));
+    }
+    row
+}
+
+/// Kind, grant, and the grant before and after a change. None for unchanged grants.
+type ChangeParts<'a> = (
+    &'static str,
+    &'a Permission,
+    Option<&'a Permission>,
+    Option<&'a Permission>,
+);
+
+fn change_parts(change: &Change) -> Option<ChangeParts<'_>> {
+    Some(match change {
+        Change::Add(p) => ("add", p, None, Some(p)),
+        Change::Remove(p) => ("remove", p, Some(p), None),
+        Change::Update { before, after } => ("update", after, Some(before), Some(after)),
+        Change::Unchanged(_) => return None,
+    })
+}
+
+fn change_record(change: &Change) -> Option<ChangeRecord<'_>> {
+    let (kind, p, before, after) = change_parts(change)?;
+    Some(ChangeRecord {
+        change: kind,
+        object_type: p.object_type,
+        id: &p.id,
+        alias: &p.alias,
+        before: before.map(|p| p.permission),
+        after: after.map(|p| p.permission),
+    })
+}
+
+/// Row of CHANGE_HEADER, None for unchanged grants
+fn change_row(change: &Change) -> Option<Vec<String>> {
+    let (kind, p, before, after) = change_parts(change)?;
+    let level = |p: Option<&Permission>| {
+        p.map(|p| permission_type_to_str(p.permission))
+            .unwrap_or_default()
+    };
+    Some(vec![
+        kind.to_string(),
+        object_type_to_str(p.object_type),
+        p.id.to_string(),
+        p.alias.to_string(),
+        level(before),
+        level(after),
+    ])
+}
+
+/// Serialize every item to JSON on its own line
+fn ndjson<T: Serialize>(items: impl IntoIterator<Item = T>) -> Result<String, BbdanError> {
+    let mut out = String::new();
+    for item in items {
+        out += &serde_json::to_string(&item)?;
+        out += "\n";
+    }
+    Ok(out)
+}
+
+/// Write a header and the rows as CSV, quoting fields where needed
+pub(crate) fn csv(
+    header: &[&str],
+    rows: impl IntoIterator<Item = Vec<String>>,
+) -> Result<String, BbdanError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(header)?;
+    for row in rows {
+        writer.write_record(&row)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// A test case of the JUnit output. `failure` is a finding, `error` means the check could not run.
+pub struct JunitCase {
+    pub name: String,
+    pub system_out: String,
+    pub failure: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Write the test cases as a JUnit XML test suite
+pub fn junit(suite: &str, cases: &[JunitCase]) -> String {
+    let failures = cases.iter().filter(|c| c.failure.is_some()).count();
+    let errors = cases.iter().filter(|c| c.error.is_some()).count();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    out += &format!(
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\">\n",
+        escape_xml(suite),
+        cases.len(),
+        failures,
+        errors
+    );
+    for case in cases {
+        out += &format!(
+            "    <testcase classname=\"{}\" name=\"{}\">\n",
+            escape_xml(suite),
+            escape_xml(&case.name)
+        );
+        if let Some(message) = &case.failure {
+            out += &format!("      <failure message=\"{}\"/>\n", escape_xml(message));
+        }
+        if let Some(message) = &case.error {
+            out += &format!("      <error message=\"{}\"/>\n", escape_xml(message));
+        }
+        if !case.system_out.is_empty() {
+            out += &format!(
+                "      <system-out>{}</system-out>\n",
+                escape_xml(&case.system_out)
+            );
+        }
+        out += "    </testcase>\n";
+    }
+    out += "  </testsuite>\n</testsuites>\n";
+    out
+}
+
+/// Escape text for XML and HTML content and attribute values
+pub(crate) fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&#39;",
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Write the rows as a Markdown table
+fn markdown_table(header: &[&str], rows: impl IntoIterator<Item = Vec<String>>) -> String {
+    let line = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+    let mut out = line(header.iter().map(|h| h.to_string()).collect());
+    out += &line(header.iter().map(|_| "---".to_string()).collect());
+    for row in rows {
+        out += &line(row.iter().map(|cell| markdown_escape(cell)).collect());
+    }
+    out
+}
+
+/// Escape the characters that would end a table cell or start formatting
+fn markdown_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if matches!(c, '|' | '\\' | '*' | '_' | '`' | '<' | '[') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Lay the rows out in aligned columns.
+/// When the output goes to a terminal, the table fits its width and permission levels are colored
+/// unless --no-color or NO_COLOR is set. Files and attachments get neither.
+fn table(header: &[&str], rows: impl IntoIterator<Item = Vec<String>>, terminal: bool) -> String {
+    let colors = terminal && console::colors_enabled();
+    let mut table = Table::new();
+    if !terminal {
+        table.force_no_tty();
+    }
+    table
+        .load_preset(presets::UTF8_FULL_CONDENSED)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(header.to_vec());
+    for row in rows {
+        table.add_row(row.into_iter().zip(header).map(|(value, column)| {
+            let is_level = colors && matches!(*column, "permission" | "before" | "after");
+            let color = match value.as_str() {
+                "admin" => Some(Color::Red),
+                "write" => Some(Color::Yellow),
+                "read" => Some(Color::Green),
+                _ => None,
+            };
+            match color {
+                Some(color) if is_level => Cell::new(value).fg(color),
+                _ => Cell::new(value),
+            }
+        }));
+    }
+    format!("{}\n", table)
+}
+
+/// One API request, written to the --log-http file
+pub struct OutputMessage {
+    pub datetime: DateTime<Local>,
+    pub url: String,
+    pub status_code: StatusCode,
+    pub elapsed: Duration,
+}
+
+impl OutputMessage {
+    pub fn new(
+        datetime: DateTime<Local>,
+        url: String,
+        status_code: StatusCode,
+        elapsed: Duration,
+    ) -> Self {
+        Self {
+            datetime,
+            url,
+            status_code,
+            elapsed,
+        }
+    }
+
+    pub fn to_formatted(&self, output: Output) -> String {
+        let dt = self.datetime.format("%Y-%m-%d %H:%M:%S").to_string();
+        let url = self.url.as_str().to_string();
+        let st = self.status_code.to_string();
+        let response_time = format!(
+            "{}.{:03}",
+            self.elapsed.as_secs(),
+            self.elapsed.subsec_millis()
+        );
+
+        let json = || {
+            serde_json::json!({
+                "datetime": dt,
+                "url": url,
+                "statusCode": st,
+                "responseTime": response_time,
+            })
+        };
+        match output {
+            Output::Csv => {
+                format!(r#""{}","{}","{}","{}""#, dt, url, st, response_time)
+            }
+            Output::Json | Output::Ndjson => json().to_string(),
+            // a flow mapping per line, so the whole file is a YAML sequence
+            Output::Yaml => format!("- {}", json()),
+            Output::Junit | Output::Markdown | Output::Table | Output::Text => {
+                format!("{} {} {} {}", dt, url, st, response_time)
+            }
+        }
+    }
+}
@@ -0,0 +1,565 @@
+use clap::ArgEnum;
+use dialoguer::{theme::ColorfulTheme, Confirm, MultiSelect};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::client::{Bitbucket, Page};
+use crate::error::{error_for_status, parse_json, BbdanError};
+use crate::reconcile::{apply, Change, Ignore};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Permission {
+    pub object_type: ObjectType,
+    pub alias: String,
+    pub id: String,
+    pub permission: PermissionType,
+    /// Where the grant of a user comes from once group grants are expanded,
+    /// `direct` or `group:<slug>` for each grant. Empty otherwise.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub via: Vec<String>,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, ArgEnum, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectType {
+    User,
+    Group,
+}
+
+pub fn object_type_to_str(t: ObjectType) -> String {
+    match t {
+        ObjectType::User => String::from("user"),
+        ObjectType::Group => String::from("group"),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ArgEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PermissionType {
+    Read,
+    Write,
+    Admin,
+}
+pub fn permission_type_to_str(p: PermissionType) -> String {
+    match p {
+        PermissionType::Read => String::from("read"),
+        PermissionType::Write => String::from("write"),
+        PermissionType::Admin => String::from("admin"),
+    }
+}
+
+/// An item of permissions-config/groups
+#[derive(Debug, Deserialize)]
+struct GroupPermission {
+    permission: PermissionType,
+    group: GroupRef,
+}
+
+#[derive(Debug, Deserialize)]
+struct GroupRef {
+    slug: String,
+    name: String,
+}
+
+/// An item of permissions-config/users
+#[derive(Debug, Deserialize)]
+struct UserPermission {
+    permission: PermissionType,
+    user: UserRef,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserRef {
+    uuid: String,
+    nickname: String,
+}
+
+/// An item of workspaces/{workspace}/members
+#[derive(Debug, Deserialize)]
+struct Member {
+    user: UserRef,
+}
+
+pub fn permission_url(bitbucket: &Bitbucket, object_type: ObjectType, id: &str) -> String {
+    let kind = match object_type {
+        ObjectType::User => "users",
+        ObjectType::Group => "groups",
+    };
+    bitbucket.repository_url(&format!("permissions-config/{}/{}", kind, id))
+}
+
+/// Resolve a user given as UUID or nickname to its UUID and nickname via the workspace members
+pub async fn resolve_user(
+    bitbucket: &Bitbucket,
+    user: &str,
+) -> Result<(String, String), BbdanError> {
+    if user.starts_with('{') {
+        return Ok((user.to_string(), user.to_string()));
+    }
+
+    let client = bitbucket.client();
+    let mut next = Some(bitbucket.workspace_url(&format!("members?pagelen={}", bitbucket.pagelen)));
+    while let Some(url) = next {
+        let resp = error_for_status(client.http_get_url(url).await?)?;
+        let page: Page<Member> = parse_json(resp).await?;
+        if let Some(m) = page.values.into_iter().find(|m| m.user.nickname == user) {
+            return Ok((m.user.uuid, m.user.nickname));
+        }
+        next = page.next;
+    }
+
+    Err(BbdanError::Invalid(format!(
+        "user {} is not a member of workspace {}",
+        user, bitbucket.workspace
+    )))
+}
+
+pub async fn list(bitbucket: Bitbucket) -> Result<Vec<Permission>, BbdanError> {
+    let mut permissions: Vec<Permission> = Vec::new();
+
+    let client = bitbucket.client();
+
+    let mut next = Some(bitbucket.repository_url(&format!(
+        "permissions-config/groups?pagelen={}",
+        bitbucket.pagelen
+    )));
+    while let Some(url) = next {
+        let resp = error_for_status(client.http_get_url(url).await?)?;
+        let page: Page<GroupPermission> = parse_json(resp).await?;
+
+        for v in page.values {
+            permissions.push(Permission {
+                object_type: ObjectType::Group,
+                alias: v.group.name,
+                id: v.group.slug,
+                permission: v.permission,
+                via: Vec::new(),
+            });
+        }
+        next = page.next;
+    }
+
+    let mut next = Some(bitbucket.repository_url(&format!(
+        "permissions-config/users?pagelen={}",
+        bitbucket.pagelen
+    )));
+    while let Some(url) = next {
+        let resp = error_for_status(client.http_get_url(url).await?)?;
+        let page: Page<UserPermission> = parse_json(resp).await?;
+
+        for v in page.values {
+            permissions.push(Permission {
+                object_type: ObjectType::User,
+                alias: v.user.nickname,
+                id: v.user.uuid,
+                permission: v.permission,
+                via: Vec::new(),
+            });
+        }
+        next = page.next;
+    }
+
+    Ok(permissions)
+}
+
+// Options for commands changing permissions
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Never prompt, answer yes to every confirmation
+    pub yes: bool,
+    /// Print the changes without sending PUT/DELETE requests
+    pub dry_run: bool,
+    /// Refuse to change a repository when more grants than this would be added, updated or removed
+    pub max_changes: Option<usize>,
+    /// Users and groups that are never removed, from the config file
+    pub protected: Option<&'static Protected>,
+    /// Users and groups left out of diffs and reconciles, from --ignore and the config file
+    pub ignore: Option<&'static Ignore>,
+}
+
+impl Options {
+    /// Refuse `pending` changes to the repository when they exceed --max-changes
+    pub fn check_max_changes(&self, slug: &str, pending: usize) -> Result<(), BbdanError> {
+        match self.max_changes.filter(|max| pending > *max) {
+            Some(max) => Err(BbdanError::Invalid(format!(
+                "{} changes to {} exceed --max-changes {}, nothing was changed",
+                pending, slug, max
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the grant must be kept, warning when it is
+    pub fn is_protected(&self, p: &Permission) -> bool {
+        let protected = self.protected.is_some_and(|protected| protected.matches(p));
+        if protected {
+            tracing::warn!(
+                "Keep: id={}, name={} is protected and never removed",
+                p.id,
+                p.alias
+            );
+        }
+        protected
+    }
+}
+
+/// Ask for confirmation, or accept right away in non-interactive and dry-run mode
+pub fn confirm(message: String, options: Options) -> Result<bool, BbdanError> {
+    if options.yes || options.dry_run {
+        tracing::info!("{}", message);
+        return Ok(true);
+    }
+    Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("{} Continue?", message))
+        .default(true)
+        .wait_for_newline(true)
+        .interact_opt()?
+        .ok_or(BbdanError::Aborted)
+}
+
+/// Ask which of the changes to make on one screen, all checked at first,
+/// or accept them all right away in non-interactive and dry-run mode
+pub fn confirm_each(messages: &[String], options: Options) -> Result<Vec<bool>, BbdanError> {
+    if options.yes || options.dry_run || messages.is_empty() {
+        for message in messages {
+            tracing::info!("{}", message);
+        }
+        return Ok(vec![true; messages.len()]);
+    }
+    let items: Vec<(&str, bool)> = messages.iter().map(|m| (m.as_str(), true)).collect();
+    let selections = MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Uncheck the changes to skip")
+        .items_checked(&items)
+        .interact_opt()?
+        .ok_or(BbdanError::Aborted)?;
+    Ok((0..messages.len())
+        .map(|i| selections.contains(&i))
+        .collect())
+}
+
+/// Permissions to show in list
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ListFilter {
+    pub object_type: Option<ObjectType>,
+    pub permission: Option<PermissionType>,
+}
+
+impl ListFilter {
+    pub fn matches(&self, p: &Permission) -> bool {
+        self.object_type.is_none_or(|t| t == p.object_type)
+            && self.permission.is_none_or(|l| l == p.permission)
+    }
+}
+
+#[derive(Debug, Clone, Copy, ArgEnum)]
+pub enum SortKey {
+    Name,
+    Permission,
+    Type,
+}
+
+/// Sort permissions by the key, breaking ties by name and id so the order is deterministic
+pub fn sort_permissions(permissions: &mut [Permission], key: SortKey, desc: bool) {
+    permissions.sort_by(|a, b| {
+        let name = |p: &Permission| (p.alias.to_lowercase(), p.id.to_string());
+        let ordering = match key {
+            SortKey::Name => name(a).cmp(&name(b)),
+            SortKey::Permission => a.permission.cmp(&b.permission).then(name(a).cmp(&name(b))),
+            SortKey::Type => a
+                .object_type
+                .cmp(&b.object_type)
+                .then(name(a).cmp(&name(b))),
+        };
+        if desc {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+/// Permissions to remove without the interactive picker
+#[derive(Debug, Clone, Default)]
+pub struct RemoveFilter {
+    pub users: Vec<String>,
+    pub groups: Vec<String>,
+    /// Every user grant
+    pub all_users: bool,
+    /// Only grants at these levels, of the users and groups above or of anyone when none are given
+    pub permissions: Vec<PermissionType>,
+}
+
+impl RemoveFilter {
+    pub fn is_empty(&self) -> bool {
+        !self.selects_principals() && self.permissions.is_empty()
+    }
+
+    /// Read the users and groups to remove from a file, see [`RemoveFilter::parse`]
+    pub fn load(path: &Path) -> Result<Self, BbdanError> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    /// The users and groups to remove, one per line. A line is `user:<UUID or nickname>`,
+    /// `group:<slug>`, or a bare UUID or group slug. Blank lines and `#` comments are skipped.
+    pub fn parse(content: &str) -> Self {
+        let mut filter = Self::default();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(user) = line.strip_prefix("user:") {
+                filter.users.push(user.trim().to_string());
+            } else if let Some(group) = line.strip_prefix("group:") {
+                filter.groups.push(group.trim().to_string());
+            } else if line.starts_with('{') {
+                filter.users.push(line.to_string());
+            } else {
+                filter.groups.push(line.to_string());
+            }
+        }
+        filter
+    }
+
+    fn selects_principals(&self) -> bool {
+        !self.users.is_empty() || !self.groups.is_empty() || self.all_users
+    }
+
+    pub fn matches(&self, p: &Permission) -> bool {
+        let names = match p.object_type {
+            ObjectType::User => &self.users,
+            ObjectType::Group => &self.groups,
+        };
+        let principal = !self.selects_principals()
+            || names.iter().any(|n| *n == p.id || *n == p.alias)
+            || (self.all_users && p.object_type == ObjectType::User);
+        let level = self.permissions.is_empty() || self.permissions.contains(&p.permission);
+        principal && level
+    }
+}
+
+/// Users and groups that are never removed, e.g. administrators and break-glass accounts,
+/// the `[protected]` table of the config file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Protected {
+    /// UUIDs or nicknames
+    pub users: Vec<String>,
+    /// Slugs or names
+    pub groups: Vec<String>,
+}
+
+impl Protected {
+    pub fn matches(&self, p: &Permission) -> bool {
+        let names = match p.object_type {
+            ObjectType::User => &self.users,
+            ObjectType::Group => &self.groups,
+        };
+        names.iter().any(|n| *n == p.id || *n == p.alias)
+    }
+}
+
+pub async fn remove(
+    bitbucket: Bitbucket,
+    filter: RemoveFilter,
+    options: Options,
+) -> Result<(), BbdanError> {
+    let permissions = list(bitbucket.clone()).await?;
+
+    let selections: Vec<usize> = if filter.is_empty() {
+        if options.yes {
+            return Err(BbdanError::Invalid(
+                "--user, --group, --all-users or --permission is required in non-interactive mode"
+                    .to_string(),
+            ));
+        }
+
+        let multiselected: Vec<String> = permissions
+            .iter()
+            .map(|x| {
+                format!(
+                    "{:?} - {:?} - {:?} - {:?}",
+                    x.object_type, x.id, x.alias, x.permission
+                )
+            })
+            .collect();
+
+        MultiSelect::with_theme(&ColorfulTheme::default())
+            .with_prompt("Pick permission you want to remove")
+            .items(&multiselected[..])
+            .interact_opt()?
+            .ok_or(BbdanError::Aborted)?
+            .into_iter()
+            .filter(|i| !options.is_protected(&permissions[*i]))
+            .collect()
+    } else {
+        let mut selections = Vec::new();
+        for (i, p) in permissions.iter().enumerate() {
+            if !filter.matches(p) || options.is_protected(p) {
+                continue;
+            }
+            let message = format!("Remove: id={}, name={}.", p.id, p.alias);
+            if confirm(message, options)? {
+                selections.push(i);
+            }
+        }
+        selections
+    };
+
+    if selections.is_empty() {
+        tracing::info!("You did not select anything :(");
+    } else {
+        for selection in selections {
+            let p = &permissions[selection];
+            apply(&bitbucket, Change::Remove(p.clone()), options).await?;
+        }
+    };
+
+    Ok(())
+}
+
+pub async fn add(
+    bitbucket: Bitbucket,
+    principal: (ObjectType, String),
+    permission: PermissionType,
+    options: Options,
+) -> Result<(), BbdanError> {
+    let (object_type, name) = principal;
+    let (id, alias) = match object_type {
+        ObjectType::User => resolve_user(&bitbucket, &name).await?,
+        ObjectType::Group => (name.to_string(), name),
+    };
+
+    let permissions = list(bitbucket.clone()).await?;
+    if let Some(p) = permissions
+        .iter()
+        .find(|p| p.object_type == object_type && p.id == id)
+    {
+        return Err(BbdanError::Invalid(format!(
+            "id={}, name={} already has {} permission, use update to change it",
+            p.id,
+            p.alias,
+            permission_type_to_str(p.permission)
+        )));
+    }
+
+    let message = format!(
+        "Add: id={}, name={}, permission={}.",
+        id,
+        alias,
+        permission_type_to_str(permission)
+    );
+    if !confirm(message, options)? {
+        tracing::info!("Skip");
+        return Ok(());
+    }
+
+    let p = Permission {
+        object_type,
+        alias,
+        id,
+        permission,
+        via: Vec::new(),
+    };
+    apply(&bitbucket, Change::Add(p), options).await
+}
+
+pub async fn update(
+    bitbucket: Bitbucket,
+    principal: (ObjectType, String),
+    permission: PermissionType,
+    options: Options,
+) -> Result<(), BbdanError> {
+    let (object_type, name) = principal;
+
+    let permissions = list(bitbucket.clone()).await?;
+    let current = match permissions
+        .iter()
+        .find(|p| p.object_type == object_type && (p.id == name || p.alias == name))
+    {
+        Some(p) => p,
+        None => {
+            return Err(BbdanError::Invalid(format!(
+                "{} has no permission on {}, use add to grant one",
+                name, bitbucket.slug
+            )))
+        }
+    };
+
+    if current.permission == permission {
+        tracing::info!("Not change: id={}, name={}", current.id, current.alias);
+        return Ok(());
+    }
+
+    let message = format!(
+        "Permission update: id={}, name={}, before={}, after={}.",
+        current.id,
+        current.alias,
+        permission_type_to_str(current.permission),
+        permission_type_to_str(permission),
+    );
+    if !confirm(message, options)? {
+        tracing::info!("Skip");
+        return Ok(());
+    }
+
+    let change = Change::Update {
+        before: current.clone(),
+        after: Permission {
+            permission,
+            ..current.clone()
+        },
+    };
+    apply(&bitbucket, change, options).await
+}
+
+pub async fn put_permission(
+    bitbucket: &Bitbucket,
+    object_type: ObjectType,
+    id: &str,
+    permission: PermissionType,
+    options: Options,
+) -> Result<(), BbdanError> {
+    let url = permission_url(bitbucket, object_type, id);
+    let mut map = HashMap::new();
+    map.insert("permission", permission_type_to_str(permission));
+
+    if options.dry_run {
+        tracing::info!("PUT {} {:?} (dry-run)", url, map);
+        return Ok(());
+    }
+    tracing::info!("PUT {}", url);
+
+    let resp = error_for_status(bitbucket.client().http_put_url(url, &map).await?)?;
+
+    let result: Value = resp.json().await?;
+    tracing::debug!("result: {}", result);
+    Ok(())
+}
+
+pub async fn delete_permission(
+    bitbucket: &Bitbucket,
+    object_type: ObjectType,
+    id: &str,
+    options: Options,
+) -> Result<(), BbdanError> {
+    let url = permission_url(bitbucket, object_type, id);
+
+    if options.dry_run {
+        tracing::info!("DELETE {} (dry-run)", url);
+        return Ok(());
+    }
+    tracing::info!("DELETE {}", url);
+
+    let resp = error_for_status(bitbucket.client().http_delete_url(url).await?)?;
+
+    // DELETE responds with 204 No Content
+    tracing::debug!("result: {}", resp.status());
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::{confirm, Options};
+use crate::reconcile::Summary;
+use crate::resources::{self, Resource};
+
+/// Columns of the pipelines configuration in show
+pub const CONFIG_HEADER: &[&str] = &["enabled"];
+
+/// Columns of the pipelines variables in list
+pub const VARIABLE_HEADER: &[&str] = &["uuid", "key", "value", "secured"];
+
+/// A variable of the pipelines of the repository, an item of pipelines_config/variables
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Variable {
+    /// Absent from the variables to create
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    pub key: String,
+    /// Absent from secured variables, whose values are never returned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default)]
+    pub secured: bool,
+}
+
+impl Variable {
+    /// The variable as a row of [`VARIABLE_HEADER`]
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.uuid.clone().unwrap_or_default(),
+            self.key.to_string(),
+            match self.secured {
+                true => "(secured)".to_string(),
+                false => self.value.clone().unwrap_or_default(),
+            },
+            self.secured.to_string(),
+        ]
+    }
+}
+
+impl Resource for Variable {
+    const PATH: &'static str = "pipelines_config/variables";
+
+    fn key(&self) -> Option<String> {
+        self.uuid.clone()
+    }
+
+    /// Whether both are the same variable, whatever their values
+    fn same(&self, other: &Variable) -> bool {
+        self.key == other.key
+    }
+
+    fn describe(&self) -> String {
+        match self.secured {
+            true => format!("key={}, secured", self.key),
+            false => format!("key={}", self.key),
+        }
+    }
+
+    /// A secured variable is created with an empty value, to be set in the destination
+    fn created(&self) -> Self {
+        if self.secured && self.value.is_none() {
+            tracing::warn!(
+                "The value of the secured variable {} cannot be read, set it in the destination",
+                self.key
+            );
+        }
+        Variable {
+            uuid: None,
+            value: Some(self.value.clone().unwrap_or_default()),
+            ..self.clone()
+        }
+    }
+}
+
+pub async fn list_variables(bitbucket: &Bitbucket) -> Result<Vec<Variable>, BbdanError> {
+    resources::list_all(bitbucket, Variable::PATH).await
+}
+
+/// Set a variable of the pipelines of the repository, creating it or replacing its value
+pub async fn set_variable(
+    bitbucket: Bitbucket,
+    key: &str,
+    value: &str,
+    secured: bool,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    set_variable_at(&bitbucket, Variable::PATH, key, value, secured, options).await
+}
+
+/// Set a variable among those at path, e.g. the deployment variables of an environment
+pub(crate) async fn set_variable_at(
+    bitbucket: &Bitbucket,
+    path: &str,
+    key: &str,
+    value: &str,
+    secured: bool,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let variable = Variable {
+        uuid: None,
+        key: key.to_string(),
+        value: Some(value.to_string()),
+        secured,
+    };
+    let existing = resources::list_all::<Variable>(bitbucket, path)
+        .await?
+        .into_iter()
+        .find(|v| v.same(&variable));
+    let mut summary = Summary::default();
+    match existing {
+        Some(v) if !v.secured && !secured && v.value.as_deref() == Some(value) => {
+            tracing::info!("{} is already set", variable.describe());
+            summary.unchanged += 1;
+        }
+        Some(v) => {
+            if !confirm(format!("Update: {}.", variable.describe()), options)? {
+                summary.skipped += 1;
+                return Ok(summary);
+            }
+            let path = format!("{}/{}", path, v.uuid.unwrap_or_default());
+            resources::put(bitbucket, &path, &variable, options).await?;
+            summary.updated += 1;
+        }
+        None => {
+            if !confirm(format!("Add: {}.", variable.describe()), options)? {
+                summary.skipped += 1;
+                return Ok(summary);
+            }
+            resources::create_at(bitbucket, path, &variable, options).await?;
+            summary.added += 1;
+        }
+    }
+    Ok(summary)
+}
+
+/// Copy the pipelines variables of src to each destination, with the result per destination.
+/// Variables a destination already has keep their values, and secured ones are created empty.
+pub async fn copy_variables(
+    src: Bitbucket,
+    dests: &[Bitbucket],
+    prune: bool,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    resources::copy::<Variable>(src, dests, prune, options).await
+}
+
+/// Whether Pipelines runs on the repository, pipelines_config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelinesConfig {
+    pub enabled: bool,
+}
+
+impl PipelinesConfig {
+    /// The configuration as a row of [`CONFIG_HEADER`]
+    pub fn row(&self) -> Vec<String> {
+        vec![self.enabled.to_string()]
+    }
+}
+
+/// The pipelines configuration of the repository. Pipelines never set up are disabled.
+pub async fn pipelines_config(bitbucket: &Bitbucket) -> Result<PipelinesConfig, BbdanError> {
+    match resources::get(bitbucket, "pipelines_config").await {
+        Err(BbdanError::Http { status, .. }) if status == StatusCode::NOT_FOUND => {
+            Ok(PipelinesConfig { enabled: false })
+        }
+        result => result,
+    }
+}
+
+/// Enable or disable Pipelines on the repository
+pub async fn set_pipelines_enabled(
+    bitbucket: &Bitbucket,
+    enabled: bool,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let mut summary = Summary::default();
+    let action = if enabled { "Enable" } else { "Disable" };
+    if pipelines_config(bitbucket).await?.enabled == enabled {
+        tracing::info!("Pipelines is already {}d", action.to_lowercase());
+        summary.unchanged += 1;
+    } else if confirm(
+        format!("{} pipelines of {}.", action, bitbucket.slug),
+        options,
+    )? {
+        let config = PipelinesConfig { enabled };
+        resources::put(bitbucket, "pipelines_config", &config, options).await?;
+        summary.updated += 1;
+    } else {
+        summary.skipped += 1;
+    }
+    Ok(summary)
+}
+
+/// Enable or disable Pipelines on each destination as it is on src, with the result per destination
+pub async fn copy_pipelines_config(
+    src: Bitbucket,
+    dests: &[Bitbucket],
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    let config = pipelines_config(&src).await?;
+    let mut results = Vec::new();
+    for dest in dests {
+        tracing::info!("Repository: {}", dest.slug);
+        let result = set_pipelines_enabled(dest, config.enabled, options)
+            .await
+            .map_err(Arc::new);
+        results.push((dest.slug.to_string(), result));
+    }
+    Ok(results)
+}
@@ -0,0 +1,235 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::document::{Grants, DOCUMENT_VERSION};
+use crate::error::BbdanError;
+use crate::permissions::{list, ObjectType, Options, Permission, PermissionType};
+use crate::reconcile::{
+    apply_changes, considered, diff_permissions, Change, ChangeFilter, Summary,
+};
+use crate::watch::describe;
+
+/// The changes a command would make, written by `plan` to be reviewed and applied as they are
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Plan {
+    pub version: u32,
+    pub workspace: String,
+    pub planned_at: DateTime<Utc>,
+    /// The command planned, e.g. `copy repo-a repo-b`
+    pub command: String,
+    pub repositories: BTreeMap<String, RepositoryPlan>,
+}
+
+/// The changes to a repository and the permissions they were planned against
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepositoryPlan {
+    pub current: Grants,
+    pub changes: Vec<PlannedChange>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlannedAction {
+    Add,
+    Update,
+    Remove,
+}
+
+/// A grant to put or delete
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedChange {
+    pub action: PlannedAction,
+    #[serde(rename = "type")]
+    pub object_type: ObjectType,
+    pub id: String,
+    pub alias: String,
+    /// Level now, none when it is granted
+    pub before: Option<PermissionType>,
+    /// Level after, none when it is revoked
+    pub after: Option<PermissionType>,
+}
+
+impl PlannedChange {
+    fn new(change: &Change) -> Option<Self> {
+        let (action, p, before, after) = match change {
+            Change::Add(p) => (PlannedAction::Add, p, None, Some(p.permission)),
+            Change::Update { before, after } => (
+                PlannedAction::Update,
+                after,
+                Some(before.permission),
+                Some(after.permission),
+            ),
+            Change::Remove(p) => (PlannedAction::Remove, p, Some(p.permission), None),
+            Change::Unchanged(_) => return None,
+        };
+        Some(Self {
+            action,
+            object_type: p.object_type,
+            id: p.id.to_string(),
+            alias: p.alias.to_string(),
+            before,
+            after,
+        })
+    }
+
+    fn change(&self) -> Result<Change, BbdanError> {
+        let grant = |level: Option<PermissionType>| {
+            let permission = level.ok_or_else(|| {
+                BbdanError::Invalid(format!("the planned change of {} lacks a level", self.id))
+            })?;
+            Ok::<_, BbdanError>(Permission {
+                object_type: self.object_type,
+                alias: self.alias.to_string(),
+                id: self.id.to_string(),
+                permission,
+                via: Vec::new(),
+            })
+        };
+        Ok(match self.action {
+            PlannedAction::Add => Change::Add(grant(self.after)?),
+            PlannedAction::Update => Change::Update {
+                before: grant(self.before)?,
+                after: grant(self.after)?,
+            },
+            PlannedAction::Remove => Change::Remove(grant(self.before)?),
+        })
+    }
+}
+
+impl Plan {
+    pub fn new(workspace: &str, command: &str) -> Self {
+        Self {
+            version: DOCUMENT_VERSION,
+            workspace: workspace.to_string(),
+            planned_at: Utc::now(),
+            command: command.to_string(),
+            repositories: BTreeMap::new(),
+        }
+    }
+
+    /// Plan the changes turning the permissions of the repository into the desired ones
+    pub async fn add(
+        &mut self,
+        dest: Bitbucket,
+        desired: &[Permission],
+        filter: &ChangeFilter,
+        options: Options,
+    ) -> Result<(), BbdanError> {
+        let current = list(dest.clone()).await?;
+        let changes: Vec<PlannedChange> = filter
+            .filter(diff_permissions(
+                &considered(current.clone(), options),
+                &considered(desired.to_vec(), options),
+            ))
+            .iter()
+            .filter_map(PlannedChange::new)
+            .collect();
+        self.repositories.insert(
+            dest.slug.to_string(),
+            RepositoryPlan {
+                current: Grants::new(&current),
+                changes,
+            },
+        );
+        Ok(())
+    }
+
+    /// Number of grants to put or delete
+    pub fn len(&self) -> usize {
+        self.repositories.values().map(|r| r.changes.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The planned changes in words, a line per change, e.g. `repo-b: granted group devs write`
+    pub fn describe(&self) -> Result<Vec<String>, BbdanError> {
+        let mut lines = Vec::new();
+        for (repo, plan) in &self.repositories {
+            for change in &plan.changes {
+                lines.push(format!("{}: {}", repo, describe(&change.change()?)));
+            }
+        }
+        Ok(lines)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), BbdanError> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, BbdanError> {
+        let plan: Self = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if plan.version > DOCUMENT_VERSION {
+            return Err(BbdanError::Invalid(format!(
+                "plan version {} is newer than the supported version {}",
+                plan.version, DOCUMENT_VERSION
+            )));
+        }
+        Ok(plan)
+    }
+}
+
+/// The grants of permissions, ignoring the names that may change without changing any access
+fn grants(permissions: &[Permission]) -> BTreeSet<(ObjectType, String, PermissionType)> {
+    permissions
+        .iter()
+        .map(|p| (p.object_type, p.id.to_string(), p.permission))
+        .collect()
+}
+
+/// Apply exactly the changes of the plan, with a summary per repository.
+/// Nothing is changed when the permissions of any repository changed since the plan was made.
+pub async fn apply_plan(
+    bitbucket: Bitbucket,
+    plan: &Plan,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    if plan.workspace != bitbucket.workspace {
+        return Err(BbdanError::Invalid(format!(
+            "the plan is for workspace {}, not {}",
+            plan.workspace, bitbucket.workspace
+        )));
+    }
+    let target = |repo: &str| Bitbucket {
+        slug: repo.to_string(),
+        ..bitbucket.clone()
+    };
+
+    let mut changed = Vec::new();
+    for (repo, repo_plan) in &plan.repositories {
+        let live = list(target(repo)).await?;
+        if grants(&live) != grants(&repo_plan.current.permissions()) {
+            changed.push(repo.to_string());
+        }
+    }
+    if !changed.is_empty() {
+        return Err(BbdanError::Invalid(format!(
+            "the permissions of {} changed since the plan was made, plan again",
+            changed.join(", ")
+        )));
+    }
+
+    let mut results = Vec::new();
+    for (repo, repo_plan) in &plan.repositories {
+        tracing::info!("Repository: {}", repo);
+        let result = async {
+            let changes = repo_plan
+                .changes
+                .iter()
+                .map(PlannedChange::change)
+                .collect::<Result<Vec<_>, _>>()?;
+            apply_changes(target(repo), changes, options).await
+        }
+        .await
+        .map_err(Arc::new);
+        results.push((repo.to_string(), result));
+    }
+    Ok(results)
+}
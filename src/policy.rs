@@ -0,0 +1,160 @@
+use globset::{Glob, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::error::BbdanError;
+use crate::permissions::{object_type_to_str, permission_type_to_str, ObjectType};
+use crate::query::Query;
+use crate::repositories::AuditResult;
+
+/// Rules every repository of the workspace is checked against, read from a YAML or JSON file
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    check: Check,
+    /// The filter as written in the file, for messages
+    expression: String,
+    /// Repositories the rule applies to, all when absent
+    repositories: Option<GlobMatcher>,
+}
+
+#[derive(Debug, Clone)]
+enum Check {
+    /// No grant may match
+    Forbid(Query),
+    /// At least one grant must match
+    Require(Query),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    rules: Vec<RuleSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    name: String,
+    forbid: Option<String>,
+    require: Option<String>,
+    repositories: Option<String>,
+}
+
+/// A rule a repository breaks
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    pub repository: String,
+    pub rule: String,
+    pub message: String,
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self, BbdanError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse a policy like
+    ///
+    /// ```yaml
+    /// rules:
+    /// - name: no individual admins
+    ///   forbid: type == 'user' && permission == 'admin'
+    /// - name: security can read everything
+    ///   require: type == 'group' && id == 'security'
+    ///   repositories: "app-*"
+    /// ```
+    ///
+    /// where `forbid` and `require` are filters as in --query.
+    pub fn parse(content: &str) -> Result<Self, BbdanError> {
+        let file: PolicyFile = serde_yaml::from_str(content)?;
+        let rules = file
+            .rules
+            .into_iter()
+            .map(|spec| {
+                let invalid =
+                    |e: String| BbdanError::Invalid(format!("rule {:?}: {}", spec.name, e));
+                let (check, expression) = match (spec.forbid, spec.require) {
+                    (Some(e), None) => (
+                        Check::Forbid(Query::parse(&e).map_err(|e| invalid(e.to_string()))?),
+                        e,
+                    ),
+                    (None, Some(e)) => (
+                        Check::Require(Query::parse(&e).map_err(|e| invalid(e.to_string()))?),
+                        e,
+                    ),
+                    _ => return Err(invalid("expected one of forbid or require".to_string())),
+                };
+                let repositories = spec
+                    .repositories
+                    .as_deref()
+                    .map(|glob| Glob::new(glob).map(|g| g.compile_matcher()))
+                    .transpose()
+                    .map_err(|e| invalid(e.to_string()))?;
+                Ok(Rule {
+                    name: spec.name,
+                    check,
+                    expression,
+                    repositories,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Policy { rules })
+    }
+
+    /// Violations of the audited repositories, in the order of the repositories then the rules.
+    /// Repositories that failed are not checked.
+    pub fn evaluate(&self, results: &[AuditResult]) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for (repo, result) in results {
+            let Ok(permissions) = result else {
+                continue;
+            };
+            for rule in &self.rules {
+                if rule
+                    .repositories
+                    .as_ref()
+                    .is_some_and(|m| !m.is_match(repo))
+                {
+                    continue;
+                }
+                let violation = |message| Violation {
+                    repository: repo.to_string(),
+                    rule: rule.name.to_string(),
+                    message,
+                };
+                match &rule.check {
+                    Check::Forbid(query) => violations.extend(
+                        permissions
+                            .iter()
+                            .filter(|p| query.matches(repo, p))
+                            .map(|p| {
+                                violation(format!(
+                                    "{} {} has {}",
+                                    object_type_to_str(p.object_type),
+                                    match p.object_type {
+                                        ObjectType::User if !p.alias.is_empty() => &p.alias,
+                                        _ => &p.id,
+                                    },
+                                    permission_type_to_str(p.permission)
+                                ))
+                            }),
+                    ),
+                    Check::Require(query) => {
+                        if !permissions.iter().any(|p| query.matches(repo, p)) {
+                            violations
+                                .push(violation(format!("no grant matches {}", rule.expression)));
+                        }
+                    }
+                }
+            }
+        }
+        violations
+    }
+}
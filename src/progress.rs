@@ -0,0 +1,49 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::io::{self, Write};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Progress bars on stderr, hidden when stderr is not a terminal
+static PROGRESS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// Progress bar over a known number of items, showing the ETA
+pub fn bar(len: usize, message: &str) -> ProgressBar {
+    let style = ProgressStyle::with_template("{msg} [{wide_bar}] {pos}/{len} ETA {eta}")
+        .expect("valid template")
+        .progress_chars("=> ");
+    PROGRESS.add(
+        ProgressBar::new(len as u64)
+            .with_style(style)
+            .with_message(message.to_string()),
+    )
+}
+
+/// Spinner counting items of a listing whose size is not known up front
+pub fn spinner(message: &str) -> ProgressBar {
+    let style = ProgressStyle::with_template("{spinner} {msg} {pos}").expect("valid template");
+    let spinner = PROGRESS.add(
+        ProgressBar::new_spinner()
+            .with_style(style)
+            .with_message(message.to_string()),
+    );
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
+
+/// Hide the progress bars while `f` writes to the terminal
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    PROGRESS.suspend(f)
+}
+
+/// Writer to stderr hiding the progress bars while a log line is written
+pub struct Stderr;
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        PROGRESS.suspend(|| io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
@@ -0,0 +1,183 @@
+use clap::ArgEnum;
+use std::sync::Arc;
+
+use crate::error::BbdanError;
+use crate::output::Field;
+use crate::permissions::Permission;
+use crate::repositories::AuditResult;
+
+/// Filter given by --query, e.g. `permission == 'admin' && type == 'user'`.
+///
+/// Compares the fields of --fields with `==` and `!=`, combined with `&&`, `||`, `!` and parentheses.
+#[derive(Debug, Clone)]
+pub struct Query(Expr);
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Eq(Field, String),
+    Ne(Field, String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Self, BbdanError> {
+        let mut parser = Parser {
+            tokens: tokenize(query)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(Query(expr)),
+            Some(token) => Err(invalid(format!("unexpected {:?}", token))),
+        }
+    }
+
+    pub fn matches(&self, repo: &str, p: &Permission) -> bool {
+        self.0.eval(repo, p)
+    }
+
+    /// The audit result of a repository with only the matching permissions
+    pub fn filter_audit(&self, (repo, result): &AuditResult) -> AuditResult {
+        let result = result.as_ref().map(|permissions| {
+            permissions
+                .iter()
+                .filter(|p| self.matches(repo, p))
+                .cloned()
+                .collect()
+        });
+        (repo.to_string(), result.map_err(Arc::clone))
+    }
+}
+
+impl Expr {
+    fn eval(&self, repo: &str, p: &Permission) -> bool {
+        match self {
+            Expr::Eq(field, value) => field.value(repo, p) == *value,
+            Expr::Ne(field, value) => field.value(repo, p) != *value,
+            Expr::Not(e) => !e.eval(repo, p),
+            Expr::And(a, b) => a.eval(repo, p) && b.eval(repo, p),
+            Expr::Or(a, b) => a.eval(repo, p) || b.eval(repo, p),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Eq,
+    Ne,
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn invalid(message: String) -> BbdanError {
+    BbdanError::Invalid(format!("invalid query: {}", message))
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, BbdanError> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' if chars.next_if_eq(&'=').is_some() => Token::Eq,
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Ne,
+            '!' => Token::Not,
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            '\'' | '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some(ch) => value.push(ch),
+                        None => return Err(invalid("unterminated string".to_string())),
+                    }
+                }
+                Token::Str(value)
+            }
+            c if c.is_ascii_alphabetic() => {
+                let mut ident = c.to_string();
+                while let Some(ch) = chars.next_if(|ch| ch.is_ascii_alphanumeric() || *ch == '_') {
+                    ident.push(ch);
+                }
+                Token::Ident(ident)
+            }
+            c => return Err(invalid(format!("unexpected {:?}", c))),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, BbdanError> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, BbdanError> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, BbdanError> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(invalid("missing )".to_string())),
+                }
+            }
+            Some(Token::Ident(name)) => {
+                let field = Field::from_str(&name, true)
+                    .map_err(|_| invalid(format!("unknown field {}", name)))?;
+                let op = self.next();
+                let value = match self.next() {
+                    Some(Token::Str(value)) => value,
+                    _ => return Err(invalid(format!("expected a quoted value after {}", name))),
+                };
+                match op {
+                    Some(Token::Eq) => Ok(Expr::Eq(field, value)),
+                    Some(Token::Ne) => Ok(Expr::Ne(field, value)),
+                    _ => Err(invalid(format!("expected == or != after {}", name))),
+                }
+            }
+            Some(token) => Err(invalid(format!("unexpected {:?}", token))),
+            None => Err(invalid("unexpected end".to_string())),
+        }
+    }
+}
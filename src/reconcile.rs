@@ -0,0 +1,538 @@
+use clap::{ArgEnum, Args as ClapArgs};
+use dialoguer::{theme::ColorfulTheme, Select};
+use futures::StreamExt;
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::journal;
+use crate::mapping::Mapping;
+use crate::permissions::{
+    confirm, confirm_each, delete_permission, list, permission_type_to_str, put_permission,
+    ObjectType, Options, Permission,
+};
+use crate::progress;
+use crate::watch::describe;
+
+/// A change turning one set of permissions into another
+#[derive(Debug, Clone)]
+pub enum Change {
+    Add(Permission),
+    Update {
+        before: Permission,
+        after: Permission,
+    },
+    Remove(Permission),
+    Unchanged(Permission),
+}
+
+/// Users and groups left out of diff and reconcile, e.g. bots and the default groups of the workspace
+#[derive(Debug, Clone)]
+pub struct Ignore(GlobSet);
+
+impl Ignore {
+    /// Globs matched against the UUID or nickname of users and the slug or name of groups
+    pub fn new(patterns: &[String]) -> Result<Self, BbdanError> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder
+                .add(Glob::new(pattern).map_err(|e| {
+                    BbdanError::Invalid(format!("invalid glob {}: {}", pattern, e))
+                })?);
+        }
+        let globs = builder
+            .build()
+            .map_err(|e| BbdanError::Invalid(e.to_string()))?;
+        Ok(Self(globs))
+    }
+
+    pub fn matches(&self, p: &Permission) -> bool {
+        self.0.is_match(&p.id) || self.0.is_match(&p.alias)
+    }
+}
+
+/// The permissions without the users and groups ignored by the options
+pub fn considered(permissions: Vec<Permission>, options: Options) -> Vec<Permission> {
+    let Some(ignore) = options.ignore else {
+        return permissions;
+    };
+    permissions
+        .into_iter()
+        .filter(|p| {
+            let ignored = ignore.matches(p);
+            if ignored {
+                tracing::debug!("Ignore: id={}, name={}", p.id, p.alias);
+            }
+            !ignored
+        })
+        .collect()
+}
+
+/// Compute the changes that turn `before` into `after`.
+/// Adds and updates come first in the order of `after`, then removes in the order of `before`.
+pub fn diff_permissions(before: &[Permission], after: &[Permission]) -> Vec<Change> {
+    // the id alone could name both a user and a group, so match on the type too
+    fn key(p: &Permission) -> (ObjectType, &str) {
+        (p.object_type, p.id.as_str())
+    }
+    let before_keys: HashMap<(ObjectType, &str), &Permission> =
+        before.iter().map(|p| (key(p), p)).collect();
+    let after_keys: HashSet<(ObjectType, &str)> = after.iter().map(key).collect();
+
+    let mut changes: Vec<Change> = Vec::new();
+    for p in after {
+        match before_keys.get(&key(p)) {
+            Some(b) if b.permission == p.permission => changes.push(Change::Unchanged(p.clone())),
+            Some(b) => changes.push(Change::Update {
+                before: (*b).clone(),
+                after: p.clone(),
+            }),
+            None => changes.push(Change::Add(p.clone())),
+        }
+    }
+    for p in before {
+        if !after_keys.contains(&key(p)) {
+            changes.push(Change::Remove(p.clone()));
+        }
+    }
+    changes
+}
+
+pub async fn diff(a: Bitbucket, b: Bitbucket, options: Options) -> Result<Vec<Change>, BbdanError> {
+    let permissions_a = considered(list(a).await?, options);
+    let permissions_b = considered(list(b).await?, options);
+    Ok(diff_permissions(&permissions_a, &permissions_b))
+}
+
+impl Change {
+    /// The change turning the permissions back to what they were before this one
+    pub fn reverse(&self) -> Change {
+        match self {
+            Change::Add(p) => Change::Remove(p.clone()),
+            Change::Update { before, after } => Change::Update {
+                before: after.clone(),
+                after: before.clone(),
+            },
+            Change::Remove(p) => Change::Add(p.clone()),
+            Change::Unchanged(p) => Change::Unchanged(p.clone()),
+        }
+    }
+}
+
+/// Which changes copy makes to the destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum CopyMode {
+    /// Only grant what the destination lacks
+    Additive,
+    /// Add, update and remove until the destination has the grants of the source
+    #[default]
+    Mirror,
+    /// Only remove the grants the source does not have
+    Prune,
+}
+
+/// Which of the changes turning a repository into the desired permissions are made
+#[derive(Debug, Clone, Default, ClapArgs)]
+pub struct ChangeFilter {
+    /// What to change in the destination: additive only grants what it lacks, mirror also updates and removes, prune only removes the grants the source lacks
+    #[clap(long, arg_enum, value_name = "MODE", default_value = "mirror")]
+    pub mode: CopyMode,
+    /// Only ever raise levels: grant and upgrade, never downgrade or remove
+    #[clap(long, conflicts_with = "downgrade-only")]
+    pub upgrade_only: bool,
+    /// Only ever lower levels: downgrade and remove, never grant or upgrade
+    #[clap(long)]
+    pub downgrade_only: bool,
+    /// Only change user grants
+    #[clap(long, conflicts_with = "groups-only")]
+    pub users_only: bool,
+    /// Only change group grants
+    #[clap(long)]
+    pub groups_only: bool,
+    /// Only change the users and groups whose UUID, nickname, slug or name matches this glob, e.g. 'team-*'. Can be repeated
+    #[clap(long, value_name = "PATTERN", value_parser = glob_matcher, multiple_occurrences = true)]
+    pub include: Vec<GlobMatcher>,
+    /// Leave the users and groups matching this glob alone. Can be repeated
+    #[clap(long, value_name = "PATTERN", value_parser = glob_matcher, multiple_occurrences = true)]
+    pub exclude: Vec<GlobMatcher>,
+}
+
+/// Compile the glob of --include or --exclude while the arguments are parsed
+pub fn glob_matcher(pattern: &str) -> Result<GlobMatcher, globset::Error> {
+    Ok(Glob::new(pattern)?.compile_matcher())
+}
+
+impl ChangeFilter {
+    /// Whether the change is made
+    pub fn allows(&self, change: &Change) -> bool {
+        // a missing grant is below read
+        let (p, raises) = match change {
+            Change::Add(p) => (p, true),
+            Change::Update { before, after } => (after, after.permission > before.permission),
+            Change::Remove(p) => (p, false),
+            Change::Unchanged(_) => return true,
+        };
+        let mode = match self.mode {
+            CopyMode::Mirror => true,
+            CopyMode::Additive => matches!(change, Change::Add(_)),
+            CopyMode::Prune => matches!(change, Change::Remove(_)),
+        };
+        let direction = !(self.upgrade_only && !raises || self.downgrade_only && raises);
+        let object_type = match p.object_type {
+            ObjectType::User => !self.groups_only,
+            ObjectType::Group => !self.users_only,
+        };
+        let matches = |globs: &[GlobMatcher]| {
+            globs
+                .iter()
+                .any(|glob| glob.is_match(&p.id) || glob.is_match(&p.alias))
+        };
+        let principal =
+            (self.include.is_empty() || matches(&self.include)) && !matches(&self.exclude);
+        mode && direction && object_type && principal
+    }
+
+    /// The changes that are made, logging the others
+    pub fn filter(&self, changes: Vec<Change>) -> Vec<Change> {
+        changes
+            .into_iter()
+            .filter(|change| {
+                let allowed = self.allows(change);
+                if !allowed {
+                    tracing::debug!("Leave: {}", describe(change));
+                }
+                allowed
+            })
+            .collect()
+    }
+}
+
+/// How merge picks the level of a user or group the sources grant different levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum Conflict {
+    /// Take the highest level
+    #[default]
+    Highest,
+    /// Ask which level to take, taking the highest with --yes
+    Prompt,
+}
+
+/// Union of the grants of the sources, one per user and group in the order first seen
+pub fn merge_permissions(
+    sources: &[(String, Vec<Permission>)],
+    conflict: Conflict,
+    options: Options,
+) -> Result<Vec<Permission>, BbdanError> {
+    // the grant of each user and group by each source
+    let mut grants: Vec<Vec<(&str, &Permission)>> = Vec::new();
+    for (repo, permissions) in sources {
+        for p in permissions {
+            match grants
+                .iter_mut()
+                .find(|g| g[0].1.object_type == p.object_type && g[0].1.id == p.id)
+            {
+                Some(g) => g.push((repo, p)),
+                None => grants.push(vec![(repo, p)]),
+            }
+        }
+    }
+
+    let mut merged = Vec::new();
+    for g in grants {
+        let highest = g
+            .iter()
+            .map(|(_, p)| *p)
+            .max_by_key(|p| p.permission)
+            .expect("every user and group has a grant");
+        let conflicting = g.iter().any(|(_, p)| p.permission != highest.permission);
+        if !conflicting || conflict == Conflict::Highest || options.yes || options.dry_run {
+            merged.push(highest.clone());
+            continue;
+        }
+        let items: Vec<String> = g
+            .iter()
+            .map(|(repo, p)| format!("{} ({})", permission_type_to_str(p.permission), repo))
+            .collect();
+        let selection = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "Level of id={}, name={}",
+                highest.id, highest.alias
+            ))
+            .items(&items)
+            .default(
+                g.iter()
+                    .position(|(_, p)| p.permission == highest.permission)
+                    .unwrap_or_default(),
+            )
+            .interact_opt()?
+            .ok_or(BbdanError::Aborted)?;
+        merged.push(g[selection].1.clone());
+    }
+    Ok(merged)
+}
+
+/// Copy the union of the grants of the sources and dest itself to dest,
+/// so the grants only dest has are kept whatever the mode
+pub async fn merge(
+    srcs: &[Bitbucket],
+    dest: Bitbucket,
+    conflict: Conflict,
+    filter: &ChangeFilter,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let mut sources = Vec::new();
+    for src in srcs {
+        sources.push((src.slug.to_string(), list(src.clone()).await?));
+    }
+    if !srcs.iter().any(|src| src.slug == dest.slug) {
+        sources.push((dest.slug.to_string(), list(dest.clone()).await?));
+    }
+    let merged = merge_permissions(&sources, conflict, options)?;
+    reconcile(dest, &merged, filter, options).await
+}
+
+/// Number of changes made by reconcile
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Summary {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    pub skipped: usize,
+}
+
+impl Summary {
+    /// Whether anything was (or in a dry run would be) added, updated or removed
+    pub fn has_changes(&self) -> bool {
+        self.added + self.updated + self.removed > 0
+    }
+}
+
+impl std::ops::AddAssign for Summary {
+    fn add_assign(&mut self, other: Summary) {
+        self.added += other.added;
+        self.updated += other.updated;
+        self.removed += other.removed;
+        self.unchanged += other.unchanged;
+        self.skipped += other.skipped;
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "added={}, updated={}, removed={}, unchanged={}, skipped={}",
+            self.added, self.updated, self.removed, self.unchanged, self.skipped
+        )
+    }
+}
+
+/// Copy the permissions of src to dest, translating the groups and users of the mapping
+/// when they are in different workspaces
+pub async fn copy(
+    src: Bitbucket,
+    dest: Bitbucket,
+    mapping: &Mapping,
+    filter: &ChangeFilter,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let permissions_src = mapping.translate(&dest, list(src).await?).await?;
+    reconcile(dest, &permissions_src, filter, options).await
+}
+
+/// Copy the permissions of src to every destination, listing src only once,
+/// and return the result per destination
+pub async fn copy_to_many(
+    src: Bitbucket,
+    dests: &[Bitbucket],
+    mapping: &Mapping,
+    filter: &ChangeFilter,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    let Some(first) = dests.first() else {
+        return Ok(Vec::new());
+    };
+    // the destinations are in one workspace
+    let permissions_src = mapping.translate(first, list(src).await?).await?;
+
+    let mut results = Vec::new();
+    for dest in dests {
+        tracing::info!("Repository: {}", dest.slug);
+        let result = reconcile(dest.clone(), &permissions_src, filter, options)
+            .await
+            .map_err(Arc::new);
+        results.push((dest.slug.to_string(), result));
+    }
+    Ok(results)
+}
+
+/// Send the request making the change
+pub async fn apply(dest: &Bitbucket, change: Change, options: Options) -> Result<(), BbdanError> {
+    if !options.dry_run {
+        journal::ready()?;
+    }
+    match &change {
+        Change::Add(p) | Change::Update { after: p, .. } => {
+            put_permission(dest, p.object_type, &p.id, p.permission, options).await?
+        }
+        Change::Remove(p) => delete_permission(dest, p.object_type, &p.id, options).await?,
+        Change::Unchanged(_) => return Ok(()),
+    }
+    if !options.dry_run {
+        // the change is live either way, so it is still reported as applied and rolled back on failure
+        if let Err(e) = journal::record(dest, &change) {
+            tracing::warn!("{}", e);
+        }
+        if let Some(history) = &dest.history {
+            history.applied(dest, &change).await;
+        }
+    }
+    Ok(())
+}
+
+/// Apply the permissions to the repository, confirming each add, update and remove the filter allows
+pub async fn reconcile(
+    dest: Bitbucket,
+    desired: &[Permission],
+    filter: &ChangeFilter,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let permissions_before = considered(list(dest.clone()).await?, options);
+    let changes = diff_permissions(&permissions_before, &considered(desired.to_vec(), options));
+    apply_changes(dest, filter.filter(changes), options).await
+}
+
+/// Confirm the adds, updates and removes on one screen, then apply the confirmed ones to the repository.
+/// Nothing is changed when there are more changes than --max-changes. Protected grants are never removed.
+pub async fn apply_changes(
+    dest: Bitbucket,
+    changes: Vec<Change>,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    // protected grants are left out before counting, they are never removed
+    let (protected, changes): (Vec<Change>, Vec<Change>) = changes
+        .into_iter()
+        .partition(|c| matches!(c, Change::Remove(p) if options.is_protected(p)));
+    let pending = changes
+        .iter()
+        .filter(|c| !matches!(c, Change::Unchanged(_)))
+        .count();
+    options.check_max_changes(&dest.slug, pending)?;
+
+    let mut summary = Summary {
+        skipped: protected.len(),
+        ..Summary::default()
+    };
+    let mut pending: Vec<(Change, String)> = Vec::new();
+    for change in changes {
+        let message = match &change {
+            Change::Unchanged(p) => {
+                tracing::info!("Not change: id={}, name={}", p.id, p.alias);
+                summary.unchanged += 1;
+                continue;
+            }
+            Change::Update { before, after } => format!(
+                "Permission update: id={}, name={}, before={}, after={}.",
+                after.id,
+                after.alias,
+                permission_type_to_str(before.permission),
+                permission_type_to_str(after.permission),
+            ),
+            Change::Add(p) => format!("Add: id={}, name={}.", p.id, p.alias),
+            Change::Remove(p) => format!("Remove: id={}, name={}.", p.id, p.alias),
+        };
+        pending.push((change, message));
+    }
+
+    let messages: Vec<String> = pending.iter().map(|(_, m)| m.to_string()).collect();
+    let mut confirmed: Vec<Change> = Vec::new();
+    for ((change, message), selected) in pending.into_iter().zip(confirm_each(&messages, options)?)
+    {
+        if !selected {
+            tracing::info!("Skip: {}", message);
+            summary.skipped += 1;
+            continue;
+        }
+        match change {
+            Change::Add(_) => summary.added += 1,
+            Change::Update { .. } => summary.updated += 1,
+            Change::Remove(_) => summary.removed += 1,
+            Change::Unchanged(_) => {}
+        }
+        confirmed.push(change);
+    }
+
+    // apply the confirmed changes --parallel at a time. After the first failure no more are
+    // started, and those in flight are let finish so the applied ones are known.
+    let bar = progress::bar(confirmed.len(), &format!("Applying to {}", dest.slug));
+    let applied: Mutex<Vec<Change>> = Mutex::new(Vec::new());
+    let failure: Mutex<Option<BbdanError>> = Mutex::new(None);
+    futures::stream::iter(confirmed)
+        .for_each_concurrent(dest.parallel, |change| async {
+            if failure.lock().unwrap().is_some() {
+                return;
+            }
+            match apply(&dest, change.clone(), options).await {
+                Ok(()) => {
+                    applied.lock().unwrap().push(change);
+                    bar.inc(1);
+                }
+                Err(e) => {
+                    failure.lock().unwrap().get_or_insert(e);
+                }
+            }
+        })
+        .await;
+    bar.finish_and_clear();
+
+    if let Some(e) = failure.into_inner().unwrap() {
+        rollback(&dest, applied.into_inner().unwrap(), options).await;
+        return Err(e);
+    }
+    Ok(summary)
+}
+
+/// Offer to revert the changes applied before a failure, so the repository is not left half changed.
+/// The outcome is logged, the failure is what the caller reports.
+async fn rollback(dest: &Bitbucket, applied: Vec<Change>, options: Options) {
+    if applied.is_empty() || options.dry_run {
+        return;
+    }
+    let message = format!(
+        "Rollback: revert the {} changes applied to {} before the failure.",
+        applied.len(),
+        dest.slug
+    );
+    match confirm(message, options) {
+        Ok(true) => {}
+        Ok(false) | Err(_) => {
+            tracing::warn!(
+                "{} is left with {} of the changes applied",
+                dest.slug,
+                applied.len()
+            );
+            return;
+        }
+    }
+
+    // newest first, one at a time, so each change is reverted on top of the state it left
+    for (i, change) in applied.iter().rev().enumerate() {
+        if let Err(e) = apply(dest, change.reverse(), options).await {
+            tracing::error!(
+                "Rollback of {} failed, {} of the changes are left applied: {}",
+                dest.slug,
+                applied.len() - i,
+                e
+            );
+            return;
+        }
+    }
+    tracing::warn!(
+        "Rolled back the {} changes applied to {}",
+        applied.len(),
+        dest.slug
+    );
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+
+use crate::matrix::Matrix;
+use crate::output::escape_xml as escape;
+use crate::permissions::{object_type_to_str, permission_type_to_str, Permission, PermissionType};
+use crate::repositories::AuditResult;
+
+/// Sorts a table by the clicked header, toggling the order on a second click
+const SORT_SCRIPT: &str = r#"
+document.querySelectorAll("table.sortable th").forEach((th, column) => {
+  th.addEventListener("click", () => {
+    const tbody = th.closest("table").querySelector("tbody");
+    const desc = th.dataset.order === "asc";
+    th.closest("tr").querySelectorAll("th").forEach(h => delete h.dataset.order);
+    th.dataset.order = desc ? "desc" : "asc";
+    const rows = Array.from(tbody.rows);
+    rows.sort((a, b) => {
+      const x = a.cells[column].dataset.sort || a.cells[column].textContent;
+      const y = b.cells[column].dataset.sort || b.cells[column].textContent;
+      const order = x.localeCompare(y, undefined, { numeric: true });
+      return desc ? -order : order;
+    });
+    rows.forEach(row => tbody.appendChild(row));
+  });
+});
+"#;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.8em; text-align: left; }
+table.sortable th { cursor: pointer; background: #f4f4f4; }
+table.sortable th[data-order="asc"]::after { content: " \25B2"; }
+table.sortable th[data-order="desc"]::after { content: " \25BC"; }
+.admin { color: #b00020; font-weight: bold; }
+.write { color: #a66a00; }
+.read { color: #2e7d32; }
+.error { color: #b00020; }
+"#;
+
+/// Self-contained HTML report of an audit: a summary followed by sortable tables
+pub fn html(workspace: &str, results: &[AuditResult]) -> String {
+    let failed: Vec<(&str, String)> = results
+        .iter()
+        .filter_map(|(repo, result)| Some((repo.as_str(), result.as_ref().err()?.to_string())))
+        .collect();
+    let grants: Vec<(&str, &Permission)> = results
+        .iter()
+        .flat_map(|(repo, result)| result.iter().flatten().map(move |p| (repo.as_str(), p)))
+        .collect();
+
+    let mut levels: BTreeMap<PermissionType, usize> = BTreeMap::new();
+    // principal -> repositories it administers
+    let mut admins: BTreeMap<(String, String, String), Vec<&str>> = BTreeMap::new();
+    for (repo, p) in &grants {
+        *levels.entry(p.permission).or_default() += 1;
+        if p.permission == PermissionType::Admin {
+            let principal = (
+                object_type_to_str(p.object_type),
+                p.alias.to_string(),
+                p.id.to_string(),
+            );
+            admins.entry(principal).or_default().push(repo);
+        }
+    }
+
+    let mut out = String::new();
+    out += "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n";
+    out += &format!("<title>Permissions of {}</title>\n", escape(workspace));
+    out += &format!("<style>{}</style>\n</head>\n<body>\n", STYLE);
+    out += &format!("<h1>Permissions of {}</h1>\n", escape(workspace));
+
+    out += "<h2>Summary</h2>\n<table>\n<tbody>\n";
+    out += &format!("<tr><th>Repositories</th><td>{}</td></tr>\n", results.len());
+    if !failed.is_empty() {
+        out += &format!(
+            "<tr><th>Failed</th><td class=\"error\">{}</td></tr>\n",
+            failed.len()
+        );
+    }
+    out += &format!("<tr><th>Grants</th><td>{}</td></tr>\n", grants.len());
+    for level in [
+        PermissionType::Admin,
+        PermissionType::Write,
+        PermissionType::Read,
+    ] {
+        let level_str = permission_type_to_str(level);
+        out += &format!(
+            "<tr><th class=\"{0}\">{0}</th><td>{1}</td></tr>\n",
+            level_str,
+            levels.get(&level).copied().unwrap_or_default()
+        );
+    }
+    out += "</tbody>\n</table>\n";
+
+    out += "<h2>Admin holders</h2>\n";
+    out += "<table class=\"sortable\">\n<thead><tr><th>type</th><th>alias</th><th>id</th><th>repositories</th><th>count</th></tr></thead>\n<tbody>\n";
+    for ((object_type, alias, id), repos) in &admins {
+        out += &format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            object_type,
+            escape(alias),
+            escape(id),
+            escape(&repos.join(", ")),
+            repos.len()
+        );
+    }
+    out += "</tbody>\n</table>\n";
+
+    if !failed.is_empty() {
+        out += "<h2>Failed repositories</h2>\n<ul>\n";
+        for (repo, e) in &failed {
+            out += &format!(
+                "<li>{}: <span class=\"error\">{}</span></li>\n",
+                escape(repo),
+                escape(e)
+            );
+        }
+        out += "</ul>\n";
+    }
+
+    out += "<h2>Permissions</h2>\n";
+    out += "<table class=\"sortable\">\n<thead><tr><th>repository</th><th>type</th><th>id</th><th>alias</th><th>permission</th></tr></thead>\n<tbody>\n";
+    for (repo, p) in &grants {
+        let level = permission_type_to_str(p.permission);
+        out += &format!(
+            // sort levels by rank rather than alphabetically
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"{}\" data-sort=\"{}\">{}</td></tr>\n",
+            escape(repo),
+            object_type_to_str(p.object_type),
+            escape(&p.id),
+            escape(&p.alias),
+            level,
+            p.permission as u8,
+            level
+        );
+    }
+    out += "</tbody>\n</table>\n";
+
+    out += &format!("<script>{}</script>\n</body>\n</html>\n", SORT_SCRIPT);
+    out
+}
+
+/// Self-contained HTML page of the permission matrix, a sortable row per repository and a column per principal
+pub fn matrix(workspace: &str, matrix: &Matrix) -> String {
+    let mut out = String::new();
+    out += "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n";
+    out += &format!(
+        "<title>Permission matrix of {}</title>\n",
+        escape(workspace)
+    );
+    out += &format!("<style>{}</style>\n</head>\n<body>\n", STYLE);
+    out += &format!("<h1>Permission matrix of {}</h1>\n", escape(workspace));
+
+    out += "<table class=\"sortable\">\n<thead><tr><th>repository</th>";
+    for principal in &matrix.principals {
+        out += &format!(
+            "<th title=\"{}\">{}</th>",
+            escape(&principal.id),
+            escape(&principal.label())
+        );
+    }
+    out += "</tr></thead>\n<tbody>\n";
+    for (repo, levels) in &matrix.rows {
+        out += &format!("<tr><td>{}</td>", escape(repo));
+        for level in levels {
+            match level {
+                // sort levels by rank rather than alphabetically, no grant first
+                Some(level) => {
+                    out += &format!(
+                        "<td class=\"{0}\" data-sort=\"{1}\">{0}</td>",
+                        permission_type_to_str(*level),
+                        *level as u8 + 1
+                    )
+                }
+                None => out += "<td data-sort=\"0\"></td>",
+            }
+        }
+        out += "</tr>\n";
+    }
+    out += "</tbody>\n</table>\n";
+
+    out += &format!("<script>{}</script>\n</body>\n</html>\n", SORT_SCRIPT);
+    out
+}
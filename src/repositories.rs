@@ -0,0 +1,342 @@
+use clap::Args as ClapArgs;
+use dialoguer::{theme::ColorfulTheme, FuzzySelect};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::Instrument;
+
+use crate::client::{Bitbucket, Page};
+use crate::error::{error_for_status, parse_json, BbdanError};
+use crate::permissions::{list, Options, Permission};
+use crate::progress;
+use crate::telemetry::SPAN_TARGET;
+
+/// Columns of the repositories in list
+pub const HEADER: &[&str] = &["slug", "project", "updated_on", "is_private"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Repository {
+    pub slug: String,
+    pub project: String,
+    pub updated_on: String,
+    pub is_private: bool,
+}
+
+impl Repository {
+    /// The repository as a row of [`HEADER`]
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.slug.to_string(),
+            self.project.to_string(),
+            self.updated_on.to_string(),
+            self.is_private.to_string(),
+        ]
+    }
+}
+
+/// An item of repositories/{workspace}
+#[derive(Debug, Deserialize)]
+struct RepositoryItem {
+    slug: String,
+    project: Option<ProjectRef>,
+    #[serde(default)]
+    updated_on: String,
+    #[serde(default)]
+    is_private: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectRef {
+    key: String,
+}
+
+/// Paging controls for commands enumerating many repositories
+#[derive(Debug, Clone, Default, ClapArgs)]
+pub struct Paging {
+    /// Stop after this many repositories
+    #[clap(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub limit: Option<usize>,
+    /// Start from this page
+    #[clap(long, value_name = "N", conflicts_with = "cursor")]
+    pub page: Option<u32>,
+    /// Resume from the cursor printed by a previous run stopped by --limit
+    #[clap(long, value_name = "CURSOR")]
+    pub cursor: Option<String>,
+}
+
+/// Repositories of the workspace selected by project or name for bulk commands
+#[derive(Debug, Clone, Default, ClapArgs)]
+pub struct Selector {
+    /// Operate on the repositories of the project with this key
+    #[clap(long, value_name = "KEY")]
+    pub project: Option<String>,
+    /// Operate on the repositories of the workspace whose slug matches this glob, e.g. 'team-a-*'
+    #[clap(long = "match", value_name = "PATTERN")]
+    pub pattern: Option<String>,
+    /// Match --match as a regular expression instead of a glob
+    #[clap(long, requires = "pattern")]
+    pub regex: bool,
+}
+
+impl Selector {
+    pub fn is_empty(&self) -> bool {
+        self.project.is_none() && self.pattern.is_none()
+    }
+
+    /// Slugs of the repositories in the workspace matching the selector
+    pub async fn select(&self, bitbucket: &Bitbucket) -> Result<Vec<String>, BbdanError> {
+        let matcher: Box<dyn Fn(&str) -> bool> = match &self.pattern {
+            None => Box::new(|_| true),
+            Some(pattern) if self.regex => {
+                let regex = regex::Regex::new(pattern).map_err(|e| {
+                    BbdanError::Invalid(format!("invalid regex {}: {}", pattern, e))
+                })?;
+                Box::new(move |slug| regex.is_match(slug))
+            }
+            Some(pattern) => {
+                let glob = globset::Glob::new(pattern)
+                    .map_err(|e| BbdanError::Invalid(format!("invalid glob {}: {}", pattern, e)))?
+                    .compile_matcher();
+                Box::new(move |slug| glob.is_match(slug))
+            }
+        };
+
+        // the project is filtered by the API, the name here
+        let query = self
+            .project
+            .as_ref()
+            .map(|key| format!("project.key=\"{}\"", key.replace('"', "\\\"")));
+        let (repositories, _) =
+            search_repositories(bitbucket, &Paging::default(), query.as_deref()).await?;
+        let slugs: Vec<String> = repositories
+            .into_iter()
+            .map(|r| r.slug)
+            .filter(|slug| matcher(slug))
+            .collect();
+        if slugs.is_empty() {
+            let mut selection = Vec::new();
+            if let Some(project) = &self.project {
+                selection.push(format!("project {}", project));
+            }
+            if let Some(pattern) = &self.pattern {
+                selection.push(pattern.to_string());
+            }
+            return Err(BbdanError::Invalid(format!(
+                "no repositories in workspace {} match {}",
+                bitbucket.workspace,
+                selection.join(" and ")
+            )));
+        }
+        tracing::info!("Selected {} repositories", slugs.len());
+        Ok(slugs)
+    }
+}
+
+/// Separates the page URL and the number of items to skip in a cursor
+pub const CURSOR_OFFSET: &str = "#offset=";
+
+/// List repositories in the workspace, following the pagination.
+/// When --limit stops the listing early, a cursor to resume from is returned as well.
+pub async fn list_repositories(
+    bitbucket: &Bitbucket,
+    paging: &Paging,
+) -> Result<(Vec<Repository>, Option<String>), BbdanError> {
+    search_repositories(bitbucket, paging, None).await
+}
+
+/// List repositories in the workspace matching the query of the API, e.g. `project.key="PROJ"`
+pub async fn search_repositories(
+    bitbucket: &Bitbucket,
+    paging: &Paging,
+    query: Option<&str>,
+) -> Result<(Vec<Repository>, Option<String>), BbdanError> {
+    let client = bitbucket.client();
+
+    let (mut next, mut skip) = match &paging.cursor {
+        Some(cursor) => match cursor.split_once(CURSOR_OFFSET) {
+            Some((url, offset)) => (
+                Some(url.to_string()),
+                offset
+                    .parse()
+                    .map_err(|_| BbdanError::Invalid(format!("invalid cursor: {}", cursor)))?,
+            ),
+            None => (Some(cursor.to_string()), 0),
+        },
+        None => {
+            let mut url = client.url(&format!(
+                "repositories/{}?pagelen={}&page={}",
+                bitbucket.workspace,
+                bitbucket.pagelen,
+                paging.page.unwrap_or(1)
+            ));
+            if let Some(query) = query {
+                let mut parsed = reqwest::Url::parse(&url)
+                    .map_err(|e| BbdanError::Invalid(format!("invalid URL {}: {}", url, e)))?;
+                parsed.query_pairs_mut().append_pair("q", query);
+                url = parsed.to_string();
+            }
+            (Some(url), 0)
+        }
+    };
+
+    let spinner = progress::spinner("Listing repositories");
+    let mut repositories: Vec<Repository> = Vec::new();
+    while let Some(url) = next {
+        let resp = error_for_status(client.http_get_url(url.to_string()).await?)?;
+        let page: Page<RepositoryItem> = parse_json(resp).await?;
+        next = page.next;
+
+        let count = page.values.len();
+        for (i, v) in page.values.into_iter().enumerate().skip(skip) {
+            repositories.push(Repository {
+                slug: v.slug,
+                project: v.project.map(|p| p.key).unwrap_or_default(),
+                updated_on: v.updated_on,
+                is_private: v.is_private,
+            });
+            spinner.inc(1);
+
+            if paging.limit == Some(repositories.len()) {
+                let cursor = if i + 1 < count {
+                    Some(format!("{}{}{}", url, CURSOR_OFFSET, i + 1))
+                } else {
+                    next
+                };
+                spinner.finish_and_clear();
+                return Ok((repositories, cursor));
+            }
+        }
+        skip = 0;
+    }
+    spinner.finish_and_clear();
+    Ok((repositories, None))
+}
+
+/// Pick a repository of the workspace with a fuzzy-searchable selector, for commands whose repo is omitted
+pub async fn pick_repository(
+    bitbucket: &Bitbucket,
+    options: Options,
+) -> Result<String, BbdanError> {
+    if options.yes {
+        return Err(BbdanError::Invalid(
+            "repo is required in non-interactive mode".to_string(),
+        ));
+    }
+    let (repositories, _) = list_repositories(bitbucket, &Paging::default()).await?;
+    if repositories.is_empty() {
+        return Err(BbdanError::Invalid(format!(
+            "no repositories in workspace {}",
+            bitbucket.workspace
+        )));
+    }
+
+    let slugs: Vec<&str> = repositories.iter().map(|r| r.slug.as_str()).collect();
+    let selection = FuzzySelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Pick a repository")
+        .items(&slugs)
+        .default(0)
+        .interact_opt()?
+        .ok_or(BbdanError::Aborted)?;
+    Ok(slugs[selection].to_string())
+}
+
+/// Repository slugs listed one per line in the file, or in stdin for `-`
+pub fn read_slugs(path: &Path) -> Result<Vec<String>, BbdanError> {
+    let content = if path == Path::new("-") {
+        let mut content = String::new();
+        io::stdin().read_to_string(&mut content)?;
+        content
+    } else {
+        std::fs::read_to_string(path)?
+    };
+    parse_slugs(&content)
+}
+
+/// Repository slugs, one per line. Blank lines and `#` comments are skipped;
+/// any other line that is not a slug is an error rather than a repository silently dropped.
+pub fn parse_slugs(content: &str) -> Result<Vec<String>, BbdanError> {
+    let mut slugs = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let slug = line.trim();
+        if slug.is_empty() || slug.starts_with('#') {
+            continue;
+        }
+        if !slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            return Err(BbdanError::Invalid(format!(
+                "line {} is not a repository slug: {}",
+                i + 1,
+                slug
+            )));
+        }
+        slugs.push(slug.to_string());
+    }
+    Ok(slugs)
+}
+
+/// Permissions of a repository, or the error listing them, shared so the results can be cloned
+pub type AuditResult = (String, Result<Vec<Permission>, Arc<BbdanError>>);
+
+/// List the permissions of every repository in the workspace, --parallel repositories at a time.
+/// `on_result` is called as soon as each repository is done, in the order they finish.
+pub async fn audit(
+    bitbucket: Bitbucket,
+    paging: &Paging,
+    on_result: impl FnMut(&AuditResult),
+) -> Result<(Vec<AuditResult>, Option<String>), BbdanError> {
+    let (repositories, cursor) = list_repositories(&bitbucket, paging).await?;
+    let slugs = repositories.into_iter().map(|r| r.slug).collect();
+    let results = audit_repositories(bitbucket, slugs, on_result).await;
+    Ok((results, cursor))
+}
+
+/// List the permissions of the repositories, --parallel repositories at a time.
+/// `on_result` is called as soon as each repository is done, in the order they finish.
+pub async fn audit_repositories(
+    bitbucket: Bitbucket,
+    slugs: Vec<String>,
+    mut on_result: impl FnMut(&AuditResult),
+) -> Vec<AuditResult> {
+    let bar = progress::bar(slugs.len(), "Auditing");
+    let failures = AtomicUsize::new(0);
+    let mut results: Vec<(usize, AuditResult)> = Vec::new();
+    let mut stream = futures::stream::iter(slugs.into_iter().enumerate())
+        .map(|(i, slug)| {
+            let target = Bitbucket {
+                slug,
+                ..bitbucket.clone()
+            };
+            let (bar, failures) = (&bar, &failures);
+            let span =
+                tracing::info_span!(target: SPAN_TARGET, "repository", repository = %target.slug);
+            async move {
+                let slug = target.slug.to_string();
+                let result = list(target).await.map_err(Arc::new);
+                if result.is_err() {
+                    let failed = failures.fetch_add(1, Ordering::Relaxed) + 1;
+                    bar.set_message(format!("Auditing, {} failed", failed));
+                }
+                bar.inc(1);
+                (i, (slug, result))
+            }
+            .instrument(span)
+        })
+        .buffer_unordered(bitbucket.parallel);
+    while let Some((i, result)) = stream.next().await {
+        on_result(&result);
+        results.push((i, result));
+    }
+    bar.finish_and_clear();
+    // keep the order of the repository listing
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
@@ -0,0 +1,305 @@
+use dialoguer::{theme::ColorfulTheme, MultiSelect};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::client::{Bitbucket, Page};
+use crate::error::{error_for_status, parse_json, BbdanError};
+use crate::journal;
+use crate::permissions::{confirm_each, Options};
+use crate::reconcile::Summary;
+
+// Settings of a repository other than its permissions, e.g. branch restrictions
+
+/// Every item of a paginated resource of the repository, e.g. `branch-restrictions`
+pub async fn list_all<T: DeserializeOwned>(
+    bitbucket: &Bitbucket,
+    path: &str,
+) -> Result<Vec<T>, BbdanError> {
+    let client = bitbucket.client();
+    let separator = if path.contains('?') { '&' } else { '?' };
+    let mut next = Some(bitbucket.repository_url(&format!(
+        "{}{}pagelen={}",
+        path, separator, bitbucket.pagelen
+    )));
+    let mut items = Vec::new();
+    while let Some(url) = next {
+        let resp = error_for_status(client.http_get_url(url).await?)?;
+        let page: Page<T> = parse_json(resp).await?;
+        items.extend(page.values);
+        next = page.next;
+    }
+    Ok(items)
+}
+
+/// A resource of the repository that is a single object, e.g. `pipelines_config`
+pub async fn get<T: DeserializeOwned>(bitbucket: &Bitbucket, path: &str) -> Result<T, BbdanError> {
+    let resp = error_for_status(
+        bitbucket
+            .client()
+            .http_get_url(bitbucket.repository_url(path))
+            .await?,
+    )?;
+    parse_json(resp).await
+}
+
+/// Create an item of a resource of the repository
+pub async fn post<T: Serialize + ?Sized>(
+    bitbucket: &Bitbucket,
+    path: &str,
+    body: &T,
+    options: Options,
+) -> Result<(), BbdanError> {
+    let url = bitbucket.repository_url(path);
+    if options.dry_run {
+        tracing::info!("POST {} {} (dry-run)", url, serde_json::to_string(body)?);
+        return Ok(());
+    }
+    tracing::info!("POST {}", url);
+    journal::ready()?;
+
+    let resp = error_for_status(bitbucket.client().http_post_url(url.clone(), body).await?)?;
+    journal(bitbucket, "POST", &url, Some(serde_json::to_value(body)?));
+    let result: Value = resp.json().await?;
+    tracing::debug!("result: {}", result);
+    Ok(())
+}
+
+/// Create or replace a resource of the repository
+pub async fn put<T: Serialize + ?Sized>(
+    bitbucket: &Bitbucket,
+    path: &str,
+    body: &T,
+    options: Options,
+) -> Result<(), BbdanError> {
+    let url = bitbucket.repository_url(path);
+    if options.dry_run {
+        tracing::info!("PUT {} {} (dry-run)", url, serde_json::to_string(body)?);
+        return Ok(());
+    }
+    tracing::info!("PUT {}", url);
+    journal::ready()?;
+
+    let resp = error_for_status(bitbucket.client().http_put_url(url.clone(), body).await?)?;
+    journal(bitbucket, "PUT", &url, Some(serde_json::to_value(body)?));
+    tracing::debug!("result: {}", resp.status());
+    Ok(())
+}
+
+/// Delete a resource of the repository
+pub async fn delete(bitbucket: &Bitbucket, path: &str, options: Options) -> Result<(), BbdanError> {
+    let url = bitbucket.repository_url(path);
+    if options.dry_run {
+        tracing::info!("DELETE {} (dry-run)", url);
+        return Ok(());
+    }
+    tracing::info!("DELETE {}", url);
+    journal::ready()?;
+
+    // DELETE responds with 204 No Content
+    let resp = error_for_status(bitbucket.client().http_delete_url(url.clone()).await?)?;
+    journal(bitbucket, "DELETE", &url, None);
+    tracing::debug!("result: {}", resp.status());
+    Ok(())
+}
+
+/// Journal a request just made. The change is live either way, so a failure is only warned about.
+pub(crate) fn journal(bitbucket: &Bitbucket, method: &str, url: &str, body: Option<Value>) {
+    if let Err(e) = journal::record_request(bitbucket, method, url, body) {
+        tracing::warn!("{}", e);
+    }
+}
+
+/// An item of a setting of repositories that can be listed, created and deleted, e.g. a branch restriction
+pub trait Resource: Clone + Serialize + DeserializeOwned {
+    /// Path of the items under the repository, e.g. `branch-restrictions`
+    const PATH: &'static str;
+    /// Created with a PUT to the path of the item rather than a POST to [`Resource::PATH`]
+    const PUT: bool = false;
+
+    /// Key of the item in its path, e.g. the id of a branch restriction
+    fn key(&self) -> Option<String>;
+
+    /// Whether both items are the same setting, whatever their keys
+    fn same(&self, other: &Self) -> bool;
+
+    /// The item in words, e.g. `kind=push, branches=main`
+    fn describe(&self) -> String;
+
+    /// The item to create in another repository, without what the API assigns to it
+    fn created(&self) -> Self {
+        self.clone()
+    }
+}
+
+/// Path of the item under the repository, e.g. `branch-restrictions/1`
+fn item_path<T: Resource>(path: &str, item: &T) -> Result<String, BbdanError> {
+    let key = item
+        .key()
+        .ok_or_else(|| BbdanError::Invalid(format!("{} has no key", item.describe())))?;
+    Ok(format!("{}/{}", path, key))
+}
+
+/// Create the item in the repository
+pub async fn create<T: Resource>(
+    bitbucket: &Bitbucket,
+    item: &T,
+    options: Options,
+) -> Result<(), BbdanError> {
+    create_at(bitbucket, T::PATH, item, options).await
+}
+
+/// Create the item among those at path rather than [`Resource::PATH`],
+/// for items under another item, e.g. the variables of an environment
+pub async fn create_at<T: Resource>(
+    bitbucket: &Bitbucket,
+    path: &str,
+    item: &T,
+    options: Options,
+) -> Result<(), BbdanError> {
+    let created = item.created();
+    if T::PUT {
+        put(bitbucket, &item_path(path, item)?, &created, options).await
+    } else {
+        post(bitbucket, path, &created, options).await
+    }
+}
+
+/// Delete the item from the repository
+pub async fn remove<T: Resource>(
+    bitbucket: &Bitbucket,
+    item: &T,
+    options: Options,
+) -> Result<(), BbdanError> {
+    remove_at(bitbucket, T::PATH, item, options).await
+}
+
+/// Delete the item from those at path rather than [`Resource::PATH`]
+pub async fn remove_at<T: Resource>(
+    bitbucket: &Bitbucket,
+    path: &str,
+    item: &T,
+    options: Options,
+) -> Result<(), BbdanError> {
+    delete(bitbucket, &item_path(path, item)?, options).await
+}
+
+/// Copy the items of src to each destination, with the result per destination.
+/// The items a destination already has are left as they are, and with `prune` those src lacks are removed.
+pub async fn copy<T: Resource>(
+    src: Bitbucket,
+    dests: &[Bitbucket],
+    prune: bool,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    let items: Vec<T> = list_all(&src, T::PATH).await?;
+    let mut results = Vec::new();
+    for dest in dests {
+        tracing::info!("Repository: {}", dest.slug);
+        let result = copy_items(dest, T::PATH, &items, prune, options)
+            .await
+            .map_err(Arc::new);
+        results.push((dest.slug.to_string(), result));
+    }
+    Ok(results)
+}
+
+/// Make the items at path of dest the same as `items`, leaving those it already has.
+/// Nothing is changed when there are more adds and removes than --max-changes.
+pub async fn copy_items<T: Resource>(
+    dest: &Bitbucket,
+    path: &str,
+    items: &[T],
+    prune: bool,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let current: Vec<T> = list_all(dest, path).await?;
+    let mut summary = Summary::default();
+
+    // (item, whether it is added rather than removed)
+    let mut changes: Vec<(&T, bool)> = Vec::new();
+    for item in items {
+        if !current.iter().any(|c| c.same(item)) {
+            changes.push((item, true));
+        }
+    }
+    for c in &current {
+        if prune && !items.iter().any(|item| item.same(c)) {
+            changes.push((c, false));
+        } else {
+            summary.unchanged += 1;
+        }
+    }
+
+    options.check_max_changes(&dest.slug, changes.len())?;
+
+    let messages: Vec<String> = changes
+        .iter()
+        .map(|(item, add)| match add {
+            true => format!("Add: {}.", item.describe()),
+            false => format!("Remove: {}.", item.describe()),
+        })
+        .collect();
+    for ((item, add), selected) in changes.into_iter().zip(confirm_each(&messages, options)?) {
+        if !selected {
+            summary.skipped += 1;
+        } else if add {
+            create_at(dest, path, item, options).await?;
+            summary.added += 1;
+        } else {
+            remove_at(dest, path, item, options).await?;
+            summary.removed += 1;
+        }
+    }
+    Ok(summary)
+}
+
+/// Remove the items `matches` selects, confirming them, or those picked interactively without it.
+/// Nothing is removed when more are selected than --max-changes.
+pub async fn remove_items<T: Resource>(
+    bitbucket: &Bitbucket,
+    matches: Option<&dyn Fn(&T) -> bool>,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let items: Vec<T> = list_all(bitbucket, T::PATH).await?;
+
+    let picked: Vec<&T> = match matches {
+        None => {
+            let described: Vec<String> = items.iter().map(|item| item.describe()).collect();
+            let selections = MultiSelect::with_theme(&ColorfulTheme::default())
+                .with_prompt("Pick what you want to remove")
+                .items(&described)
+                .interact_opt()?
+                .ok_or(BbdanError::Aborted)?;
+            options.check_max_changes(&bitbucket.slug, selections.len())?;
+            selections.into_iter().map(|i| &items[i]).collect()
+        }
+        Some(matches) => {
+            let matching: Vec<&T> = items.iter().filter(|item| matches(item)).collect();
+            options.check_max_changes(&bitbucket.slug, matching.len())?;
+            let messages: Vec<String> = matching
+                .iter()
+                .map(|item| format!("Remove: {}.", item.describe()))
+                .collect();
+            matching
+                .into_iter()
+                .zip(confirm_each(&messages, options)?)
+                .filter_map(|(item, selected)| selected.then_some(item))
+                .collect()
+        }
+    };
+
+    let mut summary = Summary {
+        unchanged: items.len() - picked.len(),
+        ..Summary::default()
+    };
+    if picked.is_empty() {
+        tracing::info!("You did not select anything :(");
+    }
+    for item in picked {
+        remove(bitbucket, item, options).await?;
+        summary.removed += 1;
+    }
+    Ok(summary)
+}
@@ -0,0 +1,136 @@
+use reqwest::{Response, StatusCode};
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+
+/// Scopes each command needs
+pub const REQUIRED_SCOPES: &[(&str, &[&str])] = &[
+    ("list", &["repository", "repository:admin"]),
+    ("copy", &["repository", "repository:admin"]),
+    ("remove", &["repository", "repository:admin"]),
+    ("branch-restrictions", &["repository:admin"]),
+    ("default-reviewers", &["repository:admin"]),
+    ("deploy-keys", &["repository:admin"]),
+    ("hooks", &["webhook"]),
+    ("pipelines-vars", &["pipeline:variable"]),
+    ("pipelines", &["repository:admin"]),
+    ("settings", &["repository:admin"]),
+    ("branching-model", &["repository:admin"]),
+    ("environments", &["repository:admin", "pipeline:variable"]),
+    ("create", &["repository:admin", "webhook"]),
+    ("repo", &["repository:delete", "repository:admin"]),
+    ("workspace-permissions", &["account"]),
+    ("groups", &["account"]),
+    (
+        "clone-setup",
+        &[
+            "repository",
+            "repository:admin",
+            "webhook",
+            "pipeline:variable",
+        ],
+    ),
+];
+
+/// Scopes granted to the credentials, read from the `X-OAuth-Scopes` response header
+pub fn granted_scopes(resp: &Response) -> Option<Vec<String>> {
+    let header = resp.headers().get("x-oauth-scopes")?.to_str().ok()?;
+    Some(
+        header
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect(),
+    )
+}
+
+pub fn has_scope(granted: &[String], scope: &str) -> bool {
+    granted.iter().any(|g| {
+        // write implies read, but admin implies neither of them
+        g == scope || (scope == "repository" && g == "repository:write")
+    })
+}
+
+/// Whether the credentials work with the workspace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Credentials {
+    Valid,
+    /// Rejected by the API
+    Invalid,
+    /// Valid, but the workspace is not accessible with them
+    NoWorkspaceAccess,
+}
+
+/// What verify found out about the credentials
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    pub credentials: Credentials,
+    /// Scopes granted to the credentials, none when the API does not tell
+    pub granted: Option<Vec<String>>,
+    /// Commands of REQUIRED_SCOPES and the scopes they miss, every command when the scopes are known
+    pub commands: Vec<(&'static str, Vec<&'static str>)>,
+    /// Status of reading the permissions of the repository, when it was checked
+    pub repository: Option<StatusCode>,
+}
+
+impl VerifyReport {
+    /// Whether nothing is missing
+    pub fn ok(&self) -> bool {
+        self.credentials == Credentials::Valid
+            && self.commands.iter().all(|(_, missing)| missing.is_empty())
+            && self.repository.is_none_or(|status| status.is_success())
+    }
+}
+
+/// Check the credentials, the scopes each command needs and, with `check_repo`, admin access to the repository
+pub async fn verify(bitbucket: Bitbucket, check_repo: bool) -> Result<VerifyReport, BbdanError> {
+    let client = bitbucket.client();
+
+    let resp = client
+        .http_get(format!(r#"repositories/{}?pagelen=1"#, bitbucket.workspace))
+        .await?;
+    let credentials = match resp.status() {
+        StatusCode::UNAUTHORIZED => Credentials::Invalid,
+        StatusCode::FORBIDDEN | StatusCode::NOT_FOUND => Credentials::NoWorkspaceAccess,
+        st if !st.is_success() => {
+            return Err(BbdanError::Http {
+                url: resp.url().to_string(),
+                status: st,
+            });
+        }
+        _ => Credentials::Valid,
+    };
+    let mut report = VerifyReport {
+        credentials,
+        granted: None,
+        commands: Vec::new(),
+        repository: None,
+    };
+    if credentials != Credentials::Valid {
+        return Ok(report);
+    }
+
+    report.granted = granted_scopes(&resp);
+    if let Some(granted) = &report.granted {
+        report.commands = REQUIRED_SCOPES
+            .iter()
+            .map(|(command, scopes)| {
+                let missing = scopes
+                    .iter()
+                    .filter(|s| !has_scope(granted, s))
+                    .copied()
+                    .collect();
+                (*command, missing)
+            })
+            .collect();
+    }
+
+    if check_repo {
+        let resp = client
+            .http_get_url(bitbucket.repository_url("permissions-config/users?pagelen=1"))
+            .await?;
+        report.repository = Some(resp.status());
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,202 @@
+use clap::ArgEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::{confirm_each, Options};
+use crate::reconcile::Summary;
+use crate::resources;
+
+/// Columns of the repository settings in show
+pub const HEADER: &[&str] = &["setting", "value"];
+
+/// A setting of the repository itself, copied by `settings copy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum Setting {
+    ForkPolicy,
+    Wiki,
+    Issues,
+    MainBranch,
+    Language,
+    Privacy,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 6] = [
+        Setting::ForkPolicy,
+        Setting::Wiki,
+        Setting::Issues,
+        Setting::MainBranch,
+        Setting::Language,
+        Setting::Privacy,
+    ];
+
+    /// Field of the repository holding the setting, e.g. `fork_policy`
+    pub fn field(self) -> &'static str {
+        match self {
+            Setting::ForkPolicy => "fork_policy",
+            Setting::Wiki => "has_wiki",
+            Setting::Issues => "has_issues",
+            Setting::MainBranch => "mainbranch",
+            Setting::Language => "language",
+            Setting::Privacy => "is_private",
+        }
+    }
+}
+
+/// The fields of repositories/{workspace}/{repo_slug} that `settings copy` covers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepositorySettings {
+    /// `allow_forks`, `no_public_forks` or `no_forks`
+    #[serde(default)]
+    pub fork_policy: String,
+    #[serde(default)]
+    pub has_wiki: bool,
+    #[serde(default)]
+    pub has_issues: bool,
+    /// Absent from repositories without any commit
+    #[serde(default)]
+    pub mainbranch: Option<MainBranch>,
+    #[serde(default)]
+    pub language: String,
+    #[serde(default)]
+    pub is_private: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MainBranch {
+    pub name: String,
+}
+
+/// A setting and its value, a row of [`HEADER`]
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingValue {
+    pub setting: &'static str,
+    pub value: String,
+}
+
+impl SettingValue {
+    pub fn row(&self) -> Vec<String> {
+        vec![self.setting.to_string(), self.value.to_string()]
+    }
+}
+
+impl RepositorySettings {
+    /// The value of the setting in words, e.g. `no_public_forks`
+    pub fn value(&self, setting: Setting) -> String {
+        match setting {
+            Setting::ForkPolicy => self.fork_policy.to_string(),
+            Setting::Wiki => self.has_wiki.to_string(),
+            Setting::Issues => self.has_issues.to_string(),
+            Setting::MainBranch => self
+                .mainbranch
+                .as_ref()
+                .map(|b| b.name.to_string())
+                .unwrap_or_default(),
+            Setting::Language => self.language.to_string(),
+            Setting::Privacy => self.is_private.to_string(),
+        }
+    }
+
+    fn json(&self, setting: Setting) -> Result<Value, BbdanError> {
+        Ok(match setting {
+            Setting::ForkPolicy => Value::from(self.fork_policy.as_str()),
+            Setting::Wiki => Value::from(self.has_wiki),
+            Setting::Issues => Value::from(self.has_issues),
+            Setting::MainBranch => serde_json::to_value(&self.mainbranch)?,
+            Setting::Language => Value::from(self.language.as_str()),
+            Setting::Privacy => Value::from(self.is_private),
+        })
+    }
+
+    pub fn rows(&self) -> Vec<SettingValue> {
+        Setting::ALL
+            .into_iter()
+            .map(|s| SettingValue {
+                setting: s.field(),
+                value: self.value(s),
+            })
+            .collect()
+    }
+}
+
+pub async fn repository_settings(bitbucket: &Bitbucket) -> Result<RepositorySettings, BbdanError> {
+    resources::get(bitbucket, "").await
+}
+
+/// Copy the settings of src, all of them when `only` is empty, to each destination,
+/// with the result per destination
+pub async fn copy_settings(
+    src: Bitbucket,
+    dests: &[Bitbucket],
+    only: &[Setting],
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    let settings = repository_settings(&src).await?;
+    let mut copied: Vec<Setting> = Setting::ALL
+        .into_iter()
+        .filter(|s| only.is_empty() || only.contains(s))
+        .collect();
+    // Nothing to copy from a repository without any commit
+    if settings.mainbranch.is_none() && copied.contains(&Setting::MainBranch) {
+        tracing::warn!("{} has no main branch, it is not copied", src.slug);
+        copied.retain(|s| *s != Setting::MainBranch);
+    }
+
+    let mut results = Vec::new();
+    for dest in dests {
+        tracing::info!("Repository: {}", dest.slug);
+        let result = apply_settings(dest, &settings, &copied, options)
+            .await
+            .map_err(Arc::new);
+        results.push((dest.slug.to_string(), result));
+    }
+    Ok(results)
+}
+
+async fn apply_settings(
+    dest: &Bitbucket,
+    settings: &RepositorySettings,
+    copied: &[Setting],
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let current = repository_settings(dest).await?;
+    let mut summary = Summary::default();
+
+    let mut changes = Vec::new();
+    for &setting in copied {
+        if current.value(setting) == settings.value(setting) {
+            summary.unchanged += 1;
+        } else {
+            changes.push(setting);
+        }
+    }
+    let messages: Vec<String> = changes
+        .iter()
+        .map(|&s| {
+            format!(
+                "Set {}: {} -> {}.",
+                s.field(),
+                current.value(s),
+                settings.value(s)
+            )
+        })
+        .collect();
+
+    // One update of the repository with every selected setting
+    let mut body = Map::new();
+    for (setting, selected) in changes.into_iter().zip(confirm_each(&messages, options)?) {
+        if selected {
+            body.insert(setting.field().to_string(), settings.json(setting)?);
+        } else {
+            summary.skipped += 1;
+        }
+    }
+    if !body.is_empty() {
+        summary.updated += body.len();
+        resources::put(dest, "", &body, options).await?;
+    }
+    Ok(summary)
+}
@@ -0,0 +1,104 @@
+use clap::Args as ClapArgs;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::branch_restrictions::BranchRestriction;
+use crate::client::Bitbucket;
+use crate::default_reviewers::Reviewer;
+use crate::deploy_keys::DeployKey;
+use crate::error::BbdanError;
+use crate::mapping::Mapping;
+use crate::permissions::Options;
+use crate::pipelines::Variable;
+use crate::reconcile::{self, ChangeFilter, Summary};
+use crate::resources::{self, Resource};
+use crate::webhooks::Webhook;
+
+/// Settings left out of clone-setup
+#[derive(Debug, Clone, Default, ClapArgs)]
+pub struct Skip {
+    /// Do not copy the permissions
+    #[clap(long)]
+    pub skip_permissions: bool,
+    /// Do not copy the branch restrictions
+    #[clap(long)]
+    pub skip_branch_restrictions: bool,
+    /// Do not copy the default reviewers
+    #[clap(long)]
+    pub skip_default_reviewers: bool,
+    /// Do not copy the webhooks
+    #[clap(long)]
+    pub skip_hooks: bool,
+    /// Do not copy the deploy keys
+    #[clap(long)]
+    pub skip_deploy_keys: bool,
+    /// Do not copy the pipelines variables
+    #[clap(long)]
+    pub skip_pipelines_vars: bool,
+}
+
+/// Copy the permissions, branch restrictions, default reviewers, webhooks, deploy keys and
+/// pipelines variables of src to dest, except those skipped, with the result per setting.
+/// Fails before copying anything when either repository is missing,
+/// but a setting failing afterwards does not stop the others from being copied.
+pub async fn clone_setup(
+    src: &Bitbucket,
+    dest: &Bitbucket,
+    skip: &Skip,
+    filter: &ChangeFilter,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    for repository in [src, dest] {
+        let _: Value = resources::get(repository, "").await?;
+    }
+
+    let mut results = Vec::new();
+    if !skip.skip_permissions {
+        tracing::info!("Setting: permissions");
+        let result = reconcile::copy(
+            src.clone(),
+            dest.clone(),
+            &Mapping::default(),
+            filter,
+            options,
+        )
+        .await
+        .map_err(Arc::new);
+        results.push(("permissions".to_string(), result));
+    }
+    if !skip.skip_branch_restrictions {
+        results.push(
+            copy_setting::<BranchRestriction>("branch-restrictions", src, dest, options).await,
+        );
+    }
+    if !skip.skip_default_reviewers {
+        results.push(copy_setting::<Reviewer>("default-reviewers", src, dest, options).await);
+    }
+    if !skip.skip_hooks {
+        results.push(copy_setting::<Webhook>("hooks", src, dest, options).await);
+    }
+    if !skip.skip_deploy_keys {
+        results.push(copy_setting::<DeployKey>("deploy-keys", src, dest, options).await);
+    }
+    if !skip.skip_pipelines_vars {
+        results.push(copy_setting::<Variable>("pipelines-vars", src, dest, options).await);
+    }
+    Ok(results)
+}
+
+/// Copy the items of src to dest, leaving those dest already has, with the result named after the setting
+async fn copy_setting<T: Resource>(
+    name: &str,
+    src: &Bitbucket,
+    dest: &Bitbucket,
+    options: Options,
+) -> (String, Result<Summary, Arc<BbdanError>>) {
+    tracing::info!("Setting: {}", name);
+    let result = match resources::list_all::<T>(src, T::PATH).await {
+        Ok(items) => resources::copy_items(dest, T::PATH, &items, false, options)
+            .await
+            .map_err(Arc::new),
+        Err(e) => Err(Arc::new(e)),
+    };
+    (name.to_string(), result)
+}
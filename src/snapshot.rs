@@ -0,0 +1,147 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::client::Bitbucket;
+use crate::document::{Grants, DOCUMENT_VERSION};
+use crate::error::BbdanError;
+use crate::permissions::Options;
+use crate::reconcile::{reconcile, ChangeFilter, Summary};
+use crate::repositories::AuditResult;
+
+/// Saved permissions of some repositories at a point in time.
+/// It is also a desired-state file of `sync` and a baseline of `watch`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub workspace: String,
+    pub taken_at: DateTime<Utc>,
+    pub repositories: BTreeMap<String, Grants>,
+}
+
+/// Directory the snapshots of the workspace are saved in, e.g. `~/.local/share/bbdan/snapshots/<workspace>`
+pub fn snapshot_dir(workspace: &str) -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("bbdan").join("snapshots").join(workspace))
+}
+
+impl Snapshot {
+    /// Snapshot of the repositories that could be listed
+    pub fn new(workspace: &str, results: &[AuditResult]) -> Self {
+        Self {
+            version: DOCUMENT_VERSION,
+            workspace: workspace.to_string(),
+            taken_at: Utc::now(),
+            repositories: results
+                .iter()
+                .filter_map(|(repo, result)| {
+                    let permissions = result.as_ref().ok()?;
+                    Some((repo.to_string(), Grants::new(permissions)))
+                })
+                .collect(),
+        }
+    }
+
+    /// Name of the snapshot, its time and the repository when there is only one,
+    /// e.g. `20240102T030405Z-repo-a`
+    pub fn name(&self) -> String {
+        let time = self.taken_at.format("%Y%m%dT%H%M%SZ").to_string();
+        match self.repositories.keys().collect::<Vec<_>>()[..] {
+            [repo] => format!("{}-{}", time, repo),
+            _ => time,
+        }
+    }
+
+    /// Save the snapshot as `<name>.json` in the directory, never overwriting another one
+    pub fn save(&self, dir: &Path) -> Result<PathBuf, BbdanError> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.json", self.name()));
+        let content = serde_json::to_string_pretty(self)?;
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        std::io::Write::write_all(&mut file, format!("{}\n", content).as_bytes())?;
+        Ok(path)
+    }
+
+    /// Load a snapshot by its name in the directory, or from a file
+    pub fn load(dir: &Path, snapshot: &str) -> Result<Self, BbdanError> {
+        let path = if Path::new(snapshot).is_file() {
+            PathBuf::from(snapshot)
+        } else {
+            dir.join(format!("{}.json", snapshot.trim_end_matches(".json")))
+        };
+        let content = fs::read_to_string(&path).map_err(|e| {
+            BbdanError::Invalid(format!("failed to read snapshot {}: {}", path.display(), e))
+        })?;
+        let snapshot: Self = serde_json::from_str(&content)?;
+        if snapshot.version > DOCUMENT_VERSION {
+            return Err(BbdanError::Invalid(format!(
+                "snapshot version {} is newer than the supported version {}",
+                snapshot.version, DOCUMENT_VERSION
+            )));
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Snapshots saved in the directory, oldest first. Files that are not snapshots are skipped.
+pub fn list_snapshots(dir: &Path) -> Result<Vec<Snapshot>, BbdanError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut snapshots = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|e| e != "json") {
+            continue;
+        }
+        match fs::read_to_string(&path)
+            .map_err(BbdanError::from)
+            .and_then(|content| Ok(serde_json::from_str::<Snapshot>(&content)?))
+        {
+            Ok(snapshot) => snapshots.push(snapshot),
+            Err(e) => tracing::warn!("Skipped {}: {}", path.display(), e),
+        }
+    }
+    snapshots.sort_by_key(|s| (s.taken_at, s.name()));
+    Ok(snapshots)
+}
+
+/// Reconcile the repository to its permissions in the snapshot
+pub async fn restore(
+    bitbucket: Bitbucket,
+    snapshot: &Snapshot,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let Some(grants) = snapshot.repositories.get(&bitbucket.slug) else {
+        return Err(BbdanError::Invalid(format!(
+            "{} is not in snapshot {}",
+            bitbucket.slug,
+            snapshot.name()
+        )));
+    };
+    if snapshot.workspace != bitbucket.workspace {
+        tracing::warn!(
+            "Restoring a snapshot of workspace {} to {}",
+            snapshot.workspace,
+            bitbucket.workspace
+        );
+    }
+    tracing::info!(
+        "Restoring {} to {}",
+        bitbucket.slug,
+        snapshot.taken_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+    reconcile(
+        bitbucket,
+        &grants.permissions(),
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+}
@@ -0,0 +1,85 @@
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{trace, Resource};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::span::EnteredSpan;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::error::BbdanError;
+
+/// Base URL of the OTLP/HTTP collector, traces are sent to `/v1/traces` of it. Setting it enables tracing.
+pub const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+/// Full URL traces are sent to, taking precedence over OTLP_ENDPOINT_ENV
+pub const OTLP_TRACES_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT";
+/// Target of the spans exported to the collector; they are kept out of the log on stderr
+pub const SPAN_TARGET: &str = "bbdan::spans";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Span of the command, closed by shutdown so it is exported even when exiting early
+    static COMMAND: RefCell<Option<EnteredSpan>> = const { RefCell::new(None) };
+}
+
+/// URL traces are sent to, when tracing is enabled by the environment
+pub fn endpoint() -> Option<String> {
+    endpoint_from(|name| std::env::var(name).ok())
+}
+
+/// URL traces are sent to, given the value of each environment variable
+pub fn endpoint_from(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let non_empty = |name| var(name).filter(|v: &String| !v.is_empty());
+    non_empty(OTLP_TRACES_ENDPOINT_ENV).or_else(|| {
+        non_empty(OTLP_ENDPOINT_ENV).map(|url| format!("{}/v1/traces", url.trim_end_matches('/')))
+    })
+}
+
+/// Layer exporting the spans to the OTLP collector of the environment, batched in the background.
+/// Must be called inside the Tokio runtime.
+pub fn layer<S>() -> Result<Option<impl Layer<S>>, BbdanError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let Some(endpoint) = endpoint() else {
+        return Ok(None);
+    };
+    // the default resource has the attributes of OTEL_RESOURCE_ATTRIBUTES
+    let service = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "bbdan".to_string());
+    let resource = Resource::default().merge(&Resource::new(vec![
+        KeyValue::new("service.name", service),
+        KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+    ]));
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(trace::config().with_resource(resource))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .map_err(|e| BbdanError::Invalid(format!("failed to set up tracing: {}", e)))?;
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Open the span of the command, the parent of the spans of its API calls
+pub fn start(command: &str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let span = tracing::info_span!(target: SPAN_TARGET, "command", otel.name = command);
+    COMMAND.with(|c| *c.borrow_mut() = Some(span.entered()));
+}
+
+/// Close the span of the command and send the spans not exported yet, before the process exits
+pub fn shutdown() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    COMMAND.with(|c| c.borrow_mut().take());
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,68 @@
+use crate::permissions::{permission_type_to_str, ObjectType, Permission};
+
+/// Terraform resources of the permissions of a repository for the DrFaust92/bitbucket provider,
+/// each with an `import` block (Terraform 1.5+) so `terraform plan` adopts the existing grants
+pub fn hcl(workspace: &str, repo: &str, permissions: &[Permission]) -> String {
+    let mut resources = String::new();
+    let mut imports = String::new();
+    for p in permissions {
+        let (kind, key) = match p.object_type {
+            ObjectType::User => ("user", "user_id"),
+            ObjectType::Group => ("group", "group_slug"),
+        };
+        // users are named by nickname rather than UUID when it is known
+        let name = match p.object_type {
+            ObjectType::User if !p.alias.is_empty() => &p.alias,
+            _ => &p.id,
+        };
+        let resource = format!("bitbucket_repository_{}_permission", kind);
+        let name = identifier(&format!("{}_{}", repo, name));
+        resources += &format!(
+            "resource \"{}\" \"{}\" {{\n  workspace  = {}\n  repo_slug  = {}\n  {:<10} = {}\n  permission = {}\n}}\n\n",
+            resource,
+            name,
+            quote(workspace),
+            quote(repo),
+            key,
+            quote(&p.id),
+            quote(&permission_type_to_str(p.permission))
+        );
+        imports += &format!(
+            "import {{\n  to = {}.{}\n  id = {}\n}}\n\n",
+            resource,
+            name,
+            quote(&format!("{}:{}:{}", workspace, repo, p.id))
+        );
+    }
+    let out = resources + &imports;
+    out.trim_end().to_string() + "\n"
+}
+
+/// A Terraform resource name: letters, digits and underscores, not starting with a digit
+fn identifier(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// An HCL string literal, with `${` and `%{` escaped so they are not read as templates
+fn quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "$${")
+        .replace("%{", "%%{");
+    format!("\"{}\"", escaped)
+}
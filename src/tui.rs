@@ -0,0 +1,586 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Flex, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{
+    Block, Clear, List, ListItem, ListState, Paragraph, Row, Table, TableState,
+};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::{
+    list, object_type_to_str, permission_type_to_str, resolve_user, ObjectType, Options,
+    Permission, PermissionType,
+};
+use crate::reconcile::{apply, Change};
+use crate::repositories::{list_repositories, Paging};
+
+const HELP: &str =
+    "↑↓ move  Tab switch pane  r/w/a set level  d remove  n add  u undo  p review  q quit";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Focus {
+    Repositories,
+    Permissions,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Browse,
+    /// Typing `user:<nickname>` or `group:<slug>` to grant
+    AddPrincipal(String),
+    /// Picking the level of the principal to grant
+    AddLevel(ObjectType, String),
+    Review,
+}
+
+/// A change waiting in the review screen
+#[derive(Debug, Clone)]
+struct Pending {
+    repo: String,
+    change: Change,
+}
+
+impl Pending {
+    fn permission(&self) -> &Permission {
+        match &self.change {
+            Change::Add(p) | Change::Remove(p) | Change::Unchanged(p) => p,
+            Change::Update { after, .. } => after,
+        }
+    }
+
+    fn is_for(&self, repo: &str, p: &Permission) -> bool {
+        let q = self.permission();
+        self.repo == repo && q.object_type == p.object_type && q.id == p.id
+    }
+
+    fn describe(&self) -> String {
+        let p = self.permission();
+        let principal = format!("{} {}", object_type_to_str(p.object_type), p.alias);
+        match &self.change {
+            Change::Add(p) => format!(
+                "{}: add {} {}",
+                self.repo,
+                principal,
+                permission_type_to_str(p.permission)
+            ),
+            Change::Update { before, after } => format!(
+                "{}: update {} {} → {}",
+                self.repo,
+                principal,
+                permission_type_to_str(before.permission),
+                permission_type_to_str(after.permission)
+            ),
+            Change::Remove(_) => format!("{}: remove {}", self.repo, principal),
+            Change::Unchanged(_) => format!("{}: keep {}", self.repo, principal),
+        }
+    }
+}
+
+struct App {
+    workspace: String,
+    dry_run: bool,
+    repositories: Vec<String>,
+    repository_state: ListState,
+    /// Permissions of the repositories visited so far
+    permissions: HashMap<String, Result<Vec<Permission>, String>>,
+    permission_state: TableState,
+    pending: Vec<Pending>,
+    review_state: ListState,
+    focus: Focus,
+    mode: Mode,
+    status: String,
+    /// q was pressed once with changes pending
+    quitting: bool,
+}
+
+enum Action {
+    Continue,
+    Apply,
+    Quit,
+}
+
+/// Browse the repositories of the workspace and their permissions, queue grants to add,
+/// change and remove, and apply them after reviewing
+pub async fn run(bitbucket: Bitbucket, options: Options) -> Result<(), BbdanError> {
+    if !std::io::stdout().is_terminal() {
+        return Err(BbdanError::Invalid("tui needs a terminal".to_string()));
+    }
+    let (repositories, _) = list_repositories(&bitbucket, &Paging::default()).await?;
+
+    let mut app = App {
+        workspace: bitbucket.workspace.to_string(),
+        dry_run: options.dry_run,
+        repositories: repositories.into_iter().map(|r| r.slug).collect(),
+        repository_state: ListState::default().with_selected(Some(0)),
+        permissions: HashMap::new(),
+        permission_state: TableState::default().with_selected(Some(0)),
+        pending: Vec::new(),
+        review_state: ListState::default().with_selected(Some(0)),
+        focus: Focus::Repositories,
+        mode: Mode::Browse,
+        status: HELP.to_string(),
+        quitting: false,
+    };
+
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(&mut terminal, &mut app, &bitbucket, options).await;
+    ratatui::restore();
+    result
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    bitbucket: &Bitbucket,
+    options: Options,
+) -> Result<(), BbdanError> {
+    loop {
+        if let Some(repo) = app.selected_repository() {
+            if !app.permissions.contains_key(&repo) {
+                terminal.draw(|frame| app.draw(frame))?;
+                let target = Bitbucket {
+                    slug: repo.to_string(),
+                    ..bitbucket.clone()
+                };
+                let result = list(target).await.map_err(|e| e.to_string());
+                app.permissions.insert(repo, result);
+            }
+        }
+        terminal.draw(|frame| app.draw(frame))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match app.handle_key(key.code) {
+            Action::Continue => {}
+            Action::Quit => return Ok(()),
+            Action::Apply => {
+                // leave the screen so the requests are logged like the other commands do
+                ratatui::restore();
+                let pending = std::mem::take(&mut app.pending);
+                let count = pending.len();
+                let mut failed = Vec::new();
+                for p in pending {
+                    app.permissions.remove(&p.repo);
+                    if let Err(e) = apply_pending(bitbucket, &p, options).await {
+                        tracing::error!("{}: {}", p.describe(), e);
+                        failed.push(p);
+                    }
+                }
+                *terminal = ratatui::try_init()?;
+                app.mode = Mode::Browse;
+                app.status = if failed.is_empty() {
+                    format!("Applied {} changes", count)
+                } else {
+                    format!(
+                        "{} of {} changes failed and are still pending",
+                        failed.len(),
+                        count
+                    )
+                };
+                app.pending = failed;
+            }
+        }
+    }
+}
+
+async fn apply_pending(
+    bitbucket: &Bitbucket,
+    pending: &Pending,
+    options: Options,
+) -> Result<(), BbdanError> {
+    let target = Bitbucket {
+        slug: pending.repo.to_string(),
+        ..bitbucket.clone()
+    };
+    let change = match &pending.change {
+        // users are typed by nickname, the API needs the UUID
+        Change::Add(p) if p.object_type == ObjectType::User => {
+            let (id, alias) = resolve_user(&target, &p.id).await?;
+            Change::Add(Permission {
+                id,
+                alias,
+                ..p.clone()
+            })
+        }
+        change => change.clone(),
+    };
+    apply(&target, change, options).await
+}
+
+impl App {
+    fn selected_repository(&self) -> Option<String> {
+        self.repository_state
+            .selected()
+            .and_then(|i| self.repositories.get(i))
+            .cloned()
+    }
+
+    /// Permissions of the selected repository with the pending changes on top
+    fn rows(&self) -> Vec<(Permission, Option<&Pending>)> {
+        let Some(repo) = self.selected_repository() else {
+            return Vec::new();
+        };
+        let mut rows: Vec<(Permission, Option<&Pending>)> = match self.permissions.get(&repo) {
+            Some(Ok(permissions)) => permissions
+                .iter()
+                .map(|p| (p.clone(), self.pending.iter().find(|q| q.is_for(&repo, p))))
+                .collect(),
+            _ => Vec::new(),
+        };
+        for pending in &self.pending {
+            if let Change::Add(p) = &pending.change {
+                if pending.repo == repo {
+                    rows.push((p.clone(), Some(pending)));
+                }
+            }
+        }
+        rows
+    }
+
+    fn handle_key(&mut self, code: KeyCode) -> Action {
+        let quitting = std::mem::take(&mut self.quitting);
+        match self.mode.clone() {
+            Mode::AddPrincipal(mut input) => match code {
+                KeyCode::Esc => self.browse(HELP),
+                KeyCode::Enter => match input.split_once(':') {
+                    Some(("user", name)) if !name.is_empty() => {
+                        self.mode = Mode::AddLevel(ObjectType::User, name.to_string());
+                    }
+                    Some(("group", name)) if !name.is_empty() => {
+                        self.mode = Mode::AddLevel(ObjectType::Group, name.to_string());
+                    }
+                    _ => self.browse("Type user:<nickname> or group:<slug>"),
+                },
+                KeyCode::Backspace => {
+                    input.pop();
+                    self.mode = Mode::AddPrincipal(input);
+                }
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    self.mode = Mode::AddPrincipal(input);
+                }
+                _ => {}
+            },
+            Mode::AddLevel(object_type, name) => {
+                if let Some(level) = level_of(code) {
+                    self.add(object_type, name, level);
+                } else if code == KeyCode::Esc {
+                    self.browse(HELP);
+                }
+            }
+            Mode::Review => match code {
+                KeyCode::Esc | KeyCode::Char('q') => self.browse(HELP),
+                KeyCode::Up | KeyCode::Char('k') => self.review_state.select_previous(),
+                KeyCode::Down | KeyCode::Char('j') => self.review_state.select_next(),
+                KeyCode::Char('x') | KeyCode::Delete => {
+                    if let Some(i) = self.review_state.selected() {
+                        if i < self.pending.len() {
+                            self.pending.remove(i);
+                        }
+                    }
+                    if self.pending.is_empty() {
+                        self.browse("No changes pending");
+                    }
+                }
+                KeyCode::Char('y') | KeyCode::Enter => return Action::Apply,
+                _ => {}
+            },
+            Mode::Browse => match code {
+                KeyCode::Char('q') | KeyCode::Esc => {
+                    if self.pending.is_empty() || quitting {
+                        return Action::Quit;
+                    }
+                    self.quitting = true;
+                    self.status = format!(
+                        "{} changes pending, press q again to discard them",
+                        self.pending.len()
+                    );
+                }
+                KeyCode::Tab | KeyCode::Left | KeyCode::Right | KeyCode::Char('h' | 'l') => {
+                    self.focus = match self.focus {
+                        Focus::Repositories => Focus::Permissions,
+                        Focus::Permissions => Focus::Repositories,
+                    };
+                }
+                KeyCode::Up | KeyCode::Char('k') => match self.focus {
+                    Focus::Repositories => self.select_repository(false),
+                    Focus::Permissions => self.permission_state.select_previous(),
+                },
+                KeyCode::Down | KeyCode::Char('j') => match self.focus {
+                    Focus::Repositories => self.select_repository(true),
+                    Focus::Permissions => self.permission_state.select_next(),
+                },
+                KeyCode::Char('n') if self.selected_repository().is_some() => {
+                    self.mode = Mode::AddPrincipal(String::new());
+                }
+                KeyCode::Char('p') => {
+                    if self.pending.is_empty() {
+                        self.status = "No changes pending".to_string();
+                    } else {
+                        self.review_state.select(Some(0));
+                        self.mode = Mode::Review;
+                    }
+                }
+                code if self.focus == Focus::Permissions => self.change_selected(code),
+                _ => {}
+            },
+        }
+        Action::Continue
+    }
+
+    fn browse(&mut self, status: &str) {
+        self.mode = Mode::Browse;
+        self.status = status.to_string();
+    }
+
+    fn select_repository(&mut self, next: bool) {
+        if next {
+            self.repository_state.select_next();
+        } else {
+            self.repository_state.select_previous();
+        }
+        self.permission_state.select(Some(0));
+    }
+
+    fn add(&mut self, object_type: ObjectType, name: String, level: PermissionType) {
+        let Some(repo) = self.selected_repository() else {
+            return;
+        };
+        // granting an existing principal again changes its level
+        let (permission, change) = match self.current(&repo, object_type, &name) {
+            Some(before) => (before.clone(), level_change(before, level)),
+            None => {
+                let permission = Permission {
+                    object_type,
+                    alias: name.to_string(),
+                    id: name,
+                    permission: level,
+                    via: Vec::new(),
+                };
+                (permission.clone(), Some(Change::Add(permission)))
+            }
+        };
+        self.pending.retain(|q| !q.is_for(&repo, &permission));
+        if let Some(change) = change {
+            self.pending.push(Pending { repo, change });
+        }
+        self.browse(HELP);
+    }
+
+    /// The grant of the principal, given by id or name, before any pending change
+    fn current(&self, repo: &str, object_type: ObjectType, name: &str) -> Option<Permission> {
+        self.permissions
+            .get(repo)?
+            .as_ref()
+            .ok()?
+            .iter()
+            .find(|p| p.object_type == object_type && (p.id == name || p.alias == name))
+            .cloned()
+    }
+
+    /// Queue a change of the selected grant, or undo the queued one
+    fn change_selected(&mut self, code: KeyCode) {
+        let (Some(repo), Some(i)) = (self.selected_repository(), self.permission_state.selected())
+        else {
+            return;
+        };
+        let Some((p, pending)) = self.rows().into_iter().nth(i) else {
+            return;
+        };
+        let added = matches!(pending.map(|q| &q.change), Some(Change::Add(_)));
+        let removed = matches!(pending.map(|q| &q.change), Some(Change::Remove(_)));
+
+        let change = match code {
+            KeyCode::Char('u') => None,
+            KeyCode::Char('d') if added || removed => None,
+            KeyCode::Char('d') => Some(Change::Remove(p.clone())),
+            code => match level_of(code) {
+                Some(level) if added => Some(Change::Add(Permission {
+                    permission: level,
+                    ..p.clone()
+                })),
+                Some(level) => {
+                    let before = self
+                        .current(&repo, p.object_type, &p.id)
+                        .unwrap_or_else(|| p.clone());
+                    level_change(before, level)
+                }
+                None => return,
+            },
+        };
+        self.pending.retain(|q| !q.is_for(&repo, &p));
+        if let Some(change) = change {
+            self.pending.push(Pending { repo, change });
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)])
+                .areas(main);
+
+        let focused = |focus: Focus| {
+            if self.focus == focus {
+                Style::default().fg(Color::Cyan)
+            } else {
+                Style::default()
+            }
+        };
+
+        let repositories: Vec<ListItem> = self
+            .repositories
+            .iter()
+            .map(|repo| {
+                if self.pending.iter().any(|p| p.repo == *repo) {
+                    ListItem::new(format!("{} *", repo)).style(Style::default().fg(Color::Yellow))
+                } else {
+                    ListItem::new(repo.as_str())
+                }
+            })
+            .collect();
+        let repositories = List::new(repositories)
+            .block(
+                Block::bordered()
+                    .title(format!("Repositories of {}", self.workspace))
+                    .border_style(focused(Focus::Repositories)),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(repositories, left, &mut self.repository_state);
+
+        let repo = self.selected_repository().unwrap_or_default();
+        let block = Block::bordered()
+            .title(format!("Permissions of {}", repo))
+            .border_style(focused(Focus::Permissions));
+        match self.permissions.get(&repo) {
+            None => frame.render_widget(Paragraph::new("Loading...").block(block), right),
+            Some(Err(e)) => frame.render_widget(
+                Paragraph::new(format!("failed to list: {}", e))
+                    .style(Style::default().fg(Color::Red))
+                    .block(block),
+                right,
+            ),
+            Some(Ok(_)) => {
+                let rows: Vec<Row> = self
+                    .rows()
+                    .into_iter()
+                    .map(|(p, pending)| {
+                        let level = permission_type_to_str(p.permission);
+                        let (level, style) = match pending.map(|q| &q.change) {
+                            Some(Change::Add(_)) => {
+                                (format!("+ {}", level), Style::default().fg(Color::Green))
+                            }
+                            Some(Change::Update { before, .. }) => (
+                                format!(
+                                    "{} → {}",
+                                    permission_type_to_str(before.permission),
+                                    level
+                                ),
+                                Style::default().fg(Color::Yellow),
+                            ),
+                            Some(Change::Remove(_)) => (
+                                format!("- {}", level),
+                                Style::default()
+                                    .fg(Color::Red)
+                                    .add_modifier(Modifier::CROSSED_OUT),
+                            ),
+                            _ => (level, Style::default()),
+                        };
+                        Row::new([object_type_to_str(p.object_type), p.id, p.alias, level])
+                            .style(style)
+                    })
+                    .collect();
+                let table = Table::new(
+                    rows,
+                    [
+                        Constraint::Length(6),
+                        Constraint::Percentage(40),
+                        Constraint::Percentage(40),
+                        Constraint::Min(14),
+                    ],
+                )
+                .header(
+                    Row::new(["type", "id", "alias", "permission"])
+                        .style(Style::default().add_modifier(Modifier::BOLD)),
+                )
+                .block(block)
+                .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+                frame.render_stateful_widget(table, right, &mut self.permission_state);
+            }
+        }
+
+        let line = match &self.mode {
+            Mode::AddPrincipal(input) => format!(
+                "Grant on {} (user:<nickname> or group:<slug>): {}",
+                repo, input
+            ),
+            Mode::AddLevel(object_type, name) => format!(
+                "Level for {} {}: r read, w write, a admin, Esc cancel",
+                object_type_to_str(*object_type),
+                name
+            ),
+            Mode::Review => "y apply  x drop  Esc back".to_string(),
+            Mode::Browse => self.status.to_string(),
+        };
+        frame.render_widget(Line::from(line), status);
+
+        if self.mode == Mode::Review {
+            let area = popup(frame.area());
+            let title = if self.dry_run {
+                "Pending changes (dry run)"
+            } else {
+                "Pending changes"
+            };
+            let items: Vec<ListItem> = self
+                .pending
+                .iter()
+                .map(|p| ListItem::new(p.describe()))
+                .collect();
+            let review = List::new(items)
+                .block(Block::bordered().title(title))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            frame.render_widget(Clear, area);
+            frame.render_stateful_widget(review, area, &mut self.review_state);
+        }
+    }
+}
+
+/// Update of the grant to the level, None when it already has it
+fn level_change(before: Permission, level: PermissionType) -> Option<Change> {
+    (before.permission != level).then(|| Change::Update {
+        after: Permission {
+            permission: level,
+            ..before.clone()
+        },
+        before,
+    })
+}
+
+fn level_of(code: KeyCode) -> Option<PermissionType> {
+    match code {
+        KeyCode::Char('r') => Some(PermissionType::Read),
+        KeyCode::Char('w') => Some(PermissionType::Write),
+        KeyCode::Char('a') => Some(PermissionType::Admin),
+        _ => None,
+    }
+}
+
+/// Centered area for the review screen
+fn popup(area: Rect) -> Rect {
+    let [area] = Layout::vertical([Constraint::Percentage(60)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [area] = Layout::horizontal([Constraint::Percentage(70)])
+        .flex(Flex::Center)
+        .areas(area);
+    area
+}
@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::client::Bitbucket;
+use crate::document::SyncDocument;
+use crate::metrics;
+use crate::notify::{Notification, Notifier};
+use crate::permissions::{object_type_to_str, permission_type_to_str, ObjectType, Permission};
+use crate::reconcile::{diff_permissions, Change};
+use crate::repositories::audit_repositories;
+
+/// Changes made to the repositories of the baseline since it was taken, by repository.
+/// Repositories without changes are left out; those that could not be listed have an error.
+pub type Drift = BTreeMap<String, Result<Vec<Change>, String>>;
+
+/// List the repositories of the baseline and compare them with it
+pub async fn detect(bitbucket: Bitbucket, baseline: &SyncDocument) -> Drift {
+    let slugs: Vec<String> = baseline.repositories.keys().cloned().collect();
+    detect_repositories(bitbucket, baseline, slugs).await
+}
+
+/// List the repositories, which must be in the baseline, and compare them with it
+pub async fn detect_repositories(
+    bitbucket: Bitbucket,
+    baseline: &SyncDocument,
+    slugs: Vec<String>,
+) -> Drift {
+    let results = audit_repositories(bitbucket, slugs, |_| {}).await;
+
+    let mut drift = Drift::new();
+    for (repo, result) in results {
+        if let Ok(live) = &result {
+            metrics::record_permissions(&repo, live);
+        }
+        let changes = result
+            .map(|live| {
+                let expected = baseline.repositories[&repo].permissions();
+                diff_permissions(&expected, &live)
+                    .into_iter()
+                    .filter(|c| !matches!(c, Change::Unchanged(_)))
+                    .collect::<Vec<_>>()
+            })
+            .map_err(|e| e.to_string());
+        if !matches!(&changes, Ok(changes) if changes.is_empty()) {
+            drift.insert(repo, changes);
+        }
+    }
+    drift
+}
+
+/// The drift of the current check that was not already there in the previous one
+pub fn new_drift(previous: &Drift, current: &Drift) -> Drift {
+    current
+        .iter()
+        .filter(|(repo, changes)| {
+            let described = |c: &Result<Vec<Change>, String>| {
+                c.as_ref()
+                    .map(|c| c.iter().map(describe).collect::<Vec<_>>())
+                    .ok()
+            };
+            previous.get(*repo).map(described) != Some(described(changes))
+        })
+        .map(|(repo, changes)| (repo.to_string(), changes.clone()))
+        .collect()
+}
+
+/// A change since the baseline in words, e.g. `granted user alice admin`
+pub fn describe(change: &Change) -> String {
+    let principal = |p: &Permission| {
+        let name = match p.object_type {
+            ObjectType::User if !p.alias.is_empty() => &p.alias,
+            _ => &p.id,
+        };
+        format!("{} {}", object_type_to_str(p.object_type), name)
+    };
+    match change {
+        Change::Add(p) => format!(
+            "granted {} {}",
+            principal(p),
+            permission_type_to_str(p.permission)
+        ),
+        Change::Remove(p) => format!(
+            "revoked {} {}",
+            principal(p),
+            permission_type_to_str(p.permission)
+        ),
+        Change::Update { before, after } => format!(
+            "changed {} from {} to {}",
+            principal(after),
+            permission_type_to_str(before.permission),
+            permission_type_to_str(after.permission)
+        ),
+        Change::Unchanged(p) => format!("kept {}", principal(p)),
+    }
+}
+
+/// Log the drift as warnings
+pub fn log(drift: &Drift) {
+    if drift.is_empty() {
+        tracing::info!("No drift from the baseline");
+    }
+    for (repo, changes) in drift {
+        match changes {
+            Ok(changes) => {
+                for change in changes {
+                    tracing::warn!("{}: {} since the baseline", repo, describe(change));
+                }
+            }
+            Err(e) => tracing::warn!("{}: failed to list: {}", repo, e),
+        }
+    }
+}
+
+/// Compare the repositories with the baseline every `interval` until interrupted,
+/// logging drift when it first appears and posting it to the channels of the notifier
+pub async fn watch(
+    bitbucket: Bitbucket,
+    baseline: &SyncDocument,
+    interval: Duration,
+    notifier: Option<&Notifier>,
+) {
+    let mut previous = Drift::new();
+    loop {
+        let drift = detect(bitbucket.clone(), baseline).await;
+        let new = new_drift(&previous, &drift);
+        if new.is_empty() && !drift.is_empty() {
+            tracing::info!("No new drift, {} repositories still differ", drift.len());
+        } else {
+            log(&new);
+            metrics::record_drift(&new);
+            if let (Some(notifier), false) = (notifier, new.is_empty()) {
+                let notification = Notification::drift(&bitbucket.workspace, &new);
+                if let Err(e) = notifier.send(&notification).await {
+                    tracing::warn!("failed to notify: {}", e);
+                }
+            }
+        }
+        previous = drift;
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => return,
+        }
+    }
+}
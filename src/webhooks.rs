@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::client::Bitbucket;
+use crate::error::BbdanError;
+use crate::permissions::{confirm, Options};
+use crate::reconcile::Summary;
+use crate::resources::{self, Resource};
+
+/// Columns of the webhooks in list
+pub const HEADER: &[&str] = &["uuid", "description", "url", "active", "events"];
+
+/// A URL called on events of the repository, an item of hooks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    /// Absent from the webhooks to add
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    pub url: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "active")]
+    pub active: bool,
+    /// e.g. `repo:push`, `pullrequest:created`
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default)]
+    pub skip_cert_verification: bool,
+    /// Whether the webhook signs its requests. The secret itself is never returned.
+    #[serde(default, skip_serializing)]
+    pub secret_set: bool,
+    /// Secret to sign the requests with, only sent when adding a webhook
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+fn active() -> bool {
+    true
+}
+
+impl Webhook {
+    fn events(&self) -> BTreeSet<&str> {
+        self.events.iter().map(String::as_str).collect()
+    }
+
+    /// The webhook as a row of [`HEADER`]
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.uuid.clone().unwrap_or_default(),
+            self.description.to_string(),
+            self.url.to_string(),
+            self.active.to_string(),
+            self.events().into_iter().collect::<Vec<_>>().join(" "),
+        ]
+    }
+}
+
+impl Resource for Webhook {
+    const PATH: &'static str = "hooks";
+
+    fn key(&self) -> Option<String> {
+        self.uuid.clone()
+    }
+
+    /// Whether both call the same URL on the same events, whatever their descriptions
+    fn same(&self, other: &Webhook) -> bool {
+        self.url == other.url && self.events() == other.events() && self.active == other.active
+    }
+
+    fn describe(&self) -> String {
+        let mut words = format!(
+            "url={}, events={}",
+            self.url,
+            self.events().into_iter().collect::<Vec<_>>().join(" ")
+        );
+        if !self.active {
+            words += ", inactive";
+        }
+        words
+    }
+
+    fn created(&self) -> Self {
+        if self.secret_set && self.secret.is_none() {
+            tracing::warn!(
+                "The secret of {} cannot be read, set it again in the destination",
+                self.url
+            );
+        }
+        Webhook {
+            uuid: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Webhooks to remove without the interactive picker
+#[derive(Debug, Clone, Default)]
+pub struct WebhookFilter {
+    pub uuids: Vec<String>,
+    pub urls: Vec<String>,
+}
+
+impl WebhookFilter {
+    pub fn is_empty(&self) -> bool {
+        self.uuids.is_empty() && self.urls.is_empty()
+    }
+
+    pub fn matches(&self, h: &Webhook) -> bool {
+        h.uuid
+            .as_ref()
+            .is_some_and(|uuid| self.uuids.contains(uuid))
+            || self.urls.contains(&h.url)
+    }
+}
+
+pub async fn list_webhooks(bitbucket: &Bitbucket) -> Result<Vec<Webhook>, BbdanError> {
+    resources::list_all(bitbucket, Webhook::PATH).await
+}
+
+/// Add a webhook to the repository, unless it already calls the URL on the same events
+pub async fn add_webhook(
+    bitbucket: Bitbucket,
+    webhook: Webhook,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    let mut summary = Summary::default();
+    if list_webhooks(&bitbucket)
+        .await?
+        .iter()
+        .any(|h| h.same(&webhook))
+    {
+        tracing::info!("The webhook {} already exists", webhook.describe());
+        summary.unchanged += 1;
+    } else if confirm(format!("Add: {}.", webhook.describe()), options)? {
+        resources::create(&bitbucket, &webhook, options).await?;
+        summary.added += 1;
+    } else {
+        summary.skipped += 1;
+    }
+    Ok(summary)
+}
+
+/// Remove the webhooks the filter matches, or those picked interactively when it is empty
+pub async fn remove_webhooks(
+    bitbucket: Bitbucket,
+    filter: &WebhookFilter,
+    options: Options,
+) -> Result<Summary, BbdanError> {
+    if filter.is_empty() {
+        if options.yes {
+            return Err(BbdanError::Invalid(
+                "--uuid or --url is required in non-interactive mode".to_string(),
+            ));
+        }
+        return resources::remove_items::<Webhook>(&bitbucket, None, options).await;
+    }
+    resources::remove_items(&bitbucket, Some(&|h| filter.matches(h)), options).await
+}
+
+/// Copy the webhooks of src, their URL, events and active flag, to each destination,
+/// with the result per destination. Secrets cannot be read, so they are not copied.
+pub async fn copy_webhooks(
+    src: Bitbucket,
+    dests: &[Bitbucket],
+    prune: bool,
+    options: Options,
+) -> Result<Vec<(String, Result<Summary, Arc<BbdanError>>)>, BbdanError> {
+    resources::copy::<Webhook>(src, dests, prune, options).await
+}
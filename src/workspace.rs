@@ -0,0 +1,89 @@
+use clap::ArgEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::client::{Bitbucket, Page};
+use crate::error::{error_for_status, parse_json, BbdanError};
+
+/// Columns of the workspace permissions in list
+pub const HEADER: &[&str] = &["uuid", "nickname", "display_name", "permission"];
+
+/// Level of a member in the workspace. Owners administer every repository
+/// whatever the repository grants, the others only have the access they are granted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkspaceRole {
+    Owner,
+    Collaborator,
+    Member,
+}
+
+impl std::fmt::Display for WorkspaceRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let role = match self {
+            WorkspaceRole::Owner => "owner",
+            WorkspaceRole::Collaborator => "collaborator",
+            WorkspaceRole::Member => "member",
+        };
+        write!(f, "{}", role)
+    }
+}
+
+/// A member of the workspace and its level, an item of workspaces/{workspace}/permissions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspacePermission {
+    pub permission: WorkspaceRole,
+    pub user: WorkspaceUser,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceUser {
+    pub uuid: String,
+    #[serde(default)]
+    pub nickname: String,
+    #[serde(default)]
+    pub display_name: String,
+}
+
+impl WorkspaceUser {
+    /// The nickname, or the display name of users without one
+    pub fn name(&self) -> &str {
+        match self.nickname.is_empty() {
+            true => &self.display_name,
+            false => &self.nickname,
+        }
+    }
+}
+
+impl WorkspacePermission {
+    /// The permission as a row of [`HEADER`]
+    pub fn row(&self) -> Vec<String> {
+        vec![
+            self.user.uuid.to_string(),
+            self.user.nickname.to_string(),
+            self.user.display_name.to_string(),
+            self.permission.to_string(),
+        ]
+    }
+}
+
+/// Every member of the workspace with its level, only those at `role` when given
+pub async fn list_workspace_permissions(
+    bitbucket: &Bitbucket,
+    role: Option<WorkspaceRole>,
+) -> Result<Vec<WorkspacePermission>, BbdanError> {
+    let client = bitbucket.client();
+    let mut next =
+        Some(bitbucket.workspace_url(&format!("permissions?pagelen={}", bitbucket.pagelen)));
+    let mut permissions = Vec::new();
+    while let Some(url) = next {
+        let resp = error_for_status(client.http_get_url(url).await?)?;
+        let page: Page<WorkspacePermission> = parse_json(resp).await?;
+        permissions.extend(
+            page.values
+                .into_iter()
+                .filter(|p| role.is_none_or(|role| p.permission == role)),
+        );
+        next = page.next;
+    }
+    Ok(permissions)
+}
@@ -0,0 +1,1486 @@
+mod common;
+
+use bbdan::access::access;
+use bbdan::client::{http_client, Auth, BitbucketClient, HttpLog};
+use bbdan::document::{DocumentFormat, SyncDocument};
+use bbdan::error::{failures_exit_code, EXIT_API, EXIT_AUTH, EXIT_PARTIAL};
+use bbdan::lifecycle::archive_repository;
+use bbdan::mapping::Mapping;
+use bbdan::notify::{Notification, Notifier, NotifyConfig};
+use bbdan::oauth::{OAuthClient, OAuthSession, OAuthToken};
+use bbdan::offboard::{offboard, Outcome};
+use bbdan::output::Output;
+use bbdan::permissions::{Protected, RemoveFilter};
+use bbdan::plan::{apply_plan, Plan};
+use bbdan::reconcile::{
+    copy_to_many, diff_permissions, glob_matcher, merge, merge_permissions, Change, ChangeFilter,
+    Conflict, CopyMode,
+};
+use bbdan::repositories::{audit, Paging, Selector};
+use bbdan::scopes::{verify, Credentials};
+use bbdan::watch;
+use bbdan::{
+    add, copy, list, remove, BbdanError, Bitbucket, ObjectType, Options, Permission, PermissionType,
+};
+use common::repo;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use wiremock::matchers::{body_json, body_string_contains, header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn group(slug: &str, permission: &str) -> Value {
+    json!({
+        "type": "repository_group_permission",
+        "permission": permission,
+        "group": {"type": "group", "slug": slug, "name": slug.to_uppercase()},
+    })
+}
+
+fn user(uuid: &str, nickname: &str, permission: &str) -> Value {
+    json!({
+        "type": "repository_user_permission",
+        "permission": permission,
+        "user": {"type": "user", "uuid": uuid, "nickname": nickname},
+    })
+}
+
+fn permissions_path(slug: &str, kind: &str) -> String {
+    format!("/repositories/ws/{}/permissions-config/{}", slug, kind)
+}
+
+/// Serve the groups and users of the repository as a single page each
+async fn mount_permissions(server: &MockServer, slug: &str, groups: Vec<Value>, users: Vec<Value>) {
+    for (kind, values) in [("groups", groups), ("users", users)] {
+        Mock::given(method("GET"))
+            .and(path(permissions_path(slug, kind)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "values": values,
+                "pagelen": 100,
+                "size": values.len(),
+                "page": 1,
+            })))
+            .mount(server)
+            .await;
+    }
+}
+
+async fn expect_delete(server: &MockServer, target: &str) {
+    Mock::given(method("DELETE"))
+        .and(path(target))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn list_follows_pagination() {
+    let server = MockServer::start().await;
+    let groups = permissions_path("repo", "groups");
+    Mock::given(method("GET"))
+        .and(path(&groups))
+        .and(header("authorization", "Bearer secret"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [group("devs", "write")],
+            "next": format!("{}{}?pagelen=1&page=2", server.uri(), groups),
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(&groups))
+        .and(query_param("page", "2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [group("admins", "admin")],
+        })))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(permissions_path("repo", "users")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [user("{u1}", "alice", "read")],
+        })))
+        .mount(&server)
+        .await;
+
+    let permissions = list(repo(&server, "repo")).await.unwrap();
+
+    let got: Vec<(ObjectType, &str, &str, PermissionType)> = permissions
+        .iter()
+        .map(|p| (p.object_type, p.id.as_str(), p.alias.as_str(), p.permission))
+        .collect();
+    assert_eq!(
+        got,
+        vec![
+            (ObjectType::Group, "devs", "DEVS", PermissionType::Write),
+            (ObjectType::Group, "admins", "ADMINS", PermissionType::Admin),
+            (ObjectType::User, "{u1}", "alice", PermissionType::Read),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn list_reports_http_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let err = list(repo(&server, "missing")).await.unwrap_err();
+    assert!(
+        matches!(err, BbdanError::Http { status, .. } if status == 404),
+        "{:?}",
+        err
+    );
+    assert_eq!(err.exit_code(), EXIT_API);
+}
+
+#[tokio::test]
+async fn list_reports_rejected_credentials() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    let err = list(repo(&server, "repo")).await.unwrap_err();
+    assert!(matches!(err, BbdanError::Auth(_)), "{:?}", err);
+    assert_eq!(err.exit_code(), EXIT_AUTH);
+}
+
+#[tokio::test]
+async fn list_reports_missing_fields() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [{"permission": "read", "group": {"name": "devs"}}],
+        })))
+        .mount(&server)
+        .await;
+
+    let err = list(repo(&server, "repo")).await.unwrap_err();
+    match err {
+        BbdanError::UnexpectedResponse { message, .. } => assert!(message.contains("slug")),
+        e => panic!("unexpected error: {:?}", e),
+    }
+}
+
+#[tokio::test]
+async fn list_waits_for_retry_after() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    mount_permissions(&server, "repo", vec![group("devs", "write")], vec![]).await;
+
+    let started = std::time::Instant::now();
+    let permissions = list(repo(&server, "repo")).await.unwrap();
+    assert_eq!(permissions.len(), 1);
+    assert!(started.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn list_gives_up_on_rate_limit_without_retry_after() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(429))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let err = list(repo(&server, "repo")).await.unwrap_err();
+    assert!(
+        matches!(err, BbdanError::Http { status, .. } if status == 429),
+        "{:?}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn list_times_out() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .mount(&server)
+        .await;
+
+    let target = Bitbucket {
+        http_client: http_client(Duration::from_millis(200), Duration::from_secs(1)).unwrap(),
+        ..repo(&server, "repo")
+    };
+    let err = list(target).await.unwrap_err();
+    assert!(
+        matches!(&err, BbdanError::Request(e) if e.is_timeout()),
+        "{:?}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn http_log_records_each_request() {
+    let server = MockServer::start().await;
+    mount_permissions(&server, "repo", vec![group("devs", "write")], vec![]).await;
+    let log_path = std::env::temp_dir().join(format!("bbdan-http-log-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&log_path);
+
+    let target = Bitbucket {
+        http_log: Some(Arc::new(HttpLog::open(&log_path, Output::Json).unwrap())),
+        ..repo(&server, "repo")
+    };
+    list(target).await.unwrap();
+
+    let content = std::fs::read_to_string(&log_path).unwrap();
+    std::fs::remove_file(&log_path).unwrap();
+    let lines: Vec<Value> = content
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["statusCode"], "200 OK");
+    assert!(lines[1]["url"]
+        .as_str()
+        .unwrap()
+        .contains("/permissions-config/users"));
+}
+
+#[tokio::test]
+async fn copy_reconciles_destination() {
+    let server = MockServer::start().await;
+    mount_permissions(
+        &server,
+        "src",
+        vec![group("devs", "write"), group("qa", "read")],
+        vec![user("{u1}", "alice", "read")],
+    )
+    .await;
+    mount_permissions(
+        &server,
+        "dest",
+        vec![
+            group("devs", "read"),
+            group("old", "write"),
+            group("qa", "read"),
+        ],
+        vec![user("{u2}", "bob", "admin")],
+    )
+    .await;
+    Mock::given(method("PUT"))
+        .and(path(permissions_path("dest", "groups/devs")))
+        .and(body_json(json!({"permission": "write"})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(group("devs", "write")))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(permissions_path("dest", "users/%7Bu1%7D")))
+        .and(body_json(json!({"permission": "read"})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(user("{u1}", "alice", "read")))
+        .expect(1)
+        .mount(&server)
+        .await;
+    expect_delete(&server, &permissions_path("dest", "groups/old")).await;
+    expect_delete(&server, &permissions_path("dest", "users/%7Bu2%7D")).await;
+
+    let options = Options {
+        yes: true,
+        dry_run: false,
+        ..Options::default()
+    };
+    let summary = copy(
+        repo(&server, "src"),
+        repo(&server, "dest"),
+        &Mapping::default(),
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        (
+            summary.added,
+            summary.updated,
+            summary.removed,
+            summary.unchanged
+        ),
+        (1, 1, 2, 1)
+    );
+}
+
+#[tokio::test]
+async fn copy_dry_run_changes_nothing() {
+    let server = MockServer::start().await;
+    mount_permissions(&server, "src", vec![group("devs", "write")], vec![]).await;
+    mount_permissions(&server, "dest", vec![group("old", "read")], vec![]).await;
+    for verb in ["PUT", "DELETE"] {
+        Mock::given(method(verb))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+    }
+
+    let options = Options {
+        yes: false,
+        dry_run: true,
+        ..Options::default()
+    };
+    let summary = copy(
+        repo(&server, "src"),
+        repo(&server, "dest"),
+        &Mapping::default(),
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+    .unwrap();
+    assert_eq!((summary.added, summary.removed), (1, 1));
+}
+
+#[tokio::test]
+async fn copy_fails_when_a_change_is_rejected() {
+    let server = MockServer::start().await;
+    mount_permissions(&server, "src", vec![group("devs", "write")], vec![]).await;
+    mount_permissions(&server, "dest", vec![], vec![]).await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        dry_run: false,
+        ..Options::default()
+    };
+    let err = copy(
+        repo(&server, "src"),
+        repo(&server, "dest"),
+        &Mapping::default(),
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+    .unwrap_err();
+    assert!(
+        matches!(err, BbdanError::Http { status, .. } if status == 403),
+        "{:?}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn copy_failing_midway_rolls_back_the_applied_changes() {
+    let server = MockServer::start().await;
+    mount_permissions(&server, "src", vec![group("devs", "write")], vec![]).await;
+    mount_permissions(&server, "dest", vec![group("old", "read")], vec![]).await;
+    Mock::given(method("PUT"))
+        .and(path(permissions_path("dest", "groups/devs")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(group("devs", "write")))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path(permissions_path("dest", "groups/old")))
+        .respond_with(ResponseTemplate::new(403))
+        .expect(1)
+        .mount(&server)
+        .await;
+    // the rollback revokes the grant added before the failure
+    Mock::given(method("DELETE"))
+        .and(path(permissions_path("dest", "groups/devs")))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        dry_run: false,
+        ..Options::default()
+    };
+    let mut dest = repo(&server, "dest");
+    dest.parallel = 1;
+    let err = copy(
+        repo(&server, "src"),
+        dest,
+        &Mapping::default(),
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+    .unwrap_err();
+    assert!(
+        matches!(err, BbdanError::Http { status, .. } if status == 403),
+        "{:?}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn apply_plan_refuses_when_the_repository_changed_since() {
+    let server = MockServer::start().await;
+    mount_permissions(&server, "src", vec![group("devs", "write")], vec![]).await;
+    let dest = MockServer::start().await;
+    mount_permissions(&dest, "dest", vec![], vec![]).await;
+
+    let mut plan = Plan::new("ws", "copy src dest");
+    plan.add(
+        repo(&dest, "dest"),
+        &list(repo(&server, "src")).await.unwrap(),
+        &ChangeFilter::default(),
+        Options::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(plan.len(), 1);
+
+    // someone granted a group after the plan was made
+    dest.reset().await;
+    mount_permissions(&dest, "dest", vec![group("ops", "read")], vec![]).await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&dest)
+        .await;
+
+    let options = Options {
+        yes: true,
+        dry_run: false,
+        ..Options::default()
+    };
+    let err = apply_plan(repo(&dest, ""), &plan, options)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("dest changed since"), "{}", err);
+}
+
+#[tokio::test]
+async fn copy_above_max_changes_changes_nothing() {
+    let server = MockServer::start().await;
+    mount_permissions(
+        &server,
+        "src",
+        vec![group("devs", "write"), group("ops", "read")],
+        vec![],
+    )
+    .await;
+    mount_permissions(&server, "dest", vec![group("devs", "write")], vec![]).await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        dry_run: false,
+        max_changes: Some(0),
+        ..Options::default()
+    };
+    let err = copy(
+        repo(&server, "src"),
+        repo(&server, "dest"),
+        &Mapping::default(),
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+    .unwrap_err();
+    assert!(
+        err.to_string().contains("exceed --max-changes 0"),
+        "{}",
+        err
+    );
+
+    // unchanged grants do not count
+    let options = Options {
+        dry_run: true,
+        max_changes: Some(1),
+        ..options
+    };
+    let summary = copy(
+        repo(&server, "src"),
+        repo(&server, "dest"),
+        &Mapping::default(),
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(summary.added, 1);
+}
+
+#[tokio::test]
+async fn additive_copy_only_adds() {
+    let server = MockServer::start().await;
+    mount_permissions(
+        &server,
+        "src",
+        vec![group("devs", "write"), group("ops", "read")],
+        vec![],
+    )
+    .await;
+    mount_permissions(
+        &server,
+        "dest",
+        vec![group("devs", "read"), group("old", "read")],
+        vec![],
+    )
+    .await;
+    Mock::given(method("PUT"))
+        .and(path(permissions_path("dest", "groups/ops")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(group("ops", "read")))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let filter = ChangeFilter {
+        mode: CopyMode::Additive,
+        ..ChangeFilter::default()
+    };
+    let summary = copy(
+        repo(&server, "src"),
+        repo(&server, "dest"),
+        &Mapping::default(),
+        &filter,
+        options,
+    )
+    .await
+    .unwrap();
+    assert_eq!((summary.added, summary.updated, summary.removed), (1, 0, 0));
+}
+
+#[test]
+fn upgrade_only_and_downgrade_only_filter_by_direction() {
+    let grant = |permission| Permission {
+        object_type: ObjectType::User,
+        alias: "alice".to_string(),
+        id: "{u1}".to_string(),
+        permission,
+        via: Vec::new(),
+    };
+    let changes = [
+        Change::Add(grant(PermissionType::Read)),
+        Change::Update {
+            before: grant(PermissionType::Read),
+            after: grant(PermissionType::Write),
+        },
+        Change::Update {
+            before: grant(PermissionType::Admin),
+            after: grant(PermissionType::Write),
+        },
+        Change::Remove(grant(PermissionType::Read)),
+    ];
+    let allowed =
+        |filter: ChangeFilter| changes.iter().map(|c| filter.allows(c)).collect::<Vec<_>>();
+
+    assert_eq!(
+        allowed(ChangeFilter {
+            upgrade_only: true,
+            ..ChangeFilter::default()
+        }),
+        [true, true, false, false]
+    );
+    assert_eq!(
+        allowed(ChangeFilter {
+            downgrade_only: true,
+            ..ChangeFilter::default()
+        }),
+        [false, false, true, true]
+    );
+    assert_eq!(
+        allowed(ChangeFilter {
+            mode: CopyMode::Prune,
+            downgrade_only: true,
+            ..ChangeFilter::default()
+        }),
+        [false, false, false, true]
+    );
+}
+
+#[test]
+fn copy_filters_select_principals() {
+    let change = |object_type, id: &str, alias: &str| {
+        Change::Add(Permission {
+            object_type,
+            alias: alias.to_string(),
+            id: id.to_string(),
+            permission: PermissionType::Read,
+            via: Vec::new(),
+        })
+    };
+    let team_a = change(ObjectType::Group, "team-a", "Team A");
+    let team_b = change(ObjectType::Group, "team-b", "Team B");
+    let ops = change(ObjectType::Group, "ops", "Ops");
+    let alice = change(ObjectType::User, "{u1}", "alice");
+
+    let filter = ChangeFilter {
+        groups_only: true,
+        include: vec![glob_matcher("team-*").unwrap()],
+        exclude: vec![glob_matcher("Team B").unwrap()],
+        ..ChangeFilter::default()
+    };
+    assert!(filter.allows(&team_a));
+    assert!(!filter.allows(&team_b));
+    assert!(!filter.allows(&ops));
+    assert!(!filter.allows(&alice));
+
+    let filter = ChangeFilter {
+        users_only: true,
+        ..ChangeFilter::default()
+    };
+    assert!(filter.allows(&alice));
+    assert!(!filter.allows(&ops));
+}
+
+#[test]
+fn diff_tells_a_user_and_a_group_with_the_same_id_apart() {
+    let grant = |object_type, permission| Permission {
+        object_type,
+        alias: "ops".to_string(),
+        id: "ops".to_string(),
+        permission,
+        via: Vec::new(),
+    };
+    let before = [grant(ObjectType::User, PermissionType::Write)];
+    let after = [grant(ObjectType::Group, PermissionType::Read)];
+
+    let changes = diff_permissions(&before, &after);
+    assert!(matches!(
+        changes.as_slice(),
+        [Change::Add(added), Change::Remove(removed)]
+            if added.object_type == ObjectType::Group && removed.object_type == ObjectType::User
+    ));
+}
+
+#[test]
+fn merge_takes_the_highest_level() {
+    let grant = |id: &str, permission| Permission {
+        object_type: ObjectType::Group,
+        alias: id.to_string(),
+        id: id.to_string(),
+        permission,
+        via: Vec::new(),
+    };
+    let sources = [
+        (
+            "a".to_string(),
+            vec![
+                grant("devs", PermissionType::Write),
+                grant("ops", PermissionType::Read),
+            ],
+        ),
+        (
+            "b".to_string(),
+            vec![
+                grant("devs", PermissionType::Read),
+                grant("ops", PermissionType::Admin),
+                grant("qa", PermissionType::Read),
+            ],
+        ),
+    ];
+    let merged = merge_permissions(
+        &sources,
+        Conflict::Prompt,
+        Options {
+            yes: true,
+            ..Options::default()
+        },
+    )
+    .unwrap();
+    let merged: Vec<_> = merged
+        .iter()
+        .map(|p| (p.id.as_str(), p.permission))
+        .collect();
+    assert_eq!(
+        merged,
+        [
+            ("devs", PermissionType::Write),
+            ("ops", PermissionType::Admin),
+            ("qa", PermissionType::Read)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn merge_keeps_the_grants_only_the_destination_has() {
+    let server = MockServer::start().await;
+    mount_permissions(&server, "a", vec![group("devs", "write")], vec![]).await;
+    mount_permissions(&server, "b", vec![group("ops", "read")], vec![]).await;
+    mount_permissions(
+        &server,
+        "c",
+        vec![group("ops", "admin")],
+        vec![user("{u1}", "alice", "read")],
+    )
+    .await;
+    Mock::given(method("PUT"))
+        .and(path(permissions_path("c", "groups/devs")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(group("devs", "write")))
+        .expect(1)
+        .mount(&server)
+        .await;
+    for verb in ["PUT", "DELETE"] {
+        Mock::given(method(verb))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+    }
+
+    let options = Options {
+        yes: true,
+        dry_run: false,
+        ..Options::default()
+    };
+    let summary = merge(
+        &[repo(&server, "a"), repo(&server, "b")],
+        repo(&server, "c"),
+        Conflict::Highest,
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        (
+            summary.added,
+            summary.updated,
+            summary.removed,
+            summary.unchanged
+        ),
+        (1, 0, 0, 2)
+    );
+}
+
+#[tokio::test]
+async fn copy_from_missing_repository_changes_nothing() {
+    let server = MockServer::start().await;
+    mount_permissions(&server, "dest", vec![group("devs", "read")], vec![]).await;
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        dry_run: false,
+        ..Options::default()
+    };
+    let result = copy(
+        repo(&server, "missing"),
+        repo(&server, "dest"),
+        &Mapping::default(),
+        &ChangeFilter::default(),
+        options,
+    )
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn copy_to_many_reports_each_destination() {
+    let server = MockServer::start().await;
+    mount_permissions(&server, "src", vec![group("devs", "write")], vec![]).await;
+    mount_permissions(&server, "a", vec![], vec![]).await;
+    mount_permissions(&server, "b", vec![group("devs", "write")], vec![]).await;
+    Mock::given(method("PUT"))
+        .and(path(permissions_path("a", "groups/devs")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(group("devs", "write")))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        dry_run: false,
+        ..Options::default()
+    };
+    let dests = ["a", "b", "missing"].map(|slug| repo(&server, slug));
+    let results = copy_to_many(
+        repo(&server, "src"),
+        &dests,
+        &Mapping::default(),
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+    .unwrap();
+
+    let summaries: Vec<(&str, Option<(usize, usize)>)> = results
+        .iter()
+        .map(|(repo, r)| {
+            let counts = r.as_ref().ok().map(|s| (s.added, s.unchanged));
+            (repo.as_str(), counts)
+        })
+        .collect();
+    assert_eq!(
+        summaries,
+        vec![("a", Some((1, 0))), ("b", Some((0, 1))), ("missing", None)]
+    );
+}
+
+#[tokio::test]
+async fn copy_to_many_keeps_refused_credentials() {
+    let server = MockServer::start().await;
+    mount_permissions(&server, "src", vec![group("devs", "write")], vec![]).await;
+    mount_permissions(&server, "b", vec![group("devs", "write")], vec![]).await;
+    for (slug, status) in [("unauthorized", 401), ("forbidden", 403)] {
+        Mock::given(method("GET"))
+            .and(path(permissions_path(slug, "groups")))
+            .respond_with(ResponseTemplate::new(status))
+            .mount(&server)
+            .await;
+    }
+
+    let options = Options {
+        yes: true,
+        dry_run: true,
+        ..Options::default()
+    };
+    for slug in ["unauthorized", "forbidden"] {
+        let dests = [repo(&server, "b"), repo(&server, slug)];
+        let results = copy_to_many(
+            repo(&server, "src"),
+            &dests,
+            &Mapping::default(),
+            &ChangeFilter::default(),
+            options,
+        )
+        .await
+        .unwrap();
+
+        assert!(results[0].1.is_ok());
+        let errors = results.iter().filter_map(|(_, r)| r.as_ref().err());
+        assert_eq!(
+            failures_exit_code(errors.map(|e| e.as_ref())),
+            Some(EXIT_AUTH),
+            "{}",
+            slug
+        );
+    }
+}
+
+#[tokio::test]
+async fn audit_reports_each_repository_as_it_is_done() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [{"slug": "a"}, {"slug": "b"}, {"slug": "broken"}],
+        })))
+        .mount(&server)
+        .await;
+    mount_permissions(&server, "a", vec![group("devs", "write")], vec![]).await;
+    mount_permissions(&server, "b", vec![], vec![user("{u1}", "alice", "admin")]).await;
+
+    let mut seen = Vec::new();
+    let (results, cursor) = audit(repo(&server, ""), &Paging::default(), |(slug, _)| {
+        seen.push(slug.to_string())
+    })
+    .await
+    .unwrap();
+
+    seen.sort();
+    assert_eq!(seen, ["a", "b", "broken"]);
+    let slugs: Vec<&str> = results.iter().map(|(slug, _)| slug.as_str()).collect();
+    assert_eq!(slugs, ["a", "b", "broken"]);
+    assert_eq!(results[0].1.as_ref().unwrap()[0].id, "devs");
+    assert_eq!(results[1].1.as_ref().unwrap()[0].id, "{u1}");
+    assert!(results[2].1.is_err());
+    assert_eq!(cursor, None);
+}
+
+#[tokio::test]
+async fn selector_matches_slugs_by_glob_or_regex() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [{"slug": "team-a-api"}, {"slug": "team-a-web"}, {"slug": "team-b-api"}],
+        })))
+        .mount(&server)
+        .await;
+
+    let glob = Selector {
+        pattern: Some("team-a-*".to_string()),
+        regex: false,
+        ..Default::default()
+    };
+    let slugs = glob.select(&repo(&server, "")).await.unwrap();
+    assert_eq!(slugs, ["team-a-api", "team-a-web"]);
+
+    let regex = Selector {
+        pattern: Some("-api$".to_string()),
+        regex: true,
+        ..Default::default()
+    };
+    let slugs = regex.select(&repo(&server, "")).await.unwrap();
+    assert_eq!(slugs, ["team-a-api", "team-b-api"]);
+
+    let none = Selector {
+        pattern: Some("team-c-*".to_string()),
+        regex: false,
+        ..Default::default()
+    };
+    assert!(none.select(&repo(&server, "")).await.is_err());
+}
+
+#[tokio::test]
+async fn selector_lists_repositories_of_the_project() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws"))
+        .and(query_param("q", "project.key=\"PROJ\""))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [{"slug": "api"}, {"slug": "web"}],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let selector = Selector {
+        project: Some("PROJ".to_string()),
+        pattern: Some("w*".to_string()),
+        ..Default::default()
+    };
+    let slugs = selector.select(&repo(&server, "")).await.unwrap();
+    assert_eq!(slugs, ["web"]);
+}
+
+#[tokio::test]
+async fn repository_listing_reports_missing_fields() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"values": [{"name": "a"}]})))
+        .mount(&server)
+        .await;
+
+    let err = audit(repo(&server, ""), &Paging::default(), |_| {})
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, BbdanError::UnexpectedResponse { ref message, .. } if message.contains("slug")),
+        "{:?}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn remove_deletes_matching_grants() {
+    let server = MockServer::start().await;
+    mount_permissions(
+        &server,
+        "repo",
+        vec![group("devs", "write")],
+        vec![user("{u1}", "alice", "read"), user("{u2}", "bob", "admin")],
+    )
+    .await;
+    expect_delete(&server, &permissions_path("repo", "users/%7Bu1%7D")).await;
+    expect_delete(&server, &permissions_path("repo", "groups/devs")).await;
+
+    let filter = RemoveFilter {
+        users: vec!["alice".to_string()],
+        groups: vec!["devs".to_string()],
+        ..RemoveFilter::default()
+    };
+    let options = Options {
+        yes: true,
+        dry_run: false,
+        ..Options::default()
+    };
+    remove(repo(&server, "repo"), filter, options)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn remove_all_users_at_a_level() {
+    let server = MockServer::start().await;
+    mount_permissions(
+        &server,
+        "repo",
+        vec![group("admins", "admin")],
+        vec![user("{u1}", "alice", "read"), user("{u2}", "bob", "admin")],
+    )
+    .await;
+    // only bob: alice is read, and admins is a group
+    expect_delete(&server, &permissions_path("repo", "users/%7Bu2%7D")).await;
+
+    let filter = RemoveFilter {
+        all_users: true,
+        permissions: vec![PermissionType::Admin],
+        ..RemoveFilter::default()
+    };
+    let options = Options {
+        yes: true,
+        dry_run: false,
+        ..Options::default()
+    };
+    remove(repo(&server, "repo"), filter, options)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn offboard_removes_the_principal_everywhere() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [{"slug": "a"}, {"slug": "b"}, {"slug": "c"}, {"slug": "d"}, {"slug": "broken"}],
+        })))
+        .mount(&server)
+        .await;
+    mount_permissions(&server, "a", vec![], vec![user("{u1}", "alice", "admin")]).await;
+    mount_permissions(&server, "b", vec![], vec![user("{u2}", "bob", "read")]).await;
+    mount_permissions(&server, "c", vec![], vec![user("{u1}", "alice", "read")]).await;
+    mount_permissions(&server, "d", vec![group("administrators", "admin")], vec![]).await;
+    expect_delete(&server, &permissions_path("a", "users/%7Bu1%7D")).await;
+    Mock::given(method("DELETE"))
+        .and(path(permissions_path("c", "users/%7Bu1%7D")))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path(permissions_path("d", "groups/administrators")))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(0)
+        .mount(&server)
+        .await;
+    let protected = Box::leak(Box::new(Protected {
+        groups: vec!["administrators".to_string()],
+        ..Protected::default()
+    }));
+
+    let options = Options {
+        yes: true,
+        dry_run: false,
+        protected: Some(protected),
+        ..Options::default()
+    };
+    let outcomes = offboard(
+        repo(&server, ""),
+        (ObjectType::User, "alice".to_string()),
+        options,
+    )
+    .await
+    .unwrap();
+
+    let repos: Vec<&str> = outcomes.iter().map(|(repo, _)| repo.as_str()).collect();
+    assert_eq!(repos, ["a", "c", "broken"]);
+    assert!(matches!(&outcomes[0].1, Outcome::Removed(p) if p.id == "{u1}"));
+    assert!(matches!(&outcomes[1].1, Outcome::Failed(_)));
+    assert!(matches!(&outcomes[2].1, Outcome::Unlisted(_)));
+
+    let outcomes = offboard(
+        repo(&server, ""),
+        (ObjectType::Group, "administrators".to_string()),
+        options,
+    )
+    .await
+    .unwrap();
+    let repos: Vec<&str> = outcomes.iter().map(|(repo, _)| repo.as_str()).collect();
+    assert_eq!(repos, ["d", "broken"]);
+    assert!(matches!(&outcomes[0].1, Outcome::Skipped(p) if p.id == "administrators"));
+}
+
+#[tokio::test]
+async fn access_combines_direct_and_group_grants() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [{"slug": "a"}, {"slug": "b"}, {"slug": "c"}],
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/1.0/groups/ws"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            {"slug": "devs", "name": "Devs", "members": [{"uuid": "{u1}", "nickname": "alice"}]},
+            {"slug": "admins", "name": "Admins", "members": [{"uuid": "{u2}", "nickname": "bob"}]},
+        ])))
+        .mount(&server)
+        .await;
+    mount_permissions(
+        &server,
+        "a",
+        vec![group("devs", "write")],
+        vec![user("{u1}", "alice", "read")],
+    )
+    .await;
+    mount_permissions(&server, "b", vec![group("admins", "admin")], vec![]).await;
+    mount_permissions(
+        &server,
+        "c",
+        vec![group("devs", "read")],
+        vec![user("{u1}", "alice", "admin")],
+    )
+    .await;
+
+    Mock::given(method("GET"))
+        .and(path("/workspaces/ws/permissions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [{
+                "permission": "owner",
+                "user": {"uuid": "{u2}", "nickname": "bob"},
+            }],
+        })))
+        .mount(&server)
+        .await;
+
+    let report = access(repo(&server, ""), "{u1}").await.unwrap();
+
+    assert_eq!(report.groups, ["devs"]);
+    let reached: Vec<(&str, PermissionType, Vec<&str>)> = report
+        .access
+        .iter()
+        .map(|a| {
+            let via = a.via.iter().map(String::as_str).collect();
+            (a.repository.as_str(), a.permission, via)
+        })
+        .collect();
+    assert_eq!(
+        reached,
+        [
+            ("a", PermissionType::Write, vec!["group:devs", "direct"]),
+            ("c", PermissionType::Admin, vec!["group:devs", "direct"]),
+        ]
+    );
+
+    // an owner administers every repository
+    let report = access(repo(&server, ""), "{u2}").await.unwrap();
+    let reached: Vec<(&str, PermissionType, Vec<&str>)> = report
+        .access
+        .iter()
+        .map(|a| {
+            let via = a.via.iter().map(String::as_str).collect();
+            (a.repository.as_str(), a.permission, via)
+        })
+        .collect();
+    assert_eq!(
+        reached,
+        [
+            ("a", PermissionType::Admin, vec!["workspace:owner"]),
+            (
+                "b",
+                PermissionType::Admin,
+                vec!["group:admins", "workspace:owner"]
+            ),
+            ("c", PermissionType::Admin, vec!["workspace:owner"]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn watch_detects_changes_since_the_baseline() {
+    let server = MockServer::start().await;
+    mount_permissions(
+        &server,
+        "a",
+        vec![group("devs", "admin")],
+        vec![user("{u1}", "alice", "read")],
+    )
+    .await;
+    mount_permissions(&server, "b", vec![group("devs", "read")], vec![]).await;
+    let baseline = SyncDocument::parse(
+        r#"{
+            "version": 1,
+            "repositories": {
+                "a": {"groups": [{"slug": "devs", "permission": "write"}]},
+                "b": {"groups": [{"slug": "devs", "permission": "read"}]}
+            }
+        }"#,
+        DocumentFormat::Json,
+    )
+    .unwrap();
+
+    let drift = watch::detect(repo(&server, ""), &baseline).await;
+
+    assert_eq!(drift.keys().collect::<Vec<_>>(), ["a"]);
+    let changes = drift["a"].as_ref().unwrap();
+    assert!(
+        matches!(&changes[0], Change::Update { after, .. } if after.permission == PermissionType::Admin)
+    );
+    assert!(matches!(&changes[1], Change::Add(p) if p.id == "{u1}"));
+    assert!(watch::new_drift(&drift, &drift).is_empty());
+}
+
+#[tokio::test]
+async fn notifier_posts_to_every_channel() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/slack"))
+        .and(body_json(
+            json!({"text": "*Policy check of ws: no violations*"}),
+        ))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/teams"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let notifier = Notifier {
+        http_client: reqwest::Client::new(),
+        config: NotifyConfig {
+            slack: Some(format!("{}/slack", server.uri())),
+            teams: Some(format!("{}/teams?secret=x", server.uri())),
+        },
+    };
+
+    let err = notifier
+        .send(&Notification::check("ws", &[]))
+        .await
+        .unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "request to the Teams webhook failed: 500 Internal Server Error"
+    );
+}
+
+#[tokio::test]
+async fn add_rejects_existing_grant() {
+    let server = MockServer::start().await;
+    mount_permissions(&server, "repo", vec![group("devs", "write")], vec![]).await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        dry_run: false,
+        ..Options::default()
+    };
+    let err = add(
+        repo(&server, "repo"),
+        (ObjectType::Group, "devs".to_string()),
+        PermissionType::Admin,
+        options,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, BbdanError::Invalid(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn oauth_client_credentials() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/site/oauth2/access_token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "access",
+            "refresh_token": "refresh",
+            "expires_in": 7200,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let oauth = OAuthClient::new(
+        reqwest::Client::new(),
+        "id".to_string(),
+        "secret".to_string(),
+        format!("{}/site/oauth2/authorize", server.uri()),
+        format!("{}/site/oauth2/access_token", server.uri()),
+    );
+    let token = oauth.client_credentials().await.unwrap();
+    assert_eq!(token.access_token, "access");
+    assert_eq!(token.refresh_token.as_deref(), Some("refresh"));
+}
+
+#[tokio::test]
+async fn oauth_session_refreshes_expired_and_rejected_tokens() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/site/oauth2/access_token"))
+        .and(body_string_contains("refresh_token=refresh"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "refreshed",
+            "expires_in": 7200,
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/user"))
+        .and(header("authorization", "Bearer refreshed"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws"))
+        .and(header("authorization", "Bearer refreshed"))
+        .respond_with(ResponseTemplate::new(401))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws"))
+        .and(header("authorization", "Bearer refreshed"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"values": []})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let oauth = OAuthClient::new(
+        reqwest::Client::new(),
+        "id".to_string(),
+        "secret".to_string(),
+        format!("{}/site/oauth2/authorize", server.uri()),
+        format!("{}/site/oauth2/access_token", server.uri()),
+    );
+    let expired = OAuthToken {
+        client_id: "id".to_string(),
+        access_token: "expired".to_string(),
+        refresh_token: Some("refresh".to_string()),
+        expires_at: chrono::Local::now(),
+    };
+    let client = BitbucketClient::new(
+        reqwest::Client::new(),
+        server.uri(),
+        Auth::OAuth(Arc::new(OAuthSession::new(oauth, expired))),
+    );
+
+    // the expired token is refreshed before the request is sent
+    let resp = client.http_get("user".to_string()).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    // the rejected token is refreshed and the request sent again
+    let resp = client
+        .http_get("repositories/ws".to_string())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+}
+
+#[tokio::test]
+async fn archive_downgrades_every_grant_to_read() {
+    let server = MockServer::start().await;
+    mount_permissions(
+        &server,
+        "old",
+        vec![group("devs", "write"), group("qa", "read")],
+        vec![user("{u1}", "alice", "admin")],
+    )
+    .await;
+    Mock::given(method("PUT"))
+        .and(path(permissions_path("old", "groups/devs")))
+        .and(body_json(json!({"permission": "read"})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(group("devs", "read")))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(permissions_path("old", "users/%7Bu1%7D")))
+        .and(body_json(json!({"permission": "read"})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(user("{u1}", "alice", "read")))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let summary = archive_repository(&repo(&server, "old"), options)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        (summary.updated, summary.removed, summary.unchanged),
+        (2, 0, 1)
+    );
+}
+
+#[tokio::test]
+async fn verify_reports_missing_scopes() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("x-oauth-scopes", "repository:write, repository:admin")
+                .set_body_json(json!({"values": []})),
+        )
+        .mount(&server)
+        .await;
+    mount_permissions(&server, "repo", vec![], vec![]).await;
+
+    let report = verify(repo(&server, "repo"), true).await.unwrap();
+    assert_eq!(report.credentials, Credentials::Valid);
+    assert!(!report.ok());
+    let missing = |command: &str| {
+        report
+            .commands
+            .iter()
+            .find(|(c, _)| *c == command)
+            .map(|(_, missing)| missing.clone())
+            .unwrap()
+    };
+    assert!(missing("copy").is_empty());
+    assert_eq!(missing("hooks"), ["webhook"]);
+    assert_eq!(report.repository.map(|s| s.as_u16()), Some(200));
+}
+
+#[tokio::test]
+async fn audit_keeps_the_error_of_each_repository() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [{"slug": "a"}, {"slug": "b"}],
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(permissions_path("a", "groups")))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(permissions_path("b", "groups")))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&server)
+        .await;
+
+    let (results, _) = audit(repo(&server, ""), &Paging::default(), |_| {})
+        .await
+        .unwrap();
+    let errors: Vec<&BbdanError> = results
+        .iter()
+        .filter_map(|(_, result)| result.as_ref().err().map(|e| e.as_ref()))
+        .collect();
+    assert!(matches!(
+        errors[..],
+        [BbdanError::Auth(_), BbdanError::Http { .. }]
+    ));
+    assert_eq!(failures_exit_code(errors), Some(EXIT_AUTH));
+
+    let not_found = BbdanError::Http {
+        url: "u".to_string(),
+        status: reqwest::StatusCode::NOT_FOUND,
+    };
+    assert_eq!(failures_exit_code([&not_found]), Some(EXIT_PARTIAL));
+    assert_eq!(failures_exit_code([]), None);
+}
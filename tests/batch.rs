@@ -0,0 +1,46 @@
+use bbdan::batch::{parse_rows, Action};
+use bbdan::permissions::RemoveFilter;
+use bbdan::{BbdanError, ObjectType, PermissionType};
+
+#[test]
+fn rows_are_parsed_after_the_header_and_comments() {
+    let content = "repo,principal_type,principal,permission,action\n# onboarding\nrepo-a, user, alice, write, add\nrepo-b,Group,devs,,remove\n";
+
+    let rows = parse_rows(content).unwrap();
+
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].line, 3);
+    assert_eq!(rows[0].object_type, ObjectType::User);
+    assert_eq!(rows[0].principal, "alice");
+    assert_eq!(rows[0].permission, Some(PermissionType::Write));
+    assert_eq!(rows[0].action, Action::Add);
+    assert_eq!(rows[1].object_type, ObjectType::Group);
+    assert_eq!(rows[1].permission, None);
+    assert_eq!(rows[1].action, Action::Remove);
+}
+
+#[test]
+fn every_invalid_row_is_reported() {
+    let content = "repo-a,team,x,read,add\nrepo-a,user,alice,,update\nrepo-a,user,alice,read,add\nrepo-b,group\n";
+
+    let err = parse_rows(content).unwrap_err();
+
+    let BbdanError::Invalid(message) = err else {
+        panic!("{:?}", err);
+    };
+    let lines: Vec<&str> = message
+        .lines()
+        .map(|l| l.split(':').next().unwrap())
+        .collect();
+    assert_eq!(lines, ["line 1", "line 2", "line 4"]);
+}
+
+#[test]
+fn principals_to_remove_are_users_or_groups() {
+    let content = "# leavers\nuser:alice\n{d3c1a2b4-0000}\n\ngroup: old-team\ncontractors\n";
+
+    let filter = RemoveFilter::parse(content);
+
+    assert_eq!(filter.users, vec!["alice", "{d3c1a2b4-0000}"]);
+    assert_eq!(filter.groups, vec!["old-team", "contractors"]);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use bbdan::branch_restrictions::copy_restrictions;
+use bbdan::Options;
+use common::{mount_page, repo};
+use serde_json::json;
+use wiremock::matchers::{body_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn copy_adds_the_rules_the_destination_lacks() {
+    let server = MockServer::start().await;
+    mount_page(
+        &server,
+        "/repositories/ws/template/branch-restrictions",
+        json!([
+            {"id": 1, "kind": "push", "branch_match_kind": "glob", "pattern": "main",
+             "users": [{"uuid": "{u1}", "nickname": "alice"}], "groups": []},
+            {"id": 2, "kind": "require_approvals_to_merge", "branch_match_kind": "glob",
+             "pattern": "main", "value": 2, "users": [], "groups": []},
+        ]),
+    )
+    .await;
+    // the same push rule under another id and without the nickname
+    mount_page(
+        &server,
+        "/repositories/ws/service/branch-restrictions",
+        json!([
+            {"id": 9, "kind": "push", "branch_match_kind": "glob", "pattern": "main",
+             "users": [{"uuid": "{u1}"}], "groups": []},
+            {"id": 10, "kind": "delete", "branch_match_kind": "glob", "pattern": "main"},
+        ]),
+    )
+    .await;
+    Mock::given(method("POST"))
+        .and(path("/repositories/ws/service/branch-restrictions"))
+        .and(body_json(json!({
+            "kind": "require_approvals_to_merge", "branch_match_kind": "glob",
+            "pattern": "main", "value": 2, "users": [], "groups": [],
+        })))
+        .respond_with(ResponseTemplate::new(201).set_body_json(json!({"id": 11})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let results = copy_restrictions(
+        repo(&server, "template"),
+        &[repo(&server, "service")],
+        false,
+        options,
+    )
+    .await
+    .unwrap();
+
+    let summary = results[0].1.as_ref().unwrap();
+    assert_eq!(
+        (summary.added, summary.removed, summary.unchanged),
+        (1, 0, 2)
+    );
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use bbdan::branching_model::copy_branching_model;
+use bbdan::Options;
+use common::repo;
+use serde_json::json;
+use wiremock::matchers::{body_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn only_the_differing_branch_types_are_sent() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws/template/branching-model/settings"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "development": {"name": "develop", "use_mainbranch": false, "is_valid": true},
+            "production": {"use_mainbranch": true, "enabled": true, "is_valid": true},
+            "branch_types": [
+                {"kind": "feature", "enabled": true, "prefix": "feature/"},
+                {"kind": "bugfix", "enabled": true, "prefix": "fix/"},
+            ],
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws/service/branching-model/settings"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "development": {"name": "develop", "use_mainbranch": false, "is_valid": true},
+            "production": {"use_mainbranch": true, "enabled": true, "is_valid": true},
+            "branch_types": [
+                {"kind": "feature", "enabled": true, "prefix": "feature/"},
+                {"kind": "bugfix", "enabled": true, "prefix": "bugfix/"},
+            ],
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path("/repositories/ws/service/branching-model/settings"))
+        .and(body_json(json!({
+            "branch_types": [{"kind": "bugfix", "enabled": true, "prefix": "fix/"}],
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let results = copy_branching_model(
+        repo(&server, "template"),
+        &[repo(&server, "service")],
+        options,
+    )
+    .await
+    .unwrap();
+
+    let summary = results[0].1.as_ref().unwrap();
+    assert_eq!((summary.updated, summary.unchanged), (1, 3));
+}
@@ -0,0 +1,39 @@
+//! Fixtures shared by the integration tests
+#![allow(dead_code)]
+
+use bbdan::{Auth, Bitbucket};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// The repository `slug` of the workspace `ws`, served by the mock server.
+/// An empty slug is the workspace itself.
+pub fn repo(server: &MockServer, slug: &str) -> Bitbucket {
+    repo_at(&server.uri(), slug)
+}
+
+/// The repository `slug` of the workspace `ws` at `base_url`, for tests that send no requests
+pub fn repo_at(base_url: &str, slug: &str) -> Bitbucket {
+    Bitbucket {
+        http_client: reqwest::Client::new(),
+        base_url: base_url.to_string(),
+        auth: Auth::Bearer {
+            token: "secret".to_string(),
+        },
+        workspace: "ws".to_string(),
+        slug: slug.to_string(),
+        pagelen: 100,
+        parallel: 4,
+        http_log: None,
+        history: None,
+    }
+}
+
+/// Serve `values` as the only page of the listing at `url_path`
+pub async fn mount_page(server: &MockServer, url_path: &str, values: serde_json::Value) {
+    Mock::given(method("GET"))
+        .and(path(url_path))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "values": values })))
+        .mount(server)
+        .await;
+}
@@ -0,0 +1,32 @@
+use bbdan::completion::script;
+use clap::{Arg, Command};
+use clap_complete::Shell;
+
+fn command() -> Command<'static> {
+    Command::new("bbdan")
+        .subcommand(Command::new("list").arg(Arg::new("repo").required(true)))
+        .subcommand(
+            Command::new("copy")
+                .arg(Arg::new("src-repo").required(true))
+                .arg(Arg::new("dest-repo").required(true)),
+        )
+        .subcommand(Command::new("login"))
+}
+
+#[test]
+fn repositories_are_completed_by_the_workspace_listing() {
+    let bash = script(&mut command(), Shell::Bash);
+    assert!(bash.contains(" $(bbdan complete-repos 2>/dev/null)\""));
+    assert!(!bash.contains("<REPO>"));
+    assert!(!bash.contains("<SRC_REPO>"));
+
+    let zsh = script(&mut command(), Shell::Zsh);
+    assert!(zsh.starts_with("#compdef bbdan\n\n_bbdan_repos() {"));
+    assert!(zsh.contains("':src-repo:_bbdan_repos' \\"));
+    assert!(zsh.contains("':dest-repo:_bbdan_repos' \\"));
+
+    let fish = script(&mut command(), Shell::Fish);
+    assert!(fish.ends_with(
+        "complete -c bbdan -n \"__fish_seen_subcommand_from list copy\" -f -a \"(bbdan complete-repos 2>/dev/null)\"\n"
+    ));
+}
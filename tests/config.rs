@@ -0,0 +1,43 @@
+use bbdan::config::parse_netrc;
+
+fn credentials(login: &str, password: &str) -> Option<(String, String)> {
+    Some((login.to_string(), password.to_string()))
+}
+
+#[test]
+fn netrc_picks_the_machine_entry_of_the_host() {
+    let content = "machine github.com login octocat password gh-secret\n\
+                   machine api.bitbucket.org login alice password bb-secret\n";
+
+    assert_eq!(
+        parse_netrc(content, "api.bitbucket.org"),
+        credentials("alice", "bb-secret")
+    );
+    assert_eq!(parse_netrc(content, "example.com"), None);
+}
+
+#[test]
+fn netrc_falls_back_to_the_default_entry() {
+    let content = "machine github.com login octocat password gh-secret\n\
+                   default login fallback password default-secret\n";
+
+    assert_eq!(
+        parse_netrc(content, "api.bitbucket.org"),
+        credentials("fallback", "default-secret")
+    );
+    assert_eq!(
+        parse_netrc(content, "github.com"),
+        credentials("octocat", "gh-secret")
+    );
+}
+
+#[test]
+fn netrc_reads_entries_spread_over_several_lines() {
+    let content = "default\n  login fallback\n  password default-secret\n\n\
+                   machine api.bitbucket.org\n  password bb-secret\n  login alice\n";
+
+    assert_eq!(
+        parse_netrc(content, "api.bitbucket.org"),
+        credentials("alice", "bb-secret")
+    );
+}
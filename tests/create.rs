@@ -0,0 +1,94 @@
+mod common;
+
+use bbdan::create::create_like;
+use bbdan::Options;
+use common::{mount_page, repo};
+use serde_json::json;
+use wiremock::matchers::{body_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn repository_is_created_like_the_template() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws/template"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "slug": "template",
+            "scm": "git",
+            "project": {"key": "PRJ", "name": "Project"},
+            "is_private": true,
+            "fork_policy": "no_forks",
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/repositories/ws/service"))
+        .and(body_json(json!({
+            "scm": "git",
+            "project": {"key": "NEW"},
+            "is_private": true,
+            "fork_policy": "no_forks",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"slug": "service"})))
+        .expect(1)
+        .mount(&server)
+        .await;
+    // Looked up before anything is copied to it
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws/service"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"slug": "service"})))
+        .mount(&server)
+        .await;
+    for slug in ["template", "service"] {
+        for resource in [
+            "permissions-config/users",
+            "permissions-config/groups",
+            "hooks",
+        ] {
+            mount_page(
+                &server,
+                &format!("/repositories/ws/{}/{}", slug, resource),
+                json!([]),
+            )
+            .await;
+        }
+    }
+    mount_page(
+        &server,
+        "/repositories/ws/template/default-reviewers",
+        json!([{"uuid": "{u1}", "nickname": "alice"}]),
+    )
+    .await;
+    mount_page(
+        &server,
+        "/repositories/ws/service/default-reviewers",
+        json!([]),
+    )
+    .await;
+    Mock::given(method("PUT"))
+        .and(path("/repositories/ws/service/default-reviewers/%7Bu1%7D"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"uuid": "{u1}"})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let results = create_like(
+        repo(&server, "template"),
+        repo(&server, "service"),
+        Some("NEW".to_string()),
+        options,
+    )
+    .await
+    .unwrap();
+
+    let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["repository", "permissions", "default-reviewers", "hooks"]
+    );
+    assert_eq!(results[2].1.as_ref().unwrap().added, 1);
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use bbdan::default_reviewers::copy_reviewers;
+use bbdan::Options;
+use common::{mount_page, repo};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn copy_with_prune_mirrors_the_reviewers() {
+    let server = MockServer::start().await;
+    mount_page(
+        &server,
+        "/repositories/ws/template/default-reviewers",
+        json!([{"uuid": "{u1}", "nickname": "alice"}, {"uuid": "{u2}", "nickname": "bob"}]),
+    )
+    .await;
+    mount_page(
+        &server,
+        "/repositories/ws/service/default-reviewers",
+        json!([{"uuid": "{u2}", "nickname": "bob"}, {"uuid": "{u3}", "nickname": "carol"}]),
+    )
+    .await;
+    Mock::given(method("PUT"))
+        .and(path("/repositories/ws/service/default-reviewers/%7Bu1%7D"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"uuid": "{u1}"})))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path("/repositories/ws/service/default-reviewers/%7Bu3%7D"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let results = copy_reviewers(
+        repo(&server, "template"),
+        &[repo(&server, "service")],
+        true,
+        options,
+    )
+    .await
+    .unwrap();
+
+    let summary = results[0].1.as_ref().unwrap();
+    assert_eq!(
+        (summary.added, summary.removed, summary.unchanged),
+        (1, 1, 1)
+    );
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use bbdan::deploy_keys::copy_deploy_keys;
+use bbdan::Options;
+use common::{mount_page, repo};
+use serde_json::json;
+use wiremock::matchers::{body_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn copy_adds_the_keys_by_their_material() {
+    let server = MockServer::start().await;
+    mount_page(
+        &server,
+        "/repositories/ws/template/deploy-keys",
+        json!([
+            {"id": 1, "key": "ssh-ed25519 AAAAci", "label": "ci", "comment": "ci@example.com"},
+            {"id": 2, "key": "ssh-ed25519 AAAAdeploy", "label": "deploy"},
+        ]),
+    )
+    .await;
+    // the ci key under another label
+    mount_page(
+        &server,
+        "/repositories/ws/service/deploy-keys",
+        json!([{"id": 7, "key": "ssh-ed25519 AAAAci", "label": "build"}]),
+    )
+    .await;
+    Mock::given(method("POST"))
+        .and(path("/repositories/ws/service/deploy-keys"))
+        .and(body_json(
+            json!({"key": "ssh-ed25519 AAAAdeploy", "label": "deploy"}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": 8})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let results = copy_deploy_keys(
+        repo(&server, "template"),
+        &[repo(&server, "service")],
+        false,
+        options,
+    )
+    .await
+    .unwrap();
+
+    let summary = results[0].1.as_ref().unwrap();
+    assert_eq!((summary.added, summary.unchanged), (1, 1));
+}
+
+#[tokio::test]
+async fn copy_with_prune_refuses_more_changes_than_max_changes() {
+    let server = MockServer::start().await;
+    mount_page(
+        &server,
+        "/repositories/ws/wrong-source/deploy-keys",
+        json!([]),
+    )
+    .await;
+    mount_page(
+        &server,
+        "/repositories/ws/service/deploy-keys",
+        json!([
+            {"id": 1, "key": "ssh-ed25519 AAAAci", "label": "ci"},
+            {"id": 2, "key": "ssh-ed25519 AAAAdeploy", "label": "deploy"},
+        ]),
+    )
+    .await;
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        max_changes: Some(1),
+        ..Options::default()
+    };
+    let results = copy_deploy_keys(
+        repo(&server, "wrong-source"),
+        &[repo(&server, "service")],
+        true,
+        options,
+    )
+    .await
+    .unwrap();
+
+    let err = results[0].1.as_ref().unwrap_err();
+    assert!(
+        err.to_string().contains("exceed --max-changes 1"),
+        "{}",
+        err
+    );
+}
@@ -0,0 +1,18 @@
+use bbdan::config::load_config;
+use bbdan::email::SmtpTls;
+
+#[test]
+fn smtp_server_is_read_from_the_config() {
+    let path = std::env::temp_dir().join(format!("bbdan-smtp-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        "[smtp]\nhost = \"127.0.0.1\"\nport = 2525\nfrom = \"bbdan <bbdan@example.com>\"\ntls = \"none\"\n",
+    )
+    .unwrap();
+    let config = load_config(Some(&path)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(config.smtp.is_complete());
+    assert_eq!(config.smtp.port, Some(2525));
+    assert!(matches!(config.smtp.tls, SmtpTls::None));
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use bbdan::environments::copy_environments;
+use bbdan::Options;
+use common::{mount_page, repo};
+use serde_json::json;
+use wiremock::matchers::{body_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn variables_are_copied_to_the_environment_of_the_same_name() {
+    let server = MockServer::start().await;
+    mount_page(
+        &server,
+        "/repositories/ws/template/environments",
+        json!([{"uuid": "{e1}", "name": "Staging", "environment_type": {"name": "Staging"}}]),
+    )
+    .await;
+    mount_page(
+        &server,
+        "/repositories/ws/template/deployments_config/environments/%7Be1%7D/variables",
+        json!([{"uuid": "{v1}", "key": "URL", "value": "https://staging", "secured": false}]),
+    )
+    .await;
+    mount_page(
+        &server,
+        "/repositories/ws/service/environments",
+        json!([{"uuid": "{e9}", "name": "Staging", "environment_type": {"name": "Staging"}}]),
+    )
+    .await;
+    mount_page(
+        &server,
+        "/repositories/ws/service/deployments_config/environments/%7Be9%7D/variables",
+        json!([]),
+    )
+    .await;
+    Mock::given(method("POST"))
+        .and(path(
+            "/repositories/ws/service/deployments_config/environments/%7Be9%7D/variables",
+        ))
+        .and(body_json(
+            json!({"key": "URL", "value": "https://staging", "secured": false}),
+        ))
+        .respond_with(ResponseTemplate::new(201).set_body_json(json!({"uuid": "{v2}"})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let results = copy_environments(
+        repo(&server, "template"),
+        &[repo(&server, "service")],
+        false,
+        options,
+    )
+    .await
+    .unwrap();
+
+    let summary = results[0].1.as_ref().unwrap();
+    assert_eq!((summary.added, summary.unchanged), (1, 1));
+}
@@ -0,0 +1,78 @@
+use bbdan::history::{History, Source};
+use bbdan::reconcile::Change;
+use bbdan::{ObjectType, Permission, PermissionType};
+
+#[test]
+fn history_shows_the_changes_between_recorded_states() {
+    let path = std::env::temp_dir().join(format!("bbdan-history-{}.sqlite3", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let permissions = vec![Permission {
+        object_type: ObjectType::Group,
+        alias: "Developers".to_string(),
+        id: "developers".to_string(),
+        permission: PermissionType::Write,
+        via: Vec::new(),
+    }];
+
+    let mut history = History::open(&path, "audit").unwrap();
+    history
+        .record("ws", "repo", Source::Observed, &permissions)
+        .unwrap();
+    // the same listing again is not a change
+    history
+        .record("ws", "repo", Source::Observed, &permissions)
+        .unwrap();
+    let mut history = History::open(&path, "sync").unwrap();
+    let admin = Permission {
+        permission: PermissionType::Admin,
+        ..permissions[0].clone()
+    };
+    history
+        .record_change(
+            "ws",
+            "repo",
+            &Change::Update {
+                before: permissions[0].clone(),
+                after: admin.clone(),
+            },
+        )
+        .unwrap();
+    history
+        .record_change("ws", "repo", &Change::Remove(admin))
+        .unwrap();
+
+    let entries = history.entries("ws", "repo").unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let changes: Vec<(&str, &str, &[String])> = entries
+        .iter()
+        .map(|e| {
+            (
+                e.source.as_str(),
+                e.run.command.as_str(),
+                e.changes.as_slice(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            (
+                "observed",
+                "audit",
+                &["has group developers write".to_string()][..]
+            ),
+            (
+                "applied",
+                "sync",
+                &["changed group developers from write to admin".to_string()][..]
+            ),
+            (
+                "applied",
+                "sync",
+                &["revoked group developers admin".to_string()][..]
+            ),
+        ]
+    );
+    assert_eq!(entries[1].run.id, entries[2].run.id);
+}
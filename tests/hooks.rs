@@ -0,0 +1,13 @@
+use bbdan::hooks::verify_signature;
+
+#[test]
+fn signature_is_the_hmac_of_the_body() {
+    let body = br#"{"repository":{"full_name":"ws/repo-a"}}"#;
+    let signature = "sha256=7ff54d6ae4e703792cad0f2cb357ef1aa23363ca0a4ee035241aee1978204618";
+
+    assert!(verify_signature("secret", body, Some(signature)));
+    assert!(!verify_signature("other", body, Some(signature)));
+    assert!(!verify_signature("secret", b"{}", Some(signature)));
+    assert!(!verify_signature("secret", body, Some("sha256=zz")));
+    assert!(!verify_signature("secret", body, None));
+}
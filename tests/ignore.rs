@@ -0,0 +1,45 @@
+use bbdan::reconcile::{considered, Ignore};
+use bbdan::{ObjectType, Options, Permission, PermissionType};
+
+fn permission(object_type: ObjectType, id: &str, alias: &str) -> Permission {
+    Permission {
+        object_type,
+        alias: alias.to_string(),
+        id: id.to_string(),
+        permission: PermissionType::Read,
+        via: Vec::new(),
+    }
+}
+
+#[test]
+fn ignore_matches_ids_and_names() {
+    let ignore = Ignore::new(&["bot-*".to_string(), "Default *".to_string()]).unwrap();
+    assert!(ignore.matches(&permission(ObjectType::User, "{u1}", "bot-ci")));
+    assert!(ignore.matches(&permission(
+        ObjectType::Group,
+        "default",
+        "Default Developers"
+    )));
+    assert!(ignore.matches(&permission(ObjectType::Group, "bot-deployers", "Deployers")));
+    assert!(!ignore.matches(&permission(ObjectType::User, "{u2}", "alice")));
+
+    assert!(Ignore::new(&["[".to_string()]).is_err());
+}
+
+#[test]
+fn considered_leaves_out_what_the_options_ignore() {
+    let permissions = vec![
+        permission(ObjectType::User, "{u1}", "bot-ci"),
+        permission(ObjectType::User, "{u2}", "alice"),
+    ];
+    assert_eq!(considered(permissions.clone(), Options::default()).len(), 2);
+
+    let ignore = Box::leak(Box::new(Ignore::new(&["bot-*".to_string()]).unwrap()));
+    let options = Options {
+        ignore: Some(ignore),
+        ..Options::default()
+    };
+    let considered = considered(permissions, options);
+    let aliases: Vec<&str> = considered.iter().map(|p| p.alias.as_str()).collect();
+    assert_eq!(aliases, ["alice"]);
+}
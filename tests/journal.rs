@@ -0,0 +1,238 @@
+mod common;
+
+use bbdan::journal::{self, Journal, JournalEntry, Journaled};
+use bbdan::reconcile::{apply, Change};
+use bbdan::{BbdanError, ObjectType, Options, Permission, PermissionType};
+use common::{repo, repo_at};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[test]
+fn journal_detects_modified_entries() {
+    let path = std::env::temp_dir().join(format!("bbdan-journal-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let bitbucket = repo_at("http://localhost", "repo");
+    let read = Permission {
+        object_type: ObjectType::User,
+        alias: "alice".to_string(),
+        id: "{u1}".to_string(),
+        permission: PermissionType::Read,
+        via: Vec::new(),
+    };
+    let admin = Permission {
+        permission: PermissionType::Admin,
+        ..read.clone()
+    };
+
+    let journal = Journal::new(&path, "copy");
+    journal
+        .append(&bitbucket, &Change::Add(read.clone()))
+        .unwrap();
+    journal
+        .append(
+            &bitbucket,
+            &Change::Update {
+                before: read,
+                after: admin,
+            },
+        )
+        .unwrap();
+
+    journal
+        .append_request(
+            &bitbucket,
+            "POST",
+            "http://localhost/repositories/ws/repo/pipelines_config/variables",
+            Some(json!({"key": "TOKEN", "value": "hunter2", "secured": true})),
+        )
+        .unwrap();
+
+    let entries = journal::read(&path).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert!(matches!(
+        entries[1].change,
+        Journaled::Grant {
+            before: Some(PermissionType::Read),
+            after: Some(PermissionType::Admin),
+            ..
+        }
+    ));
+    assert_eq!(entries[1].previous, entries[0].hash);
+    let Journaled::Request { method, body, .. } = &entries[2].change else {
+        panic!("not a request: {:?}", entries[2]);
+    };
+    assert_eq!(method, "POST");
+    assert_eq!(body.as_ref().unwrap()["value"], "********");
+    journal::verify(&entries).unwrap();
+
+    // a grant is journaled as before requests were, so older journals still verify
+    let line = std::fs::read_to_string(&path).unwrap();
+    assert!(line.contains(r#""repository":"repo","type":"user","id":"{u1}","alias":"alice","before":null,"after":"read","previous":"""#));
+
+    let mut tampered = entries.clone();
+    tampered[0].change = entries[1].change.clone();
+    assert!(journal::verify(&tampered).is_err());
+    assert!(journal::verify(&entries[1..]).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn entry(
+    run: &str,
+    id: &str,
+    before: Option<PermissionType>,
+    after: Option<PermissionType>,
+) -> JournalEntry {
+    JournalEntry {
+        timestamp: chrono::Utc::now(),
+        run: run.to_string(),
+        actor: "alice@host".to_string(),
+        command: "copy".to_string(),
+        reverts: None,
+        workspace: "ws".to_string(),
+        repository: "repo".to_string(),
+        change: Journaled::Grant {
+            object_type: ObjectType::Group,
+            id: id.to_string(),
+            alias: id.to_string(),
+            before,
+            after,
+        },
+        previous: String::new(),
+        hash: String::new(),
+    }
+}
+
+#[test]
+fn reverse_reverts_the_net_changes_of_a_run() {
+    use PermissionType::*;
+    let entries = vec![
+        entry("1", "devs", Some(Read), Some(Write)),
+        entry("2", "devs", Some(Write), Some(Admin)),
+        entry("2", "devs", Some(Admin), Some(Read)),
+        entry("2", "old", Some(Write), None),
+        entry("2", "new", None, Some(Read)),
+        entry("2", "tmp", None, Some(Read)),
+        entry("2", "tmp", Some(Read), None),
+        JournalEntry {
+            change: Journaled::Request {
+                method: "DELETE".to_string(),
+                url: "http://localhost/repositories/ws/repo/hooks/{h1}".to_string(),
+                body: None,
+            },
+            ..entry("2", "", None, None)
+        },
+    ];
+    assert_eq!(journal::last_run(&entries, "ws"), Some("2"));
+    assert_eq!(journal::last_run(&entries, "other"), None);
+
+    let changes = journal::reverse(&entries, "ws", "2");
+    let changes: Vec<String> = changes["repo"]
+        .iter()
+        .map(|c| match c {
+            Change::Add(p) => format!("add {} {:?}", p.id, p.permission),
+            Change::Update { before, after } => {
+                format!(
+                    "update {} {:?} {:?}",
+                    after.id, before.permission, after.permission
+                )
+            }
+            Change::Remove(p) => format!("remove {} {:?}", p.id, p.permission),
+            Change::Unchanged(p) => format!("unchanged {}", p.id),
+        })
+        .collect();
+    assert_eq!(
+        changes,
+        vec!["remove new Read", "add old Write", "update devs Read Write"]
+    );
+    assert!(journal::reverse(&entries, "other", "2").is_empty());
+}
+
+#[test]
+fn last_run_skips_undos_and_the_runs_they_reverted() {
+    use PermissionType::*;
+    let undo = |run: &str, reverts: Option<&str>| JournalEntry {
+        command: "undo".to_string(),
+        reverts: reverts.map(String::from),
+        ..entry(run, "devs", Some(Admin), Some(Write))
+    };
+    let mut entries = vec![
+        entry("1", "devs", Some(Read), Some(Write)),
+        entry("2", "devs", Some(Write), Some(Admin)),
+        undo("3", Some("2")),
+    ];
+    assert_eq!(journal::last_run(&entries, "ws"), Some("1"));
+
+    entries.push(undo("4", Some("1")));
+    assert_eq!(journal::last_run(&entries, "ws"), None);
+
+    // an undo journaled before the reverted run was recorded
+    let entries = vec![entry("1", "devs", Some(Read), Some(Write)), undo("2", None)];
+    assert_eq!(journal::last_run(&entries, "ws"), Some("1"));
+}
+
+#[tokio::test]
+async fn change_is_refused_when_the_journal_is_broken() {
+    let server = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .and(path("/repositories/ws/repo/permissions-config/groups/devs"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"permission": "write"})))
+        .expect(0)
+        .mount(&server)
+        .await;
+    let path = std::env::temp_dir().join(format!("bbdan-broken-{}.jsonl", std::process::id()));
+    std::fs::write(&path, "{\"timestamp\": \n").unwrap();
+    journal::enable(Journal::new(&path, "copy"));
+
+    let bitbucket = repo(&server, "repo");
+    let devs = Permission {
+        object_type: ObjectType::Group,
+        alias: "devs".to_string(),
+        id: "devs".to_string(),
+        permission: PermissionType::Write,
+        via: Vec::new(),
+    };
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let result = apply(&bitbucket, Change::Add(devs), options).await;
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(result, Err(BbdanError::Invalid(e)) if e.contains("is broken")));
+}
+
+#[test]
+fn concurrent_runs_keep_the_chain_intact() {
+    let path = std::env::temp_dir().join(format!("bbdan-concurrent-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let grant = |i: usize| Permission {
+        object_type: ObjectType::User,
+        alias: format!("user{}", i),
+        id: format!("{{u{}}}", i),
+        permission: PermissionType::Read,
+        via: Vec::new(),
+    };
+
+    let runs: Vec<_> = (0..4)
+        .map(|run| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let journal = Journal::new(&path, "sync");
+                let bitbucket = repo_at("http://localhost", "repo");
+                for i in 0..10 {
+                    journal
+                        .append(&bitbucket, &Change::Add(grant(run * 10 + i)))
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for run in runs {
+        run.join().unwrap();
+    }
+
+    let entries = journal::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(entries.len(), 40);
+    journal::verify(&entries).unwrap();
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use bbdan::mapping::Mapping;
+use bbdan::{Bitbucket, ObjectType, Permission, PermissionType};
+use common::repo_at;
+
+#[tokio::test]
+async fn mapping_translates_groups_and_users() {
+    let path = std::env::temp_dir().join(format!("bbdan-mapping-{}.yaml", std::process::id()));
+    std::fs::write(
+        &path,
+        "groups:\n  Developers: engineering\nusers:\n  alice: \"{a2}\"\n",
+    )
+    .unwrap();
+    let mapping = Mapping::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let dest = Bitbucket {
+        workspace: "other".to_string(),
+        ..repo_at("http://localhost", "repo")
+    };
+    let permission = |object_type, id: &str, alias: &str| Permission {
+        object_type,
+        alias: alias.to_string(),
+        id: id.to_string(),
+        permission: PermissionType::Write,
+        via: Vec::new(),
+    };
+    let translated = mapping
+        .translate(
+            &dest,
+            vec![
+                permission(ObjectType::Group, "developers", "Developers"),
+                permission(ObjectType::User, "{a1}", "alice"),
+                permission(ObjectType::Group, "ops", "Ops"),
+            ],
+        )
+        .await
+        .unwrap();
+
+    let ids: Vec<_> = translated.iter().map(|p| p.id.as_str()).collect();
+    assert_eq!(ids, ["engineering", "{a2}", "ops"]);
+    assert!(translated
+        .iter()
+        .all(|p| p.permission == PermissionType::Write));
+}
@@ -0,0 +1,30 @@
+use bbdan::metrics;
+use bbdan::{ObjectType, Permission, PermissionType};
+
+#[test]
+fn metrics_count_grants_per_level() {
+    let permissions = vec![
+        Permission {
+            object_type: ObjectType::Group,
+            alias: "Developers".to_string(),
+            id: "developers".to_string(),
+            permission: PermissionType::Write,
+            via: Vec::new(),
+        },
+        Permission {
+            object_type: ObjectType::User,
+            alias: "alice".to_string(),
+            id: "{u1}".to_string(),
+            permission: PermissionType::Write,
+            via: Vec::new(),
+        },
+    ];
+
+    metrics::record_permissions("repo", &permissions);
+    metrics::record_request("GET", 200);
+    let out = metrics::render();
+
+    assert!(out.contains("bbdan_permissions{permission=\"write\",repository=\"repo\"} 2\n"));
+    assert!(out.contains("bbdan_permissions{permission=\"admin\",repository=\"repo\"} 0\n"));
+    assert!(out.contains("bbdan_api_requests_total{method=\"GET\",status=\"200\"} 1\n"));
+}
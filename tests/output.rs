@@ -0,0 +1,282 @@
+use bbdan::document::{DocumentFormat, PermissionsDocument};
+use bbdan::matrix::Matrix;
+use bbdan::output::{self, Field, Output, Template};
+use bbdan::report;
+use bbdan::terraform;
+use bbdan::{BbdanError, ObjectType, Permission, PermissionType};
+use std::sync::Arc;
+
+#[test]
+fn csv_has_header_and_escapes_fields() {
+    let permissions = vec![Permission {
+        object_type: ObjectType::Group,
+        alias: "Developers, \"core\"".to_string(),
+        id: "developers".to_string(),
+        permission: PermissionType::Write,
+        via: Vec::new(),
+    }];
+
+    let out =
+        output::permissions("workspace", "repo", &permissions, &[], Output::Csv, false).unwrap();
+
+    assert_eq!(
+        out,
+        "type,id,alias,permission\ngroup,developers,\"Developers, \"\"core\"\"\",write\n"
+    );
+}
+
+#[test]
+fn yaml_round_trips_through_the_export_document() {
+    let permissions = vec![
+        Permission {
+            object_type: ObjectType::Group,
+            alias: "Developers".to_string(),
+            id: "developers".to_string(),
+            permission: PermissionType::Write,
+            via: Vec::new(),
+        },
+        Permission {
+            object_type: ObjectType::User,
+            alias: "alice".to_string(),
+            id: "{u1}".to_string(),
+            permission: PermissionType::Admin,
+            via: Vec::new(),
+        },
+    ];
+
+    let out =
+        output::permissions("workspace", "repo", &permissions, &[], Output::Yaml, false).unwrap();
+    let document = PermissionsDocument::parse(&out, DocumentFormat::Yaml).unwrap();
+
+    assert_eq!(document.workspace, "workspace");
+    assert_eq!(document.repository, "repo");
+    let parsed = document.grants.permissions();
+    assert_eq!(parsed.len(), 2);
+    assert_eq!(parsed[0].id, "developers");
+    assert_eq!(parsed[1].id, "{u1}");
+    assert_eq!(parsed[1].permission, PermissionType::Admin);
+}
+
+#[test]
+fn template_renders_permissions() {
+    let path = std::env::temp_dir().join(format!("bbdan-template-{}.tera", std::process::id()));
+    std::fs::write(
+        &path,
+        "{{ repository }}\n{% for p in permissions %}{{ p.alias }}={{ p.permission }}\n{% endfor %}",
+    )
+    .unwrap();
+    let template = Template::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let permissions = vec![Permission {
+        object_type: ObjectType::User,
+        alias: "alice".to_string(),
+        id: "{u1}".to_string(),
+        permission: PermissionType::Read,
+        via: Vec::new(),
+    }];
+    let out = template
+        .permissions("workspace", "repo", &permissions)
+        .unwrap();
+
+    assert_eq!(out, "repo\nalice=read\n");
+}
+
+#[test]
+fn write_replaces_the_file() {
+    let dir = std::env::temp_dir().join(format!("bbdan-output-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("permissions.csv");
+    std::fs::write(&path, "old content that is longer than the new one\n").unwrap();
+
+    output::write(&path, "new\n").unwrap();
+
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+    let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+    assert_eq!(files.len(), 1, "the temporary file is left behind");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn fields_select_and_order_columns() {
+    let permissions = vec![Permission {
+        object_type: ObjectType::User,
+        alias: "alice".to_string(),
+        id: "{u1}".to_string(),
+        permission: PermissionType::Read,
+        via: Vec::new(),
+    }];
+    let fields = [Field::Permission, Field::Type, Field::Repository];
+
+    let csv = output::permissions(
+        "workspace",
+        "repo",
+        &permissions,
+        &fields,
+        Output::Csv,
+        false,
+    )
+    .unwrap();
+    let json = output::permissions(
+        "workspace",
+        "repo",
+        &permissions,
+        &fields,
+        Output::Ndjson,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(csv, "permission,type,repository\nread,user,repo\n");
+    assert_eq!(
+        json,
+        "{\"permission\":\"read\",\"objectType\":\"user\",\"repository\":\"repo\"}\n"
+    );
+}
+
+#[test]
+fn markdown_escapes_table_cells() {
+    let permissions = vec![Permission {
+        object_type: ObjectType::Group,
+        alias: "dev|ops_team".to_string(),
+        id: "devops".to_string(),
+        permission: PermissionType::Read,
+        via: Vec::new(),
+    }];
+
+    let out = output::permissions(
+        "workspace",
+        "repo",
+        &permissions,
+        &[],
+        Output::Markdown,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(
+        out,
+        "# repo\n\n| type | id | alias | permission |\n| --- | --- | --- | --- |\n| group | devops | dev\\|ops\\_team | read |\n"
+    );
+}
+
+#[test]
+fn report_summarizes_admin_holders() {
+    let results = vec![
+        (
+            "app".to_string(),
+            Ok(vec![Permission {
+                object_type: ObjectType::Group,
+                alias: "<admins>".to_string(),
+                id: "admins".to_string(),
+                permission: PermissionType::Admin,
+                via: Vec::new(),
+            }]),
+        ),
+        (
+            "broken".to_string(),
+            Err(Arc::new(BbdanError::Invalid("request failed".to_string()))),
+        ),
+    ];
+
+    let html = report::html("workspace", &results);
+
+    assert!(html.contains("<tr><th>Repositories</th><td>2</td></tr>"));
+    assert!(html.contains("<tr><th>Failed</th><td class=\"error\">1</td></tr>"));
+    assert!(html.contains(
+        "<tr><td>group</td><td>&lt;admins&gt;</td><td>admins</td><td>app</td><td>1</td></tr>"
+    ));
+    assert!(!html.contains("<admins>"));
+}
+
+#[test]
+fn junit_reports_a_test_case_per_repository() {
+    let results = vec![
+        ("app".to_string(), Ok(vec![])),
+        (
+            "broken".to_string(),
+            Err(Arc::new(BbdanError::Invalid("404 <Not Found>".to_string()))),
+        ),
+    ];
+
+    let xml = output::audit("workspace", &results, &[], Output::Junit, false).unwrap();
+
+    assert!(xml
+        .contains("<testsuite name=\"audit workspace\" tests=\"2\" failures=\"0\" errors=\"1\">"));
+    assert!(xml.contains("<testcase classname=\"audit workspace\" name=\"app\">\n      <system-out>type,id,alias,permission\n</system-out>"));
+    assert!(xml.contains("<error message=\"failed to list: 404 &lt;Not Found&gt;\"/>"));
+}
+
+#[test]
+fn matrix_has_a_column_per_principal() {
+    let grant = |object_type, id: &str, alias: &str, permission| Permission {
+        object_type,
+        alias: alias.to_string(),
+        id: id.to_string(),
+        permission,
+        via: Vec::new(),
+    };
+    let results = vec![
+        (
+            "a".to_string(),
+            Ok(vec![
+                grant(ObjectType::User, "{u1}", "alice", PermissionType::Admin),
+                grant(
+                    ObjectType::Group,
+                    "devs",
+                    "Developers",
+                    PermissionType::Write,
+                ),
+            ]),
+        ),
+        (
+            "broken".to_string(),
+            Err(Arc::new(BbdanError::Invalid("forbidden".to_string()))),
+        ),
+        (
+            "b".to_string(),
+            Ok(vec![grant(
+                ObjectType::Group,
+                "devs",
+                "Developers",
+                PermissionType::Read,
+            )]),
+        ),
+    ];
+
+    let out = Matrix::new(&results).csv().unwrap();
+
+    assert_eq!(
+        out,
+        "repository,group:devs,user:alice\na,write,admin\nb,read,\n"
+    );
+}
+
+#[test]
+fn terraform_has_a_resource_and_import_per_grant() {
+    let permissions = vec![Permission {
+        object_type: ObjectType::User,
+        alias: "alice.smith".to_string(),
+        id: "{u1}".to_string(),
+        permission: PermissionType::Admin,
+        via: Vec::new(),
+    }];
+
+    let out = terraform::hcl("ws", "2024-app", &permissions);
+
+    assert_eq!(
+        out,
+        r#"resource "bitbucket_repository_user_permission" "_2024_app_alice_smith" {
+  workspace  = "ws"
+  repo_slug  = "2024-app"
+  user_id    = "{u1}"
+  permission = "admin"
+}
+
+import {
+  to = bitbucket_repository_user_permission._2024_app_alice_smith
+  id = "ws:2024-app:{u1}"
+}
+"#
+    );
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use bbdan::pipelines::{copy_pipelines_config, copy_variables};
+use bbdan::Options;
+use common::{mount_page, repo};
+use serde_json::json;
+use wiremock::matchers::{body_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn secured_variables_are_copied_empty() {
+    let server = MockServer::start().await;
+    mount_page(
+        &server,
+        "/repositories/ws/template/pipelines_config/variables",
+        json!([
+            {"uuid": "{v1}", "key": "REGION", "value": "eu-west-1", "secured": false},
+            {"uuid": "{v2}", "key": "TOKEN", "secured": true},
+        ]),
+    )
+    .await;
+    // REGION keeps its value
+    mount_page(
+        &server,
+        "/repositories/ws/service/pipelines_config/variables",
+        json!([{"uuid": "{v3}", "key": "REGION", "value": "us-east-1", "secured": false}]),
+    )
+    .await;
+    Mock::given(method("POST"))
+        .and(path("/repositories/ws/service/pipelines_config/variables"))
+        .and(body_json(
+            json!({"key": "TOKEN", "value": "", "secured": true}),
+        ))
+        .respond_with(ResponseTemplate::new(201).set_body_json(json!({"uuid": "{v4}"})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let results = copy_variables(
+        repo(&server, "template"),
+        &[repo(&server, "service")],
+        false,
+        options,
+    )
+    .await
+    .unwrap();
+
+    let summary = results[0].1.as_ref().unwrap();
+    assert_eq!((summary.added, summary.unchanged), (1, 1));
+}
+
+#[tokio::test]
+async fn pipelines_are_enabled_as_on_the_template() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws/template/pipelines_config"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"enabled": true})))
+        .mount(&server)
+        .await;
+    // Pipelines never set up on the new repository
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws/service/pipelines_config"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({"type": "error"})))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws/enabled/pipelines_config"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"enabled": true})))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path("/repositories/ws/service/pipelines_config"))
+        .and(body_json(json!({"enabled": true})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"enabled": true})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let results = copy_pipelines_config(
+        repo(&server, "template"),
+        &[repo(&server, "service"), repo(&server, "enabled")],
+        options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(results[0].1.as_ref().unwrap().updated, 1);
+    assert_eq!(results[1].1.as_ref().unwrap().unchanged, 1);
+}
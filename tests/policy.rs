@@ -0,0 +1,79 @@
+use bbdan::policy::Policy;
+use bbdan::{BbdanError, ObjectType, Permission, PermissionType};
+use std::sync::Arc;
+
+fn grant(object_type: ObjectType, id: &str, level: PermissionType) -> Permission {
+    Permission {
+        object_type,
+        alias: id.to_string(),
+        id: id.to_string(),
+        permission: level,
+        via: Vec::new(),
+    }
+}
+
+#[test]
+fn policy_reports_forbidden_and_missing_grants() {
+    let policy = Policy::parse(
+        r#"
+rules:
+- name: no individual admins
+  forbid: type == 'user' && permission == 'admin'
+- name: security everywhere
+  require: type == 'group' && id == 'security'
+  repositories: "app-*"
+"#,
+    )
+    .unwrap();
+    let results = vec![
+        (
+            "app-web".to_string(),
+            Ok(vec![
+                grant(ObjectType::User, "alice", PermissionType::Admin),
+                grant(ObjectType::Group, "devs", PermissionType::Write),
+            ]),
+        ),
+        (
+            "app-api".to_string(),
+            Ok(vec![grant(
+                ObjectType::Group,
+                "security",
+                PermissionType::Read,
+            )]),
+        ),
+        ("infra".to_string(), Ok(vec![])),
+        (
+            "broken".to_string(),
+            Err(Arc::new(BbdanError::Invalid("forbidden".to_string()))),
+        ),
+    ];
+
+    let violations = policy.evaluate(&results);
+
+    let found: Vec<(&str, &str, &str)> = violations
+        .iter()
+        .map(|v| (v.repository.as_str(), v.rule.as_str(), v.message.as_str()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("app-web", "no individual admins", "user alice has admin"),
+            (
+                "app-web",
+                "security everywhere",
+                "no grant matches type == 'group' && id == 'security'"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn policy_rejects_rules_without_exactly_one_check() {
+    for content in [
+        "rules:\n- name: empty\n",
+        "rules:\n- name: both\n  forbid: type == 'user'\n  require: type == 'group'\n",
+        "rules:\n- name: typo\n  forbid: level == 'admin'\n",
+    ] {
+        assert!(Policy::parse(content).is_err(), "{}", content);
+    }
+}
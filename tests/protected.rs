@@ -0,0 +1,155 @@
+mod common;
+
+use bbdan::config::load_config;
+use bbdan::mapping::Mapping;
+use bbdan::permissions::{Protected, RemoveFilter};
+use bbdan::reconcile::ChangeFilter;
+use bbdan::{copy, remove, Options};
+use common::repo;
+use serde_json::json;
+use std::sync::OnceLock;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+async fn mount_groups(server: &MockServer, slug: &str, groups: &[&str]) {
+    let values: Vec<_> = groups
+        .iter()
+        .map(|g| {
+            json!({
+                "type": "repository_group_permission",
+                "permission": "admin",
+                "group": {"type": "group", "slug": g, "name": g},
+            })
+        })
+        .collect();
+    for (kind, values) in [("groups", values), ("users", vec![])] {
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/repositories/ws/{}/permissions-config/{}",
+                slug, kind
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "values": values,
+                "pagelen": 100,
+                "size": values.len(),
+                "page": 1,
+            })))
+            .mount(server)
+            .await;
+    }
+}
+
+/// The administrators group protected by a config file, loaded once for every test of the run
+fn administrators() -> &'static Protected {
+    static PROTECTED: OnceLock<Protected> = OnceLock::new();
+    PROTECTED.get_or_init(|| {
+        let config_path =
+            std::env::temp_dir().join(format!("bbdan-protected-{}.toml", std::process::id()));
+        std::fs::write(&config_path, "[protected]\ngroups = [\"administrators\"]\n").unwrap();
+        let config = load_config(Some(&config_path)).unwrap();
+        std::fs::remove_file(&config_path).unwrap();
+        config.protected
+    })
+}
+
+#[tokio::test]
+async fn copy_never_removes_protected_groups() {
+    let server = MockServer::start().await;
+    mount_groups(&server, "src", &[]).await;
+    mount_groups(&server, "dest", &["administrators", "old"]).await;
+    Mock::given(method("DELETE"))
+        .and(path("/repositories/ws/dest/permissions-config/groups/old"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path(
+            "/repositories/ws/dest/permissions-config/groups/administrators",
+        ))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        protected: Some(administrators()),
+        ..Options::default()
+    };
+    let summary = copy(
+        repo(&server, "src"),
+        repo(&server, "dest"),
+        &Mapping::default(),
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(summary.removed, 1);
+    assert_eq!(summary.skipped, 1);
+}
+
+#[tokio::test]
+async fn protected_groups_do_not_count_toward_max_changes() {
+    let server = MockServer::start().await;
+    mount_groups(&server, "src", &[]).await;
+    mount_groups(&server, "dest", &["administrators", "old"]).await;
+    Mock::given(method("DELETE"))
+        .and(path("/repositories/ws/dest/permissions-config/groups/old"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        max_changes: Some(1),
+        protected: Some(administrators()),
+        ..Options::default()
+    };
+    let summary = copy(
+        repo(&server, "src"),
+        repo(&server, "dest"),
+        &Mapping::default(),
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(summary.removed, 1);
+    assert_eq!(summary.skipped, 1);
+}
+
+#[tokio::test]
+async fn remove_never_removes_protected_groups() {
+    let server = MockServer::start().await;
+    mount_groups(&server, "repo", &["administrators", "old"]).await;
+    Mock::given(method("DELETE"))
+        .and(path("/repositories/ws/repo/permissions-config/groups/old"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path(
+            "/repositories/ws/repo/permissions-config/groups/administrators",
+        ))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let filter = RemoveFilter {
+        groups: vec!["administrators".to_string(), "old".to_string()],
+        ..RemoveFilter::default()
+    };
+    let options = Options {
+        yes: true,
+        protected: Some(administrators()),
+        ..Options::default()
+    };
+    remove(repo(&server, "repo"), filter, options)
+        .await
+        .unwrap();
+}
@@ -0,0 +1,49 @@
+use bbdan::query::Query;
+use bbdan::{ObjectType, Permission, PermissionType};
+
+fn permission(object_type: ObjectType, alias: &str, level: PermissionType) -> Permission {
+    Permission {
+        object_type,
+        alias: alias.to_string(),
+        id: alias.to_string(),
+        permission: level,
+        via: Vec::new(),
+    }
+}
+
+#[test]
+fn query_combines_comparisons() {
+    let query =
+        Query::parse("permission == 'admin' && (type == \"user\" || !(repository != 'infra'))")
+            .unwrap();
+
+    assert!(query.matches(
+        "app",
+        &permission(ObjectType::User, "alice", PermissionType::Admin)
+    ));
+    assert!(query.matches(
+        "infra",
+        &permission(ObjectType::Group, "ops", PermissionType::Admin)
+    ));
+    assert!(!query.matches(
+        "app",
+        &permission(ObjectType::Group, "ops", PermissionType::Admin)
+    ));
+    assert!(!query.matches(
+        "app",
+        &permission(ObjectType::User, "bob", PermissionType::Write)
+    ));
+}
+
+#[test]
+fn query_rejects_invalid_expressions() {
+    for query in [
+        "perm == 'admin'",
+        "permission == admin",
+        "(alias == 'a'",
+        "alias == 'a' &&",
+        "alias = 'a'",
+    ] {
+        assert!(Query::parse(query).is_err(), "{} is accepted", query);
+    }
+}
@@ -0,0 +1,19 @@
+use bbdan::repositories::parse_slugs;
+use bbdan::BbdanError;
+
+#[test]
+fn slugs_are_read_one_per_line() {
+    let text = "repo-a\n\n# archived\n  repo_b.old  \n";
+    assert_eq!(parse_slugs(text).unwrap(), vec!["repo-a", "repo_b.old"]);
+}
+
+#[test]
+fn lines_that_are_not_slugs_are_rejected() {
+    let err = parse_slugs("repo-a\nrepo b\n").unwrap_err();
+    assert!(
+        matches!(&err, BbdanError::Invalid(e) if e.contains("line 2")),
+        "{:?}",
+        err
+    );
+    assert!(parse_slugs("slug,project\nrepo-a,PROJ\n").is_err());
+}
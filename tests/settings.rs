@@ -0,0 +1,73 @@
+mod common;
+
+use bbdan::settings::{copy_settings, Setting};
+use bbdan::Options;
+use common::repo;
+use serde_json::json;
+use wiremock::matchers::{body_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn only_the_differing_settings_are_updated() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws/template"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "slug": "template",
+            "fork_policy": "no_public_forks",
+            "has_wiki": false,
+            "has_issues": true,
+            "mainbranch": {"name": "main", "type": "branch"},
+            "language": "rust",
+            "is_private": true,
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws/service"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "slug": "service",
+            "fork_policy": "allow_forks",
+            "has_wiki": false,
+            "has_issues": false,
+            "mainbranch": {"name": "master", "type": "branch"},
+            "language": "",
+            "is_private": true,
+        })))
+        .mount(&server)
+        .await;
+    // The language is left out by --only
+    Mock::given(method("PUT"))
+        .and(path("/repositories/ws/service"))
+        .and(body_json(json!({
+            "fork_policy": "no_public_forks",
+            "has_issues": true,
+            "mainbranch": {"name": "main"},
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"slug": "service"})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let only = [
+        Setting::ForkPolicy,
+        Setting::Wiki,
+        Setting::Issues,
+        Setting::MainBranch,
+    ];
+    let results = copy_settings(
+        repo(&server, "template"),
+        &[repo(&server, "service")],
+        &only,
+        options,
+    )
+    .await
+    .unwrap();
+
+    let summary = results[0].1.as_ref().unwrap();
+    assert_eq!((summary.updated, summary.unchanged), (3, 1));
+}
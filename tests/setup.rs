@@ -0,0 +1,59 @@
+mod common;
+
+use bbdan::reconcile::ChangeFilter;
+use bbdan::setup::{clone_setup, Skip};
+use bbdan::Options;
+use common::repo;
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn a_failing_setting_does_not_stop_the_others() {
+    let server = MockServer::start().await;
+    for slug in ["template", "service"] {
+        Mock::given(method("GET"))
+            .and(path(format!("/repositories/ws/{}", slug)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "slug": slug })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/repositories/ws/{}/default-reviewers", slug)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"values": []})))
+            .mount(&server)
+            .await;
+    }
+    // Webhooks need a scope the credentials lack
+    Mock::given(method("GET"))
+        .and(path("/repositories/ws/template/hooks"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({"type": "error"})))
+        .mount(&server)
+        .await;
+
+    let skip = Skip {
+        skip_permissions: true,
+        skip_branch_restrictions: true,
+        skip_deploy_keys: true,
+        skip_pipelines_vars: true,
+        ..Skip::default()
+    };
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let results = clone_setup(
+        &repo(&server, "template"),
+        &repo(&server, "service"),
+        &skip,
+        &ChangeFilter::default(),
+        options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].0, "default-reviewers");
+    assert!(results[0].1.is_ok());
+    assert_eq!(results[1].0, "hooks");
+    assert!(results[1].1.is_err());
+}
@@ -0,0 +1,38 @@
+use bbdan::snapshot::{list_snapshots, Snapshot};
+use bbdan::{BbdanError, ObjectType, Permission, PermissionType};
+use std::sync::Arc;
+
+#[test]
+fn snapshots_are_saved_and_found_by_name() {
+    let dir = std::env::temp_dir().join(format!("bbdan-snapshots-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let permissions = vec![Permission {
+        object_type: ObjectType::Group,
+        alias: "Developers".to_string(),
+        id: "developers".to_string(),
+        permission: PermissionType::Write,
+        via: Vec::new(),
+    }];
+    let results = vec![
+        ("repo-a".to_string(), Ok(permissions)),
+        (
+            "repo-b".to_string(),
+            Err(Arc::new(BbdanError::Invalid("404 Not Found".to_string()))),
+        ),
+    ];
+
+    let snapshot = Snapshot::new("ws", &results);
+    assert!(snapshot.name().ends_with("Z-repo-a"));
+    snapshot.save(&dir).unwrap();
+    // a second snapshot of the same second never replaces the first
+    assert!(snapshot.save(&dir).is_err());
+
+    let loaded = Snapshot::load(&dir, &snapshot.name()).unwrap();
+    let restored = loaded.repositories["repo-a"].permissions();
+    assert_eq!(restored.len(), 1);
+    assert_eq!(restored[0].id, "developers");
+    assert_eq!(restored[0].permission, PermissionType::Write);
+    assert!(!loaded.repositories.contains_key("repo-b"));
+    assert_eq!(list_snapshots(&dir).unwrap().len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,27 @@
+use bbdan::telemetry::{endpoint_from, OTLP_ENDPOINT_ENV, OTLP_TRACES_ENDPOINT_ENV};
+use std::collections::HashMap;
+
+fn endpoint(vars: &[(&str, &str)]) -> Option<String> {
+    let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+    endpoint_from(|name| vars.get(name).map(|v| v.to_string()))
+}
+
+#[test]
+fn tracing_is_enabled_by_the_otlp_endpoint() {
+    assert_eq!(endpoint(&[]), None);
+    assert_eq!(endpoint(&[(OTLP_ENDPOINT_ENV, "")]), None);
+
+    assert_eq!(
+        endpoint(&[(OTLP_ENDPOINT_ENV, "http://collector:4318/")]).as_deref(),
+        Some("http://collector:4318/v1/traces")
+    );
+
+    assert_eq!(
+        endpoint(&[
+            (OTLP_ENDPOINT_ENV, "http://collector:4318/"),
+            (OTLP_TRACES_ENDPOINT_ENV, "http://traces:4318/custom"),
+        ])
+        .as_deref(),
+        Some("http://traces:4318/custom")
+    );
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use bbdan::webhooks::copy_webhooks;
+use bbdan::Options;
+use common::{mount_page, repo};
+use serde_json::json;
+use wiremock::matchers::{body_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn copy_sends_the_url_events_and_active_flag() {
+    let server = MockServer::start().await;
+    mount_page(
+        &server,
+        "/repositories/ws/template/hooks",
+        json!([{
+            "uuid": "{h1}", "url": "https://ci.example.com/hook", "description": "CI",
+            "active": false, "events": ["repo:push"], "secret_set": true,
+        }]),
+    )
+    .await;
+    mount_page(&server, "/repositories/ws/service/hooks", json!([])).await;
+    Mock::given(method("POST"))
+        .and(path("/repositories/ws/service/hooks"))
+        .and(body_json(json!({
+            "url": "https://ci.example.com/hook", "description": "CI", "active": false,
+            "events": ["repo:push"], "skip_cert_verification": false,
+        })))
+        .respond_with(ResponseTemplate::new(201).set_body_json(json!({"uuid": "{h2}"})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let results = copy_webhooks(
+        repo(&server, "template"),
+        &[repo(&server, "service")],
+        false,
+        options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(results[0].1.as_ref().unwrap().added, 1);
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use bbdan::groups::{add_members, find_group, remove_members, GroupMembers};
+use bbdan::workspace::{list_workspace_permissions, WorkspaceRole};
+use bbdan::{ObjectType, Options, Permission, PermissionType};
+use common::repo;
+use serde_json::json;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn membership(uuid: &str, nickname: &str, permission: &str) -> serde_json::Value {
+    json!({
+        "type": "workspace_membership",
+        "permission": permission,
+        "user": {"type": "user", "uuid": uuid, "nickname": nickname},
+        "workspace": {"slug": "ws"},
+    })
+}
+
+#[tokio::test]
+async fn workspace_permissions_follow_pagination_and_filter_by_role() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/workspaces/ws/permissions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [membership("{u1}", "alice", "owner")],
+            "next": format!("{}/workspaces/ws/permissions?page=2", server.uri()),
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/workspaces/ws/permissions"))
+        .and(query_param("page", "2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [
+                membership("{u2}", "bob", "member"),
+                membership("{u3}", "carol", "owner"),
+            ],
+        })))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+
+    let all = list_workspace_permissions(&repo(&server, ""), None)
+        .await
+        .unwrap();
+    assert_eq!(all.len(), 3);
+
+    let owners = list_workspace_permissions(&repo(&server, ""), Some(WorkspaceRole::Owner))
+        .await
+        .unwrap();
+    let nicknames: Vec<&str> = owners.iter().map(|p| p.user.nickname.as_str()).collect();
+    assert_eq!(nicknames, ["alice", "carol"]);
+}
+
+#[tokio::test]
+async fn group_is_found_by_slug_or_name() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/1.0/groups/ws"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            {"slug": "developers", "name": "Developers", "members": [
+                {"uuid": "{u1}", "nickname": "alice", "display_name": "Alice"},
+                {"uuid": "{u2}", "nickname": "bob", "display_name": "Bob"},
+            ]},
+            {"slug": "admins", "name": "Admins", "members": []},
+        ])))
+        .mount(&server)
+        .await;
+
+    let group = find_group(&repo(&server, ""), "Developers").await.unwrap();
+    assert_eq!(group.slug, "developers");
+    assert_eq!(group.members.len(), 2);
+
+    let missing = find_group(&repo(&server, ""), "qa").await;
+    assert!(missing.is_err());
+}
+
+#[tokio::test]
+async fn only_missing_members_are_added_and_only_members_removed() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/1.0/groups/ws"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            {"slug": "developers", "name": "Developers", "members": [
+                {"uuid": "{u1}", "nickname": "alice"},
+            ]},
+        ])))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path("/1.0/groups/ws/developers/members/%7Bu2%7D"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path("/1.0/groups/ws/developers/members/%7Bu1%7D"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = Options {
+        yes: true,
+        ..Options::default()
+    };
+    let users = ["{u1}".to_string(), "{u2}".to_string()];
+    let limited = Options {
+        max_changes: Some(0),
+        ..options
+    };
+    let err = add_members(&repo(&server, ""), "developers", &users, limited)
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("exceed --max-changes 0"),
+        "{}",
+        err
+    );
+    let err = remove_members(&repo(&server, ""), "developers", &users, limited)
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("exceed --max-changes 0"),
+        "{}",
+        err
+    );
+
+    let added = add_members(&repo(&server, ""), "developers", &users, options)
+        .await
+        .unwrap();
+    assert_eq!((added.added, added.unchanged), (1, 1));
+
+    let removed = remove_members(&repo(&server, ""), "developers", &users, options)
+        .await
+        .unwrap();
+    assert_eq!((removed.removed, removed.unchanged), (1, 1));
+}
+
+fn grant(object_type: ObjectType, id: &str, permission: PermissionType) -> Permission {
+    Permission {
+        object_type,
+        alias: id.to_string(),
+        id: id.to_string(),
+        permission,
+        via: Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn expanded_grants_keep_the_highest_level_per_user() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/1.0/groups/ws"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            {"slug": "developers", "name": "Developers", "members": [
+                {"uuid": "{u1}", "nickname": "alice"},
+                {"uuid": "{u2}", "nickname": "bob"},
+            ]},
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/workspaces/ws/permissions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "values": [
+                membership("{u3}", "carol", "owner"),
+                membership("{u2}", "bob", "member"),
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let members = GroupMembers::load(&repo(&server, "")).await.unwrap();
+    let expanded = members.expand(vec![
+        grant(ObjectType::User, "{u1}", PermissionType::Admin),
+        grant(ObjectType::Group, "developers", PermissionType::Write),
+        grant(ObjectType::Group, "partners", PermissionType::Read),
+    ]);
+
+    let rows: Vec<(&str, PermissionType, String)> = expanded
+        .iter()
+        .map(|p| (p.id.as_str(), p.permission, p.via.join(" ")))
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            (
+                "{u1}",
+                PermissionType::Admin,
+                "direct group:developers".to_string()
+            ),
+            (
+                "{u2}",
+                PermissionType::Write,
+                "group:developers".to_string()
+            ),
+            // Not a group of the workspace, kept as it is
+            ("partners", PermissionType::Read, String::new()),
+            // Owners administer every repository
+            ("{u3}", PermissionType::Admin, "workspace:owner".to_string()),
+        ]
+    );
+}